@@ -20,14 +20,14 @@ use crate::core::core::transaction::Transaction;
 use crate::core::core::verifier_cache::VerifierCache;
 use crate::handlers::blocks_api::{BlockHandler, HeaderHandler};
 use crate::handlers::chain_api::{ChainHandler, KernelHandler, OutputHandler};
-use crate::handlers::pool_api::PoolHandler;
+use crate::handlers::pool_api::{FeeEstimateHandler, PoolHandler};
 use crate::handlers::transactions_api::TxHashSetHandler;
 use crate::handlers::version_api::VersionHandler;
 use crate::pool::{self, BlockChain, PoolAdapter, PoolEntry};
 use crate::rest::*;
 use crate::types::{
-	BlockHeaderPrintable, BlockPrintable, LocatedTxKernel, OutputListing, OutputPrintable, Tip,
-	Version,
+	BlockHeaderPrintable, BlockPrintable, FeeEstimate, LocatedTxKernel, OutputHistoryPrintable,
+	OutputListing, OutputPrintable, PoolListing, PoolQuery, Tip, Version,
 };
 use crate::util::RwLock;
 use std::sync::Weak;
@@ -178,6 +178,7 @@ where
 	/// If not supplied, `min_height` will be set to 0 and `max_height` will be set to the head of the chain.
 	/// The method will start at the block height `max_height` and traverse the kernel MMR backwards,
 	/// until either the kernel is found or `min_height` is reached.
+	/// Nodes running with the kernel excess index enabled look the kernel up directly instead.
 	///
 	/// # Arguments
 	/// * `excess` - kernel excess to look for.
@@ -238,6 +239,25 @@ where
 		)
 	}
 
+	/// Retrieves the history of an output commitment, the blocks on the current chain
+	/// creating and spending it, in height order.
+	/// Requires the node to run with the output history index enabled.
+	///
+	/// # Arguments
+	/// * `commit` - the output commitment, spent or unspent.
+	///
+	/// # Returns
+	/// * Result Containing:
+	/// * A vector of [`OutputHistoryPrintable`](types/struct.OutputHistoryPrintable.html)
+	/// * or [`Error`](struct.Error.html) if an error is encountered.
+	///
+	pub fn get_output_history(&self, commit: String) -> Result<Vec<OutputHistoryPrintable>, Error> {
+		let output_handler = OutputHandler {
+			chain: self.chain.clone(),
+		};
+		output_handler.get_output_history(commit)
+	}
+
 	/// UTXO traversal. Retrieves last utxos since a `start_index` until a `max`.
 	///
 	/// # Arguments
@@ -334,6 +354,43 @@ where
 		pool_handler.get_unconfirmed_transactions()
 	}
 
+	/// Estimates the fee rate a transaction should pay to be mined within 1 up to
+	/// `target_blocks` blocks, based on the recent blocks and the transaction pool.
+	///
+	/// # Arguments
+	/// * `target_blocks` - highest number of blocks to estimate for, at most 60.
+	///
+	/// # Returns
+	/// * Result Containing:
+	/// * A vector of [`FeeEstimate`](types/struct.FeeEstimate.html), one per target
+	/// * or [`Error`](struct.Error.html) if an error is encountered.
+	///
+	pub fn estimate_fee(&self, target_blocks: u64) -> Result<Vec<FeeEstimate>, Error> {
+		let fee_handler = FeeEstimateHandler {
+			chain: self.chain.clone(),
+			tx_pool: self.tx_pool.clone(),
+		};
+		fee_handler.estimate_fee(target_blocks)
+	}
+
+	/// Returns a page of the unconfirmed transactions in the transaction pool
+	/// matching the provided filters. Will not return transactions in the stempool.
+	///
+	/// # Arguments
+	/// * `query` - [`PoolQuery`](types/struct.PoolQuery.html) filters and paging.
+	///
+	/// # Returns
+	/// * Result Containing:
+	/// * A [`PoolListing`](types/struct.PoolListing.html)
+	/// * or [`Error`](struct.Error.html) if an error is encountered.
+	///
+	pub fn get_pool_transactions(&self, query: PoolQuery) -> Result<PoolListing, Error> {
+		let pool_handler = PoolHandler {
+			tx_pool: self.tx_pool.clone(),
+		};
+		pool_handler.get_pool_transactions(query)
+	}
+
 	/// Push new transaction to our local transaction pool.
 	///
 	/// # Arguments
@@ -343,7 +400,7 @@ where
 	/// # Returns
 	/// * Result Containing:
 	/// * `Ok(())` if the transaction was pushed successfully
-	/// * or [`Error`](struct.Error.html) if an error is encountered.
+	/// * or [`Error`](struct.Error.html) if an error is encountered, `TxRejected` if refused by the pool.
 	///
 	pub fn push_transaction(&self, tx: Transaction, fluff: Option<bool>) -> Result<(), Error> {
 		let pool_handler = PoolHandler {
@@ -22,8 +22,8 @@ use crate::pool::PoolEntry;
 use crate::pool::{BlockChain, PoolAdapter};
 use crate::rest::ErrorKind;
 use crate::types::{
-	BlockHeaderPrintable, BlockPrintable, LocatedTxKernel, OutputListing, OutputPrintable, Tip,
-	Version,
+	BlockHeaderPrintable, BlockPrintable, FeeEstimate, LocatedTxKernel, OutputHistoryPrintable,
+	OutputListing, OutputPrintable, PoolListing, PoolQuery, Tip, Version,
 };
 use crate::util;
 
@@ -445,6 +445,53 @@ pub trait ForeignRpc: Sync + Send {
 		include_merkle_proof: Option<bool>,
 	) -> Result<Vec<OutputPrintable>, ErrorKind>;
 
+	/**
+	Networked version of [Foreign::get_output_history](struct.Foreign.html#method.get_output_history).
+
+	# Json rpc example
+
+	```
+	# grin_api::doctest_helper_json_rpc_foreign_assert_response!(
+	# r#"
+	{
+		"jsonrpc": "2.0",
+		"method": "get_output_history",
+		"params": ["08b7e57c448db5ef25aa119dde2312c64d7ff1b890c416c6dda5ec73cbfed2edea"],
+		"id": 1
+	}
+	# "#
+	# ,
+	# r#"
+	{
+		"id": 1,
+		"jsonrpc": "2.0",
+		"result": {
+			"Ok": [
+			{
+				"block_hash": "0000003fd5fc9a6f49f26fdf5a5d4fd0bbf8d0e7b2e1c9bf3a69f2bea3ecc2b4",
+				"height": 1,
+				"kernels": [],
+				"output_type": "Coinbase",
+				"spent": false
+			},
+			{
+				"block_hash": "000001e0c9bc2b96a8dbe8ae2d4fb2a4ab2ba2e23d7e1f7c6a4bd45bf0c3c8a5",
+				"height": 1442,
+				"kernels": [
+				"09c868a2fed619580f296e91d2819b6b3ae61ab734bf3d9c3eafa6d9700f00361b"
+				],
+				"output_type": null,
+				"spent": true
+			}
+			]
+		}
+	}
+	# "#
+	# );
+	```
+	 */
+	fn get_output_history(&self, commit: String) -> Result<Vec<OutputHistoryPrintable>, ErrorKind>;
+
 	/**
 	Networked version of [Foreign::get_unspent_outputs](struct.Foreign.html#method.get_unspent_outputs).
 
@@ -676,9 +723,54 @@ pub trait ForeignRpc: Sync + Send {
 	 */
 	fn get_unconfirmed_transactions(&self) -> Result<Vec<PoolEntry>, ErrorKind>;
 
+	/**
+	Networked version of [Foreign::get_pool_transactions](struct.Foreign.html#method.get_pool_transactions).
+
+	# Json rpc example
+
+	```
+	# grin_api::doctest_helper_json_rpc_foreign_assert_response!(
+	# r#"
+	{
+		"jsonrpc": "2.0",
+		"method": "get_pool_transactions",
+		"params": [
+			{
+				"kernel_excess": null,
+				"output_commit": null,
+				"min_fee": 10000000,
+				"max_fee": null,
+				"offset": 20,
+				"limit": 10
+			}
+		],
+		"id": 1
+	}
+	# "#
+	# ,
+	# r#"
+	{
+		"id": 1,
+		"jsonrpc": "2.0",
+		"result": {
+			"Ok": {
+				"total": 20,
+				"entries": []
+			}
+		}
+	}
+	# "#
+	# );
+	```
+	 */
+	fn get_pool_transactions(&self, query: PoolQuery) -> Result<PoolListing, ErrorKind>;
+
 	/**
 	Networked version of [Foreign::push_transaction](struct.Foreign.html#method.push_transaction).
 
+	A transaction refused by the pool fails with a `TxRejected` error giving the
+	reason, i.e. `{"Err": {"TxRejected": {"reason": "low_fee", "message": "Low fee transaction 1000"}}}`.
+
 	# Json rpc example
 
 	```
@@ -740,6 +832,49 @@ pub trait ForeignRpc: Sync + Send {
 	```
 	 */
 	fn push_transaction(&self, tx: Transaction, fluff: Option<bool>) -> Result<(), ErrorKind>;
+
+	/**
+	Networked version of [Foreign::estimate_fee](struct.Foreign.html#method.estimate_fee).
+
+	# Json rpc example
+
+	```
+	# grin_api::doctest_helper_json_rpc_foreign_assert_response!(
+	# r#"
+	{
+		"jsonrpc": "2.0",
+		"method": "estimate_fee",
+		"params": [3],
+		"id": 1
+	}
+	# "#
+	# ,
+	# r#"
+	{
+		"id": 1,
+		"jsonrpc": "2.0",
+		"result": {
+			"Ok": [
+				{
+					"target_blocks": 1,
+					"fee_rate": 1200000
+				},
+				{
+					"target_blocks": 2,
+					"fee_rate": 800000
+				},
+				{
+					"target_blocks": 3,
+					"fee_rate": 500000
+				}
+			]
+		}
+	}
+	# "#
+	# );
+	```
+	 */
+	fn estimate_fee(&self, target_blocks: u64) -> Result<Vec<FeeEstimate>, ErrorKind>;
 }
 
 impl<B, P, V> ForeignRpc for Foreign<B, P, V>
@@ -813,6 +948,10 @@ where
 		.map_err(|e| e.kind().clone())
 	}
 
+	fn get_output_history(&self, commit: String) -> Result<Vec<OutputHistoryPrintable>, ErrorKind> {
+		Foreign::get_output_history(self, commit).map_err(|e| e.kind().clone())
+	}
+
 	fn get_unspent_outputs(
 		&self,
 		start_index: u64,
@@ -844,9 +983,17 @@ where
 	fn get_unconfirmed_transactions(&self) -> Result<Vec<PoolEntry>, ErrorKind> {
 		Foreign::get_unconfirmed_transactions(self).map_err(|e| e.kind().clone())
 	}
+
+	fn get_pool_transactions(&self, query: PoolQuery) -> Result<PoolListing, ErrorKind> {
+		Foreign::get_pool_transactions(self, query).map_err(|e| e.kind().clone())
+	}
 	fn push_transaction(&self, tx: Transaction, fluff: Option<bool>) -> Result<(), ErrorKind> {
 		Foreign::push_transaction(self, tx, fluff).map_err(|e| e.kind().clone())
 	}
+
+	fn estimate_fee(&self, target_blocks: u64) -> Result<Vec<FeeEstimate>, ErrorKind> {
+		Foreign::estimate_fee(self, target_blocks).map_err(|e| e.kind().clone())
+	}
 }
 
 #[doc(hidden)]
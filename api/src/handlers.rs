@@ -14,6 +14,7 @@
 
 pub mod blocks_api;
 pub mod chain_api;
+pub mod events_api;
 pub mod peers_api;
 pub mod pool_api;
 pub mod server_api;
@@ -21,6 +22,7 @@ pub mod transactions_api;
 pub mod utils;
 pub mod version_api;
 
+use self::events_api::{EventPublisher, EventsHandler};
 use crate::auth::{
 	BasicAuthMiddleware, BasicAuthURIMiddleware, GRIN_BASIC_REALM, GRIN_FOREIGN_BASIC_REALM,
 };
@@ -29,7 +31,7 @@ use crate::chain::{Chain, SyncState};
 use crate::core::core::verifier_cache::VerifierCache;
 use crate::foreign::Foreign;
 use crate::foreign_rpc::ForeignRpc;
-use crate::owner::Owner;
+use crate::owner::{Owner, StratumShares, WebHookDeadLetters};
 use crate::owner_rpc::OwnerRpc;
 use crate::p2p;
 use crate::pool;
@@ -54,6 +56,9 @@ pub fn node_apis<B, P, V>(
 	tx_pool: Arc<RwLock<pool::TransactionPool<B, P, V>>>,
 	peers: Arc<p2p::Peers>,
 	sync_state: Arc<chain::SyncState>,
+	publisher: Arc<EventPublisher>,
+	webhooks: Option<Arc<dyn WebHookDeadLetters>>,
+	stratum: Option<Arc<dyn StratumShares>>,
 	api_secret: Option<String>,
 	foreign_api_secret: Option<String>,
 	tls_config: Option<TLSConfig>,
@@ -81,9 +86,14 @@ where
 		Arc::downgrade(&chain),
 		Arc::downgrade(&peers),
 		Arc::downgrade(&sync_state),
+		webhooks.as_ref().map(Arc::downgrade),
+		stratum.as_ref().map(Arc::downgrade),
 	);
 	router.add_route("/v2/owner", Arc::new(api_handler))?;
 
+	let events_handler = EventsHandler::new(Arc::downgrade(&chain), publisher);
+	router.add_route("/v2/events", Arc::new(events_handler))?;
+
 	// Add basic auth to v2 foreign API
 	if let Some(api_secret) = foreign_api_secret {
 		let api_basic_auth =
@@ -124,15 +134,25 @@ pub struct OwnerAPIHandlerV2 {
 	pub chain: Weak<Chain>,
 	pub peers: Weak<p2p::Peers>,
 	pub sync_state: Weak<SyncState>,
+	pub webhooks: Option<Weak<dyn WebHookDeadLetters>>,
+	pub stratum: Option<Weak<dyn StratumShares>>,
 }
 
 impl OwnerAPIHandlerV2 {
 	/// Create a new owner API handler for GET methods
-	pub fn new(chain: Weak<Chain>, peers: Weak<p2p::Peers>, sync_state: Weak<SyncState>) -> Self {
+	pub fn new(
+		chain: Weak<Chain>,
+		peers: Weak<p2p::Peers>,
+		sync_state: Weak<SyncState>,
+		webhooks: Option<Weak<dyn WebHookDeadLetters>>,
+		stratum: Option<Weak<dyn StratumShares>>,
+	) -> Self {
 		OwnerAPIHandlerV2 {
 			chain,
 			peers,
 			sync_state,
+			webhooks,
+			stratum,
 		}
 	}
 }
@@ -143,6 +163,8 @@ impl crate::router::Handler for OwnerAPIHandlerV2 {
 			self.chain.clone(),
 			self.peers.clone(),
 			self.sync_state.clone(),
+			self.webhooks.clone(),
+			self.stratum.clone(),
 		);
 
 		Box::pin(async move {
@@ -164,6 +164,25 @@ impl OutputHandler {
 		Ok(outputs)
 	}
 
+	// history of an output commitment from the output history index
+	pub fn get_output_history(&self, commit: String) -> Result<Vec<OutputHistoryPrintable>, Error> {
+		let commit = util::from_hex(&commit)
+			.map_err(|_| ErrorKind::RequestError("invalid commit hex".into()))?;
+		if commit.len() != 33 {
+			return Err(ErrorKind::RequestError("invalid commit length".into()).into());
+		}
+		let commit = Commitment::from_vec(commit);
+
+		let chain = w(&self.chain)?;
+		let history = chain
+			.get_output_history(&commit)
+			.map_err(|e| ErrorKind::Internal(format!("{}", e)))?
+			.iter()
+			.map(OutputHistoryPrintable::from)
+			.collect();
+		Ok(history)
+	}
+
 	// allows traversal of utxo set
 	pub fn get_unspent_outputs(
 		&self,
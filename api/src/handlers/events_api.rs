@@ -0,0 +1,550 @@
+// Copyright 2021 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Streaming of node events (blocks, headers, transactions) over a WebSocket.
+
+use super::utils::w;
+use crate::chain::{self, BlockStatus};
+use crate::core::core::hash::Hashed;
+use crate::core::core::{Block, BlockHeader, Transaction, TxKernel};
+use crate::p2p::types::PeerAddr;
+use crate::rest::*;
+use crate::router::{Handler, ResponseFuture};
+use crate::util::ToHex;
+use crate::web::*;
+use futures::{SinkExt, StreamExt};
+use hyper::header::{HeaderValue, CONNECTION, SEC_WEBSOCKET_ACCEPT, UPGRADE};
+use hyper::{Body, Request, StatusCode};
+use serde_json::{json, Value};
+use std::collections::{HashSet, VecDeque};
+use std::str::FromStr;
+use std::sync::{Arc, Weak};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::broadcast;
+use tokio_tungstenite::tungstenite::protocol::Role;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+/// Number of events buffered per subscriber before it is considered lagging.
+const EVENT_CHANNEL_CAPACITY: usize = 1024;
+
+/// Number of replayed block hashes remembered to skip duplicate live events.
+const REPLAY_DEDUP_WINDOW: usize = 64;
+
+/// GUID used to derive the Sec-WebSocket-Accept header (RFC 6455).
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Topics a WebSocket client can subscribe to.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum EventTopic {
+	/// A block was accepted by our chain (next, fork or reorg)
+	BlockAccepted,
+	/// A block was received from a peer
+	BlockReceived,
+	/// A block header was received from a peer
+	HeaderReceived,
+	/// A transaction was received from a peer
+	TxReceived,
+	/// A block was disconnected from our chain by a reorg
+	BlockDisconnected,
+	/// A reorg was fully processed, rolled back txs are back in the pool
+	ReorgCompleted,
+}
+
+impl EventTopic {
+	/// All available topics.
+	pub const ALL: [EventTopic; 6] = [
+		EventTopic::BlockAccepted,
+		EventTopic::BlockReceived,
+		EventTopic::HeaderReceived,
+		EventTopic::TxReceived,
+		EventTopic::BlockDisconnected,
+		EventTopic::ReorgCompleted,
+	];
+}
+
+impl FromStr for EventTopic {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<EventTopic, Error> {
+		match s.trim() {
+			"block_accepted" => Ok(EventTopic::BlockAccepted),
+			"block_received" => Ok(EventTopic::BlockReceived),
+			"header_received" => Ok(EventTopic::HeaderReceived),
+			"tx_received" => Ok(EventTopic::TxReceived),
+			"block_disconnected" => Ok(EventTopic::BlockDisconnected),
+			"reorg_completed" => Ok(EventTopic::ReorgCompleted),
+			_ => Err(ErrorKind::Argument(format!("Unknown event topic: {}", s)).into()),
+		}
+	}
+}
+
+/// A single event as sent to WebSocket clients. The JSON payload is
+/// serialized once when the event is published and shared by all subscribers.
+#[derive(Debug, Clone)]
+pub struct NodeEvent {
+	/// Topic of the event
+	pub topic: EventTopic,
+	/// Hash of the block, header or transaction
+	pub hash: String,
+	/// Serialized JSON message
+	pub json: String,
+}
+
+impl NodeEvent {
+	fn new(topic: EventTopic, hash: String, height: Option<u64>, mut payload: Value) -> NodeEvent {
+		payload["topic"] = json!(topic);
+		payload["hash"] = json!(hash);
+		if let Some(height) = height {
+			payload["height"] = json!(height);
+		}
+		NodeEvent {
+			topic,
+			hash,
+			json: payload.to_string(),
+		}
+	}
+
+	fn block_accepted(block: &Block, status: &BlockStatus) -> NodeEvent {
+		let payload = match status {
+			BlockStatus::Next { prev } => json!({
+				"status": "head",
+				"prev": tip_json(prev),
+				"data": block,
+			}),
+			BlockStatus::Fork {
+				prev,
+				head,
+				fork_point,
+			} => json!({
+				"status": "fork",
+				"prev": tip_json(prev),
+				"head": tip_json(head),
+				"fork_point": tip_json(fork_point),
+				"depth": head.height.saturating_sub(fork_point.height),
+				"data": block,
+			}),
+			BlockStatus::Reorg {
+				prev,
+				prev_head,
+				fork_point,
+			} => json!({
+				"status": "reorg",
+				"prev": tip_json(prev),
+				"prev_head": tip_json(prev_head),
+				"fork_point": tip_json(fork_point),
+				"depth": prev_head.height.saturating_sub(fork_point.height),
+				"data": block,
+			}),
+		};
+		NodeEvent::new(
+			EventTopic::BlockAccepted,
+			block.hash().to_hex(),
+			Some(block.header.height),
+			payload,
+		)
+	}
+
+	/// Block from the current chain, sent when a client resumes from a height.
+	fn block_replayed(block: &Block) -> NodeEvent {
+		NodeEvent::new(
+			EventTopic::BlockAccepted,
+			block.hash().to_hex(),
+			Some(block.header.height),
+			json!({
+				"status": "head",
+				"replayed": true,
+				"data": block,
+			}),
+		)
+	}
+}
+
+fn tip_json(tip: &chain::Tip) -> Value {
+	json!({ "hash": tip.hash().to_hex(), "height": tip.height })
+}
+
+/// Publishes node events to all connected WebSocket clients.
+/// Events are dropped on the floor when nobody is listening.
+pub struct EventPublisher {
+	sender: broadcast::Sender<Arc<NodeEvent>>,
+}
+
+impl Default for EventPublisher {
+	fn default() -> EventPublisher {
+		EventPublisher::new()
+	}
+}
+
+impl EventPublisher {
+	/// Create a new publisher with no subscribers.
+	pub fn new() -> EventPublisher {
+		let (sender, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+		EventPublisher { sender }
+	}
+
+	/// Subscribe to all subsequently published events.
+	pub fn subscribe(&self) -> broadcast::Receiver<Arc<NodeEvent>> {
+		self.sender.subscribe()
+	}
+
+	/// Number of currently subscribed clients.
+	pub fn subscriber_count(&self) -> usize {
+		self.sender.receiver_count()
+	}
+
+	fn publish<F>(&self, f: F)
+	where
+		F: FnOnce() -> NodeEvent,
+	{
+		if self.subscriber_count() == 0 {
+			return;
+		}
+		// Sending only fails if the last subscriber went away in the meantime.
+		let _ = self.sender.send(Arc::new(f()));
+	}
+
+	/// A block was accepted by the chain.
+	pub fn block_accepted(&self, block: &Block, status: &BlockStatus) {
+		self.publish(|| NodeEvent::block_accepted(block, status));
+	}
+
+	/// A block was received from a peer.
+	pub fn block_received(&self, block: &Block, addr: &PeerAddr) {
+		self.publish(|| {
+			NodeEvent::new(
+				EventTopic::BlockReceived,
+				block.hash().to_hex(),
+				Some(block.header.height),
+				json!({ "peer": addr, "data": block }),
+			)
+		});
+	}
+
+	/// A block header was received from a peer.
+	pub fn header_received(&self, header: &BlockHeader, addr: &PeerAddr) {
+		self.publish(|| {
+			NodeEvent::new(
+				EventTopic::HeaderReceived,
+				header.hash().to_hex(),
+				Some(header.height),
+				json!({ "peer": addr, "data": header }),
+			)
+		});
+	}
+
+	/// A transaction was received from a peer.
+	pub fn tx_received(&self, tx: &Transaction) {
+		self.publish(|| {
+			NodeEvent::new(
+				EventTopic::TxReceived,
+				tx.hash().to_hex(),
+				None,
+				json!({ "data": tx }),
+			)
+		});
+	}
+
+	/// A block was disconnected from the chain by a reorg.
+	pub fn block_disconnected(&self, header: &BlockHeader) {
+		self.publish(|| {
+			NodeEvent::new(
+				EventTopic::BlockDisconnected,
+				header.hash().to_hex(),
+				Some(header.height),
+				json!({ "data": header }),
+			)
+		});
+	}
+
+	/// A reorg caused by the provided block has been processed, the kernels
+	/// are those of the rolled back txs returned to the pool.
+	pub fn reorg_completed(&self, block: &Block, status: &BlockStatus, kernels: &[TxKernel]) {
+		let (prev_head, fork_point) = match status {
+			BlockStatus::Reorg {
+				prev_head,
+				fork_point,
+				..
+			} => (prev_head, fork_point),
+			_ => return,
+		};
+		self.publish(|| {
+			NodeEvent::new(
+				EventTopic::ReorgCompleted,
+				block.hash().to_hex(),
+				Some(block.header.height),
+				json!({
+					"prev_head": tip_json(prev_head),
+					"fork_point": tip_json(fork_point),
+					"depth": prev_head.height.saturating_sub(fork_point.height),
+					"kernels": kernels,
+				}),
+			)
+		});
+	}
+}
+
+/// Subscription request sent by a client over an open WebSocket.
+#[derive(Deserialize, Debug)]
+struct SubscriptionRequest {
+	#[serde(default)]
+	subscribe: Vec<EventTopic>,
+	#[serde(default)]
+	unsubscribe: Vec<EventTopic>,
+}
+
+/// WebSocket endpoint streaming node events.
+///
+/// GET /v2/events
+/// GET /v2/events?topics=block_accepted,header_received
+/// GET /v2/events?topics=block_accepted&from_height=1000
+///
+/// Subscribes to all topics unless `topics` is provided. With `from_height`
+/// the blocks of the current chain from that height (or the chain tail if
+/// higher) up to the head are first sent as `block_accepted` events flagged
+/// `replayed`, followed by live events. Once connected the client may change
+/// its topics by sending `{"subscribe": [...]}` or `{"unsubscribe": [...]}`.
+/// A client falling too far behind gets an error message and is disconnected,
+/// it can reconnect resuming from the last height it processed.
+pub struct EventsHandler {
+	pub chain: Weak<chain::Chain>,
+	pub publisher: Arc<EventPublisher>,
+}
+
+impl EventsHandler {
+	/// Create a new events handler.
+	pub fn new(chain: Weak<chain::Chain>, publisher: Arc<EventPublisher>) -> EventsHandler {
+		EventsHandler { chain, publisher }
+	}
+
+	fn parse_params(
+		&self,
+		req: &Request<Body>,
+	) -> Result<(HashSet<EventTopic>, Option<u64>), Error> {
+		let params = QueryParams::from(req.uri().query());
+		let mut topics = HashSet::new();
+		let mut err = None;
+		params.process_multival_param("topics", |t| match t.parse() {
+			Ok(t) => {
+				topics.insert(t);
+			}
+			Err(e) => err = Some(e),
+		});
+		if let Some(e) = err {
+			return Err(e);
+		}
+		if topics.is_empty() {
+			topics.extend(EventTopic::ALL.iter());
+		}
+		let from_height = match params.get("from_height") {
+			Some(h) => Some(h.parse().map_err(|_| {
+				ErrorKind::RequestError("invalid value of parameter from_height".to_owned())
+			})?),
+			None => None,
+		};
+		Ok((topics, from_height))
+	}
+}
+
+/// Computes the Sec-WebSocket-Accept value for a client handshake key.
+fn websocket_accept_key(key: &[u8]) -> String {
+	let mut data = key.to_vec();
+	data.extend_from_slice(WEBSOCKET_GUID.as_bytes());
+	let digest = ring::digest::digest(&ring::digest::SHA1_FOR_LEGACY_USE_ONLY, &data);
+	base64::encode(digest.as_ref())
+}
+
+fn header_contains(req: &Request<Body>, name: hyper::header::HeaderName, value: &str) -> bool {
+	req.headers()
+		.get_all(name)
+		.iter()
+		.filter_map(|v| v.to_str().ok())
+		.flat_map(|v| v.split(','))
+		.any(|v| v.trim().eq_ignore_ascii_case(value))
+}
+
+impl Handler for EventsHandler {
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		let (topics, from_height) = match self.parse_params(&req) {
+			Ok(p) => p,
+			Err(e) => return response(StatusCode::BAD_REQUEST, format!("{}", e)),
+		};
+		if !header_contains(&req, UPGRADE, "websocket")
+			|| !header_contains(&req, CONNECTION, "upgrade")
+			|| !header_contains(&req, hyper::header::SEC_WEBSOCKET_VERSION, "13")
+		{
+			return response(
+				StatusCode::BAD_REQUEST,
+				"expected a websocket upgrade request",
+			);
+		}
+		let accept_key = match req.headers().get(hyper::header::SEC_WEBSOCKET_KEY) {
+			Some(key) => websocket_accept_key(key.as_bytes()),
+			None => return response(StatusCode::BAD_REQUEST, "missing Sec-WebSocket-Key"),
+		};
+
+		// Subscribe before replaying so no event falls in between.
+		let events = self.publisher.subscribe();
+		let chain = self.chain.clone();
+		tokio::spawn(async move {
+			match req.into_body().on_upgrade().await {
+				Ok(upgraded) => {
+					let ws = WebSocketStream::from_raw_socket(upgraded, Role::Server, None).await;
+					let session = EventSession {
+						topics,
+						replayed: VecDeque::new(),
+					};
+					if let Err(e) = session.run(ws, chain, events, from_height).await {
+						debug!("events websocket closed: {}", e);
+					}
+				}
+				Err(e) => error!("events websocket upgrade failed: {}", e),
+			}
+		});
+
+		let mut resp = just_response(StatusCode::SWITCHING_PROTOCOLS, "");
+		let headers = resp.headers_mut();
+		headers.insert(UPGRADE, HeaderValue::from_static("websocket"));
+		headers.insert(CONNECTION, HeaderValue::from_static("Upgrade"));
+		match HeaderValue::from_str(&accept_key) {
+			Ok(v) => {
+				headers.insert(SEC_WEBSOCKET_ACCEPT, v);
+			}
+			Err(_) => return response(StatusCode::INTERNAL_SERVER_ERROR, ""),
+		}
+		Box::pin(futures::future::ok(resp))
+	}
+}
+
+/// State of a single WebSocket client.
+struct EventSession {
+	topics: HashSet<EventTopic>,
+	/// Hashes of the most recently replayed blocks.
+	replayed: VecDeque<String>,
+}
+
+impl EventSession {
+	async fn run<S>(
+		mut self,
+		mut ws: WebSocketStream<S>,
+		chain: Weak<chain::Chain>,
+		mut events: broadcast::Receiver<Arc<NodeEvent>>,
+		from_height: Option<u64>,
+	) -> Result<(), String>
+	where
+		S: AsyncRead + AsyncWrite + Unpin,
+	{
+		if let Some(from_height) = from_height {
+			if self.topics.contains(&EventTopic::BlockAccepted) {
+				self.replay(&mut ws, &chain, from_height).await?;
+			}
+		}
+
+		loop {
+			tokio::select! {
+				msg = ws.next() => match msg {
+					Some(Ok(Message::Text(text))) => {
+						let reply = self.update_topics(&text);
+						ws.send(Message::Text(reply)).await.map_err(|e| e.to_string())?;
+					}
+					Some(Ok(Message::Close(_))) | None => return Ok(()),
+					Some(Ok(_)) => {}
+					Some(Err(e)) => return Err(e.to_string()),
+				},
+				event = events.recv() => match event {
+					Ok(event) => {
+						if self.wants(&event) {
+							ws.send(Message::Text(event.json.clone()))
+								.await
+								.map_err(|e| e.to_string())?;
+						}
+					}
+					Err(broadcast::RecvError::Lagged(n)) => {
+						let msg = json!({ "error": format!("client lagging, {} events dropped", n) });
+						let _ = ws.send(Message::Text(msg.to_string())).await;
+						let _ = ws.close(None).await;
+						return Err("client lagging".to_owned());
+					}
+					Err(broadcast::RecvError::Closed) => {
+						let _ = ws.close(None).await;
+						return Ok(());
+					}
+				},
+			}
+		}
+	}
+
+	/// Sends blocks of the current chain from the given height up to the head.
+	async fn replay<S>(
+		&mut self,
+		ws: &mut WebSocketStream<S>,
+		chain: &Weak<chain::Chain>,
+		from_height: u64,
+	) -> Result<(), String>
+	where
+		S: AsyncRead + AsyncWrite + Unpin,
+	{
+		let mut height = {
+			let chain = w(chain).map_err(|e| e.to_string())?;
+			let tail = chain.tail().map_err(|e| e.to_string())?;
+			from_height.max(tail.height)
+		};
+		loop {
+			// Re-read the head after each block, the chain may move during replay.
+			let event = {
+				let chain = w(chain).map_err(|e| e.to_string())?;
+				let head = chain.head().map_err(|e| e.to_string())?;
+				if height > head.height {
+					return Ok(());
+				}
+				let header = chain
+					.get_header_by_height(height)
+					.map_err(|e| e.to_string())?;
+				let block = chain.get_block(&header.hash()).map_err(|e| e.to_string())?;
+				NodeEvent::block_replayed(&block)
+			};
+			if self.replayed.len() == REPLAY_DEDUP_WINDOW {
+				self.replayed.pop_front();
+			}
+			self.replayed.push_back(event.hash.clone());
+			ws.send(Message::Text(event.json))
+				.await
+				.map_err(|e| e.to_string())?;
+			height += 1;
+		}
+	}
+
+	fn wants(&self, event: &NodeEvent) -> bool {
+		self.topics.contains(&event.topic)
+			&& !(event.topic == EventTopic::BlockAccepted && self.replayed.contains(&event.hash))
+	}
+
+	fn update_topics(&mut self, text: &str) -> String {
+		match serde_json::from_str::<SubscriptionRequest>(text) {
+			Ok(req) => {
+				self.topics.extend(req.subscribe.iter());
+				for t in &req.unsubscribe {
+					self.topics.remove(t);
+				}
+				let mut topics: Vec<_> = self.topics.iter().collect();
+				topics.sort_by_key(|t| EventTopic::ALL.iter().position(|a| a == *t));
+				json!({ "topics": topics }).to_string()
+			}
+			Err(e) => {
+				json!({ "error": format!("invalid subscription request: {}", e) }).to_string()
+			}
+		}
+	}
+}
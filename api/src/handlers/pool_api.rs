@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use super::utils::w;
+use crate::chain::Chain;
 use crate::core::core::hash::Hashed;
 use crate::core::core::verifier_cache::VerifierCache;
 use crate::core::core::Transaction;
@@ -22,6 +23,7 @@ use crate::rest::*;
 use crate::router::{Handler, ResponseFuture};
 use crate::types::*;
 use crate::util;
+use crate::util::secp::pedersen::Commitment;
 use crate::util::RwLock;
 use crate::web::*;
 use failure::ResultExt;
@@ -86,6 +88,48 @@ where
 		let txpool = pool_arc.read();
 		Ok(txpool.txpool.entries.clone())
 	}
+	pub fn get_pool_transactions(&self, query: PoolQuery) -> Result<PoolListing, Error> {
+		let kernel_excess = query
+			.kernel_excess
+			.as_ref()
+			.map(|x| parse_commitment(x, "kernel_excess"))
+			.transpose()?;
+		let output_commit = query
+			.output_commit
+			.as_ref()
+			.map(|x| parse_commitment(x, "output_commit"))
+			.transpose()?;
+
+		// will only read from txpool
+		let pool_arc = w(&self.tx_pool)?;
+		let pool = pool_arc.read();
+		let header = pool
+			.blockchain
+			.chain_head()
+			.context(ErrorKind::Internal("Failed to get chain head".to_owned()))?;
+		let matching: Vec<_> = pool
+			.txpool
+			.entries
+			.iter()
+			.filter(|x| {
+				let fee = x.tx.fee(header.height);
+				kernel_excess.is_none_or(|c| x.tx.kernels().iter().any(|k| k.excess == c))
+					&& output_commit
+						.is_none_or(|c| x.tx.outputs().iter().any(|o| o.commitment() == c))
+					&& query.min_fee.is_none_or(|min| fee >= min)
+					&& query.max_fee.is_none_or(|max| fee <= max)
+			})
+			.collect();
+		Ok(PoolListing {
+			total: matching.len() as u64,
+			entries: matching
+				.into_iter()
+				.skip(query.offset.unwrap_or(0) as usize)
+				.take(query.limit.map_or(usize::MAX, |x| x as usize))
+				.cloned()
+				.collect(),
+		})
+	}
 	pub fn push_transaction(&self, tx: Transaction, fluff: Option<bool>) -> Result<(), Error> {
 		let pool_arc = w(&self.tx_pool)?;
 		let source = pool::TxSource::PushApi;
@@ -105,10 +149,87 @@ where
 			.context(ErrorKind::Internal("Failed to get chain head".to_owned()))?;
 		tx_pool
 			.add_to_pool(source, tx, !fluff.unwrap_or(false), &header)
-			.context(ErrorKind::Internal("Failed to update pool".to_owned()))?;
+			.map_err(|e| ErrorKind::TxRejected((&e).into()))?;
 		Ok(())
 	}
 }
+
+/// Number of recent blocks fee estimates are based on.
+const FEE_ESTIMATE_BLOCKS: u64 = 60;
+
+/// Highest number of blocks fees can be estimated for.
+pub const MAX_FEE_ESTIMATE_TARGET: u64 = 60;
+
+pub struct FeeEstimateHandler<B, P, V>
+where
+	B: BlockChain,
+	P: PoolAdapter,
+	V: VerifierCache + 'static,
+{
+	pub chain: Weak<Chain>,
+	pub tx_pool: Weak<RwLock<pool::TransactionPool<B, P, V>>>,
+}
+
+impl<B, P, V> FeeEstimateHandler<B, P, V>
+where
+	B: BlockChain,
+	P: PoolAdapter,
+	V: VerifierCache + 'static,
+{
+	pub fn estimate_fee(&self, target_blocks: u64) -> Result<Vec<FeeEstimate>, Error> {
+		if target_blocks == 0 || target_blocks > MAX_FEE_ESTIMATE_TARGET {
+			return Err(ErrorKind::Argument(format!(
+				"target_blocks must be between 1 and {}",
+				MAX_FEE_ESTIMATE_TARGET
+			))
+			.into());
+		}
+
+		// Recent blocks, oldest first, read before locking the pool.
+		let chain = w(&self.chain)?;
+		let mut hash = chain
+			.head()
+			.context(ErrorKind::Internal("Failed to get chain head".to_owned()))?
+			.last_block_h;
+		let mut blocks = vec![];
+		while (blocks.len() as u64) < FEE_ESTIMATE_BLOCKS {
+			let block = match chain.get_block(&hash) {
+				Ok(block) => block,
+				Err(_) => break,
+			};
+			if block.header.height == 0 {
+				break;
+			}
+			hash = block.header.prev_hash;
+			blocks.push(block);
+		}
+		blocks.reverse();
+
+		let pool_arc = w(&self.tx_pool)?;
+		let rates = pool_arc
+			.read()
+			.estimate_fee(target_blocks, &blocks)
+			.context(ErrorKind::Internal("Failed to estimate fee".to_owned()))?;
+		Ok(rates
+			.into_iter()
+			.zip(1..)
+			.map(|(fee_rate, target_blocks)| FeeEstimate {
+				target_blocks,
+				fee_rate,
+			})
+			.collect())
+	}
+}
+
+fn parse_commitment(hex: &str, name: &str) -> Result<Commitment, Error> {
+	let vec =
+		util::from_hex(hex).map_err(|_| ErrorKind::Argument(format!("invalid {} hex", name)))?;
+	if vec.len() != 33 {
+		return Err(ErrorKind::Argument(format!("invalid {} length", name)).into());
+	}
+	Ok(Commitment::from_vec(vec))
+}
+
 /// Dummy wrapper for the hex-encoded serialized transaction.
 #[derive(Serialize, Deserialize)]
 struct TxWrapper {
@@ -165,7 +286,7 @@ where
 		.context(ErrorKind::Internal("Failed to get chain head".to_owned()))?;
 	tx_pool
 		.add_to_pool(source, tx, !fluff, &header)
-		.context(ErrorKind::Internal("Failed to update pool".to_owned()))?;
+		.map_err(|e| ErrorKind::TxRejected((&e).into()))?;
 	Ok(())
 }
 
@@ -180,9 +301,13 @@ where
 		Box::pin(async move {
 			let res = match update_pool(pool, req).await {
 				Ok(_) => just_response(StatusCode::OK, ""),
-				Err(e) => {
-					just_response(StatusCode::INTERNAL_SERVER_ERROR, format!("failed: {}", e))
-				}
+				Err(e) => match e.kind() {
+					ErrorKind::TxRejected(reject) => just_response(
+						StatusCode::BAD_REQUEST,
+						serde_json::to_string(reject).unwrap_or_else(|_| reject.to_string()),
+					),
+					_ => just_response(StatusCode::INTERNAL_SERVER_ERROR, format!("failed: {}", e)),
+				},
 			};
 			Ok(res)
 		})
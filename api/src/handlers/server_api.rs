@@ -91,6 +91,18 @@ fn sync_status_to_api(sync_status: SyncStatus) -> (String, Option<serde_json::Va
 				json!({ "downloaded_size": stats.downloaded_size, "total_size": stats.total_size }),
 			),
 		),
+		SyncStatus::TxHashsetPibdDownload(stats) => (
+			"txhashset_pibd_download".to_string(),
+			Some(json!({
+				"segments_done": stats.segments_done(),
+				"segments_total": stats.segments_total(),
+				"bitmap": stats.bitmap,
+				"output": stats.output,
+				"rangeproof": stats.rangeproof,
+				"kernel": stats.kernel,
+				"peers": stats.peers,
+			})),
+		),
 		SyncStatus::TxHashsetRangeProofsValidation {
 			rproofs,
 			rproofs_total,
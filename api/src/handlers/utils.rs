@@ -24,7 +24,7 @@ use std::sync::{Arc, Weak};
 // All handlers use `Weak` references instead of `Arc` to avoid cycles that
 // can never be destroyed. These 2 functions are simple helpers to reduce the
 // boilerplate of dealing with `Weak`.
-pub fn w<T>(weak: &Weak<T>) -> Result<Arc<T>, Error> {
+pub fn w<T: ?Sized>(weak: &Weak<T>) -> Result<Arc<T>, Error> {
 	weak.upgrade()
 		.ok_or_else(|| ErrorKind::Internal("failed to upgrade weak reference".to_owned()).into())
 }
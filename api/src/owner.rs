@@ -18,12 +18,35 @@ use crate::chain::{Chain, SyncState};
 use crate::handlers::chain_api::{ChainCompactHandler, ChainValidationHandler};
 use crate::handlers::peers_api::{PeerHandler, PeersConnectedHandler};
 use crate::handlers::server_api::StatusHandler;
+use crate::handlers::utils::w;
 use crate::p2p::types::PeerInfoDisplay;
 use crate::p2p::{self, PeerData};
 use crate::rest::*;
-use crate::types::Status;
+use crate::types::{PplnsWindow, Status, StratumBlock, WebHookDeadLetter};
 use std::net::SocketAddr;
-use std::sync::Weak;
+use std::sync::{Arc, Weak};
+
+/// Access to the webhook notifications that could not be delivered,
+/// implemented by the node's webhook delivery queue.
+pub trait WebHookDeadLetters: Send + Sync {
+	/// List the dead letters, oldest first, optionally with their payloads.
+	fn dead_letters(&self, include_payload: bool) -> Result<Vec<WebHookDeadLetter>, Error>;
+
+	/// Queue the given dead letters (all of them if `None`) for delivery
+	/// again, returning how many were queued.
+	fn replay_dead_letters(&self, ids: Option<Vec<u64>>) -> Result<usize, Error>;
+}
+
+/// Access to the shares and blocks recorded by the stratum server.
+pub trait StratumShares: Send + Sync {
+	/// List the blocks found by the stratum workers, oldest first.
+	fn found_blocks(&self) -> Result<Vec<StratumBlock>, Error>;
+
+	/// The PPLNS window of the block found at the given height (the last
+	/// found block if `None`), covering `window` share difficulty (twice the
+	/// block difficulty if `None`).
+	fn pplns_window(&self, height: Option<u64>, window: Option<u64>) -> Result<PplnsWindow, Error>;
+}
 
 /// Main interface into all node API functions.
 /// Node APIs are split into two seperate blocks of functionality
@@ -36,6 +59,8 @@ pub struct Owner {
 	pub chain: Weak<Chain>,
 	pub peers: Weak<p2p::Peers>,
 	pub sync_state: Weak<SyncState>,
+	pub webhooks: Option<Weak<dyn WebHookDeadLetters>>,
+	pub stratum: Option<Weak<dyn StratumShares>>,
 }
 
 impl Owner {
@@ -47,16 +72,26 @@ impl Owner {
 	/// * `tx_pool` - A non-owning reference of the transaction pool.
 	/// * `peers` - A non-owning reference of the peers.
 	/// * `sync_state` - A non-owning reference of the `sync_state`.
+	/// * `webhooks` - A non-owning reference of the webhook queue, if webhooks are configured.
+	/// * `stratum` - A non-owning reference of the stratum share store, if stratum is enabled.
 	///
 	/// # Returns
 	/// * An instance of the Node holding references to the current chain, transaction pool, peers and sync_state.
 	///
 
-	pub fn new(chain: Weak<Chain>, peers: Weak<p2p::Peers>, sync_state: Weak<SyncState>) -> Self {
+	pub fn new(
+		chain: Weak<Chain>,
+		peers: Weak<p2p::Peers>,
+		sync_state: Weak<SyncState>,
+		webhooks: Option<Weak<dyn WebHookDeadLetters>>,
+		stratum: Option<Weak<dyn StratumShares>>,
+	) -> Self {
 		Owner {
 			chain,
 			peers,
 			sync_state,
+			webhooks,
+			stratum,
 		}
 	}
 
@@ -176,4 +211,84 @@ impl Owner {
 		};
 		peer_handler.unban_peer(addr)
 	}
+
+	/// Retrieves the webhook notifications that could not be delivered after
+	/// exhausting their retries.
+	///
+	/// # Arguments
+	/// * `include_payload` - whether to include the JSON payload of each notification.
+	///
+	/// # Returns
+	/// * Result Containing:
+	/// * A vector of [`WebHookDeadLetter`](types/struct.WebHookDeadLetter.html)
+	/// * or [`Error`](struct.Error.html) if an error is encountered.
+	///
+	pub fn get_webhook_dead_letters(
+		&self,
+		include_payload: Option<bool>,
+	) -> Result<Vec<WebHookDeadLetter>, Error> {
+		self.webhooks()?
+			.dead_letters(include_payload.unwrap_or(false))
+	}
+
+	/// Queues webhook notifications that could not be delivered for delivery again.
+	///
+	/// # Arguments
+	/// * `ids` - the ids of the dead letters to replay, all of them if `None`.
+	///
+	/// # Returns
+	/// * Result Containing:
+	/// * The number of notifications queued again
+	/// * or [`Error`](struct.Error.html) if an error is encountered.
+	///
+	pub fn replay_webhook_dead_letters(&self, ids: Option<Vec<u64>>) -> Result<usize, Error> {
+		self.webhooks()?.replay_dead_letters(ids)
+	}
+
+	fn webhooks(&self) -> Result<Arc<dyn WebHookDeadLetters>, Error> {
+		match self.webhooks {
+			Some(ref webhooks) => w(webhooks),
+			None => Err(ErrorKind::Internal("webhooks are not configured".to_owned()).into()),
+		}
+	}
+
+	/// Retrieves the blocks found by the workers of the stratum server.
+	///
+	/// # Returns
+	/// * Result Containing:
+	/// * A vector of [`StratumBlock`](types/struct.StratumBlock.html), oldest first
+	/// * or [`Error`](struct.Error.html) if an error is encountered.
+	///
+	pub fn get_stratum_blocks(&self) -> Result<Vec<StratumBlock>, Error> {
+		self.stratum()?.found_blocks()
+	}
+
+	/// Retrieves the PPLNS (Pay Per Last N Shares) window of a block found by
+	/// the workers of the stratum server: the shares submitted up to the one
+	/// that found the block, split by worker.
+	///
+	/// # Arguments
+	/// * `height` - the height of the found block, the last found block if `None`,
+	///   skipping orphaned blocks.
+	/// * `window` - the share difficulty covered, twice the block difficulty if `None`.
+	///
+	/// # Returns
+	/// * Result Containing:
+	/// * A [`PplnsWindow`](types/struct.PplnsWindow.html)
+	/// * or [`Error`](struct.Error.html) if an error is encountered.
+	///
+	pub fn get_pplns_window(
+		&self,
+		height: Option<u64>,
+		window: Option<u64>,
+	) -> Result<PplnsWindow, Error> {
+		self.stratum()?.pplns_window(height, window)
+	}
+
+	fn stratum(&self) -> Result<Arc<dyn StratumShares>, Error> {
+		match self.stratum {
+			Some(ref stratum) => w(stratum),
+			None => Err(ErrorKind::Internal("stratum server is not enabled".to_owned()).into()),
+		}
+	}
 }
@@ -18,7 +18,7 @@ use crate::owner::Owner;
 use crate::p2p::types::PeerInfoDisplay;
 use crate::p2p::PeerData;
 use crate::rest::ErrorKind;
-use crate::types::Status;
+use crate::types::{PplnsWindow, Status, StratumBlock, WebHookDeadLetter};
 use std::net::SocketAddr;
 
 /// Public definition used to generate Node jsonrpc api.
@@ -163,6 +163,7 @@ pub trait OwnerRpc: Sync + Send {
 				"flags": "Defunct",
 				"last_banned": 0,
 				"last_connected": 1570129317,
+				"score": 0,
 				"user_agent": "MW/Grin 2.0.0"
 			}
 			]
@@ -352,6 +353,180 @@ pub trait OwnerRpc: Sync + Send {
 	```
 	 */
 	fn unban_peer(&self, peer_addr: SocketAddr) -> Result<(), ErrorKind>;
+
+	/**
+	Networked version of [Owner::get_webhook_dead_letters](struct.Owner.html#method.get_webhook_dead_letters).
+
+	# Json rpc example
+
+	```
+	# grin_api::doctest_helper_json_rpc_owner_assert_response!(
+	# r#"
+	{
+		"jsonrpc": "2.0",
+		"method": "get_webhook_dead_letters",
+		"params": [false],
+		"id": 1
+	}
+	# "#
+	# ,
+	# r#"
+	{
+		"id": 1,
+		"jsonrpc": "2.0",
+		"result": {
+			"Ok": [
+				{
+					"id": 42,
+					"url": "http://127.0.0.1:8080/acceptedblock",
+					"event": "block_accepted",
+					"created_at": 1636026461,
+					"attempts": 15,
+					"last_error": "error trying to connect: tcp connect error: Connection refused (os error 111)",
+					"payload": null
+				}
+			]
+		}
+	}
+	# "#
+	# );
+	```
+	 */
+	fn get_webhook_dead_letters(
+		&self,
+		include_payload: Option<bool>,
+	) -> Result<Vec<WebHookDeadLetter>, ErrorKind>;
+
+	/**
+	Networked version of [Owner::replay_webhook_dead_letters](struct.Owner.html#method.replay_webhook_dead_letters).
+
+	# Json rpc example
+
+	```
+	# grin_api::doctest_helper_json_rpc_owner_assert_response!(
+	# r#"
+	{
+		"jsonrpc": "2.0",
+		"method": "replay_webhook_dead_letters",
+		"params": [[42]],
+		"id": 1
+	}
+	# "#
+	# ,
+	# r#"
+	{
+		"id": 1,
+		"jsonrpc": "2.0",
+		"result": {
+			"Ok": 1
+		}
+	}
+	# "#
+	# );
+	```
+	 */
+	fn replay_webhook_dead_letters(&self, ids: Option<Vec<u64>>) -> Result<usize, ErrorKind>;
+
+	/**
+	Networked version of [Owner::get_stratum_blocks](struct.Owner.html#method.get_stratum_blocks).
+
+	# Json rpc example
+
+	```
+	# grin_api::doctest_helper_json_rpc_owner_assert_response!(
+	# r#"
+	{
+		"jsonrpc": "2.0",
+		"method": "get_stratum_blocks",
+		"params": [],
+		"id": 1
+	}
+	# "#
+	# ,
+	# r#"
+	{
+		"id": 1,
+		"jsonrpc": "2.0",
+		"result": {
+			"Ok": [
+				{
+					"height": 1431234,
+					"hash": "00000ab9cb5b8af00d3a1c48d9f6c4f6c7e2e6e6ac1d2f1d3d5f7f7d0c6c1b2a",
+					"worker": "alice",
+					"difficulty": 3410612549,
+					"share_id": 81234,
+					"timestamp": 1636026461,
+					"status": "Confirmed"
+				}
+			]
+		}
+	}
+	# "#
+	# );
+	```
+	 */
+	fn get_stratum_blocks(&self) -> Result<Vec<StratumBlock>, ErrorKind>;
+
+	/**
+	Networked version of [Owner::get_pplns_window](struct.Owner.html#method.get_pplns_window).
+
+	# Json rpc example
+
+	```
+	# grin_api::doctest_helper_json_rpc_owner_assert_response!(
+	# r#"
+	{
+		"jsonrpc": "2.0",
+		"method": "get_pplns_window",
+		"params": [1431234, null],
+		"id": 1
+	}
+	# "#
+	# ,
+	# r#"
+	{
+		"id": 1,
+		"jsonrpc": "2.0",
+		"result": {
+			"Ok": {
+				"block": {
+					"height": 1431234,
+					"hash": "00000ab9cb5b8af00d3a1c48d9f6c4f6c7e2e6e6ac1d2f1d3d5f7f7d0c6c1b2a",
+					"worker": "alice",
+					"difficulty": 3410612549,
+					"share_id": 81234,
+					"timestamp": 1636026461,
+					"status": "Confirmed"
+				},
+				"window": 6821225098,
+				"difficulty": 6821300000,
+				"start": 1636019112,
+				"workers": [
+					{
+						"worker": "bob",
+						"shares": 4120,
+						"difficulty": 4092800000,
+						"fraction": 0.6
+					},
+					{
+						"worker": "alice",
+						"shares": 2731,
+						"difficulty": 2728500000,
+						"fraction": 0.4
+					}
+				]
+			}
+		}
+	}
+	# "#
+	# );
+	```
+	 */
+	fn get_pplns_window(
+		&self,
+		height: Option<u64>,
+		window: Option<u64>,
+	) -> Result<PplnsWindow, ErrorKind>;
 }
 
 impl OwnerRpc for Owner {
@@ -382,6 +557,29 @@ impl OwnerRpc for Owner {
 	fn unban_peer(&self, addr: SocketAddr) -> Result<(), ErrorKind> {
 		Owner::unban_peer(self, addr).map_err(|e| e.kind().clone())
 	}
+
+	fn get_webhook_dead_letters(
+		&self,
+		include_payload: Option<bool>,
+	) -> Result<Vec<WebHookDeadLetter>, ErrorKind> {
+		Owner::get_webhook_dead_letters(self, include_payload).map_err(|e| e.kind().clone())
+	}
+
+	fn replay_webhook_dead_letters(&self, ids: Option<Vec<u64>>) -> Result<usize, ErrorKind> {
+		Owner::replay_webhook_dead_letters(self, ids).map_err(|e| e.kind().clone())
+	}
+
+	fn get_stratum_blocks(&self) -> Result<Vec<StratumBlock>, ErrorKind> {
+		Owner::get_stratum_blocks(self).map_err(|e| e.kind().clone())
+	}
+
+	fn get_pplns_window(
+		&self,
+		height: Option<u64>,
+		window: Option<u64>,
+	) -> Result<PplnsWindow, ErrorKind> {
+		Owner::get_pplns_window(self, height, window).map_err(|e| e.kind().clone())
+	}
 }
 
 #[doc(hidden)]
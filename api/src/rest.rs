@@ -19,6 +19,7 @@
 //! register them on a ApiServer.
 
 use crate::router::{Handler, HandlerObj, ResponseFuture, Router, RouterError};
+use crate::types::TxReject;
 use crate::web::response;
 use failure::{Backtrace, Context, Fail, ResultExt};
 use futures::channel::oneshot;
@@ -58,6 +59,8 @@ pub enum ErrorKind {
 	ResponseError(String),
 	#[fail(display = "Router error: {}", _0)]
 	Router(RouterError),
+	#[fail(display = "Transaction rejected: {}", _0)]
+	TxRejected(TxReject),
 }
 
 impl Fail for Error {
@@ -16,9 +16,10 @@ use crate::chain;
 use crate::core::consensus::YEAR_HEIGHT;
 use crate::core::core::hash::Hashed;
 use crate::core::core::merkle_proof::MerkleProof;
-use crate::core::core::{FeeFields, KernelFeatures, TxKernel};
+use crate::core::core::{transaction, FeeFields, KernelFeatures, TxKernel};
 use crate::core::{core, ser};
 use crate::p2p;
+use crate::pool::{PoolEntry, PoolError};
 use crate::util::secp::pedersen;
 use crate::util::{self, ToHex};
 use serde::de::MapAccess;
@@ -463,7 +464,8 @@ impl<'de> serde::de::Deserialize<'de> for OutputPrintable {
 				}
 
 				if output_type.is_none()
-					|| commit.is_none() || spent.is_none()
+					|| commit.is_none()
+					|| spent.is_none()
 					|| proof_hash.is_none()
 					|| mmr_index.is_none()
 				{
@@ -733,12 +735,248 @@ pub struct LocatedTxKernel {
 	pub mmr_index: u64,
 }
 
+/// A block creating or spending an output commitment, from the node's
+/// (optional) output history index.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OutputHistoryPrintable {
+	/// Hash of the block creating or spending the output
+	pub block_hash: String,
+	/// Height of the block creating or spending the output
+	pub height: u64,
+	/// Whether the output was spent (as opposed to created) in this block
+	pub spent: bool,
+	/// The type of output created, if created in this block
+	pub output_type: Option<OutputType>,
+	/// Excess commitments of the kernels in the spending block, one of which
+	/// belongs to the transaction spending the output
+	pub kernels: Vec<String>,
+}
+
+impl From<&chain::OutputHistoryEntry> for OutputHistoryPrintable {
+	fn from(entry: &chain::OutputHistoryEntry) -> Self {
+		let (spent, output_type, kernels) = match entry.event {
+			chain::OutputHistoryEvent::Created(features) => {
+				let output_type = if features.is_coinbase() {
+					OutputType::Coinbase
+				} else {
+					OutputType::Transaction
+				};
+				(false, Some(output_type), vec![])
+			}
+			chain::OutputHistoryEvent::Spent(ref kernels) => {
+				(true, None, kernels.iter().map(|k| k.to_hex()).collect())
+			}
+		};
+		OutputHistoryPrintable {
+			block_hash: entry.block_hash.to_hex(),
+			height: entry.height,
+			spent,
+			output_type,
+			kernels,
+		}
+	}
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct PoolInfo {
 	/// Size of the pool
 	pub pool_size: usize,
 }
 
+/// Machine readable reason for the transaction pool to reject a tx.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TxRejectReason {
+	/// The tx fee is too low for its weight
+	LowFee,
+	/// The tx double spends pool txs without paying enough to replace them
+	LowFeeReplacement,
+	/// The tx double spends more pool txs than it can replace
+	TooManyReplacements,
+	/// The tx creates an output already in the utxo set or the pool
+	DuplicateCommitment,
+	/// The tx is already in the pool
+	DuplicateTx,
+	/// The tx spends a coinbase output before it matured
+	ImmatureCoinbase,
+	/// The tx lock height is above the next block height
+	ImmatureLockHeight,
+	/// An NRD kernel of the tx does not meet its relative lock height
+	NrdRelativeHeight,
+	/// The tx has NRD kernels, not accepted by this node yet
+	NrdNotEnabled,
+	/// The tx weight is above the max tx weight
+	OverWeight,
+	/// The pool is full
+	PoolFull,
+	/// The tx is invalid
+	InvalidTx,
+	/// Any other reason
+	Other,
+}
+
+/// Why the transaction pool rejected a tx.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct TxReject {
+	/// Machine readable reason
+	pub reason: TxRejectReason,
+	/// Pool error description
+	pub message: String,
+}
+
+impl fmt::Display for TxReject {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", self.message)
+	}
+}
+
+impl From<&PoolError> for TxReject {
+	fn from(e: &PoolError) -> TxReject {
+		let reason = match e {
+			PoolError::LowFeeTransaction(_) => TxRejectReason::LowFee,
+			PoolError::LowFeeReplacement(_) => TxRejectReason::LowFeeReplacement,
+			PoolError::TooManyReplacements(_) => TxRejectReason::TooManyReplacements,
+			PoolError::DuplicateCommitment => TxRejectReason::DuplicateCommitment,
+			PoolError::DuplicateTx => TxRejectReason::DuplicateTx,
+			PoolError::ImmatureCoinbase => TxRejectReason::ImmatureCoinbase,
+			PoolError::ImmatureTransaction => TxRejectReason::ImmatureLockHeight,
+			PoolError::NRDKernelRelativeHeight => TxRejectReason::NrdRelativeHeight,
+			PoolError::NRDKernelPreHF3 | PoolError::NRDKernelNotEnabled => {
+				TxRejectReason::NrdNotEnabled
+			}
+			PoolError::InvalidTx(transaction::Error::TooHeavy) => TxRejectReason::OverWeight,
+			PoolError::OverCapacity => TxRejectReason::PoolFull,
+			PoolError::InvalidTx(_)
+			| PoolError::InvalidBlock(_)
+			| PoolError::Keychain(_)
+			| PoolError::Committed(_) => TxRejectReason::InvalidTx,
+			PoolError::DandelionError | PoolError::Persistence(_) | PoolError::Other(_) => {
+				TxRejectReason::Other
+			}
+		};
+		TxReject {
+			reason,
+			message: e.to_string(),
+		}
+	}
+}
+
+/// Filters and paging for the transaction pool content. Txs must match all
+/// the provided filters.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct PoolQuery {
+	/// Hex of a kernel excess of the tx
+	pub kernel_excess: Option<String>,
+	/// Hex of an output commitment of the tx
+	pub output_commit: Option<String>,
+	/// Minimum tx fee
+	pub min_fee: Option<u64>,
+	/// Maximum tx fee
+	pub max_fee: Option<u64>,
+	/// Number of matching txs to skip
+	pub offset: Option<u64>,
+	/// Maximum number of txs to return
+	pub limit: Option<u64>,
+}
+
+/// A page of the transaction pool content.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PoolListing {
+	/// Number of txs matching the query
+	pub total: u64,
+	/// The txs of the requested page, in pool order
+	pub entries: Vec<PoolEntry>,
+}
+
+/// Recommended fee rate for a tx to be mined within a number of blocks.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct FeeEstimate {
+	/// Number of blocks the tx should be mined within
+	pub target_blocks: u64,
+	/// Fee per weight unit
+	pub fee_rate: u64,
+}
+
+/// A webhook notification the node gave up delivering after exhausting
+/// its retries.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct WebHookDeadLetter {
+	/// Id of the notification, increasing in the order notifications were queued
+	pub id: u64,
+	/// Url the notification is posted to
+	pub url: String,
+	/// Event that triggered the notification (i.e. block_accepted)
+	pub event: String,
+	/// Time the notification was queued (unix timestamp)
+	pub created_at: i64,
+	/// Number of failed delivery attempts
+	pub attempts: u32,
+	/// Error returned by the last delivery attempt
+	pub last_error: String,
+	/// The JSON payload of the notification, if requested
+	pub payload: Option<serde_json::Value>,
+}
+
+/// A block found by the workers of the stratum server.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct StratumBlock {
+	/// Height of the block
+	pub height: u64,
+	/// Hash of the block
+	pub hash: String,
+	/// Login (or id) of the worker that found the block
+	pub worker: String,
+	/// Difficulty of the block
+	pub difficulty: u64,
+	/// Id of the share that found the block
+	pub share_id: u64,
+	/// Time the block was found (unix timestamp)
+	pub timestamp: i64,
+	/// Whether the block is (still) part of the chain
+	pub status: StratumBlockStatus,
+}
+
+/// Status of a block found by the workers of the stratum server.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum StratumBlockStatus {
+	/// Not buried deep enough yet, the block may still get orphaned
+	Unconfirmed,
+	/// Buried in the chain until its coinbase matured
+	Confirmed,
+	/// Not part of the chain anymore
+	Orphaned,
+}
+
+/// The shares of a worker in a PPLNS window.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PplnsShare {
+	/// Login (or id) of the worker
+	pub worker: String,
+	/// Number of shares submitted by the worker in the window
+	pub shares: u64,
+	/// Total difficulty of these shares
+	pub difficulty: u64,
+	/// Fraction of the window's difficulty contributed by the worker
+	pub fraction: f64,
+}
+
+/// The last shares (by difficulty) submitted up to and including the one
+/// that found a block, the basis of a Pay Per Last N Shares reward split.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PplnsWindow {
+	/// The block found at the end of the window
+	pub block: StratumBlock,
+	/// Requested share difficulty covered by the window
+	pub window: u64,
+	/// Total difficulty of the shares in the window, less than the requested
+	/// window if not enough shares were recorded
+	pub difficulty: u64,
+	/// Time of the first share in the window (unix timestamp)
+	pub start: i64,
+	/// Shares per worker, by decreasing difficulty
+	pub workers: Vec<PplnsShare>,
+}
+
 #[cfg(test)]
 mod test {
 	use super::*;
@@ -772,4 +1010,19 @@ mod test {
 		let serialized = serde_json::to_string(&deserialized).unwrap();
 		assert_eq!(serialized, hex_commit);
 	}
+	#[test]
+	fn serialize_tx_reject() {
+		let reject: TxReject = (&PoolError::LowFeeTransaction(1000)).into();
+		assert_eq!(
+			serde_json::to_string(&reject).unwrap(),
+			"{\"reason\":\"low_fee\",\"message\":\"Low fee transaction 1000\"}"
+		);
+
+		let reject: TxReject = (&PoolError::InvalidTx(transaction::Error::TooHeavy)).into();
+		assert_eq!(reject.reason, TxRejectReason::OverWeight);
+		let reject: TxReject = (&PoolError::NRDKernelNotEnabled).into();
+		assert_eq!(reject.reason, TxRejectReason::NrdNotEnabled);
+		let reject: TxReject = (&PoolError::Other("failed".to_string())).into();
+		assert_eq!(reject.reason, TxRejectReason::Other);
+	}
 }
@@ -39,6 +39,10 @@ where
 			}
 			// place holder
 			ErrorKind::Router(_) => response(StatusCode::INTERNAL_SERVER_ERROR, ""),
+			ErrorKind::TxRejected(reject) => match serde_json::to_string(reject) {
+				Ok(json) => response(StatusCode::BAD_REQUEST, json),
+				Err(_) => response(StatusCode::BAD_REQUEST, reject.to_string()),
+			},
 		},
 	}
 }
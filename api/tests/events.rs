@@ -0,0 +1,159 @@
+use grin_api as api;
+use grin_core as core;
+use grin_p2p as p2p;
+use grin_util as util;
+
+use crate::api::*;
+use crate::core::core::hash::Hashed;
+use crate::core::core::{BlockHeader, Transaction};
+use crate::core::global::{self, ChainTypes};
+use crate::p2p::types::PeerAddr;
+use crate::util::ToHex;
+use futures::{SinkExt, StreamExt};
+use serde_json::Value;
+use std::net::SocketAddr;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::runtime::Runtime;
+use tokio::time::timeout;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+fn start_server(server_addr: &str, publisher: Arc<EventPublisher>) -> ApiServer {
+	let mut router = Router::new();
+	router
+		.add_route(
+			"/v2/events",
+			Arc::new(EventsHandler::new(Weak::new(), publisher)),
+		)
+		.expect("add_route failed");
+	let mut server = ApiServer::new();
+	let addr: SocketAddr = server_addr.parse().expect("unable to parse server address");
+	assert!(server.start(addr, router, None).is_ok());
+	server
+}
+
+// Connect with a std socket (with retries while the server starts up)
+// and hand it over to tokio.
+async fn tcp_connect(server_addr: &str) -> TcpStream {
+	let mut tries = 0;
+	loop {
+		match std::net::TcpStream::connect(server_addr) {
+			Ok(stream) => {
+				stream.set_nonblocking(true).unwrap();
+				return TcpStream::from_std(stream).unwrap();
+			}
+			Err(e) if tries > 5 => panic!("failed to connect: {}", e),
+			Err(_) => tries += 1,
+		}
+		tokio::time::delay_for(Duration::from_millis(500)).await;
+	}
+}
+
+async fn connect(server_addr: &str, query: &str) -> WebSocketStream<TcpStream> {
+	let stream = tcp_connect(server_addr).await;
+	let url = format!("ws://{}/v2/events{}", server_addr, query);
+	let (ws, _) = tokio_tungstenite::client_async(url.as_str(), stream)
+		.await
+		.unwrap();
+	ws
+}
+
+async fn next_json(ws: &mut WebSocketStream<TcpStream>) -> Value {
+	let msg = timeout(Duration::from_secs(5), ws.next())
+		.await
+		.expect("timed out waiting for event")
+		.unwrap()
+		.unwrap();
+	match msg {
+		Message::Text(text) => serde_json::from_str(&text).unwrap(),
+		m => panic!("unexpected message {:?}", m),
+	}
+}
+
+async fn wait_for_subscribers(publisher: &EventPublisher, count: usize) {
+	while publisher.subscriber_count() < count {
+		tokio::time::delay_for(Duration::from_millis(10)).await;
+	}
+}
+
+#[test]
+fn test_events_topics() {
+	util::init_test_logger();
+	global::set_local_chain_type(ChainTypes::AutomatedTesting);
+	let server_addr = "127.0.0.1:14435";
+	let publisher = Arc::new(EventPublisher::new());
+	let mut server = start_server(server_addr, publisher.clone());
+
+	let header = BlockHeader::default();
+	let tx = Transaction::empty();
+	let peer = PeerAddr::Ip("127.0.0.1:13414".parse().unwrap());
+
+	Runtime::new().unwrap().block_on(async {
+		let mut ws = connect(server_addr, "?topics=tx_received").await;
+		wait_for_subscribers(&publisher, 1).await;
+
+		// Only the subscribed topic comes through.
+		publisher.header_received(&header, &peer);
+		publisher.tx_received(&tx);
+		let event = next_json(&mut ws).await;
+		assert_eq!(event["topic"], "tx_received");
+		assert_eq!(event["hash"], tx.hash().to_hex());
+
+		// Change the subscription on the open socket.
+		ws.send(Message::Text(
+			r#"{"subscribe":["header_received"],"unsubscribe":["tx_received"]}"#.to_string(),
+		))
+		.await
+		.unwrap();
+		let reply = next_json(&mut ws).await;
+		assert_eq!(reply["topics"], serde_json::json!(["header_received"]));
+
+		publisher.tx_received(&tx);
+		publisher.header_received(&header, &peer);
+		let event = next_json(&mut ws).await;
+		assert_eq!(event["topic"], "header_received");
+		assert_eq!(event["hash"], header.hash().to_hex());
+		assert_eq!(event["height"], 0);
+		assert_eq!(event["peer"], "127.0.0.1:13414");
+
+		// Bad subscription requests are reported, not fatal.
+		ws.send(Message::Text(r#"{"subscribe":["nope"]}"#.to_string()))
+			.await
+			.unwrap();
+		assert!(next_json(&mut ws).await["error"].is_string());
+
+		ws.close(None).await.unwrap();
+	});
+
+	assert!(server.stop());
+}
+
+#[test]
+fn test_events_bad_request() {
+	util::init_test_logger();
+	global::set_local_chain_type(ChainTypes::AutomatedTesting);
+	let server_addr = "127.0.0.1:14436";
+	let publisher = Arc::new(EventPublisher::new());
+	let mut server = start_server(server_addr, publisher.clone());
+
+	Runtime::new().unwrap().block_on(async {
+		// Unknown topic and plain (non upgrade) requests are rejected.
+		for path in &["/v2/events?topics=block_mined", "/v2/events"] {
+			let mut stream = tcp_connect(server_addr).await;
+			let req = format!(
+				"GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+				path, server_addr
+			);
+			stream.write_all(req.as_bytes()).await.unwrap();
+			let mut resp = String::new();
+			stream.read_to_string(&mut resp).await.unwrap();
+			assert!(resp.starts_with("HTTP/1.1 400"), "{}", resp);
+		}
+	});
+	assert_eq!(publisher.subscriber_count(), 0);
+
+	assert!(server.stop());
+}
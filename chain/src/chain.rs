@@ -15,28 +15,30 @@
 //! Facade and handler for the rest of the blockchain implementation
 //! and mostly the chain pipeline.
 
-use crate::core::core::hash::{Hash, Hashed};
+use crate::core::core::hash::{Hash, Hashed, ZERO_HASH};
 use crate::core::core::merkle_proof::MerkleProof;
+use crate::core::core::pmmr::{self, ReadablePMMR};
 use crate::core::core::verifier_cache::VerifierCache;
 use crate::core::core::{
-	Block, BlockHeader, BlockSums, Committed, Inputs, KernelFeatures, Output, OutputIdentifier,
-	SegmentIdentifier, Transaction, TxKernel,
+	Block, BlockHeader, BlockSums, CommitWrapper, Committed, HeaderVersion, Inputs, KernelFeatures,
+	Output, OutputIdentifier, SegmentIdentifier, Transaction, TxKernel,
 };
 use crate::core::global;
 use crate::core::pow;
-use crate::core::ser::ProtocolVersion;
+use crate::core::ser::{PMMRIndexHashable, ProtocolVersion};
 use crate::error::{Error, ErrorKind};
 use crate::pipe;
 use crate::store;
 use crate::txhashset;
-use crate::txhashset::{PMMRHandle, Segmenter, TxHashSet};
+use crate::txhashset::{Desegmenter, PMMRHandle, PibdProgress, Segmenter, TxHashSet};
 use crate::types::{
-	BlockStatus, ChainAdapter, CommitPos, NoStatus, Options, Tip, TxHashsetWriteStatus,
+	BlockOutputsProof, BlockStatus, ChainAdapter, CommitPos, NoStatus, NoopAdapter, Options,
+	OutputHistoryEntry, OutputHistoryEvent, Tip, TxHashsetWriteStatus,
 };
 use crate::util::secp::pedersen::{Commitment, RangeProof};
 use crate::util::RwLock;
 use grin_store::Error::NotFoundErr;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::{self, File};
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicUsize, Ordering};
@@ -49,6 +51,18 @@ pub const MAX_ORPHAN_SIZE: usize = 200;
 /// When evicting, very old orphans are evicted first
 const MAX_ORPHAN_AGE_SECS: u64 = 300;
 
+/// Subdir of our tmp dir where we rebuild the txhashset from PIBD segments
+const PIBD_SUBDIR: &str = "pibd";
+
+/// Subdir of our db_root where a reindex rebuilds the chain state
+const REINDEX_SUBDIR: &str = "reindex";
+
+/// Subdir of our db_root holding the previous chain state while swapping in a reindexed one
+const REINDEX_BACKUP_SUBDIR: &str = "reindex_backup";
+
+/// Subdirs of our db_root making up the chain state
+const CHAIN_STATE_SUBDIRS: [&str; 3] = ["lmdb", "txhashset", "header"];
+
 #[derive(Debug, Clone)]
 struct Orphan {
 	block: Block,
@@ -151,9 +165,12 @@ pub struct Chain {
 	header_pmmr: Arc<RwLock<txhashset::PMMRHandle<BlockHeader>>>,
 	verifier_cache: Arc<RwLock<dyn VerifierCache>>,
 	pibd_segmenter: Arc<RwLock<Option<Segmenter>>>,
+	pibd_desegmenter: Arc<RwLock<Option<Desegmenter>>>,
 	// POW verification function
 	pow_verifier: fn(&BlockHeader) -> Result<(), pow::Error>,
 	archive_mode: bool,
+	output_history: bool,
+	kernel_pos_index: bool,
 	genesis: BlockHeader,
 }
 
@@ -183,6 +200,14 @@ impl Chain {
 
 		setup_head(&genesis, &store, &mut header_pmmr, &mut txhashset)?;
 
+		// The genesis block is removed along with other historical blocks when pruning,
+		// restore it if we now run in archive mode.
+		if archive_mode && !store.block_exists(&genesis.hash())? {
+			let batch = store.batch()?;
+			batch.save_block(&genesis)?;
+			batch.commit()?;
+		}
+
 		// Initialize the output_pos index based on UTXO set
 		// and NRD kernel_pos index based recent kernel history.
 		{
@@ -200,9 +225,12 @@ impl Chain {
 			txhashset: Arc::new(RwLock::new(txhashset)),
 			header_pmmr: Arc::new(RwLock::new(header_pmmr)),
 			pibd_segmenter: Arc::new(RwLock::new(None)),
+			pibd_desegmenter: Arc::new(RwLock::new(None)),
 			pow_verifier,
 			verifier_cache,
 			archive_mode,
+			output_history: false,
+			kernel_pos_index: false,
 			genesis: genesis.header,
 		};
 
@@ -226,11 +254,213 @@ impl Chain {
 		Ok(chain)
 	}
 
+	/// Rebuild our chain state (the txhashset and header MMRs along with the
+	/// output_pos, NRD kernel and spent indices) by replaying the full blocks of our
+	/// current chain from the local db, starting from genesis.
+	/// Useful after an unclean shutdown or a corrupted MMR file, this requires all
+	/// full blocks back to genesis (i.e. an archive node or a node that never pruned).
+	/// Does not involve the network. The provided progress callback is called with
+	/// the height of each replayed block and the height we are replaying up to.
+	pub fn reindex<F>(
+		db_root: String,
+		genesis: Block,
+		pow_verifier: fn(&BlockHeader) -> Result<(), pow::Error>,
+		verifier_cache: Arc<RwLock<dyn VerifierCache>>,
+		archive_mode: bool,
+		progress: F,
+	) -> Result<Chain, Error>
+	where
+		F: Fn(u64, u64),
+	{
+		let root = PathBuf::from(&db_root);
+		let sandbox = root.join(REINDEX_SUBDIR);
+		recover_reindex_swap(&root)?;
+		if sandbox.exists() {
+			fs::remove_dir_all(&sandbox).map_err(|e| reindex_io_error("clean sandbox", e))?;
+		}
+
+		// Find the full blocks of our current chain, making sure we have all of them
+		// before we start rebuilding anything.
+		let store = store::ChainStore::new(&db_root)?;
+		let hashes = {
+			let mut header = store.head_header()?;
+			let mut hashes = Vec::with_capacity(header.height as usize);
+			while header.height > 0 {
+				if !store.block_exists(&header.hash())? {
+					return Err(ErrorKind::Other(format!(
+						"reindex: missing full block {} at {}, reindex requires the full block history",
+						header.hash(),
+						header.height
+					))
+					.into());
+				}
+				hashes.push(header.hash());
+				header = store.get_previous_header(&header)?;
+			}
+			if header.hash() != genesis.hash() {
+				return Err(ErrorKind::Other("reindex: genesis mismatch".to_string()).into());
+			}
+			hashes.reverse();
+			hashes
+		};
+
+		// Replay the blocks into a fresh chain in our sandbox, leaving our current
+		// state untouched until the new one is complete.
+		{
+			let chain = Chain::init(
+				sandbox
+					.to_str()
+					.expect("valid reindex sandbox path")
+					.to_string(),
+				Arc::new(NoopAdapter {}),
+				genesis.clone(),
+				pow_verifier,
+				verifier_cache.clone(),
+				archive_mode,
+			)?;
+			let total = hashes.len() as u64;
+			for hash in hashes {
+				let block = store.get_block(&hash)?;
+				let height = block.header.height;
+				chain.process_block(block, Options::NONE)?;
+				progress(height, total);
+			}
+		}
+		drop(store);
+
+		// Swap the new state in, keeping the previous one in a backup until done.
+		// Removing the sandbox marks the swap as complete, see recover_reindex_swap.
+		let backup = root.join(REINDEX_BACKUP_SUBDIR);
+		fs::create_dir_all(&backup).map_err(|e| reindex_io_error("create backup", e))?;
+		for subdir in CHAIN_STATE_SUBDIRS.iter() {
+			if root.join(subdir).exists() {
+				fs::rename(root.join(subdir), backup.join(subdir))
+					.map_err(|e| reindex_io_error("back up previous state", e))?;
+			}
+		}
+		for subdir in CHAIN_STATE_SUBDIRS.iter() {
+			fs::rename(sandbox.join(subdir), root.join(subdir))
+				.map_err(|e| reindex_io_error("swap in new state", e))?;
+		}
+		fs::remove_dir_all(&sandbox).map_err(|e| reindex_io_error("clean sandbox", e))?;
+		fs::remove_dir_all(&backup).map_err(|e| reindex_io_error("clean backup", e))?;
+
+		Chain::init(
+			db_root,
+			Arc::new(NoopAdapter {}),
+			genesis,
+			pow_verifier,
+			verifier_cache,
+			archive_mode,
+		)
+	}
+
 	/// Are we running with archive_mode enabled?
 	pub fn archive_mode(&self) -> bool {
 		self.archive_mode
 	}
 
+	/// Enable (or disable) the optional output history index, recording the blocks
+	/// creating and spending each output commitment.
+	/// When first enabled we backfill the index from the full blocks in our db.
+	/// This can take a while, so do this before the chain is shared.
+	pub fn init_output_history(&mut self, enabled: bool) -> Result<(), Error> {
+		self.output_history = enabled;
+		if !enabled {
+			// Backfill again (to fill any gap) if this is re-enabled later.
+			if self.store.is_output_history_backfilled()? {
+				let batch = self.store.batch()?;
+				batch.save_output_history_backfilled(false)?;
+				batch.commit()?;
+			}
+			return Ok(());
+		}
+		if self.store.is_output_history_backfilled()? {
+			return Ok(());
+		}
+
+		let tail = self.store.tail()?;
+		let head = self.store.head()?;
+		info!(
+			"init_output_history: backfilling from {} to {}",
+			tail.height, head.height
+		);
+		let mut batch = self.store.batch()?;
+		for height in tail.height..=head.height {
+			let hash = self.get_header_hash_by_height(height)?;
+			if let Ok(b) = batch.get_block(&hash) {
+				index_output_history(&b, &batch)?;
+			}
+			if height % 1000 == 0 {
+				batch.commit()?;
+				batch = self.store.batch()?;
+				debug!("init_output_history: backfilled up to {}", height);
+			}
+		}
+		batch.save_output_history_backfilled(true)?;
+		batch.commit()?;
+		info!("init_output_history: backfilled up to {}", head.height);
+		Ok(())
+	}
+
+	/// Enable (or disable) the optional kernel_pos index, allowing kernels to be looked up
+	/// by excess without scanning the kernel MMR.
+	/// The index is (re)built from the kernel MMR if not already complete for our
+	/// current txhashset. This can take a while, so do this before the chain is shared.
+	pub fn init_kernel_pos_index(&mut self, enabled: bool) -> Result<(), Error> {
+		self.kernel_pos_index = enabled;
+		let mut txhashset = self.txhashset.write();
+		if !enabled {
+			txhashset.set_kernel_pos_index(false);
+			// The index will no longer be maintained, rebuild if this is re-enabled later.
+			if self.store.is_kernel_pos_indexed()? {
+				let batch = self.store.batch()?;
+				batch.save_kernel_pos_indexed(false)?;
+				batch.commit()?;
+			}
+			return Ok(());
+		}
+		if !self.store.is_kernel_pos_indexed()? {
+			info!("init_kernel_pos_index: building kernel_pos index from the kernel MMR");
+			txhashset.init_kernel_pos_index(&self.header_pmmr.read())?;
+		}
+		txhashset.set_kernel_pos_index(true);
+		Ok(())
+	}
+
+	/// Is the output history index enabled?
+	pub fn output_history_enabled(&self) -> bool {
+		self.output_history
+	}
+
+	/// History of the provided output commitment from the output history index,
+	/// the blocks on our current chain creating and spending it, in height order.
+	/// A commitment may be created again after being spent.
+	pub fn get_output_history(
+		&self,
+		commit: &Commitment,
+	) -> Result<Vec<OutputHistoryEntry>, Error> {
+		if !self.output_history {
+			return Err(ErrorKind::Other("output history index not enabled".to_string()).into());
+		}
+		let header_pmmr = self.header_pmmr.read();
+		let head = self.head()?;
+		let mut entries = vec![];
+		for entry in self.store.get_output_history(commit)? {
+			if entry.height > head.height {
+				continue;
+			}
+			if header_pmmr.get_header_hash_by_height(entry.height)? == entry.block_hash {
+				entries.push(entry);
+			}
+		}
+		entries.sort_by_key(|entry| match entry.event {
+			OutputHistoryEvent::Created(_) => (entry.height, 0),
+			OutputHistoryEvent::Spent(_) => (entry.height, 1),
+		});
+		Ok(entries)
+	}
+
 	/// Return our shared header MMR handle.
 	pub fn header_pmmr(&self) -> Arc<RwLock<PMMRHandle<BlockHeader>>> {
 		self.header_pmmr.clone()
@@ -347,6 +577,23 @@ impl Chain {
 		Err(ErrorKind::Orphan.into())
 	}
 
+	/// Headers of the blocks rewound by a reorg from the previous head back to
+	/// (but excluding) the fork point, most recent first.
+	fn disconnected_headers(
+		&self,
+		prev_head: &Tip,
+		fork_point: &Tip,
+	) -> Result<Vec<BlockHeader>, Error> {
+		let mut headers = vec![];
+		let mut hash = prev_head.last_block_h;
+		while hash != fork_point.last_block_h {
+			let header = self.get_block_header(&hash)?;
+			hash = header.prev_hash;
+			headers.push(header);
+		}
+		Ok(headers)
+	}
+
 	/// Attempt to add a new block to the chain.
 	/// Returns true if it has been added to the longest chain
 	/// or false if it has added to a fork (or orphan?).
@@ -377,6 +624,9 @@ impl Chain {
 			// A node shutdown at this point can be catastrophic...
 			// We prevent this via the stop_lock (see above).
 			if maybe_new_head.is_ok() {
+				if self.output_history {
+					index_output_history(&b, &ctx.batch)?;
+				}
 				ctx.batch.commit()?;
 			}
 
@@ -395,6 +645,20 @@ impl Chain {
 				);
 
 				// notifying other parts of the system of the update
+				if let BlockStatus::Reorg {
+					prev_head,
+					fork_point,
+					..
+				} = status
+				{
+					match self.disconnected_headers(&prev_head, &fork_point) {
+						Ok(headers) => self.adapter.blocks_disconnected(&headers, opts),
+						Err(e) => error!(
+							"Failed to read headers disconnected by reorg at {}: {:?}",
+							b.header.height, e
+						),
+					}
+				}
 				self.adapter.block_accepted(&b, status, opts);
 
 				Ok(head)
@@ -904,6 +1168,11 @@ impl Chain {
 		Ok(fork_point.height < header_head.height.saturating_sub(horizon))
 	}
 
+	/// Sandbox dir used when rebuilding the txhashset from PIBD segments.
+	fn get_pibd_dir(&self) -> PathBuf {
+		self.get_tmp_dir().join(PIBD_SUBDIR)
+	}
+
 	/// Clean the temporary sandbox folder
 	pub fn clean_txhashset_sandbox(&self) {
 		txhashset::clean_txhashset_folder(&self.get_tmp_dir());
@@ -982,11 +1251,170 @@ impl Chain {
 			Some(&header),
 		)?;
 
+		self.validate_and_replace_txhashset(&header, &mut txhashset, sandbox_dir, status)?;
+
+		Ok(false)
+	}
+
+	/// The desegmenter (if any) currently rebuilding the txhashset from PIBD segments.
+	pub fn desegmenter(&self) -> Arc<RwLock<Option<Desegmenter>>> {
+		self.pibd_desegmenter.clone()
+	}
+
+	/// Initialize a desegmenter to rebuild the txhashset at the provided archive header
+	/// from PIBD segments. An existing desegmenter for the same header is left untouched.
+	/// If we were previously interrupted while syncing the same archive header we resume
+	/// from the progress saved in the db, otherwise we start over with a fresh txhashset
+	/// in a dedicated sandbox.
+	pub fn init_desegmenter(&self, archive_header: &BlockHeader) -> Result<(), Error> {
+		let mut desegmenter = self.pibd_desegmenter.write();
+		if let Some(d) = desegmenter.as_ref() {
+			if d.header() == archive_header {
+				return Ok(());
+			}
+		}
+		if let Some(d) = desegmenter.take() {
+			d.txhashset().write().release_backend_files();
+		}
+
+		if let Some(progress) = self.store.get_pibd_progress()? {
+			if progress.archive_header_hash == archive_header.hash() {
+				match self.resume_desegmenter(archive_header, &progress) {
+					Ok(d) => {
+						*desegmenter = Some(d);
+						return Ok(());
+					}
+					Err(e) => warn!(
+						"init_desegmenter: failed to resume from {:?}, starting over: {}",
+						progress, e
+					),
+				}
+			}
+		}
+
+		debug!(
+			"init_desegmenter: initializing new desegmenter for {} at {}",
+			archive_header.hash(),
+			archive_header.height
+		);
+
+		self.delete_pibd_progress()?;
+		txhashset::clean_txhashset_folder(&self.get_pibd_dir());
+		let txhashset = self.open_pibd_txhashset()?;
+
+		*desegmenter = Some(Desegmenter::new(
+			Arc::new(RwLock::new(txhashset)),
+			self.header_pmmr.clone(),
+			archive_header.clone(),
+			self.store.clone(),
+		));
+		Ok(())
+	}
+
+	// Reopen the partially built txhashset in our PIBD sandbox and resume from the
+	// provided progress.
+	fn resume_desegmenter(
+		&self,
+		archive_header: &BlockHeader,
+		progress: &PibdProgress,
+	) -> Result<Desegmenter, Error> {
+		let txhashset = Arc::new(RwLock::new(self.open_pibd_txhashset()?));
+		let mut desegmenter = Desegmenter::new(
+			txhashset.clone(),
+			self.header_pmmr.clone(),
+			archive_header.clone(),
+			self.store.clone(),
+		);
+		if let Err(e) = desegmenter.resume(progress) {
+			txhashset.write().release_backend_files();
+			return Err(e);
+		}
+		Ok(desegmenter)
+	}
+
+	fn open_pibd_txhashset(&self) -> Result<TxHashSet, Error> {
+		txhashset::TxHashSet::open(
+			self.get_pibd_dir()
+				.to_str()
+				.expect("invalid sandbox folder")
+				.to_owned(),
+			self.store.clone(),
+			None,
+		)
+	}
+
+	/// Drop the current desegmenter (if any), keeping its sandbox and saved progress
+	/// so PIBD can later resume from where it left off.
+	pub fn release_desegmenter(&self) {
+		if let Some(d) = self.pibd_desegmenter.write().take() {
+			d.txhashset().write().release_backend_files();
+		}
+	}
+
+	/// Drop the current desegmenter (if any), clean up its sandbox and forget
+	/// about any saved progress.
+	pub fn reset_desegmenter(&self) {
+		let mut desegmenter = self.pibd_desegmenter.write();
+		if let Some(d) = desegmenter.take() {
+			d.txhashset().write().release_backend_files();
+		}
+		txhashset::clean_txhashset_folder(&self.get_pibd_dir());
+		if let Err(e) = self.delete_pibd_progress() {
+			error!("reset_desegmenter: failed to delete PIBD progress: {}", e);
+		}
+	}
+
+	fn delete_pibd_progress(&self) -> Result<(), Error> {
+		let batch = self.store.batch()?;
+		batch.delete_pibd_progress()?;
+		batch.commit()?;
+		Ok(())
+	}
+
+	/// Once all PIBD segments have been received and applied, fully validate the
+	/// resulting txhashset and replace our own with it, as we would for a txhashset
+	/// received as a zip archive.
+	pub fn finalize_desegmenter(&self, status: &dyn TxHashsetWriteStatus) -> Result<(), Error> {
+		let desegmenter = self
+			.pibd_desegmenter
+			.write()
+			.take()
+			.ok_or_else(|| ErrorKind::SyncError("no desegmenter to finalize".to_string()))?;
+		if !desegmenter.is_complete() {
+			return Err(ErrorKind::SyncError("desegmenter not complete".to_string()).into());
+		}
+
+		status.on_setup();
+
+		let fork_point = self.fork_point()?;
+		if !self.check_txhashset_needed(&fork_point)? {
+			warn!("finalize_desegmenter: txhashset no longer needed! ignored.");
+			return Err(ErrorKind::InvalidTxHashSet("not needed".to_owned()).into());
+		}
+
+		let header = desegmenter.header().clone();
+		let txhashset = desegmenter.txhashset();
+		let mut txhashset = txhashset.write();
+		self.validate_and_replace_txhashset(&header, &mut txhashset, self.get_pibd_dir(), status)?;
+
+		// Our txhashset has been replaced, no more PIBD progress to resume from.
+		self.delete_pibd_progress()
+	}
+
+	/// Fully validate the txhashset in the provided sandbox and, if valid,
+	/// move it into place replacing our current txhashset.
+	fn validate_and_replace_txhashset(
+		&self,
+		header: &BlockHeader,
+		txhashset: &mut TxHashSet,
+		sandbox_dir: PathBuf,
+		status: &dyn TxHashsetWriteStatus,
+	) -> Result<(), Error> {
 		// Validate the full kernel history.
 		// Check kernel MMR root for every block header.
 		// Check NRD relative height rules for full kernel history.
 		{
-			self.validate_kernel_history(&header, &txhashset)?;
+			self.validate_kernel_history(header, txhashset)?;
 
 			let header_pmmr = self.header_pmmr.read();
 			let batch = self.store.batch()?;
@@ -994,43 +1422,38 @@ impl Chain {
 		}
 
 		// all good, prepare a new batch and update all the required records
-		debug!("txhashset_write: rewinding a 2nd time (writeable)");
+		debug!("validate_and_replace_txhashset: rewinding a 2nd time (writeable)");
 
 		let mut header_pmmr = self.header_pmmr.write();
 		let mut batch = self.store.batch()?;
-		txhashset::extending(
-			&mut header_pmmr,
-			&mut txhashset,
-			&mut batch,
-			|ext, batch| {
-				let extension = &mut ext.extension;
-				extension.rewind(&header, batch)?;
-
-				// Validate the extension, generating the utxo_sum and kernel_sum.
-				// Full validation, including rangeproofs and kernel signature verification.
-				let (utxo_sum, kernel_sum) =
-					extension.validate(&self.genesis, false, status, &header)?;
-
-				// Save the block_sums (utxo_sum, kernel_sum) to the db for use later.
-				batch.save_block_sums(
-					&header.hash(),
-					BlockSums {
-						utxo_sum,
-						kernel_sum,
-					},
-				)?;
+		txhashset::extending(&mut header_pmmr, txhashset, &mut batch, |ext, batch| {
+			let extension = &mut ext.extension;
+			extension.rewind(header, batch)?;
+
+			// Validate the extension, generating the utxo_sum and kernel_sum.
+			// Full validation, including rangeproofs and kernel signature verification.
+			let (utxo_sum, kernel_sum) =
+				extension.validate(&self.genesis, false, status, header)?;
+
+			// Save the block_sums (utxo_sum, kernel_sum) to the db for use later.
+			batch.save_block_sums(
+				&header.hash(),
+				BlockSums {
+					utxo_sum,
+					kernel_sum,
+				},
+			)?;
 
-				Ok(())
-			},
-		)?;
+			Ok(())
+		})?;
 
-		debug!("txhashset_write: finished validating and rebuilding");
+		debug!("validate_and_replace_txhashset: finished validating and rebuilding");
 
 		status.on_save();
 
 		// Save the new head to the db and rebuild the header by height index.
 		{
-			let tip = Tip::from_header(&header);
+			let tip = Tip::from_header(header);
 			batch.save_body_head(&tip)?;
 
 			// Reset the body tail to the body head after a txhashset write
@@ -1046,7 +1469,7 @@ impl Chain {
 		// Commit all the changes to the db.
 		batch.commit()?;
 
-		debug!("txhashset_write: finished committing the batch (head etc.)");
+		debug!("validate_and_replace_txhashset: finished committing the batch (head etc.)");
 
 		// Sandbox full validation ok, go to overwrite txhashset on db root
 		{
@@ -1059,22 +1482,22 @@ impl Chain {
 			txhashset.release_backend_files();
 			txhashset::txhashset_replace(sandbox_dir, PathBuf::from(self.db_root.clone()))?;
 
-			// Re-open on db root dir
-			txhashset = txhashset::TxHashSet::open(
-				self.db_root.clone(),
-				self.store.clone(),
-				Some(&header),
-			)?;
+			// Re-open on db root dir and replace the chain txhashset with the newly built one.
+			*txhashset_ref =
+				txhashset::TxHashSet::open(self.db_root.clone(), self.store.clone(), Some(header))?;
 
-			// Replace the chain txhashset with the newly built one.
-			*txhashset_ref = txhashset;
+			// Rebuild the kernel_pos index (if enabled) for our new txhashset.
+			if self.kernel_pos_index {
+				txhashset_ref.init_kernel_pos_index(&header_pmmr)?;
+				txhashset_ref.set_kernel_pos_index(true);
+			}
 		}
 
-		debug!("txhashset_write: replaced our txhashset with the new one");
+		debug!("validate_and_replace_txhashset: replaced our txhashset with the new one");
 
 		status.on_done();
 
-		Ok(false)
+		Ok(())
 	}
 
 	/// Cleanup old blocks from the db.
@@ -1188,6 +1611,158 @@ impl Chain {
 		Ok(())
 	}
 
+	/// Hashes of (up to count) full blocks missing at or below our body tail,
+	/// highest first. Only relevant in archive mode where we want the full block
+	/// history, for example after restarting a pruned node with archive_mode enabled.
+	pub fn historical_blocks_to_sync(&self, count: usize) -> Result<Vec<Hash>, Error> {
+		if !self.archive_mode() {
+			return Ok(vec![]);
+		}
+		// No body tail means we never pruned (or never synced) any block.
+		let tail = match self.tail() {
+			Ok(tail) => tail,
+			Err(_) => return Ok(vec![]),
+		};
+
+		let header_pmmr = self.header_pmmr.read();
+		let mut hashes = vec![];
+		let mut height = tail.height;
+		while height > 0 && hashes.len() < count {
+			let hash = header_pmmr.get_header_hash_by_height(height)?;
+			if !self.block_exists(hash)? {
+				hashes.push(hash);
+			}
+			height -= 1;
+		}
+		Ok(hashes)
+	}
+
+	/// The hashes needed, together with the block outputs, to rebuild the output
+	/// and rangeproof roots committed to in its header. Served by archive nodes
+	/// along historical blocks, as pruned nodes no longer have all of them.
+	pub fn get_block_outputs_proof(&self, b: &Block) -> Result<BlockOutputsProof, Error> {
+		self.is_on_current_chain(&b.header)?;
+		let prev = self.get_previous_header(&b.header)?;
+		let (output_leaves, rproof_leaves) = output_leaves(&prev, b);
+		let txhashset = self.txhashset.read();
+		let size = b.header.output_mmr_size;
+		Ok(BlockOutputsProof {
+			output_hashes: proof_hashes(
+				&txhashset.output_pmmr_at(&b.header),
+				size,
+				&output_leaves,
+			)?,
+			rangeproof_hashes: proof_hashes(
+				&txhashset.rangeproof_pmmr_at(&b.header),
+				size,
+				&rproof_leaves,
+			)?,
+		})
+	}
+
+	/// Add a historical full block at or below our body tail, as requested from
+	/// archive peers when running in archive mode, along its outputs proof.
+	/// The block cannot be applied to our current txhashset so we only validate it
+	/// in isolation and against its header on our current chain (including the
+	/// kernels, that are never pruned from our kernel MMR).
+	/// The body tail moves back as far as we now have contiguous full blocks.
+	pub fn process_historical_block(
+		&self,
+		b: &Block,
+		proof: &BlockOutputsProof,
+	) -> Result<(), Error> {
+		if !self.archive_mode() {
+			return Err(ErrorKind::Unfit("not running in archive mode".into()).into());
+		}
+		let tail = self.tail()?;
+		if b.header.height == 0 || b.header.height > tail.height {
+			return Err(ErrorKind::Unfit("not a historical block".into()).into());
+		}
+		if self.block_exists(b.hash())? {
+			return Ok(());
+		}
+		self.is_on_current_chain(&b.header)?;
+
+		let prev = self.get_previous_header(&b.header)?;
+		b.validate(&prev.total_kernel_offset, self.verifier_cache.clone())
+			.map_err(ErrorKind::InvalidBlockProof)?;
+
+		let n_outputs =
+			pmmr::n_leaves(b.header.output_mmr_size) - pmmr::n_leaves(prev.output_mmr_size);
+		let n_kernels =
+			pmmr::n_leaves(b.header.kernel_mmr_size) - pmmr::n_leaves(prev.kernel_mmr_size);
+		if b.outputs().len() as u64 != n_outputs || b.kernels().len() as u64 != n_kernels {
+			return Err(ErrorKind::InvalidMMRSize.into());
+		}
+		{
+			let txhashset = self.txhashset.read();
+			let kernel_pmmr = txhashset.kernel_pmmr_at(&b.header);
+			let first_idx = pmmr::n_leaves(prev.kernel_mmr_size);
+			for (i, kernel) in b.kernels().iter().enumerate() {
+				let pos = pmmr::insertion_to_pmmr_index(first_idx + i as u64 + 1);
+				if kernel_pmmr.get_data(pos).as_ref() != Some(kernel) {
+					return Err(ErrorKind::InvalidRoot.into());
+				}
+			}
+
+			// Rebuild the output and rangeproof roots with the block outputs in place
+			// and the proof hashes for everything else, to check they are the ones
+			// committed to in the header. Our own MMRs may have been compacted since,
+			// but every hash still in them has to match along the way.
+			// From header version 3 the output root also commits to the bitmap of
+			// unspent outputs at the time, which we cannot rebuild for historical
+			// blocks. The rangeproof root then commits to the outputs, each proof
+			// having been verified against its output commitment above.
+			let (output_leaves, rproof_leaves) = output_leaves(&prev, b);
+			let size = b.header.output_mmr_size;
+			let output_root = root_from_proof(
+				&txhashset.output_pmmr_at(&b.header),
+				size,
+				&output_leaves,
+				&proof.output_hashes,
+			)?;
+			if b.header.version < HeaderVersion(3) && output_root != b.header.output_root {
+				return Err(ErrorKind::InvalidRoot.into());
+			}
+			let rproof_root = root_from_proof(
+				&txhashset.rangeproof_pmmr_at(&b.header),
+				size,
+				&rproof_leaves,
+				&proof.rangeproof_hashes,
+			)?;
+			if rproof_root != b.header.range_proof_root {
+				return Err(ErrorKind::InvalidRoot.into());
+			}
+		}
+
+		let header_pmmr = self.header_pmmr.read();
+		let batch = self.store.batch()?;
+		batch.save_block(b)?;
+		if self.output_history {
+			index_output_history(b, &batch)?;
+		}
+
+		let mut tail_height = tail.height;
+		while tail_height > 0 {
+			let hash = header_pmmr.get_header_hash_by_height(tail_height - 1)?;
+			if !batch.block_exists(&hash)? {
+				break;
+			}
+			tail_height -= 1;
+		}
+		if tail_height < tail.height {
+			let hash = header_pmmr.get_header_hash_by_height(tail_height)?;
+			let header = batch.get_block_header(&hash)?;
+			batch.save_body_tail(&Tip::from_header(&header))?;
+			debug!(
+				"process_historical_block: body tail moved to {} at {}",
+				hash, tail_height
+			);
+		}
+		batch.commit()?;
+		Ok(())
+	}
+
 	/// returns the last n nodes inserted into the output sum tree
 	pub fn get_last_n_output(&self, distance: u64) -> Vec<(Hash, OutputIdentifier)> {
 		self.txhashset.read().last_n_output(distance)
@@ -1470,6 +2045,166 @@ impl Chain {
 	}
 }
 
+/// The output and rangeproof MMR leaves of the block outputs, by position.
+fn output_leaves(prev: &BlockHeader, b: &Block) -> (BTreeMap<u64, Hash>, BTreeMap<u64, Hash>) {
+	let first_idx = pmmr::n_leaves(prev.output_mmr_size);
+	let positions = (0..b.outputs().len() as u64)
+		.map(|i| pmmr::insertion_to_pmmr_index(first_idx + i + 1))
+		.zip(b.outputs());
+	let output_leaves = positions
+		.clone()
+		.map(|(pos, out)| (pos, out.identifier().hash_with_index(pos - 1)))
+		.collect();
+	let rproof_leaves = positions
+		.map(|(pos, out)| (pos, out.proof.hash_with_index(pos - 1)))
+		.collect();
+	(output_leaves, rproof_leaves)
+}
+
+/// The hashes of our MMR needed to rebuild its root at the given size from the
+/// provided leaves. Fails if one of them has been compacted away.
+fn proof_hashes<P: ReadablePMMR>(
+	pmmr: &P,
+	size: u64,
+	leaves: &BTreeMap<u64, Hash>,
+) -> Result<Vec<Hash>, Error> {
+	let mut hashes = vec![];
+	rebuild_root(pmmr, size, leaves, &mut |pos| {
+		let hash = pmmr.get_from_file(pos).ok_or_else(|| -> Error {
+			ErrorKind::Unfit(format!("mmr hash at {} has been compacted", pos)).into()
+		})?;
+		hashes.push(hash);
+		Ok(hash)
+	})?;
+	Ok(hashes)
+}
+
+/// Rebuild the root of the MMR of the given size from the provided leaves and
+/// the hashes of a proof, all of them used.
+fn root_from_proof<P: ReadablePMMR>(
+	pmmr: &P,
+	size: u64,
+	leaves: &BTreeMap<u64, Hash>,
+	proof_hashes: &[Hash],
+) -> Result<Hash, Error> {
+	let mut hashes = proof_hashes.iter();
+	let root = rebuild_root(pmmr, size, leaves, &mut |_| {
+		hashes
+			.next()
+			.cloned()
+			.ok_or_else(|| ErrorKind::InvalidRoot.into())
+	})?;
+	if hashes.next().is_some() {
+		return Err(ErrorKind::InvalidRoot.into());
+	}
+	Ok(root)
+}
+
+/// Rebuild the root of the MMR of the given size, hashing the provided leaves (by
+/// position) into it and getting the hashes of all other subtrees from `subtree`.
+/// Every hash we rebuild or get must match the one in the MMR itself, unless
+/// compacted away.
+fn rebuild_root<P: ReadablePMMR>(
+	pmmr: &P,
+	size: u64,
+	leaves: &BTreeMap<u64, Hash>,
+	subtree: &mut dyn FnMut(u64) -> Result<Hash, Error>,
+) -> Result<Hash, Error> {
+	fn node_hash<P: ReadablePMMR>(
+		pmmr: &P,
+		pos: u64,
+		leaves: &BTreeMap<u64, Hash>,
+		subtree: &mut dyn FnMut(u64) -> Result<Hash, Error>,
+	) -> Result<Hash, Error> {
+		let hash = if leaves.range(pmmr::bintree_range(pos)).next().is_none() {
+			subtree(pos)?
+		} else if pmmr::is_leaf(pos) {
+			leaves[&pos]
+		} else {
+			let height = pmmr::bintree_postorder_height(pos);
+			let left = node_hash(pmmr, pos - (1 << height), leaves, subtree)?;
+			let right = node_hash(pmmr, pos - 1, leaves, subtree)?;
+			(left, right).hash_with_index(pos - 1)
+		};
+		if pmmr.get_from_file(pos).is_some_and(|stored| stored != hash) {
+			return Err(ErrorKind::InvalidRoot.into());
+		}
+		Ok(hash)
+	}
+
+	let mut root = None;
+	for peak in pmmr::peaks(size).into_iter().rev() {
+		let hash = node_hash(pmmr, peak, leaves, subtree)?;
+		root = match root {
+			None => Some(hash),
+			Some(rhash) => Some((hash, rhash).hash_with_index(size)),
+		};
+	}
+	Ok(root.unwrap_or(ZERO_HASH))
+}
+
+fn reindex_io_error(action: &str, e: std::io::Error) -> Error {
+	ErrorKind::Other(format!("reindex: failed to {}: {}", action, e)).into()
+}
+
+/// Complete or roll back a reindex interrupted while swapping in the new chain state.
+/// The new state is complete once the sandbox is gone, otherwise we restore the
+/// previous state from its backup.
+fn recover_reindex_swap(root: &Path) -> Result<(), Error> {
+	let backup = root.join(REINDEX_BACKUP_SUBDIR);
+	if !backup.exists() {
+		return Ok(());
+	}
+	if root.join(REINDEX_SUBDIR).exists() {
+		warn!("reindex: previous reindex interrupted, restoring previous chain state");
+		for subdir in CHAIN_STATE_SUBDIRS.iter() {
+			if root.join(subdir).exists() {
+				fs::remove_dir_all(root.join(subdir))
+					.map_err(|e| reindex_io_error("remove partial state", e))?;
+			}
+			if backup.join(subdir).exists() {
+				fs::rename(backup.join(subdir), root.join(subdir))
+					.map_err(|e| reindex_io_error("restore previous state", e))?;
+			}
+		}
+	}
+	fs::remove_dir_all(&backup).map_err(|e| reindex_io_error("clean backup", e))
+}
+
+/// Record the outputs created and spent by this block in the output history index.
+fn index_output_history(b: &Block, batch: &store::Batch<'_>) -> Result<(), Error> {
+	let block_hash = b.hash();
+	let height = b.header.height;
+	for out in b.outputs() {
+		batch.add_output_history(
+			&out.commitment(),
+			OutputHistoryEntry {
+				block_hash,
+				height,
+				event: OutputHistoryEvent::Created(out.features()),
+			},
+		)?;
+	}
+	let kernels: Vec<_> = b
+		.kernels()
+		.iter()
+		.filter(|k| !k.is_coinbase())
+		.map(|k| k.excess())
+		.collect();
+	let inputs: Vec<CommitWrapper> = b.inputs().into();
+	for input in inputs {
+		batch.add_output_history(
+			&input.commitment(),
+			OutputHistoryEntry {
+				block_hash,
+				height,
+				event: OutputHistoryEvent::Spent(kernels.clone()),
+			},
+		)?;
+	}
+	Ok(())
+}
+
 fn setup_head(
 	genesis: &Block,
 	store: &store::ChainStore,
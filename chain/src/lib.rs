@@ -49,6 +49,7 @@ pub use crate::chain::{Chain, MAX_ORPHAN_SIZE};
 pub use crate::error::{Error, ErrorKind};
 pub use crate::store::ChainStore;
 pub use crate::types::{
-	BlockStatus, ChainAdapter, Options, SyncState, SyncStatus, Tip, TxHashsetDownloadStats,
-	TxHashsetWriteStatus,
+	BlockOutputsProof, BlockStatus, ChainAdapter, Options, OutputHistoryEntry, OutputHistoryEvent,
+	PibdPeerStats, SegmentDownloadStats, SyncState, SyncStatus, Tip, TxHashsetDownloadStats,
+	TxHashsetPibdStats, TxHashsetWriteStatus, MAX_OUTPUTS_PROOF_HASHES,
 };
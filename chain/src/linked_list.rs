@@ -226,6 +226,33 @@ impl<T> MultiIndex<T> {
 	}
 }
 
+impl<T> MultiIndex<T>
+where
+	T: PosEntry,
+{
+	/// Peek the head of the list for the specified commitment, reading the db
+	/// directly so lookups do not contend for the write lock with a batch.
+	pub fn peek_pos_readonly(
+		&self,
+		db: &store::Store,
+		commit: Commitment,
+	) -> Result<Option<T>, Error> {
+		match db.get_ser(&self.list_key(commit))? {
+			None => Ok(None),
+			Some(ListWrapper::Single { pos }) => Ok(Some(pos)),
+			Some(ListWrapper::Multi { head, .. }) => {
+				if let Some(ListEntry::Head { pos, .. }) =
+					db.get_ser(&self.entry_key(commit, head))?
+				{
+					Ok(Some(pos))
+				} else {
+					Err(Error::OtherErr("expected head to be head variant".into()))
+				}
+			}
+		}
+	}
+}
+
 impl<T> ListIndex for MultiIndex<T>
 where
 	T: PosEntry,
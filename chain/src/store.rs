@@ -20,12 +20,13 @@ use crate::core::core::{Block, BlockHeader, BlockSums};
 use crate::core::pow::Difficulty;
 use crate::core::ser::{ProtocolVersion, Readable, Writeable};
 use crate::linked_list::MultiIndex;
-use crate::types::{CommitPos, Tip};
+use crate::txhashset::{BitmapChunk, PibdProgress};
+use crate::types::{CommitPos, OutputHistoryEntry, Tip};
 use crate::util::secp::pedersen::Commitment;
 use croaring::Bitmap;
 use grin_core::ser;
 use grin_store as store;
-use grin_store::{option_to_not_found, to_key, Error};
+use grin_store::{option_to_not_found, to_key, u64_to_key, Error};
 use std::convert::TryInto;
 use std::sync::Arc;
 
@@ -43,9 +44,21 @@ pub const NRD_KERNEL_LIST_PREFIX: u8 = b'K';
 /// Prefix for NRD kernel pos index entries.
 pub const NRD_KERNEL_ENTRY_PREFIX: u8 = b'k';
 
+/// Prefix for kernel pos index lists.
+pub const KERNEL_POS_LIST_PREFIX: u8 = b'E';
+/// Prefix for kernel pos index entries.
+pub const KERNEL_POS_ENTRY_PREFIX: u8 = b'e';
+const KERNEL_POS_INDEXED_PREFIX: u8 = b'i';
+
 const BLOCK_SUMS_PREFIX: u8 = b'M';
 const BLOCK_SPENT_PREFIX: u8 = b'S';
 
+const PIBD_PROGRESS_PREFIX: u8 = b'P';
+const PIBD_BITMAP_PREFIX: u8 = b'B';
+
+const OUTPUT_HISTORY_PREFIX: u8 = b'O';
+const OUTPUT_HISTORY_BACKFILLED_PREFIX: u8 = b'o';
+
 /// All chain-related database operations
 pub struct ChainStore {
 	db: store::Store,
@@ -127,6 +140,50 @@ impl ChainStore {
 		self.db.get_ser(&to_key(OUTPUT_POS_PREFIX, commit))
 	}
 
+	/// Progress of an interrupted PIBD state sync, if any.
+	pub fn get_pibd_progress(&self) -> Result<Option<PibdProgress>, Error> {
+		self.db.get_ser(&[PIBD_PROGRESS_PREFIX])
+	}
+
+	/// Output bitmap chunks of the PIBD bitmap segment at the provided index.
+	pub fn get_pibd_bitmap_chunks(&self, idx: u64) -> Result<Vec<BitmapChunk>, Error> {
+		option_to_not_found(
+			self.db.get_ser(&u64_to_key(PIBD_BITMAP_PREFIX, idx)),
+			|| format!("PIBD bitmap segment: {}", idx),
+		)
+	}
+
+	/// Output history index entries for the given output commitment.
+	/// Entries may reference blocks no longer on the current chain.
+	pub fn get_output_history(
+		&self,
+		commit: &Commitment,
+	) -> Result<Vec<OutputHistoryEntry>, Error> {
+		Ok(self
+			.db
+			.get_ser(&to_key(OUTPUT_HISTORY_PREFIX, commit))?
+			.unwrap_or_default())
+	}
+
+	/// Has the output history index been backfilled from the blocks we had before
+	/// it was enabled?
+	pub fn is_output_history_backfilled(&self) -> Result<bool, Error> {
+		let flag: Option<BoolFlag> = self.db.get_ser(&[OUTPUT_HISTORY_BACKFILLED_PREFIX])?;
+		Ok(flag.map(bool::from).unwrap_or(false))
+	}
+
+	/// Is the (optional) kernel_pos index complete for our current txhashset?
+	pub fn is_kernel_pos_indexed(&self) -> Result<bool, Error> {
+		let flag: Option<BoolFlag> = self.db.get_ser(&[KERNEL_POS_INDEXED_PREFIX])?;
+		Ok(flag.map(bool::from).unwrap_or(false))
+	}
+
+	/// Most recent pos of the kernel with the given excess in the (optional)
+	/// kernel_pos index.
+	pub fn get_kernel_pos(&self, excess: Commitment) -> Result<Option<CommitPos>, Error> {
+		kernel_pos_index().peek_pos_readonly(&self.db, excess)
+	}
+
 	/// Builds a new batch to be used with this store.
 	pub fn batch(&self) -> Result<Batch<'_>, Error> {
 		Ok(Batch {
@@ -344,6 +401,57 @@ impl<'a> Batch<'a> {
 		})
 	}
 
+	/// Save the progress of our PIBD state sync.
+	pub fn save_pibd_progress(&self, progress: &PibdProgress) -> Result<(), Error> {
+		self.db.put_ser(&[PIBD_PROGRESS_PREFIX], progress)
+	}
+
+	/// Save the output bitmap chunks of an applied PIBD bitmap segment.
+	pub fn save_pibd_bitmap_chunks(&self, idx: u64, chunks: &[BitmapChunk]) -> Result<(), Error> {
+		self.db
+			.put_ser(&u64_to_key(PIBD_BITMAP_PREFIX, idx), &chunks.to_vec())
+	}
+
+	/// Delete the progress of our PIBD state sync along with the saved bitmap chunks.
+	pub fn delete_pibd_progress(&self) -> Result<(), Error> {
+		let progress: Option<PibdProgress> = self.db.get_ser(&[PIBD_PROGRESS_PREFIX])?;
+		if let Some(progress) = progress {
+			for idx in 0..progress.bitmap_segments {
+				self.db.delete(&u64_to_key(PIBD_BITMAP_PREFIX, idx))?;
+			}
+			self.db.delete(&[PIBD_PROGRESS_PREFIX])?;
+		}
+		Ok(())
+	}
+
+	/// Add an entry to the output history index for the given output commitment.
+	/// Adding the same entry again is a no-op.
+	pub fn add_output_history(
+		&self,
+		commit: &Commitment,
+		entry: OutputHistoryEntry,
+	) -> Result<(), Error> {
+		let key = to_key(OUTPUT_HISTORY_PREFIX, commit);
+		let mut entries: Vec<OutputHistoryEntry> = self.db.get_ser(&key)?.unwrap_or_default();
+		if !entries.contains(&entry) {
+			entries.push(entry);
+			self.db.put_ser(&key, &entries)?;
+		}
+		Ok(())
+	}
+
+	/// Flag the output history index as backfilled (or not).
+	pub fn save_output_history_backfilled(&self, backfilled: bool) -> Result<(), Error> {
+		self.db
+			.put_ser(&[OUTPUT_HISTORY_BACKFILLED_PREFIX], &BoolFlag(backfilled))
+	}
+
+	/// Flag the kernel_pos index as complete (or not) for our current txhashset.
+	pub fn save_kernel_pos_indexed(&self, indexed: bool) -> Result<(), Error> {
+		self.db
+			.put_ser(&[KERNEL_POS_INDEXED_PREFIX], &BoolFlag(indexed))
+	}
+
 	/// Commits this batch. If it's a child batch, it will be merged with the
 	/// parent, otherwise the batch is written to db.
 	pub fn commit(self) -> Result<(), Error> {
@@ -480,6 +588,13 @@ pub fn nrd_recent_kernel_index() -> MultiIndex<CommitPos> {
 	MultiIndex::init(NRD_KERNEL_LIST_PREFIX, NRD_KERNEL_ENTRY_PREFIX)
 }
 
+/// Init the (optional) kernel_pos index backed by the underlying db.
+/// Maps every kernel excess commitment to its pos in the kernel MMR (and block height),
+/// maintaining insertion order for the rare case of duplicate excess commitments.
+pub fn kernel_pos_index() -> MultiIndex<CommitPos> {
+	MultiIndex::init(KERNEL_POS_LIST_PREFIX, KERNEL_POS_ENTRY_PREFIX)
+}
+
 struct BoolFlag(bool);
 
 impl From<BoolFlag> for bool {
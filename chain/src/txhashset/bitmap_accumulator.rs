@@ -182,6 +182,22 @@ impl BitmapAccumulator {
 		self.readonly_pmmr().root().expect("no root, invalid tree")
 	}
 
+	/// Build the underlying bitmap from the chunks in our accumulator.
+	/// Used when the accumulator was rebuilt from (PIBD) bitmap segments
+	/// and we do not have the original bitmap.
+	pub fn as_bitmap(&self) -> Result<Bitmap, Error> {
+		let chunks = self
+			.backend
+			.data
+			.as_ref()
+			.ok_or_else(|| ErrorKind::Other("bitmap accumulator has no data".to_string()))?;
+		let mut bitmap = Bitmap::create();
+		for (chunk_idx, chunk) in chunks.iter().enumerate() {
+			bitmap.add_many(&chunk.set_iter(chunk_idx * 1024).collect::<Vec<u32>>());
+		}
+		Ok(bitmap)
+	}
+
 	/// Readonly access to our internal data.
 	pub fn readonly_pmmr(&self) -> ReadonlyPMMR<BitmapChunk, VecBackend<BitmapChunk>> {
 		ReadonlyPMMR::at(&self.backend, self.backend.size())
@@ -215,6 +231,16 @@ impl BitmapChunk {
 	pub fn any(&self) -> bool {
 		self.0.any()
 	}
+
+	/// Iterator over the idx of all bits set to 1 in this chunk,
+	/// offset by the provided idx of the first bit in the chunk.
+	pub fn set_iter(&self, idx_offset: usize) -> impl Iterator<Item = u32> + '_ {
+		self.0
+			.iter()
+			.enumerate()
+			.filter(|(_, val)| *val)
+			.map(move |(idx, _)| (idx + idx_offset) as u32)
+	}
 }
 
 impl PMMRable for BitmapChunk {
@@ -238,11 +264,10 @@ impl Writeable for BitmapChunk {
 }
 
 impl Readable for BitmapChunk {
-	/// Reading is not currently supported, just return an empty one for now.
-	/// We store the underlying roaring bitmap externally for the bitmap accumulator
-	/// and the "hash only" backend means we never actually read these chunks.
-	fn read<R: Reader>(_reader: &mut R) -> Result<BitmapChunk, ser::Error> {
-		Ok(BitmapChunk::new())
+	/// Chunks are read back when resuming an interrupted PIBD state sync.
+	fn read<R: Reader>(reader: &mut R) -> Result<BitmapChunk, ser::Error> {
+		let bytes = reader.read_fixed_bytes(Self::LEN_BYTES)?;
+		Ok(BitmapChunk(BitVec::from_bytes(&bytes)))
 	}
 }
 
@@ -0,0 +1,628 @@
+// Copyright 2021 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reconstruction of a txhashset from the segments received during PIBD.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::core::core::hash::{Hash, Hashed};
+use crate::core::core::pmmr::{self, segment::SegmentError};
+use crate::core::core::{BlockHeader, OutputIdentifier, Segment, SegmentIdentifier, TxKernel};
+use crate::core::ser::{self, Readable, Reader, Writeable, Writer};
+use crate::error::{Error, ErrorKind};
+use crate::store::ChainStore;
+use crate::txhashset::{self, BitmapAccumulator, BitmapChunk, PMMRHandle, TxHashSet};
+use crate::util::secp::pedersen::RangeProof;
+use crate::util::RwLock;
+use croaring::Bitmap;
+
+/// Height of the output bitmap segments we request from peers.
+pub const BITMAP_SEGMENT_HEIGHT: u8 = 9;
+/// Height of the output segments we request from peers.
+pub const OUTPUT_SEGMENT_HEIGHT: u8 = 11;
+/// Height of the rangeproof segments we request from peers.
+pub const RANGEPROOF_SEGMENT_HEIGHT: u8 = 9;
+/// Height of the kernel segments we request from peers.
+pub const KERNEL_SEGMENT_HEIGHT: u8 = 11;
+
+/// Maximum number of segments (per MMR) beyond the next one to be applied
+/// that we are willing to request and hold on to.
+const SEGMENT_WINDOW: u64 = 64;
+
+/// The MMR a PIBD segment belongs to.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum SegmentType {
+	/// Output bitmap (accumulator) segment.
+	Bitmap,
+	/// Output segment.
+	Output,
+	/// Rangeproof segment.
+	RangeProof,
+	/// Kernel segment.
+	Kernel,
+}
+
+/// Identifier of a PIBD segment along with the MMR it belongs to.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct SegmentTypeIdentifier {
+	/// The MMR this segment belongs to.
+	pub segment_type: SegmentType,
+	/// Identifier of the segment within the MMR.
+	pub identifier: SegmentIdentifier,
+}
+
+impl SegmentTypeIdentifier {
+	/// Create a new segment type identifier.
+	pub fn new(segment_type: SegmentType, identifier: SegmentIdentifier) -> SegmentTypeIdentifier {
+		SegmentTypeIdentifier {
+			segment_type,
+			identifier,
+		}
+	}
+}
+
+/// Progress of a PIBD state sync, saved to the db so an interrupted sync can
+/// resume where it stopped (for the same archive header).
+/// Segments of each MMR are applied in order, so the set of applied segments
+/// is fully described by the number of segments applied for each MMR.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PibdProgress {
+	/// Hash of the archive header we are syncing the txhashset for.
+	pub archive_header_hash: Hash,
+	/// Number of output bitmap segments applied.
+	pub bitmap_segments: u64,
+	/// Number of output segments applied.
+	pub output_segments: u64,
+	/// Number of rangeproof segments applied.
+	pub rangeproof_segments: u64,
+	/// Number of kernel segments applied.
+	pub kernel_segments: u64,
+	/// Size of the (partially built) output MMR.
+	pub output_mmr_size: u64,
+	/// Size of the (partially built) rangeproof MMR.
+	pub rproof_mmr_size: u64,
+	/// Size of the (partially built) kernel MMR.
+	pub kernel_mmr_size: u64,
+}
+
+impl Writeable for PibdProgress {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.archive_header_hash.write(writer)?;
+		writer.write_u64(self.bitmap_segments)?;
+		writer.write_u64(self.output_segments)?;
+		writer.write_u64(self.rangeproof_segments)?;
+		writer.write_u64(self.kernel_segments)?;
+		writer.write_u64(self.output_mmr_size)?;
+		writer.write_u64(self.rproof_mmr_size)?;
+		writer.write_u64(self.kernel_mmr_size)
+	}
+}
+
+impl Readable for PibdProgress {
+	fn read<R: Reader>(reader: &mut R) -> Result<PibdProgress, ser::Error> {
+		Ok(PibdProgress {
+			archive_header_hash: Hash::read(reader)?,
+			bitmap_segments: reader.read_u64()?,
+			output_segments: reader.read_u64()?,
+			rangeproof_segments: reader.read_u64()?,
+			kernel_segments: reader.read_u64()?,
+			output_mmr_size: reader.read_u64()?,
+			rproof_mmr_size: reader.read_u64()?,
+			kernel_mmr_size: reader.read_u64()?,
+		})
+	}
+}
+
+/// Desegmenter for rebuilding a txhashset from PIBD segments.
+///
+/// Every segment is validated against the roots in the archive header as soon as
+/// it is received. Segments of each MMR are applied in order to a fresh txhashset,
+/// out of order segments are held on to until the preceding ones arrive.
+/// The output bitmap is needed to validate output and rangeproof segments so we
+/// only accept these once all bitmap segments have been applied.
+/// Progress is saved to the db as segments are applied, see `PibdProgress`.
+pub struct Desegmenter {
+	txhashset: Arc<RwLock<TxHashSet>>,
+	header_pmmr: Arc<RwLock<PMMRHandle<BlockHeader>>>,
+	archive_header: BlockHeader,
+	store: Arc<ChainStore>,
+
+	bitmap_accumulator: BitmapAccumulator,
+	bitmap_cache: Option<Bitmap>,
+	bitmap_mmr_size: u64,
+
+	/// Sizes of the output, rangeproof and kernel MMRs built so far.
+	mmr_sizes: (u64, u64, u64),
+
+	bitmap_segments: BTreeMap<u64, Segment<BitmapChunk>>,
+	output_segments: BTreeMap<u64, Segment<OutputIdentifier>>,
+	rangeproof_segments: BTreeMap<u64, Segment<RangeProof>>,
+	kernel_segments: BTreeMap<u64, Segment<TxKernel>>,
+
+	next_bitmap_idx: u64,
+	next_output_idx: u64,
+	next_rangeproof_idx: u64,
+	next_kernel_idx: u64,
+}
+
+impl Desegmenter {
+	/// Create a new desegmenter applying segments for the provided archive header
+	/// to the provided (empty) txhashset.
+	pub fn new(
+		txhashset: Arc<RwLock<TxHashSet>>,
+		header_pmmr: Arc<RwLock<PMMRHandle<BlockHeader>>>,
+		archive_header: BlockHeader,
+		store: Arc<ChainStore>,
+	) -> Desegmenter {
+		// The bitmap MMR has a leaf for every chunk of 1024 outputs.
+		let bitmap_leaves = n_chunks(pmmr::n_leaves(archive_header.output_mmr_size), 1024);
+		let bitmap_mmr_size = pmmr::insertion_to_pmmr_index(bitmap_leaves + 1) - 1;
+		let mmr_sizes = txhashset.read().sizes();
+
+		Desegmenter {
+			txhashset,
+			header_pmmr,
+			archive_header,
+			store,
+			bitmap_accumulator: BitmapAccumulator::new(),
+			bitmap_cache: None,
+			bitmap_mmr_size,
+			mmr_sizes,
+			bitmap_segments: BTreeMap::new(),
+			output_segments: BTreeMap::new(),
+			rangeproof_segments: BTreeMap::new(),
+			kernel_segments: BTreeMap::new(),
+			next_bitmap_idx: 0,
+			next_output_idx: 0,
+			next_rangeproof_idx: 0,
+			next_kernel_idx: 0,
+		}
+	}
+
+	/// Resume from the provided progress, saved to the db by a previous desegmenter
+	/// for the same archive header. The txhashset we were provided must be the one
+	/// this previous desegmenter was building.
+	pub fn resume(&mut self, progress: &PibdProgress) -> Result<(), Error> {
+		if progress.archive_header_hash != self.archive_header.hash() {
+			return Err(ErrorKind::SyncError("PIBD progress header mismatch".to_string()).into());
+		}
+		let sizes = (
+			progress.output_mmr_size,
+			progress.rproof_mmr_size,
+			progress.kernel_mmr_size,
+		);
+		if sizes != self.mmr_sizes {
+			return Err(ErrorKind::SyncError(format!(
+				"PIBD progress inconsistent with txhashset, {:?} vs {:?}",
+				sizes, self.mmr_sizes
+			))
+			.into());
+		}
+		let counts = [
+			(SegmentType::Bitmap, progress.bitmap_segments),
+			(SegmentType::Output, progress.output_segments),
+			(SegmentType::RangeProof, progress.rangeproof_segments),
+			(SegmentType::Kernel, progress.kernel_segments),
+		];
+		if counts.iter().any(|&(t, n)| n > self.segment_count(t)) {
+			return Err(ErrorKind::SyncError("PIBD progress out of range".to_string()).into());
+		}
+
+		for idx in 0..progress.bitmap_segments {
+			for chunk in self.store.get_pibd_bitmap_chunks(idx)? {
+				self.bitmap_accumulator.append_chunk(chunk)?;
+			}
+		}
+		self.next_bitmap_idx = progress.bitmap_segments;
+		self.next_output_idx = progress.output_segments;
+		self.next_rangeproof_idx = progress.rangeproof_segments;
+		self.next_kernel_idx = progress.kernel_segments;
+		self.check_bitmap_complete()?;
+
+		debug!(
+			"desegmenter: resuming for {} at {}: {:?}",
+			self.archive_header.hash(),
+			self.archive_header.height,
+			progress
+		);
+		Ok(())
+	}
+
+	/// Our current progress, as saved to the db.
+	pub fn progress(&self) -> PibdProgress {
+		PibdProgress {
+			archive_header_hash: self.archive_header.hash(),
+			bitmap_segments: self.next_bitmap_idx,
+			output_segments: self.next_output_idx,
+			rangeproof_segments: self.next_rangeproof_idx,
+			kernel_segments: self.next_kernel_idx,
+			output_mmr_size: self.mmr_sizes.0,
+			rproof_mmr_size: self.mmr_sizes.1,
+			kernel_mmr_size: self.mmr_sizes.2,
+		}
+	}
+
+	/// The archive header the segments are for.
+	pub fn header(&self) -> &BlockHeader {
+		&self.archive_header
+	}
+
+	/// The txhashset the segments are applied to.
+	pub fn txhashset(&self) -> Arc<RwLock<TxHashSet>> {
+		self.txhashset.clone()
+	}
+
+	/// Total number of segments for the provided MMR.
+	pub fn segment_count(&self, segment_type: SegmentType) -> u64 {
+		let n_leaves = match segment_type {
+			SegmentType::Bitmap => pmmr::n_leaves(self.bitmap_mmr_size),
+			SegmentType::Output | SegmentType::RangeProof => {
+				pmmr::n_leaves(self.archive_header.output_mmr_size)
+			}
+			SegmentType::Kernel => pmmr::n_leaves(self.archive_header.kernel_mmr_size),
+		};
+		n_chunks(n_leaves, 1 << Desegmenter::segment_height(segment_type))
+	}
+
+	/// Number of segments applied so far for the provided MMR.
+	pub fn applied_count(&self, segment_type: SegmentType) -> u64 {
+		match segment_type {
+			SegmentType::Bitmap => self.next_bitmap_idx,
+			SegmentType::Output => self.next_output_idx,
+			SegmentType::RangeProof => self.next_rangeproof_idx,
+			SegmentType::Kernel => self.next_kernel_idx,
+		}
+	}
+
+	/// Height of the segments we request for the provided MMR.
+	pub fn segment_height(segment_type: SegmentType) -> u8 {
+		match segment_type {
+			SegmentType::Bitmap => BITMAP_SEGMENT_HEIGHT,
+			SegmentType::Output => OUTPUT_SEGMENT_HEIGHT,
+			SegmentType::RangeProof => RANGEPROOF_SEGMENT_HEIGHT,
+			SegmentType::Kernel => KERNEL_SEGMENT_HEIGHT,
+		}
+	}
+
+	/// Have all segments been received and applied?
+	pub fn is_complete(&self) -> bool {
+		[
+			SegmentType::Bitmap,
+			SegmentType::Output,
+			SegmentType::RangeProof,
+			SegmentType::Kernel,
+		]
+		.iter()
+		.all(|&t| self.applied_count(t) == self.segment_count(t))
+	}
+
+	fn is_cached(&self, segment_type: SegmentType, idx: u64) -> bool {
+		match segment_type {
+			SegmentType::Bitmap => self.bitmap_segments.contains_key(&idx),
+			SegmentType::Output => self.output_segments.contains_key(&idx),
+			SegmentType::RangeProof => self.rangeproof_segments.contains_key(&idx),
+			SegmentType::Kernel => self.kernel_segments.contains_key(&idx),
+		}
+	}
+
+	/// Segments we still need, in the order we would like to receive them.
+	/// Only considers segments within a window after the next segment to be
+	/// applied for each MMR. MMRs are interleaved so they progress together.
+	/// Output and rangeproof segments are only requested once the bitmap is complete.
+	pub fn next_desired_segments(&self) -> Vec<SegmentTypeIdentifier> {
+		let mut types = vec![SegmentType::Kernel];
+		if self.bitmap_cache.is_some() {
+			types.insert(0, SegmentType::Output);
+			types.insert(1, SegmentType::RangeProof);
+		} else {
+			types.insert(0, SegmentType::Bitmap);
+		}
+
+		let mut per_type: Vec<Vec<SegmentTypeIdentifier>> = types
+			.iter()
+			.map(|&t| {
+				let next = self.applied_count(t);
+				let end = (next + SEGMENT_WINDOW).min(self.segment_count(t));
+				(next..end)
+					.filter(|&idx| !self.is_cached(t, idx))
+					.map(|idx| {
+						SegmentTypeIdentifier::new(
+							t,
+							SegmentIdentifier {
+								height: Desegmenter::segment_height(t),
+								idx,
+							},
+						)
+					})
+					.rev()
+					.collect()
+			})
+			.collect();
+
+		let mut res = vec![];
+		while per_type.iter().any(|x| !x.is_empty()) {
+			for ids in per_type.iter_mut() {
+				if let Some(id) = ids.pop() {
+					res.push(id);
+				}
+			}
+		}
+		res
+	}
+
+	/// Check the segment is one we want: correct height, within the window
+	/// and not seen before. Returns false if the segment can be ignored.
+	fn check_segment(
+		&self,
+		segment_type: SegmentType,
+		id: SegmentIdentifier,
+	) -> Result<bool, Error> {
+		if id.height != Desegmenter::segment_height(segment_type) {
+			return Err(ErrorKind::InvalidSegmentHeight.into());
+		}
+		if id.idx >= self.segment_count(segment_type) {
+			return Err(ErrorKind::SegmentError(SegmentError::NonExistent).into());
+		}
+		let next = self.applied_count(segment_type);
+		Ok(id.idx >= next
+			&& id.idx < next + SEGMENT_WINDOW
+			&& !self.is_cached(segment_type, id.idx))
+	}
+
+	/// Validate and add a bitmap segment, along with the output root provided by the peer.
+	/// The output root is hashed together with the bitmap root to give the
+	/// output root committed to in the header.
+	pub fn add_bitmap_segment(
+		&mut self,
+		segment: Segment<BitmapChunk>,
+		output_root: Hash,
+	) -> Result<(), Error> {
+		if !self.check_segment(SegmentType::Bitmap, segment.identifier())? {
+			return Ok(());
+		}
+		segment.validate_with(
+			self.bitmap_mmr_size,
+			None,
+			self.archive_header.output_root,
+			self.archive_header.output_mmr_size,
+			output_root,
+			true,
+		)?;
+		self.bitmap_segments
+			.insert(segment.identifier().idx, segment);
+		self.apply_bitmap_segments()
+	}
+
+	fn apply_bitmap_segments(&mut self) -> Result<(), Error> {
+		let store = self.store.clone();
+		let batch = store.batch()?;
+		let mut applied = false;
+		while let Some(segment) = self.bitmap_segments.remove(&self.next_bitmap_idx) {
+			let chunks: Vec<BitmapChunk> = segment.leaf_iter().map(|(_, c)| c.clone()).collect();
+			for chunk in chunks.iter() {
+				self.bitmap_accumulator.append_chunk(chunk.clone())?;
+			}
+			batch.save_pibd_bitmap_chunks(self.next_bitmap_idx, &chunks)?;
+			self.next_bitmap_idx += 1;
+			applied = true;
+		}
+		if applied {
+			batch.save_pibd_progress(&self.progress())?;
+			batch.commit()?;
+		}
+		self.check_bitmap_complete()
+	}
+
+	// Build the output bitmap once all bitmap segments have been applied.
+	fn check_bitmap_complete(&mut self) -> Result<(), Error> {
+		if self.bitmap_cache.is_none()
+			&& self.next_bitmap_idx == self.segment_count(SegmentType::Bitmap)
+		{
+			let bitmap = self.bitmap_accumulator.as_bitmap()?;
+			debug!(
+				"desegmenter: bitmap complete for {} at {}, {} unspent outputs",
+				self.archive_header.hash(),
+				self.archive_header.height,
+				bitmap.cardinality(),
+			);
+			self.bitmap_cache = Some(bitmap);
+		}
+		Ok(())
+	}
+
+	/// Validate and add an output segment.
+	/// Ignored if we do not have the full bitmap yet as it is required for validation.
+	pub fn add_output_segment(&mut self, segment: Segment<OutputIdentifier>) -> Result<(), Error> {
+		let bitmap = match self.bitmap_cache {
+			Some(ref bitmap) => bitmap,
+			None => return Ok(()),
+		};
+		if !self.check_segment(SegmentType::Output, segment.identifier())? {
+			return Ok(());
+		}
+		segment.validate_with(
+			self.archive_header.output_mmr_size,
+			Some(bitmap),
+			self.archive_header.output_root,
+			self.archive_header.output_mmr_size,
+			self.bitmap_accumulator.root(),
+			false,
+		)?;
+		self.output_segments
+			.insert(segment.identifier().idx, segment);
+		self.apply_output_segments()
+	}
+
+	fn apply_output_segments(&mut self) -> Result<(), Error> {
+		let (start, end) = ready_range(&self.output_segments, self.next_output_idx);
+		if start == end {
+			return Ok(());
+		}
+
+		let mmr_size = self.archive_header.output_mmr_size;
+		let bitmap = self
+			.bitmap_cache
+			.as_ref()
+			.ok_or_else(|| ErrorKind::SyncError("missing output bitmap".to_string()))?;
+		let segments = &self.output_segments;
+		let mut progress = self.progress();
+		progress.output_segments = end;
+		self.mmr_sizes = self.extending(progress, |extension| {
+			for idx in start..end {
+				extension.apply_output_segment(&segments[&idx], mmr_size, bitmap)?;
+			}
+			Ok(())
+		})?;
+
+		// Only move on once the segments are safely applied.
+		self.output_segments = self.output_segments.split_off(&end);
+		self.next_output_idx = end;
+		Ok(())
+	}
+
+	/// Validate and add a rangeproof segment.
+	/// Ignored if we do not have the full bitmap yet as it is required for validation.
+	pub fn add_rangeproof_segment(&mut self, segment: Segment<RangeProof>) -> Result<(), Error> {
+		let bitmap = match self.bitmap_cache {
+			Some(ref bitmap) => bitmap,
+			None => return Ok(()),
+		};
+		if !self.check_segment(SegmentType::RangeProof, segment.identifier())? {
+			return Ok(());
+		}
+		segment.validate(
+			self.archive_header.output_mmr_size,
+			Some(bitmap),
+			self.archive_header.range_proof_root,
+		)?;
+		self.rangeproof_segments
+			.insert(segment.identifier().idx, segment);
+		self.apply_rangeproof_segments()
+	}
+
+	fn apply_rangeproof_segments(&mut self) -> Result<(), Error> {
+		let (start, end) = ready_range(&self.rangeproof_segments, self.next_rangeproof_idx);
+		if start == end {
+			return Ok(());
+		}
+
+		let mmr_size = self.archive_header.output_mmr_size;
+		let bitmap = self
+			.bitmap_cache
+			.as_ref()
+			.ok_or_else(|| ErrorKind::SyncError("missing output bitmap".to_string()))?;
+		let segments = &self.rangeproof_segments;
+		let mut progress = self.progress();
+		progress.rangeproof_segments = end;
+		self.mmr_sizes = self.extending(progress, |extension| {
+			for idx in start..end {
+				extension.apply_rangeproof_segment(&segments[&idx], mmr_size, bitmap)?;
+			}
+			Ok(())
+		})?;
+
+		// Only move on once the segments are safely applied.
+		self.rangeproof_segments = self.rangeproof_segments.split_off(&end);
+		self.next_rangeproof_idx = end;
+		Ok(())
+	}
+
+	/// Validate and add a kernel segment.
+	pub fn add_kernel_segment(&mut self, segment: Segment<TxKernel>) -> Result<(), Error> {
+		if !self.check_segment(SegmentType::Kernel, segment.identifier())? {
+			return Ok(());
+		}
+		segment.validate(
+			self.archive_header.kernel_mmr_size,
+			None,
+			self.archive_header.kernel_root,
+		)?;
+		self.kernel_segments
+			.insert(segment.identifier().idx, segment);
+		self.apply_kernel_segments()
+	}
+
+	fn apply_kernel_segments(&mut self) -> Result<(), Error> {
+		let (start, end) = ready_range(&self.kernel_segments, self.next_kernel_idx);
+		if start == end {
+			return Ok(());
+		}
+
+		let segments = &self.kernel_segments;
+		let mut progress = self.progress();
+		progress.kernel_segments = end;
+		self.mmr_sizes = self.extending(progress, |extension| {
+			for idx in start..end {
+				extension.apply_kernel_segment(&segments[&idx])?;
+			}
+			Ok(())
+		})?;
+
+		// Only move on once the segments are safely applied.
+		self.kernel_segments = self.kernel_segments.split_off(&end);
+		self.next_kernel_idx = end;
+		Ok(())
+	}
+
+	/// Apply segments to our txhashset via a (writeable) extension and save the provided
+	/// progress, returning the resulting MMR sizes. Changes are discarded if anything goes
+	/// wrong, leaving both the txhashset and our progress untouched.
+	fn extending<F>(&self, mut progress: PibdProgress, inner: F) -> Result<(u64, u64, u64), Error>
+	where
+		F: FnOnce(&mut txhashset::Extension<'_>) -> Result<(), Error>,
+	{
+		let now = Instant::now();
+		let mut header_pmmr = self.header_pmmr.write();
+		let mut txhashset = self.txhashset.write();
+		let mut batch = self.store.batch()?;
+		let sizes =
+			txhashset::extending(&mut header_pmmr, &mut txhashset, &mut batch, |ext, _| {
+				inner(ext.extension)?;
+				Ok(ext.extension.sizes())
+			})?;
+
+		// The txhashset files are synced at this point, record our progress.
+		progress.output_mmr_size = sizes.0;
+		progress.rproof_mmr_size = sizes.1;
+		progress.kernel_mmr_size = sizes.2;
+		batch.save_pibd_progress(&progress)?;
+		batch.commit()?;
+
+		trace!(
+			"desegmenter: applied segments, took {}ms",
+			now.elapsed().as_millis()
+		);
+		Ok(sizes)
+	}
+}
+
+/// Range of the segments we can apply next, consecutive from the provided index.
+fn ready_range<T>(segments: &BTreeMap<u64, Segment<T>>, start: u64) -> (u64, u64) {
+	let mut end = start;
+	while segments.contains_key(&end) {
+		end += 1;
+	}
+	(start, end)
+}
+
+/// Number of chunks of the given size required to hold n items.
+fn n_chunks(n: u64, chunk_size: u64) -> u64 {
+	if n == 0 {
+		0
+	} else {
+		(n - 1) / chunk_size + 1
+	}
+}
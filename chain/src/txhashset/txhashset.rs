@@ -19,6 +19,7 @@ use crate::core::consensus::WEEK_HEIGHT;
 use crate::core::core::committed::Committed;
 use crate::core::core::hash::{Hash, Hashed};
 use crate::core::core::merkle_proof::MerkleProof;
+use crate::core::core::pmmr::segment::{Segment, SegmentError};
 use crate::core::core::pmmr::{
 	self, Backend, ReadablePMMR, ReadonlyPMMR, RewindablePMMR, VecBackend, PMMR,
 };
@@ -35,6 +36,7 @@ use crate::util::secp::pedersen::{Commitment, RangeProof};
 use crate::util::{file, secp_static, zip};
 use croaring::Bitmap;
 use grin_store::pmmr::{clean_files_by_prefix, PMMRBackend};
+use std::collections::HashMap;
 use std::fs::{self, File};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
@@ -153,6 +155,9 @@ pub struct TxHashSet {
 
 	// chain store used as index of commitments to MMR positions
 	commit_index: Arc<ChainStore>,
+
+	// maintain the (optional) kernel_pos index as we apply and rewind blocks
+	kernel_pos_index: bool,
 }
 
 impl TxHashSet {
@@ -231,6 +236,7 @@ impl TxHashSet {
 				kernel_pmmr_h,
 				bitmap_accumulator,
 				commit_index,
+				kernel_pos_index: false,
 			})
 		} else {
 			Err(ErrorKind::TxHashSetErr("failed to open kernel PMMR".to_string()).into())
@@ -371,6 +377,32 @@ impl TxHashSet {
 		let max_index = max_index.unwrap_or(self.kernel_pmmr_h.last_pos);
 
 		let pmmr = ReadonlyPMMR::at(&self.kernel_pmmr_h.backend, self.kernel_pmmr_h.last_pos);
+
+		// Use the kernel_pos index (if enabled) to avoid scanning the kernel MMR.
+		// We only need to fall back to scanning if the most recent kernel with this excess
+		// is beyond max_index (and we are looking for an earlier duplicate).
+		if self.kernel_pos_index {
+			match self.kernel_pos_from_index(excess) {
+				Ok(Some(pos)) if pos.pos <= max_index => {
+					if pos.pos < min_index {
+						return None;
+					}
+					if let Some(kernel) = pmmr.get_data(pos.pos) {
+						if &kernel.excess == excess {
+							return Some((kernel, pos.pos));
+						}
+					}
+					warn!(
+						"find_kernel: kernel_pos index inconsistent for {:?} at {}",
+						excess, pos.pos
+					);
+				}
+				Ok(Some(_)) => {}
+				Ok(None) => return None,
+				Err(e) => warn!("find_kernel: failed to read kernel_pos index: {}", e),
+			}
+		}
+
 		let mut index = max_index + 1;
 		while index > min_index {
 			index -= 1;
@@ -383,6 +415,80 @@ impl TxHashSet {
 		None
 	}
 
+	fn kernel_pos_from_index(&self, excess: &Commitment) -> Result<Option<CommitPos>, Error> {
+		let pos = self.commit_index.get_kernel_pos(*excess)?;
+		Ok(pos)
+	}
+
+	/// Is the (optional) kernel_pos index maintained as we apply and rewind blocks?
+	pub fn kernel_pos_index(&self) -> bool {
+		self.kernel_pos_index
+	}
+
+	/// Enable (or disable) the kernel_pos index.
+	/// The index must be complete (see init_kernel_pos_index) before enabling it.
+	pub fn set_kernel_pos_index(&mut self, enabled: bool) {
+		self.kernel_pos_index = enabled;
+	}
+
+	/// (Re)build the kernel_pos index from the full kernel MMR.
+	/// Commits to the db periodically as this may involve millions of kernels.
+	/// The index is only flagged as complete once we reach the end of the kernel MMR.
+	pub fn init_kernel_pos_index(
+		&self,
+		header_pmmr: &PMMRHandle<BlockHeader>,
+	) -> Result<(), Error> {
+		let now = Instant::now();
+		let kernel_index = store::kernel_pos_index();
+
+		let batch = self.commit_index.batch()?;
+		batch.save_kernel_pos_indexed(false)?;
+		kernel_index.clear(&batch)?;
+		batch.commit()?;
+
+		let kernel_pmmr =
+			ReadonlyPMMR::at(&self.kernel_pmmr_h.backend, self.kernel_pmmr_h.last_pos);
+
+		let mut batch = self.commit_index.batch()?;
+		let mut current_header =
+			batch.get_block_header(&header_pmmr.get_header_hash_by_height(0)?)?;
+		let mut count = 0;
+		for pos in 1..=self.kernel_pmmr_h.last_pos {
+			if !pmmr::is_leaf(pos) {
+				continue;
+			}
+			if let Some(kernel) = kernel_pmmr.get_data(pos) {
+				while pos > current_header.kernel_mmr_size {
+					let hash = header_pmmr.get_header_hash_by_height(current_header.height + 1)?;
+					current_header = batch.get_block_header(&hash)?;
+				}
+				let commit_pos = CommitPos {
+					pos,
+					height: current_header.height,
+				};
+				kernel_index.push_pos(&batch, kernel.excess(), commit_pos)?;
+				count += 1;
+				if count % 100_000 == 0 {
+					batch.commit()?;
+					batch = self.commit_index.batch()?;
+					debug!(
+						"init_kernel_pos_index: pushed {} entries, up to height {}",
+						count, current_header.height
+					);
+				}
+			}
+		}
+		batch.save_kernel_pos_indexed(true)?;
+		batch.commit()?;
+
+		debug!(
+			"init_kernel_pos_index: pushed {} entries to the index, took {}s",
+			count,
+			now.elapsed().as_secs(),
+		);
+		Ok(())
+	}
+
 	/// Get MMR roots.
 	pub fn roots(&self) -> TxHashSetRoots {
 		let output_pmmr =
@@ -402,6 +508,15 @@ impl TxHashSet {
 		}
 	}
 
+	/// Sizes of each of the MMRs (output, rangeproof and kernel).
+	pub fn sizes(&self) -> (u64, u64, u64) {
+		(
+			self.output_pmmr_h.last_pos,
+			self.rproof_pmmr_h.last_pos,
+			self.kernel_pmmr_h.last_pos,
+		)
+	}
+
 	/// Return Commit's MMR position
 	pub fn get_output_pos(&self, commit: &Commitment) -> Result<u64, Error> {
 		Ok(self.commit_index.get_output_pos(&commit)?)
@@ -1031,6 +1146,9 @@ pub struct Extension<'a> {
 
 	bitmap_accumulator: BitmapAccumulator,
 
+	/// Maintain the kernel_pos index?
+	kernel_pos_index: bool,
+
 	/// Rollback flag.
 	rollback: bool,
 }
@@ -1080,6 +1198,7 @@ impl<'a> Extension<'a> {
 				trees.kernel_pmmr_h.last_pos,
 			),
 			bitmap_accumulator: trees.bitmap_accumulator.clone(),
+			kernel_pos_index: trees.kernel_pos_index,
 			rollback: false,
 		}
 	}
@@ -1263,6 +1382,9 @@ impl<'a> Extension<'a> {
 			let pos = self.apply_kernel(kernel)?;
 			let commit_pos = CommitPos { pos, height };
 			apply_kernel_rules(kernel, commit_pos, batch)?;
+			if self.kernel_pos_index {
+				store::kernel_pos_index().push_pos(batch, kernel.excess(), commit_pos)?;
+			}
 		}
 		Ok(())
 	}
@@ -1276,6 +1398,48 @@ impl<'a> Extension<'a> {
 		Ok(pos)
 	}
 
+	/// Apply an output segment received during PIBD to the output MMR.
+	/// Segments must be applied in order and must have been validated against
+	/// the provided (rebuilt) output bitmap.
+	pub fn apply_output_segment(
+		&mut self,
+		segment: &Segment<OutputIdentifier>,
+		mmr_size: u64,
+		bitmap: &Bitmap,
+	) -> Result<(), Error> {
+		apply_prunable_segment(&mut self.output_pmmr, segment, mmr_size, bitmap)
+	}
+
+	/// Apply a rangeproof segment received during PIBD to the rangeproof MMR.
+	/// Segments must be applied in order and must have been validated against
+	/// the provided (rebuilt) output bitmap.
+	pub fn apply_rangeproof_segment(
+		&mut self,
+		segment: &Segment<RangeProof>,
+		mmr_size: u64,
+		bitmap: &Bitmap,
+	) -> Result<(), Error> {
+		apply_prunable_segment(&mut self.rproof_pmmr, segment, mmr_size, bitmap)
+	}
+
+	/// Apply a kernel segment received during PIBD to the kernel MMR.
+	/// The kernel MMR is not prunable so every leaf is in the segment.
+	/// Segments must be applied in order.
+	pub fn apply_kernel_segment(&mut self, segment: &Segment<TxKernel>) -> Result<(), Error> {
+		for (pos, kernel) in segment.leaf_iter() {
+			if pos != self.kernel_pmmr.unpruned_size() + 1 {
+				return Err(ErrorKind::TxHashSetErr(format!(
+					"kernel segment out of order, leaf at {} after {}",
+					pos,
+					self.kernel_pmmr.unpruned_size()
+				))
+				.into());
+			}
+			self.apply_kernel(kernel)?;
+		}
+		Ok(())
+	}
+
 	/// Build a Merkle proof for the given output and the block
 	/// this extension is currently referencing.
 	/// Note: this relies on the MMR being stable even after pruning/compaction.
@@ -1418,6 +1582,14 @@ impl<'a> Extension<'a> {
 			}
 		}
 
+		// Rewind the kernel_pos index (if enabled) for all kernels in the block being rewound.
+		if self.kernel_pos_index {
+			let kernel_index = store::kernel_pos_index();
+			for kernel in block.kernels() {
+				kernel_index.rewind(batch, kernel.excess(), prev_header.kernel_mmr_size)?;
+			}
+		}
+
 		// Update output_pos based on "unspending" all spent pos from this block.
 		// This is necessary to ensure the output_pos index correctly reflects a
 		// reused output commitment. For example an output at pos 1, spent, reused at pos 2.
@@ -1844,6 +2016,102 @@ pub fn zip_write(
 	Ok(())
 }
 
+/// Apply a segment of a prunable MMR (outputs or rangeproofs) to the provided PMMR.
+/// We walk the segment to determine which nodes are pruned based on the bitmap,
+/// exactly as `Segment::root` does during validation. We then push the required
+/// leaves and the roots of any pruned subtrees, in order. Leaves not in the bitmap
+/// are removed after being pushed, as they are spent as of the segment header.
+fn apply_prunable_segment<T, B>(
+	pmmr: &mut PMMR<'_, T, B>,
+	segment: &Segment<T>,
+	mmr_size: u64,
+	bitmap: &Bitmap,
+) -> Result<(), Error>
+where
+	T: PMMRable<E = T>,
+	B: Backend<T>,
+{
+	// A fully pruned segment consists of a single hash, the first unpruned parent.
+	// This may be shared with neighbouring segments so may already be applied.
+	if segment.root(mmr_size, Some(bitmap))?.is_none() {
+		let (hash, pos) = segment.first_unpruned_parent(mmr_size, Some(bitmap))?;
+		if pos > pmmr.unpruned_size() {
+			pmmr.push_pruned_subtree(hash, pos)
+				.map_err(ErrorKind::TxHashSetErr)?;
+		}
+		return Ok(());
+	}
+
+	let leaf_idx = |pos: u64| pmmr::n_leaves(pos) - 1;
+	let required = |pos: u64| {
+		let idx = leaf_idx(pos);
+		let sibling_idx = if pmmr::is_left_sibling(pos) {
+			idx + 1
+		} else {
+			idx - 1
+		};
+		bitmap.contains(idx as u32) || bitmap.contains(sibling_idx as u32) || pos == mmr_size
+	};
+
+	// Walk the segment in postorder, tracking whether each node is pruned.
+	// A pruned node with an unpruned sibling is the root of a pruned subtree.
+	// Any pruned node left on the stack at the end is a pruned peak.
+	let mut to_apply: Vec<(u64, bool)> = vec![];
+	let mut stack: Vec<(u64, bool)> = vec![];
+	let (first, last) = segment.segment_pos_range(mmr_size);
+	for pos in first..=last {
+		let pruned = if pmmr::is_leaf(pos) {
+			let pruned = !required(pos);
+			if !pruned {
+				to_apply.push((pos, true));
+			}
+			pruned
+		} else {
+			let right = stack.pop().ok_or(SegmentError::MissingHash(pos))?;
+			let left = stack.pop().ok_or(SegmentError::MissingHash(pos))?;
+			match (left, right) {
+				((left_pos, true), (_, false)) => to_apply.push((left_pos, false)),
+				((_, false), (right_pos, true)) => to_apply.push((right_pos, false)),
+				_ => {}
+			}
+			left.1 && right.1
+		};
+		stack.push((pos, pruned));
+	}
+	for (pos, pruned) in stack {
+		if pruned {
+			to_apply.push((pos, false));
+		}
+	}
+	to_apply.sort_unstable();
+
+	let hashes: HashMap<u64, Hash> = segment.hash_iter().collect();
+	let leaves: HashMap<u64, &T> = segment.leaf_iter().collect();
+	for (pos, is_leaf) in to_apply {
+		if pmmr::bintree_leftmost(pos) != pmmr.unpruned_size() + 1 {
+			return Err(ErrorKind::TxHashSetErr(format!(
+				"segment out of order, pos {} after {}",
+				pos,
+				pmmr.unpruned_size()
+			))
+			.into());
+		}
+		if is_leaf {
+			let leaf = leaves.get(&pos).ok_or(SegmentError::MissingLeaf(pos))?;
+			pmmr.push(leaf).map_err(ErrorKind::TxHashSetErr)?;
+			if !bitmap.contains(leaf_idx(pos) as u32) {
+				pmmr.prune(pos).map_err(ErrorKind::TxHashSetErr)?;
+			}
+		} else {
+			let hash = hashes.get(&pos).ok_or(SegmentError::MissingHash(pos))?;
+			pmmr.push_pruned_subtree(*hash, pos)
+				.map_err(ErrorKind::TxHashSetErr)?;
+		}
+	}
+
+	Ok(())
+}
+
 /// Overwrite txhashset folders in "to" folder with "from" folder
 pub fn txhashset_replace(from: PathBuf, to: PathBuf) -> Result<(), Error> {
 	debug!("txhashset_replace: move from {:?} to {:?}", from, to);
@@ -17,10 +17,12 @@
 use chrono::prelude::{DateTime, Utc};
 
 use crate::core::core::hash::{Hash, Hashed, ZERO_HASH};
-use crate::core::core::{Block, BlockHeader, HeaderVersion};
+use crate::core::core::{Block, BlockHeader, HeaderVersion, OutputFeatures};
 use crate::core::pow::Difficulty;
 use crate::core::ser::{self, PMMRIndexHashable, Readable, Reader, Writeable, Writer};
 use crate::error::{Error, ErrorKind};
+use crate::txhashset::SegmentTypeIdentifier;
+use crate::util::secp::pedersen::Commitment;
 use crate::util::{RwLock, RwLockWriteGuard};
 
 bitflags! {
@@ -38,7 +40,7 @@ bitflags! {
 }
 
 /// Various status sync can be in, whether it's fast sync or archival.
-#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
 pub enum SyncStatus {
 	/// Initial State (we do not yet know if we are/should be syncing)
 	Initial,
@@ -56,6 +58,8 @@ pub enum SyncStatus {
 	},
 	/// Downloading the various txhashsets
 	TxHashsetDownload(TxHashsetDownloadStats),
+	/// Downloading the txhashset as PIBD segments from several peers
+	TxHashsetPibdDownload(TxHashsetPibdStats),
 	/// Setting up before validation
 	TxHashsetSetup,
 	/// Validating the kernels
@@ -117,10 +121,88 @@ impl Default for TxHashsetDownloadStats {
 	}
 }
 
+/// Download progress of the segments of a single MMR during PIBD
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct SegmentDownloadStats {
+	/// segments received and applied
+	pub segments_done: u64,
+	/// segments in total
+	pub segments_total: u64,
+}
+
+/// Stats for a peer we request PIBD segments from
+#[derive(Debug, Clone, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct PibdPeerStats {
+	/// peer address
+	pub addr: String,
+	/// segments requested from this peer
+	pub segments_requested: u64,
+	/// segments received from this peer
+	pub segments_received: u64,
+	/// requests this peer did not answer in time
+	pub segments_timed_out: u64,
+	/// requests currently in flight
+	pub in_flight: u64,
+	/// average time it took this peer to provide a segment
+	pub avg_latency_ms: u64,
+}
+
+/// Stats for TxHashsetPibdDownload stage
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
+pub struct TxHashsetPibdStats {
+	/// when download started
+	pub start_time: DateTime<Utc>,
+	/// time of the latest update
+	pub update_time: DateTime<Utc>,
+	/// output bitmap segments
+	pub bitmap: SegmentDownloadStats,
+	/// output segments
+	pub output: SegmentDownloadStats,
+	/// rangeproof segments
+	pub rangeproof: SegmentDownloadStats,
+	/// kernel segments
+	pub kernel: SegmentDownloadStats,
+	/// peers serving us segments
+	pub peers: Vec<PibdPeerStats>,
+}
+
+impl TxHashsetPibdStats {
+	/// Segments received and applied, across all MMRs
+	pub fn segments_done(&self) -> u64 {
+		self.bitmap.segments_done
+			+ self.output.segments_done
+			+ self.rangeproof.segments_done
+			+ self.kernel.segments_done
+	}
+
+	/// Segments in total, across all MMRs
+	pub fn segments_total(&self) -> u64 {
+		self.bitmap.segments_total
+			+ self.output.segments_total
+			+ self.rangeproof.segments_total
+			+ self.kernel.segments_total
+	}
+}
+
+impl Default for TxHashsetPibdStats {
+	fn default() -> Self {
+		TxHashsetPibdStats {
+			start_time: Utc::now(),
+			update_time: Utc::now(),
+			bitmap: SegmentDownloadStats::default(),
+			output: SegmentDownloadStats::default(),
+			rangeproof: SegmentDownloadStats::default(),
+			kernel: SegmentDownloadStats::default(),
+			peers: vec![],
+		}
+	}
+}
+
 /// Current sync state. Encapsulates the current SyncStatus.
 pub struct SyncState {
 	current: RwLock<SyncStatus>,
 	sync_error: RwLock<Option<Error>>,
+	pibd_received: RwLock<Vec<(SegmentTypeIdentifier, String)>>,
 }
 
 impl SyncState {
@@ -129,6 +211,7 @@ impl SyncState {
 		SyncState {
 			current: RwLock::new(SyncStatus::Initial),
 			sync_error: RwLock::new(None),
+			pibd_received: RwLock::new(vec![]),
 		}
 	}
 
@@ -140,7 +223,7 @@ impl SyncState {
 
 	/// Current syncing status
 	pub fn status(&self) -> SyncStatus {
-		*self.current.read()
+		self.current.read().clone()
 	}
 
 	/// Update the syncing status
@@ -169,7 +252,7 @@ impl SyncState {
 		F: Fn(SyncStatus) -> bool,
 	{
 		let status = self.current.write();
-		if f(*status) {
+		if f(status.clone()) {
 			self.update_with_guard(new_status, status)
 		} else {
 			false
@@ -181,6 +264,11 @@ impl SyncState {
 		*self.current.write() = SyncStatus::TxHashsetDownload(stats);
 	}
 
+	/// Update PIBD txhashset downloading progress
+	pub fn update_txhashset_pibd_download(&self, stats: TxHashsetPibdStats) {
+		*self.current.write() = SyncStatus::TxHashsetPibdDownload(stats);
+	}
+
 	/// Communicate sync error
 	pub fn set_sync_error(&self, error: Error) {
 		*self.sync_error.write() = Some(error);
@@ -191,10 +279,25 @@ impl SyncState {
 		self.sync_error.read().as_ref().map(|e| e.to_string())
 	}
 
+	/// Get the kind of the sync error
+	pub fn sync_error_kind(&self) -> Option<ErrorKind> {
+		self.sync_error.read().as_ref().map(|e| e.kind())
+	}
+
 	/// Clear sync error
 	pub fn clear_sync_error(&self) {
 		*self.sync_error.write() = None;
 	}
+
+	/// Record a PIBD segment we accepted, along with the peer that sent it
+	pub fn pibd_segment_received(&self, id: SegmentTypeIdentifier, peer: String) {
+		self.pibd_received.write().push((id, peer));
+	}
+
+	/// Take the PIBD segments received since the last call
+	pub fn take_pibd_received(&self) -> Vec<(SegmentTypeIdentifier, String)> {
+		std::mem::take(&mut *self.pibd_received.write())
+	}
 }
 
 impl TxHashsetWriteStatus for SyncState {
@@ -327,6 +430,120 @@ impl Writeable for CommitPos {
 	}
 }
 
+/// What happened to an output commitment in a given block.
+#[derive(Clone, Debug, PartialEq)]
+pub enum OutputHistoryEvent {
+	/// The output was created with the provided features.
+	Created(OutputFeatures),
+	/// The output was spent. Transactions are aggregated in a block so we cannot tell
+	/// which kernel spent it, these are the excess commitments of all the (non-coinbase)
+	/// kernels in the spending block.
+	Spent(Vec<Commitment>),
+}
+
+/// An entry in the (optional) output history index, the block in which an output
+/// commitment was created or spent.
+/// Entries are recorded for every block we process, including blocks on forks,
+/// so need to be checked against the current chain when read back.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutputHistoryEntry {
+	/// Hash of the block creating or spending the output.
+	pub block_hash: Hash,
+	/// Height of the block creating or spending the output.
+	pub height: u64,
+	/// Whether the output was created or spent.
+	pub event: OutputHistoryEvent,
+}
+
+impl Readable for OutputHistoryEntry {
+	fn read<R: Reader>(reader: &mut R) -> Result<OutputHistoryEntry, ser::Error> {
+		let block_hash = Hash::read(reader)?;
+		let height = reader.read_u64()?;
+		let event = match reader.read_u8()? {
+			0 => OutputHistoryEvent::Created(OutputFeatures::read(reader)?),
+			1 => OutputHistoryEvent::Spent(Vec::read(reader)?),
+			_ => return Err(ser::Error::CorruptedData),
+		};
+		Ok(OutputHistoryEntry {
+			block_hash,
+			height,
+			event,
+		})
+	}
+}
+
+impl Writeable for OutputHistoryEntry {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.block_hash.write(writer)?;
+		writer.write_u64(self.height)?;
+		match self.event {
+			OutputHistoryEvent::Created(ref features) => {
+				writer.write_u8(0)?;
+				features.write(writer)?;
+			}
+			OutputHistoryEvent::Spent(ref kernels) => {
+				writer.write_u8(1)?;
+				kernels.write(writer)?;
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Maximum number of hashes of each MMR in a block outputs proof, well above
+/// what rebuilding the root of any MMR takes.
+pub const MAX_OUTPUTS_PROOF_HASHES: u16 = 256;
+
+/// Hashes from the output and rangeproof MMRs of an archive node, needed to
+/// rebuild the roots committed to by the header of a historical block from
+/// the block outputs. A pruned node compacted them away along with the spent
+/// outputs. Hashes are in the order rebuilding the roots reads them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BlockOutputsProof {
+	/// Hashes from the output MMR
+	pub output_hashes: Vec<Hash>,
+	/// Hashes from the rangeproof MMR
+	pub rangeproof_hashes: Vec<Hash>,
+}
+
+fn read_proof_hashes<R: Reader>(reader: &mut R) -> Result<Vec<Hash>, ser::Error> {
+	let len = reader.read_u16()?;
+	if len > MAX_OUTPUTS_PROOF_HASHES {
+		return Err(ser::Error::TooLargeReadErr);
+	}
+	let mut hashes = Vec::with_capacity(len as usize);
+	for _ in 0..len {
+		hashes.push(Hash::read(reader)?);
+	}
+	Ok(hashes)
+}
+
+fn write_proof_hashes<W: Writer>(writer: &mut W, hashes: &[Hash]) -> Result<(), ser::Error> {
+	writer.write_u16(hashes.len() as u16)?;
+	for h in hashes {
+		h.write(writer)?;
+	}
+	Ok(())
+}
+
+impl Readable for BlockOutputsProof {
+	fn read<R: Reader>(reader: &mut R) -> Result<BlockOutputsProof, ser::Error> {
+		let output_hashes = read_proof_hashes(reader)?;
+		let rangeproof_hashes = read_proof_hashes(reader)?;
+		Ok(BlockOutputsProof {
+			output_hashes,
+			rangeproof_hashes,
+		})
+	}
+}
+
+impl Writeable for BlockOutputsProof {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		write_proof_hashes(writer, &self.output_hashes)?;
+		write_proof_hashes(writer, &self.rangeproof_hashes)
+	}
+}
+
 /// The tip of a fork. A handle to the fork ancestry from its leaf in the
 /// blockchain tree. References the max height and the latest and previous
 /// blocks
@@ -405,6 +622,11 @@ pub trait ChainAdapter {
 	/// The blockchain pipeline has accepted this block as valid and added
 	/// it to our chain.
 	fn block_accepted(&self, block: &Block, status: BlockStatus, opts: Options);
+
+	/// A reorg disconnected the blocks with the provided headers from our
+	/// chain, most recent first. Called right before `block_accepted` for the
+	/// block that caused the reorg.
+	fn blocks_disconnected(&self, _headers: &[BlockHeader], _opts: Options) {}
 }
 
 /// Inform the caller of the current status of a txhashset write operation,
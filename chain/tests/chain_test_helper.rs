@@ -17,10 +17,11 @@ use self::chain::types::Options;
 use self::chain::Chain;
 use self::core::core::hash::Hashed;
 use self::core::core::verifier_cache::LruVerifierCache;
-use self::core::core::Block;
+use self::core::core::{Block, BlockHeader, KernelFeatures, Transaction};
 use self::core::genesis;
 use self::core::global::ChainTypes;
-use self::core::libtx::{self, reward};
+use self::core::libtx::{self, build, reward, ProofBuilder};
+use self::core::pow::Difficulty;
 use self::core::{consensus, global, pow};
 use self::keychain::{ExtKeychainPath, Keychain};
 use self::util::RwLock;
@@ -67,6 +68,60 @@ where
 	genesis::genesis_dev().with_reward(reward.0, reward.1)
 }
 
+/// Build a block on top of prev with the provided txs, its reward going to the
+/// key at key_idx. The proof is random so the block needs processing with
+/// Options::SKIP_POW.
+#[allow(dead_code)]
+pub fn prepare_block<K>(
+	kc: &K,
+	prev: &BlockHeader,
+	chain: &Chain,
+	key_idx: u32,
+	txs: &[Transaction],
+) -> Block
+where
+	K: Keychain,
+{
+	let height = prev.height + 1;
+	let key_id = ExtKeychainPath::new(1, key_idx, 0, 0, 0).to_identifier();
+	let fees = txs.iter().map(|tx| tx.fee(height)).sum();
+	let reward = libtx::reward::output(kc, &ProofBuilder::new(kc), &key_id, fees, false).unwrap();
+	let mut b = Block::new(prev, txs, Difficulty::from_num(height), reward).unwrap();
+	b.header.timestamp = prev.timestamp + Duration::seconds(60);
+	b.header.pow.total_difficulty = prev.total_difficulty() + Difficulty::from_num(height);
+	b.header.pow.proof = pow::Proof::random(global::proofsize());
+	chain.set_txhashset_roots(&mut b).unwrap();
+	b
+}
+
+/// Build a tx spending the coinbase outputs of the keys at key_idxs (as
+/// rewarded by prepare_block) into a single output to the key at out_idx.
+#[allow(dead_code)]
+pub fn spend_coinbase<K>(kc: &K, key_idxs: &[u32], out_idx: u32) -> Transaction
+where
+	K: Keychain,
+{
+	let mut parts: Vec<_> = key_idxs
+		.iter()
+		.map(|idx| {
+			let key_id = ExtKeychainPath::new(1, *idx, 0, 0, 0).to_identifier();
+			build::coinbase_input(consensus::REWARD, key_id)
+		})
+		.collect();
+	let key_id = ExtKeychainPath::new(1, out_idx, 0, 0, 0).to_identifier();
+	parts.push(build::output(
+		consensus::REWARD * key_idxs.len() as u64 - 20000,
+		key_id,
+	));
+	build::transaction(
+		KernelFeatures::Plain { fee: 20000.into() },
+		&parts,
+		kc,
+		&ProofBuilder::new(kc),
+	)
+	.unwrap()
+}
+
 /// Mine a chain of specified length to assist with automated tests.
 /// Probably a good idea to call clean_output_dir at the beginning and end of each test.
 #[allow(dead_code)]
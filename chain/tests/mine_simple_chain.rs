@@ -35,14 +35,18 @@ mod chain_test_helper;
 
 use self::chain_test_helper::{clean_output_dir, init_chain, mine_chain};
 
-/// Adapter to retrieve last status
+/// Adapter to retrieve last status and the blocks disconnected by the last reorg
 pub struct StatusAdapter {
 	pub last_status: RwLock<Option<BlockStatus>>,
+	pub disconnected: RwLock<Vec<BlockHeader>>,
 }
 
 impl StatusAdapter {
 	pub fn new(last_status: RwLock<Option<BlockStatus>>) -> Self {
-		StatusAdapter { last_status }
+		StatusAdapter {
+			last_status,
+			disconnected: RwLock::new(vec![]),
+		}
 	}
 }
 
@@ -50,6 +54,10 @@ impl ChainAdapter for StatusAdapter {
 	fn block_accepted(&self, _b: &Block, status: BlockStatus, _opts: Options) {
 		*self.last_status.write() = Some(status);
 	}
+
+	fn blocks_disconnected(&self, headers: &[BlockHeader], _opts: Options) {
+		*self.disconnected.write() = headers.to_vec();
+	}
 }
 
 /// Creates a `Chain` instance with `StatusAdapter` attached to it.
@@ -359,11 +367,14 @@ fn mine_reorg() {
 
 		// Add blocks to main chain with gradually increasing difficulty
 		let mut prev = chain.head_header().unwrap();
+		let mut main_headers = vec![];
 		for n in 1..=NUM_BLOCKS_MAIN {
 			let b = prepare_block(&kc, &prev, &chain, n);
 			prev = b.header.clone();
+			main_headers.push(b.header.clone());
 			chain.process_block(b, chain::Options::SKIP_POW).unwrap();
 		}
+		assert!(adapter.disconnected.read().is_empty());
 
 		let head = chain.head().unwrap();
 		assert_eq!(head.height, NUM_BLOCKS_MAIN);
@@ -391,6 +402,16 @@ fn mine_reorg() {
 			})
 		);
 
+		// The rewound main chain blocks are reported, most recent first
+		let disconnected: Vec<_> = main_headers
+			.iter()
+			.skip((NUM_BLOCKS_MAIN - REORG_DEPTH) as usize)
+			.rev()
+			.cloned()
+			.collect();
+		assert_eq!(disconnected.len(), REORG_DEPTH as usize);
+		assert_eq!(*adapter.disconnected.read(), disconnected);
+
 		// Chain should be switched to the reorganized chain
 		let head = chain.head().unwrap();
 		assert_eq!(head.height, NUM_BLOCKS_MAIN - REORG_DEPTH + 1);
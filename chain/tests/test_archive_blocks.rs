@@ -0,0 +1,174 @@
+// Copyright 2021 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use self::chain::types::NoopAdapter;
+use self::chain::{BlockOutputsProof, Chain, Options};
+use self::core::core::hash::{Hashed, ZERO_HASH};
+use self::core::core::verifier_cache::LruVerifierCache;
+use self::core::core::{Block, KernelFeatures, Transaction};
+use self::core::global::{self, ChainTypes};
+use self::core::libtx::{build, ProofBuilder};
+use self::core::{consensus, pow};
+use self::keychain::{ExtKeychain, ExtKeychainPath, Keychain};
+use self::util::RwLock;
+use grin_chain as chain;
+use grin_core as core;
+use grin_keychain as keychain;
+use grin_util as util;
+use std::sync::Arc;
+
+mod chain_test_helper;
+
+use self::chain_test_helper::{clean_output_dir, genesis_block, init_chain, prepare_block};
+
+const FEE: u32 = 20000;
+
+fn reopen_archive_chain(dir_name: &str, genesis: Block) -> Chain {
+	Chain::init(
+		dir_name.to_string(),
+		Arc::new(NoopAdapter {}),
+		genesis,
+		pow::verify_size,
+		Arc::new(RwLock::new(LruVerifierCache::new())),
+		true,
+	)
+	.unwrap()
+}
+
+/// Spend the coinbase rewarded at height - 4 along the output of the tx in the
+/// previous block (if any), so all but the latest outputs end up spent.
+/// Returns the tx and the amount of its output.
+fn spend_tx(kc: &ExtKeychain, height: u32, prev_amount: u64) -> (Transaction, u64) {
+	let key_id = |idx| ExtKeychainPath::new(1, idx, 0, 0, 0).to_identifier();
+	// Blocks from height 5 include the fees of their tx in the reward.
+	let reward = match height - 4 {
+		1..=4 => consensus::REWARD,
+		_ => consensus::REWARD + FEE as u64,
+	};
+	let mut parts = vec![build::coinbase_input(reward, key_id(height - 4))];
+	if prev_amount > 0 {
+		parts.push(build::input(prev_amount, key_id(1000 + height - 1)));
+	}
+	let amount = reward + prev_amount - FEE as u64;
+	parts.push(build::output(amount, key_id(1000 + height)));
+	let tx = build::transaction(
+		KernelFeatures::Plain { fee: FEE.into() },
+		&parts,
+		kc,
+		&ProofBuilder::new(kc),
+	)
+	.unwrap();
+	(tx, amount)
+}
+
+// Restart a pruned node in archive mode and fill in the missing historical blocks
+// from an archive node, proving their outputs against the compacted MMRs.
+#[test]
+fn archive_sync_historical_blocks() {
+	util::init_test_logger();
+	global::set_local_chain_type(ChainTypes::AutomatedTesting);
+	let archive_dir = ".grin.archive_blocks_src";
+	let chain_dir = ".grin.archive_blocks";
+	clean_output_dir(archive_dir);
+	clean_output_dir(chain_dir);
+
+	let kc = ExtKeychain::from_random_seed(false).unwrap();
+	let genesis = genesis_block(&kc);
+	let archive = init_chain(archive_dir, genesis.clone());
+
+	let blocks = {
+		let chain = init_chain(chain_dir, genesis.clone());
+		let mut blocks = vec![];
+		let mut amount = 0;
+		for height in 1..100 {
+			let mut txs = vec![];
+			if height > 4 {
+				let (tx, tx_amount) = spend_tx(&kc, height, amount);
+				txs.push(tx);
+				amount = tx_amount;
+			}
+			let prev = archive.head_header().unwrap();
+			let b = prepare_block(&kc, &prev, &archive, height, &txs);
+			archive.process_block(b.clone(), Options::SKIP_POW).unwrap();
+			chain.process_block(b.clone(), Options::SKIP_POW).unwrap();
+			blocks.push(b);
+		}
+
+		// Compact the chain, removing blocks beyond the horizon (20 blocks) and
+		// the hashes of the spent outputs below them.
+		chain.compact().unwrap();
+		assert_eq!(chain.tail().unwrap().height, 79);
+		assert!(!chain.block_exists(blocks[0].hash()).unwrap());
+		assert!(chain.historical_blocks_to_sync(10).unwrap().is_empty());
+		assert!(chain.get_block_outputs_proof(&blocks[10]).is_err());
+		blocks
+	};
+
+	{
+		let chain = reopen_archive_chain(chain_dir, genesis);
+		let missing = chain.historical_blocks_to_sync(10).unwrap();
+		let expected: Vec<_> = blocks[68..78].iter().rev().map(|b| b.hash()).collect();
+		assert_eq!(missing, expected);
+
+		let process = |b: &Block| {
+			let proof = archive.get_block_outputs_proof(b).unwrap();
+			chain.process_historical_block(b, &proof)
+		};
+
+		// A block body that does not match its header is rejected.
+		let bad_block = Block {
+			header: blocks[10].header.clone(),
+			body: blocks[11].body.clone(),
+		};
+		let proof = archive.get_block_outputs_proof(&blocks[10]).unwrap();
+		let res = chain.process_historical_block(&bad_block, &proof);
+		assert!(res.unwrap_err().is_bad_data());
+
+		// So is a block with a proof that does not match its outputs, on either
+		// side of the header version 3 hard fork.
+		for b in &blocks[1..10] {
+			let mut proof = archive.get_block_outputs_proof(b).unwrap();
+			proof.rangeproof_hashes[0] = ZERO_HASH;
+			let res = chain.process_historical_block(b, &proof);
+			assert!(res.unwrap_err().is_bad_data());
+			let mut proof = archive.get_block_outputs_proof(b).unwrap();
+			proof.output_hashes.pop();
+			let res = chain.process_historical_block(b, &proof);
+			assert!(res.unwrap_err().is_bad_data());
+		}
+		let res = chain.process_historical_block(&blocks[10], &BlockOutputsProof::default());
+		assert!(res.unwrap_err().is_bad_data());
+
+		// The tail only moves back once we have contiguous full blocks.
+		for b in &blocks[9..40] {
+			process(b).unwrap();
+		}
+		assert_eq!(chain.tail().unwrap().height, 79);
+		for b in blocks[40..78].iter().rev() {
+			process(b).unwrap();
+		}
+		assert_eq!(chain.tail().unwrap().height, 10);
+		for b in &blocks[..9] {
+			process(b).unwrap();
+		}
+		assert_eq!(chain.tail().unwrap().height, 0);
+		assert!(chain.historical_blocks_to_sync(10).unwrap().is_empty());
+
+		// Blocks above the tail are not historical.
+		assert!(process(&blocks[98]).is_err());
+	}
+
+	clean_output_dir(archive_dir);
+	clean_output_dir(chain_dir);
+}
@@ -0,0 +1,76 @@
+// Copyright 2021 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use self::chain::Chain;
+use self::core::core::hash::Hashed;
+use self::core::core::verifier_cache::LruVerifierCache;
+use self::core::pow;
+use self::util::RwLock;
+use grin_chain as chain;
+use grin_core as core;
+use grin_util as util;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+mod chain_test_helper;
+
+use self::chain_test_helper::{clean_output_dir, mine_chain};
+
+// Throw away the txhashset of a chain and rebuild it from the blocks in the db.
+#[test]
+fn reindex_chain_from_local_blocks() {
+	util::init_test_logger();
+	let chain_dir = ".grin.reindex";
+	clean_output_dir(chain_dir);
+
+	let (genesis, head, header) = {
+		let chain = mine_chain(chain_dir, 30);
+		let genesis_hash = chain.get_header_by_height(0).unwrap().hash();
+		let genesis = chain.get_block(&genesis_hash).unwrap();
+		(genesis, chain.head().unwrap(), chain.head_header().unwrap())
+	};
+
+	// Simulate a corrupted txhashset.
+	let kernel_dir = Path::new(chain_dir).join("txhashset").join("kernel");
+	fs::remove_dir_all(&kernel_dir).unwrap();
+
+	{
+		let replayed = AtomicUsize::new(0);
+		let chain = Chain::reindex(
+			chain_dir.to_string(),
+			genesis,
+			pow::verify_size,
+			Arc::new(RwLock::new(LruVerifierCache::new())),
+			false,
+			|height, total| {
+				assert_eq!(total, 29);
+				assert_eq!(replayed.fetch_add(1, Ordering::Relaxed) as u64 + 1, height);
+			},
+		)
+		.unwrap();
+		assert_eq!(replayed.load(Ordering::Relaxed), 29);
+		assert_eq!(chain.head().unwrap(), head);
+		assert_eq!(chain.header_head().unwrap(), head);
+		assert_eq!(chain.head_header().unwrap(), header);
+		chain.validate(false).unwrap();
+
+		// The new state was rebuilt in a sandbox and swapped in.
+		assert!(!Path::new(chain_dir).join("reindex").exists());
+		assert!(!Path::new(chain_dir).join("reindex_backup").exists());
+	}
+
+	clean_output_dir(chain_dir);
+}
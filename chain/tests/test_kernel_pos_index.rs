@@ -0,0 +1,121 @@
+// Copyright 2021 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use self::chain::linked_list::ListIndex;
+use self::chain::{store, Chain, Options};
+use self::core::core::{BlockHeader, Transaction};
+use self::core::global::ChainTypes;
+use self::core::{global, pow};
+use self::keychain::{ExtKeychain, Keychain};
+use self::util::secp::pedersen::Commitment;
+use grin_chain as chain;
+use grin_core as core;
+use grin_keychain as keychain;
+use grin_util as util;
+
+mod chain_test_helper;
+
+use self::chain_test_helper::{clean_output_dir, init_chain, prepare_block, spend_coinbase};
+
+fn process_block<K>(
+	kc: &K,
+	chain: &Chain,
+	prev: &BlockHeader,
+	key_idx: u32,
+	txs: &[Transaction],
+) -> BlockHeader
+where
+	K: Keychain,
+{
+	let b = prepare_block(kc, prev, chain, key_idx, txs);
+	chain.process_block(b.clone(), Options::SKIP_POW).unwrap();
+	b.header
+}
+
+fn indexed_height(chain: &Chain, excess: Commitment) -> Option<u64> {
+	let store = chain.store();
+	let batch = store.batch().unwrap();
+	store::kernel_pos_index()
+		.peek_pos(&batch, excess)
+		.unwrap()
+		.map(|pos| pos.height)
+}
+
+#[test]
+fn kernel_pos_index() {
+	global::set_local_chain_type(ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+	let chain_dir = ".grin.kernel_pos_index";
+	clean_output_dir(chain_dir);
+
+	{
+		let kc = ExtKeychain::from_random_seed(false).unwrap();
+		let genesis = pow::mine_genesis_block().unwrap();
+		let mut chain = init_chain(chain_dir, genesis);
+
+		for height in 1..5 {
+			let prev = chain.head_header().unwrap();
+			process_block(&kc, &chain, &prev, height, &[]);
+		}
+		let fork_prev = chain.head_header().unwrap();
+		let tx = spend_coinbase(&kc, &[1], 101);
+		let excess = tx.kernels()[0].excess();
+		process_block(&kc, &chain, &fork_prev, 5, &[tx]);
+		assert_eq!(indexed_height(&chain, excess), None);
+
+		// Build the index for the existing chain.
+		chain.init_kernel_pos_index(true).unwrap();
+		assert!(chain.store().is_kernel_pos_indexed().unwrap());
+		assert_eq!(indexed_height(&chain, excess), Some(5));
+		let (kernel, height, _) = chain
+			.get_kernel_height(&excess, None, None)
+			.unwrap()
+			.unwrap();
+		assert_eq!(kernel.excess(), excess);
+		assert_eq!(height, 5);
+		assert!(chain
+			.get_kernel_height(&excess, Some(6), None)
+			.unwrap()
+			.is_none());
+
+		// Reorg to a fork without this kernel, the index is rewound.
+		let prev = process_block(&kc, &chain, &fork_prev, 1005, &[]);
+		let prev = process_block(&kc, &chain, &prev, 1006, &[]);
+		assert_eq!(chain.head_header().unwrap(), prev);
+		assert_eq!(indexed_height(&chain, excess), None);
+		assert!(chain
+			.get_kernel_height(&excess, None, None)
+			.unwrap()
+			.is_none());
+
+		// New kernels are indexed as blocks are applied.
+		let tx = spend_coinbase(&kc, &[2], 102);
+		let excess = tx.kernels()[0].excess();
+		process_block(&kc, &chain, &prev, 7, &[tx]);
+		assert_eq!(indexed_height(&chain, excess), Some(7));
+
+		// Disabling the index means rebuilding it when enabled again.
+		chain.init_kernel_pos_index(false).unwrap();
+		assert!(!chain.store().is_kernel_pos_indexed().unwrap());
+		chain.init_kernel_pos_index(true).unwrap();
+		assert_eq!(indexed_height(&chain, excess), Some(7));
+		let (_, height, _) = chain
+			.get_kernel_height(&excess, None, None)
+			.unwrap()
+			.unwrap();
+		assert_eq!(height, 7);
+	}
+
+	clean_output_dir(chain_dir);
+}
@@ -0,0 +1,109 @@
+// Copyright 2021 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use self::chain::{Chain, Options, OutputHistoryEvent};
+use self::core::core::hash::Hashed;
+use self::core::core::{Block, BlockHeader, OutputFeatures};
+use self::core::global::ChainTypes;
+use self::core::{global, pow};
+use self::keychain::{ExtKeychain, Keychain};
+use grin_chain as chain;
+use grin_core as core;
+use grin_keychain as keychain;
+use grin_util as util;
+
+mod chain_test_helper;
+
+use self::chain_test_helper::{clean_output_dir, init_chain, prepare_block, spend_coinbase};
+
+fn process_block<K>(kc: &K, chain: &Chain, prev: &BlockHeader, key_idx: u32) -> Block
+where
+	K: Keychain,
+{
+	let b = prepare_block(kc, prev, chain, key_idx, &[]);
+	chain.process_block(b.clone(), Options::SKIP_POW).unwrap();
+	b
+}
+
+// Follow an output through creation, spend and a reorg undoing the spend.
+#[test]
+fn output_history_index() {
+	global::set_local_chain_type(ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+	let chain_dir = ".grin.output_history";
+	clean_output_dir(chain_dir);
+
+	{
+		let kc = ExtKeychain::from_random_seed(false).unwrap();
+		let genesis = pow::mine_genesis_block().unwrap();
+		let mut chain = init_chain(chain_dir, genesis);
+
+		// Blocks processed before the index is enabled are backfilled.
+		let mut blocks = vec![];
+		for height in 1..6 {
+			let prev = chain.head_header().unwrap();
+			blocks.push(process_block(&kc, &chain, &prev, height));
+		}
+		let commit = blocks[0].outputs()[0].commitment();
+		assert!(chain.get_output_history(&commit).is_err());
+		chain.init_output_history(true).unwrap();
+
+		let history = chain.get_output_history(&commit).unwrap();
+		assert_eq!(history.len(), 1);
+		assert_eq!(history[0].block_hash, blocks[0].hash());
+		assert_eq!(history[0].height, 1);
+		assert_eq!(
+			history[0].event,
+			OutputHistoryEvent::Created(OutputFeatures::Coinbase)
+		);
+
+		// Spend the coinbase output from block 1.
+		let tx = spend_coinbase(&kc, &[1], 100);
+		let fork_prev = chain.head_header().unwrap();
+		let spend_block = prepare_block(&kc, &fork_prev, &chain, 6, std::slice::from_ref(&tx));
+		chain
+			.process_block(spend_block.clone(), Options::SKIP_POW)
+			.unwrap();
+
+		let history = chain.get_output_history(&commit).unwrap();
+		assert_eq!(history.len(), 2);
+		assert_eq!(history[1].block_hash, spend_block.hash());
+		assert_eq!(history[1].height, 6);
+		assert_eq!(
+			history[1].event,
+			OutputHistoryEvent::Spent(vec![tx.kernels()[0].excess()])
+		);
+
+		// The new output from the spending tx is in the index too.
+		let new_commit = tx.outputs()[0].commitment();
+		let history = chain.get_output_history(&new_commit).unwrap();
+		assert_eq!(history.len(), 1);
+		assert_eq!(
+			history[0].event,
+			OutputHistoryEvent::Created(OutputFeatures::Plain)
+		);
+
+		// Reorg to a fork that does not spend it.
+		let fork_block = process_block(&kc, &chain, &fork_prev, 1006);
+		process_block(&kc, &chain, &fork_block.header, 1007);
+		assert_eq!(chain.head().unwrap().height, 7);
+
+		let history = chain.get_output_history(&commit).unwrap();
+		assert_eq!(history.len(), 1);
+		assert_eq!(history[0].block_hash, blocks[0].hash());
+		assert!(chain.get_output_history(&new_commit).unwrap().is_empty());
+	}
+
+	clean_output_dir(chain_dir);
+}
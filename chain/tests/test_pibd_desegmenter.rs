@@ -0,0 +1,208 @@
+// Copyright 2021 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use self::chain::txhashset::{SegmentType, Segmenter};
+use self::chain::types::NoStatus;
+use self::chain::{Chain, Options};
+use self::core::core::hash::Hashed;
+use self::core::core::Block;
+use self::core::global::ChainTypes;
+use self::core::{global, pow};
+use self::keychain::{ExtKeychain, Keychain};
+use grin_chain as chain;
+use grin_core as core;
+use grin_keychain as keychain;
+use grin_util as util;
+
+mod chain_test_helper;
+
+use self::chain_test_helper::{clean_output_dir, init_chain, prepare_block, spend_coinbase};
+
+// Mine a chain spending a few coinbase outputs so the output and rangeproof MMRs
+// have pruned leaves below the archive header.
+fn mine_src_chain(dir: &str, genesis: Block) -> (Chain, Vec<Block>) {
+	let src = init_chain(dir, genesis);
+	let kc = ExtKeychain::from_random_seed(false).unwrap();
+	let mut blocks = vec![];
+	for height in 1..36 {
+		let txs = match height {
+			6 => vec![spend_coinbase(&kc, &[1], 100)],
+			8 => vec![spend_coinbase(&kc, &[2, 4], 101)],
+			_ => vec![],
+		};
+		let prev = src.head_header().unwrap();
+		let b = prepare_block(&kc, &prev, &src, height, &txs);
+		src.process_block(b.clone(), Options::SKIP_POW).unwrap();
+		blocks.push(b);
+	}
+	(src, blocks)
+}
+
+// Request and apply the desired segments of the provided types, as state sync would,
+// until there are none left.
+fn apply_segments(dest: &Chain, segmenter: &Segmenter, types: &[SegmentType]) {
+	let desegmenter = dest.desegmenter();
+	loop {
+		let desired: Vec<_> = desegmenter
+			.read()
+			.as_ref()
+			.unwrap()
+			.next_desired_segments()
+			.into_iter()
+			.filter(|id| types.contains(&id.segment_type))
+			.collect();
+		if desired.is_empty() {
+			break;
+		}
+		let mut desegmenter = desegmenter.write();
+		let desegmenter = desegmenter.as_mut().unwrap();
+		for id in desired {
+			match id.segment_type {
+				SegmentType::Bitmap => {
+					let (segment, output_root) = segmenter.bitmap_segment(id.identifier).unwrap();
+					desegmenter
+						.add_bitmap_segment(segment, output_root)
+						.unwrap();
+				}
+				SegmentType::Output => {
+					let (segment, _) = segmenter.output_segment(id.identifier).unwrap();
+					desegmenter.add_output_segment(segment).unwrap();
+				}
+				SegmentType::RangeProof => {
+					let segment = segmenter.rangeproof_segment(id.identifier).unwrap();
+					desegmenter.add_rangeproof_segment(segment).unwrap();
+				}
+				SegmentType::Kernel => {
+					let segment = segmenter.kernel_segment(id.identifier).unwrap();
+					desegmenter.add_kernel_segment(segment).unwrap();
+				}
+			}
+		}
+	}
+}
+
+const ALL_SEGMENT_TYPES: [SegmentType; 4] = [
+	SegmentType::Bitmap,
+	SegmentType::Output,
+	SegmentType::RangeProof,
+	SegmentType::Kernel,
+];
+
+// Rebuild the txhashset of one chain from the PIBD segments served by another,
+// then body sync the remaining blocks.
+#[test]
+fn rebuild_txhashset_from_segments() {
+	global::set_local_chain_type(ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+	let src_dir = ".grin_pibd_src";
+	let dest_dir = ".grin_pibd_dest";
+	clean_output_dir(src_dir);
+	clean_output_dir(dest_dir);
+
+	{
+		let genesis = pow::mine_genesis_block().unwrap();
+		let (src, blocks) = mine_src_chain(src_dir, genesis.clone());
+
+		let segmenter = src.segmenter().unwrap();
+		let archive_header = segmenter.header().clone();
+		assert_eq!(archive_header.height, 10);
+
+		let dest = init_chain(dest_dir, genesis);
+		let headers: Vec<_> = blocks.iter().map(|b| b.header.clone()).collect();
+		dest.sync_block_headers(&headers, Options::SKIP_POW)
+			.unwrap();
+
+		dest.init_desegmenter(&archive_header).unwrap();
+		apply_segments(&dest, &segmenter, &ALL_SEGMENT_TYPES);
+		let desegmenter = dest.desegmenter();
+		assert!(desegmenter.read().as_ref().unwrap().is_complete());
+
+		dest.finalize_desegmenter(&NoStatus).unwrap();
+		assert!(desegmenter.read().is_none());
+		assert_eq!(dest.head().unwrap().last_block_h, archive_header.hash());
+		dest.validate(false).unwrap();
+
+		// Now body sync the blocks after the archive header.
+		for b in blocks.into_iter().skip(archive_header.height as usize) {
+			dest.process_block(b, Options::SKIP_POW).unwrap();
+		}
+		assert_eq!(dest.head().unwrap(), src.head().unwrap());
+		dest.validate(false).unwrap();
+	}
+
+	clean_output_dir(src_dir);
+	clean_output_dir(dest_dir);
+}
+
+// Interrupt PIBD partway through and check we resume from the saved progress
+// after a restart.
+#[test]
+fn resume_txhashset_from_segments() {
+	global::set_local_chain_type(ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+	// Keep our PIBD sandbox (in the tmp dir next to the db) apart from the one above.
+	let test_dir = ".grin_pibd_resume";
+	let src_dir = ".grin_pibd_resume/src";
+	let dest_dir = ".grin_pibd_resume/dest";
+	clean_output_dir(test_dir);
+
+	{
+		let genesis = pow::mine_genesis_block().unwrap();
+		let (src, blocks) = mine_src_chain(src_dir, genesis.clone());
+		let segmenter = src.segmenter().unwrap();
+		let archive_header = segmenter.header().clone();
+
+		{
+			let dest = init_chain(dest_dir, genesis.clone());
+			let headers: Vec<_> = blocks.iter().map(|b| b.header.clone()).collect();
+			dest.sync_block_headers(&headers, Options::SKIP_POW)
+				.unwrap();
+			dest.init_desegmenter(&archive_header).unwrap();
+			apply_segments(
+				&dest,
+				&segmenter,
+				&[SegmentType::Bitmap, SegmentType::Kernel],
+			);
+		}
+
+		// Restart
+		let dest = init_chain(dest_dir, genesis);
+		let progress = dest.store().get_pibd_progress().unwrap().unwrap();
+		assert_eq!(progress.archive_header_hash, archive_header.hash());
+		assert_eq!(progress.bitmap_segments, 1);
+		assert_eq!(progress.kernel_segments, 1);
+		assert_eq!(progress.output_segments, 0);
+		assert_eq!(progress.kernel_mmr_size, archive_header.kernel_mmr_size);
+
+		dest.init_desegmenter(&archive_header).unwrap();
+		{
+			let desegmenter = dest.desegmenter();
+			let desegmenter = desegmenter.read();
+			let desegmenter = desegmenter.as_ref().unwrap();
+			assert_eq!(desegmenter.progress(), progress);
+			assert!(desegmenter
+				.next_desired_segments()
+				.iter()
+				.all(|id| id.segment_type == SegmentType::Output
+					|| id.segment_type == SegmentType::RangeProof));
+		}
+		apply_segments(&dest, &segmenter, &ALL_SEGMENT_TYPES);
+		dest.finalize_desegmenter(&NoStatus).unwrap();
+		assert!(dest.store().get_pibd_progress().unwrap().is_none());
+		assert_eq!(dest.head().unwrap().last_block_h, archive_header.hash());
+		dest.validate(false).unwrap();
+	}
+
+	clean_output_dir(test_dir);
+}
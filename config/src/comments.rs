@@ -121,6 +121,27 @@ fn comments() -> HashMap<String, String> {
 		"archive_mode".to_string(),
 		"
 #run the node in \"full archive\" mode (default is fast-sync, pruned node)
+#a pruned node restarted in archive mode fetches its missing historical blocks from archive peers
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"output_history_index".to_string(),
+		"
+#maintain an index of the blocks creating and spending each output commitment
+#used by the get_output_history foreign api, best combined with archive_mode
+#the index is backfilled from the full blocks we have when first enabled
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"kernel_excess_index".to_string(),
+		"
+#maintain an index of kernel excess commitments, speeding up get_kernel lookups
+#without min_height/max_height hints (e.g. wallets verifying payment proofs)
+#the index is built from the kernel MMR on startup when first enabled
 "
 		.to_string(),
 	);
@@ -189,6 +210,33 @@ fn comments() -> HashMap<String, String> {
 		.to_string(),
 	);
 
+	retval.insert(
+		"max_attempts".to_string(),
+		"
+#Webhook notifications are queued on disk and retried until the endpoint
+#accepts them, in order for each url. After this many failed attempts a
+#notification is moved to the dead letter list, which can be inspected and
+#replayed via the owner api.
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"retry_base_delay".to_string(),
+		"
+#The delay in seconds before the first retry, doubling on each further retry.
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"retry_max_delay".to_string(),
+		"
+#The maximum delay in seconds between two retries.
+"
+		.to_string(),
+	);
+
 	retval.insert(
 		"[server.dandelion_config]".to_string(),
 		"
@@ -312,6 +360,27 @@ fn comments() -> HashMap<String, String> {
 # A preferred dandelion_peer, mainly used for testing dandelion
 # dandelion_peer = \"10.0.0.1:13144\"
 
+#encrypt the transport with peers supporting it, falling back to plaintext otherwise
+#this is opportunistic, unauthenticated encryption: it hides the traffic from passive
+#observers but peers are not authenticated, an active attacker in the middle of a
+#connection can still read it or have it silently fall back to plaintext
+#encrypted_transport = false
+
+#SOCKS5 proxy (i.e. Tor) to use for all outbound peer connections and dns seed
+#lookups, also allows connecting to onion peers
+#socks_proxy = \"127.0.0.1:9050\"
+
+#our onion service address, advertised to peers supporting it once connected
+#onion_address = \"<56 base32 chars>.onion:3414\"
+
+#bandwidth limits in bytes per second, across all peers and for any single peer,
+#unlimited by default. All messages count towards the limits, headers and
+#transactions being sent ahead of blocks, segments and txhashset archives.
+#upload_limit = 1000000
+#download_limit = 1000000
+#peer_upload_limit = 250000
+#peer_download_limit = 250000
+
 #########################################
 ### MEMPOOL CONFIGURATION             ###
 #########################################
@@ -361,6 +430,22 @@ fn comments() -> HashMap<String, String> {
 		.to_string(),
 	);
 
+	retval.insert(
+		"persist_pool".to_string(),
+		"
+#save the pool transactions on shutdown and restore them on startup
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"persist_period".to_string(),
+		"
+#period (in minutes) between saves of the pool transactions, 0 to only save on shutdown
+"
+		.to_string(),
+	);
+
 	retval.insert(
 		"[server.stratum_mining_config]".to_string(),
 		"
@@ -404,10 +489,32 @@ fn comments() -> HashMap<String, String> {
 		.to_string(),
 	);
 
+	retval.insert(
+		"vardiff_retarget_time".to_string(),
+		"
+#if set, the share difficulty of each worker is adjusted (starting from
+#minimum_share_difficulty) so it submits this many shares per minute
+#vardiff_shares_per_minute = 6
+
+#how often, in seconds, the share difficulty of each worker is adjusted
+"
+		.to_string(),
+	);
+
 	retval.insert(
 		"wallet_listener_url".to_string(),
 		"
 #the wallet receiver to which coinbase rewards will be sent
+
+#file restricting mining to the logins it lists, one per line as
+#\"login password [wallet_listener_url]\", the rewards of the blocks found
+#by a login go to its own wallet receiver if it has one
+#credentials_file = \"stratum_credentials\"
+
+#path of TLS certificate file, workers connect over TLS if set
+#tls_certificate_file = \"\"
+#private key for the TLS certificate
+#tls_certificate_key = \"\"
 "
 		.to_string(),
 	);
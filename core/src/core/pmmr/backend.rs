@@ -29,6 +29,12 @@ pub trait Backend<T: PMMRable> {
 	/// help the implementation.
 	fn append(&mut self, data: &T, hashes: &[Hash]) -> Result<(), String>;
 
+	/// Append the root hash of a subtree that was pruned and compacted elsewhere
+	/// (we never had the data beneath it), followed by the parent hashes this
+	/// completes. The position of the subtree root is provided so the pruned
+	/// positions beneath it can be accounted for.
+	fn append_pruned_subtree(&mut self, pos: u64, hashes: &[Hash]) -> Result<(), String>;
+
 	/// Rewind the backend state to a previous position, as if all append
 	/// operations after that had been canceled. Expects a position in the PMMR
 	/// to rewind to as well as bitmaps representing the positions added and
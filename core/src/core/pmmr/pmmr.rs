@@ -242,6 +242,39 @@ where
 		Ok(elmt_pos)
 	}
 
+	/// Push the root hash of a pruned subtree onto the MMR, as we do when
+	/// rebuilding a prunable MMR from segments. The subtree must start right
+	/// after the current last position. Parent hashes are added as necessary
+	/// to build the associated peak, exactly as in `push`.
+	pub fn push_pruned_subtree(&mut self, hash: Hash, pos: u64) -> Result<(), String> {
+		if bintree_leftmost(pos) != self.last_pos + 1 {
+			return Err(format!(
+				"pruned subtree at {} does not start after last pos {}",
+				pos, self.last_pos
+			));
+		}
+
+		let mut current_hash = hash;
+		let mut hashes = vec![current_hash];
+		let mut current = pos;
+
+		// hash with all immediately preceding peaks while we are a right sibling
+		while !is_left_sibling(current) {
+			let (parent, sibling) = family(current);
+			let left_hash = self
+				.backend
+				.get_peak_from_file(sibling)
+				.ok_or("missing left sibling in tree, should not have been pruned")?;
+			current_hash = (left_hash, current_hash).hash_with_index(parent - 1);
+			hashes.push(current_hash);
+			current = parent;
+		}
+
+		self.backend.append_pruned_subtree(pos, &hashes)?;
+		self.last_pos = current;
+		Ok(())
+	}
+
 	/// Saves a snapshot of the MMR tagged with the block hash.
 	/// Specifically - snapshots the utxo file as we need this rewound before
 	/// sending the txhashset zip file to another node for fast-sync.
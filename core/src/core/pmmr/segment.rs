@@ -46,7 +46,7 @@ impl fmt::Display for SegmentError {
 }
 
 /// Tuple that defines a segment of a given PMMR
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
 pub struct SegmentIdentifier {
 	/// Height of a segment
 	pub height: u8,
@@ -822,6 +822,27 @@ pub enum Weighting {
 	NoLimit,
 }
 
+impl Weighting {
+	/// Max weight allowed with this weighting, None if not limited.
+	///
+	/// Note: Taking a max tx and building a block from it we need to allow room
+	/// for the additional coinbase reward (1 output + 1 kernel).
+	pub fn max_weight(&self) -> Option<u64> {
+		// A coinbase reward is a single output and a single kernel (for now).
+		// We need to account for this when verifying max tx weights.
+		let coinbase_weight = consensus::OUTPUT_WEIGHT + consensus::KERNEL_WEIGHT;
+
+		match *self {
+			Weighting::AsTransaction => Some(global::max_tx_weight()),
+			Weighting::AsLimitedTransaction(max_weight) => {
+				Some(min(global::max_block_weight(), max_weight).saturating_sub(coinbase_weight))
+			}
+			Weighting::AsBlock => Some(global::max_block_weight()),
+			Weighting::NoLimit => None,
+		}
+	}
+}
+
 /// TransactionBody is a common abstraction for transaction and block
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct TransactionBody {
@@ -1111,28 +1132,14 @@ impl TransactionBody {
 	/// Verify the body is not too big in terms of number of inputs|outputs|kernels.
 	/// Weight rules vary depending on the "weight type" (block or tx or pool).
 	fn verify_weight(&self, weighting: Weighting) -> Result<(), Error> {
-		// A coinbase reward is a single output and a single kernel (for now).
-		// We need to account for this when verifying max tx weights.
-		let coinbase_weight = consensus::OUTPUT_WEIGHT + consensus::KERNEL_WEIGHT;
-
 		// If "tx" body then remember to reduce the max_block_weight by the weight of a kernel.
 		// If "limited tx" then compare against the provided max_weight.
 		// If "block" body then verify weight based on full set of inputs|outputs|kernels.
 		// If "pool" body then skip weight verification (pool can be larger than single block).
-		//
-		// Note: Taking a max tx and building a block from it we need to allow room
-		// for the additional coinbase reward (1 output + 1 kernel).
-		//
-		let max_weight = match weighting {
-			Weighting::AsTransaction => global::max_tx_weight(),
-			Weighting::AsLimitedTransaction(max_weight) => {
-				min(global::max_block_weight(), max_weight).saturating_sub(coinbase_weight)
-			}
-			Weighting::AsBlock => global::max_block_weight(),
-			Weighting::NoLimit => {
-				// We do not verify "tx as pool" weight so we are done here.
-				return Ok(());
-			}
+		let max_weight = match weighting.max_weight() {
+			Some(max_weight) => max_weight,
+			// We do not verify "tx as pool" weight so we are done here.
+			None => return Ok(()),
 		};
 
 		if self.weight() > max_weight {
@@ -0,0 +1,127 @@
+// Copyright 2021 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Upload and download bandwidth limits, global and per peer.
+
+use crate::types::P2PConfig;
+use crate::util::Mutex;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Token bucket limiting transfers to a number of bytes per second, allowing
+/// bursts of up to a second worth of transfers.
+pub struct RateLimiter {
+	rate: u64,
+	allowance: f64,
+	last_update: Instant,
+}
+
+impl RateLimiter {
+	/// A new limiter, rate in bytes per second.
+	pub fn new(rate: u64) -> RateLimiter {
+		RateLimiter {
+			rate,
+			allowance: rate as f64,
+			last_update: Instant::now(),
+		}
+	}
+
+	/// Accounts for the transfer of the provided number of bytes, returning
+	/// how long to wait for it to fit within our rate.
+	pub fn consume(&mut self, bytes: u64) -> Duration {
+		self.consume_at(bytes, Instant::now())
+	}
+
+	/// Accounts for the transfer of the provided number of bytes at the
+	/// provided instant, returning the debt built up beyond our rate.
+	pub fn consume_at(&mut self, bytes: u64, now: Instant) -> Duration {
+		let elapsed = now.duration_since(self.last_update).as_secs_f64();
+		self.last_update = now;
+
+		let rate = self.rate as f64;
+		self.allowance = (self.allowance + elapsed * rate).min(rate) - bytes as f64;
+		if self.allowance >= 0.0 {
+			Duration::from_secs(0)
+		} else {
+			Duration::from_secs_f64(-self.allowance / rate)
+		}
+	}
+}
+
+/// Limiters a transfer direction of a connection is subject to: its own and
+/// the global one, shared with all other connections.
+#[derive(Clone, Default)]
+pub struct Limits {
+	limiters: Vec<Arc<Mutex<RateLimiter>>>,
+}
+
+impl Limits {
+	/// Accounts for the transfer of the provided number of bytes, returning
+	/// how long to wait for it to fit within all limits.
+	pub fn delay(&self, bytes: u64) -> Duration {
+		self.limiters
+			.iter()
+			.map(|l| l.lock().consume(bytes))
+			.max()
+			.unwrap_or_default()
+	}
+
+	/// Blocks until the transfer of the provided number of bytes fits within
+	/// all limits.
+	pub fn throttle(&self, bytes: u64) {
+		let delay = self.delay(bytes);
+		if delay > Duration::from_secs(0) {
+			thread::sleep(delay);
+		}
+	}
+}
+
+/// Bandwidth limits from our config, handing out the limits of each new
+/// connection. Limits are in bytes per second, unset or 0 meaning unlimited.
+pub struct Bandwidth {
+	upload: Option<Arc<Mutex<RateLimiter>>>,
+	download: Option<Arc<Mutex<RateLimiter>>>,
+	peer_upload: Option<u64>,
+	peer_download: Option<u64>,
+}
+
+impl Bandwidth {
+	pub fn new(config: &P2PConfig) -> Bandwidth {
+		let limiter = |rate: Option<u64>| {
+			rate.filter(|r| *r > 0)
+				.map(|r| Arc::new(Mutex::new(RateLimiter::new(r))))
+		};
+		Bandwidth {
+			upload: limiter(config.upload_limit),
+			download: limiter(config.download_limit),
+			peer_upload: config.peer_upload_limit.filter(|r| *r > 0),
+			peer_download: config.peer_download_limit.filter(|r| *r > 0),
+		}
+	}
+
+	/// Upload and download limits of a new connection.
+	pub fn limits(&self) -> (Limits, Limits) {
+		let limits = |global: &Option<Arc<Mutex<RateLimiter>>>, peer: Option<u64>| {
+			let mut limiters = vec![];
+			limiters.extend(global.clone());
+			limiters.extend(peer.map(|r| Arc::new(Mutex::new(RateLimiter::new(r)))));
+			Limits { limiters }
+		};
+		(
+			limits(&self.upload, self.peer_upload),
+			limits(&self.download, self.peer_download),
+		)
+	}
+}
@@ -23,6 +23,7 @@
 use crate::core::global::header_size_bytes;
 use crate::core::ser::{BufReader, ProtocolVersion, Readable};
 use crate::msg::{Message, MsgHeader, MsgHeaderWrapper, Type};
+use crate::noise::FrameReader;
 use crate::types::{AttachmentMeta, AttachmentUpdate, Error};
 use crate::{
 	core::core::block::{BlockHeader, UntrustedBlockHeader},
@@ -65,14 +66,14 @@ impl State {
 
 pub struct Codec {
 	pub version: ProtocolVersion,
-	stream: TcpStream,
+	stream: FrameReader<TcpStream>,
 	buffer: BytesMut,
 	state: State,
 	bytes_read: usize,
 }
 
 impl Codec {
-	pub fn new(version: ProtocolVersion, stream: TcpStream) -> Self {
+	pub fn new(version: ProtocolVersion, stream: FrameReader<TcpStream>) -> Self {
 		Self {
 			version,
 			stream,
@@ -84,7 +85,7 @@ impl Codec {
 
 	/// Destroy the codec and return the reader
 	pub fn stream(self) -> TcpStream {
-		self.stream
+		self.stream.into_inner()
 	}
 
 	/// Inform codec next `len` bytes are an attachment
@@ -119,7 +120,7 @@ impl Codec {
 			None => HEADER_IO_TIMEOUT,
 			_ => BODY_IO_TIMEOUT,
 		};
-		self.stream.set_read_timeout(Some(timeout))?;
+		self.stream.get_ref().set_read_timeout(Some(timeout))?;
 		Ok(())
 	}
 
@@ -254,9 +255,16 @@ fn decode_message(
 		Type::TransactionKernel => Message::TransactionKernel(msg.body()?),
 		Type::GetTransaction => Message::GetTransaction(msg.body()?),
 		Type::Transaction => Message::Transaction(msg.body()?),
+		Type::TransactionKernels => Message::TransactionKernels(msg.body()?),
+		Type::GetTransactions => Message::GetTransactions(msg.body()?),
+		Type::Transactions => Message::Transactions(msg.body()?),
+		Type::GetBlockTransactions => Message::GetBlockTransactions(msg.body()?),
+		Type::BlockTransactions => Message::BlockTransactions(msg.body()?),
 		Type::StemTransaction => Message::StemTransaction(msg.body()?),
 		Type::GetBlock => Message::GetBlock(msg.body()?),
 		Type::Block => Message::Block(msg.body()?),
+		Type::GetHistoricalBlock => Message::GetHistoricalBlock(msg.body()?),
+		Type::HistoricalBlock => Message::HistoricalBlock(msg.body()?),
 		Type::GetCompactBlock => Message::GetCompactBlock(msg.body()?),
 		Type::CompactBlock => Message::CompactBlock(msg.body()?),
 		Type::GetHeaders => Message::GetHeaders(msg.body()?),
@@ -273,7 +281,7 @@ fn decode_message(
 		Type::RangeProofSegment => Message::RangeProofSegment(msg.body()?),
 		Type::GetKernelSegment => Message::GetKernelSegment(msg.body()?),
 		Type::KernelSegment => Message::KernelSegment(msg.body()?),
-		Type::Error | Type::Hand | Type::Shake | Type::Headers => {
+		Type::Error | Type::Hand | Type::Shake | Type::KeyExchange | Type::Headers => {
 			return Err(Error::UnexpectedMessage)
 		}
 	};
@@ -20,11 +20,14 @@
 //! forces us to go through some additional gymnastic to loop over the async
 //! stream and make sure we get the right number of bytes out.
 
+use crate::bandwidth::Limits;
 use crate::codec::{Codec, BODY_IO_TIMEOUT};
 use crate::core::ser::ProtocolVersion;
 use crate::msg::{write_message, Consumed, Message, Msg};
+use crate::noise::{FrameReader, FrameWriter, Session};
 use crate::types::Error;
 use crate::util::{RateCounter, RwLock};
+use std::collections::VecDeque;
 use std::fs::File;
 use std::io::{self, Write};
 use std::net::{Shutdown, TcpStream};
@@ -42,6 +45,10 @@ const CHANNEL_TIMEOUT: Duration = Duration::from_millis(1000);
 /// connection. Allows providing an optional response.
 pub trait MessageHandler: Send + 'static {
 	fn consume(&self, message: Message) -> Result<Consumed, Error>;
+
+	/// The peer sent us something we could not make sense of, the connection
+	/// is about to be dropped.
+	fn protocol_violation(&self, e: &Error);
 }
 
 // Macro to simplify the boilerplate around I/O and Grin error handling
@@ -139,18 +146,42 @@ pub struct Tracker {
 	pub sent_bytes: Arc<RwLock<RateCounter>>,
 	/// Bytes we've received.
 	pub received_bytes: Arc<RwLock<RateCounter>>,
+	/// Limits on the bytes we send.
+	upload: Limits,
+	/// Limits on the bytes we receive.
+	download: Limits,
 }
 
 impl Tracker {
 	pub fn new() -> Tracker {
+		Tracker::with_limits(Limits::default(), Limits::default())
+	}
+
+	/// A tracker subject to the provided upload and download limits.
+	pub fn with_limits(upload: Limits, download: Limits) -> Tracker {
 		let received_bytes = Arc::new(RwLock::new(RateCounter::new()));
 		let sent_bytes = Arc::new(RwLock::new(RateCounter::new()));
 		Tracker {
 			received_bytes,
 			sent_bytes,
+			upload,
+			download,
 		}
 	}
 
+	/// Waits as needed for the bytes about to be sent to fit within our upload
+	/// limits. Bulk data only gets sent once nothing else is pending, see
+	/// `SendQueue`.
+	pub fn throttle_sent(&self, size: u64) {
+		self.upload.throttle(size);
+	}
+
+	/// Waits as needed for the bytes we just received to fit within our
+	/// download limits, slowing down the peer.
+	pub fn throttle_received(&self, size: u64) {
+		self.download.throttle(size);
+	}
+
 	pub fn inc_received(&self, size: u64) {
 		self.received_bytes.write().inc(size);
 	}
@@ -170,9 +201,11 @@ impl Tracker {
 
 /// Start listening on the provided connection and wraps it. Does not hang
 /// the current thread, instead just returns a future and the Connection
-/// itself.
+/// itself. Traffic is encrypted if a session was negotiated during the
+/// handshake.
 pub fn listen<H>(
 	stream: TcpStream,
+	session: Option<Session>,
 	version: ProtocolVersion,
 	tracker: Arc<Tracker>,
 	handler: H,
@@ -188,10 +221,18 @@ where
 		send_channel: send_tx,
 	};
 
+	// Split out tcp stream out into separate reader/writer halves.
+	let (sender, receiver) = match session {
+		Some(session) => (Some(session.sender), Some(session.receiver)),
+		None => (None, None),
+	};
+	let reader = FrameReader::new(stream.try_clone()?, receiver);
+	let writer = FrameWriter::new(stream, sender);
+
 	let (reader_thread, writer_thread) = poll(
-		stream,
+		Codec::new(version, reader),
+		writer,
 		conn_handle.clone(),
-		version,
 		handler,
 		send_rx,
 		stopped.clone(),
@@ -209,9 +250,9 @@ where
 }
 
 fn poll<H>(
-	conn: TcpStream,
+	mut codec: Codec,
+	mut writer: FrameWriter<TcpStream>,
 	conn_handle: ConnHandle,
-	version: ProtocolVersion,
 	handler: H,
 	send_rx: mpsc::Receiver<Msg>,
 	stopped: Arc<AtomicBool>,
@@ -220,10 +261,13 @@ fn poll<H>(
 where
 	H: MessageHandler,
 {
-	// Split out tcp stream out into separate reader/writer halves.
-	let reader = conn.try_clone().expect("clone conn for reader failed");
-	let mut writer = conn.try_clone().expect("clone conn for writer failed");
+	let peer_addr = writer
+		.get_ref()
+		.peer_addr()
+		.map(|a| a.to_string())
+		.unwrap_or_else(|_| "?".to_owned());
 	let reader_stopped = stopped.clone();
+	let reader_peer_addr = peer_addr.clone();
 
 	let reader_tracker = tracker.clone();
 	let writer_tracker = tracker;
@@ -231,11 +275,6 @@ where
 	let reader_thread = thread::Builder::new()
 		.name("peer_read".to_string())
 		.spawn(move || {
-			let peer_addr = reader
-				.peer_addr()
-				.map(|a| a.to_string())
-				.unwrap_or_else(|_| "?".to_owned());
-			let mut codec = Codec::new(version, reader);
 			let mut attachment: Option<File> = None;
 			loop {
 				// check the close channel
@@ -260,6 +299,17 @@ where
 					}
 					_ => reader_tracker.inc_received(bytes_read),
 				}
+				reader_tracker.throttle_received(bytes_read);
+
+				if let Err(
+					e @ Error::BadMessage
+					| e @ Error::UnexpectedMessage
+					| e @ Error::MsgLen
+					| e @ Error::Serialization(_),
+				) = &next
+				{
+					handler.protocol_violation(e);
+				}
 
 				let message = match try_break!(next) {
 					Some(Message::Unknown(type_byte)) => {
@@ -274,6 +324,7 @@ where
 							Some(a) => a,
 							None => {
 								error!("Received unexpected attachment chunk");
+								handler.protocol_violation(&Error::UnexpectedMessage);
 								break;
 							}
 						};
@@ -315,31 +366,40 @@ where
 				}
 			}
 
-			debug!("Shutting down reader connection with {}", peer_addr);
+			debug!("Shutting down reader connection with {}", reader_peer_addr);
 			let _ = codec.stream().shutdown(Shutdown::Both);
 		})?;
 
 	let writer_thread = thread::Builder::new()
 		.name("peer_write".to_string())
 		.spawn(move || {
-			let mut retry_send = Err(());
-			let _ = writer.set_write_timeout(Some(BODY_IO_TIMEOUT));
+			let mut queue = SendQueue::default();
+			let _ = writer.get_ref().set_write_timeout(Some(BODY_IO_TIMEOUT));
 			loop {
-				let maybe_data = retry_send.or_else(|_| send_rx.recv_timeout(CHANNEL_TIMEOUT));
-				retry_send = Err(());
-				match maybe_data {
-					Ok(data) => {
-						let written =
-							try_break!(write_message(&mut writer, &data, writer_tracker.clone()));
-						if written.is_none() {
-							retry_send = Ok(data);
+				if queue.is_empty() {
+					match send_rx.recv_timeout(CHANNEL_TIMEOUT) {
+						Ok(data) => queue.push_back(data),
+						Err(RecvTimeoutError::Disconnected) => {
+							debug!("peer_write: mpsc channel disconnected during recv_timeout");
+							break;
 						}
+						Err(RecvTimeoutError::Timeout) => {}
 					}
-					Err(RecvTimeoutError::Disconnected) => {
-						debug!("peer_write: mpsc channel disconnected during recv_timeout");
-						break;
+				}
+				// pick up everything else pending, so it can jump ahead of bulk data
+				while queue.len() < SEND_CHANNEL_CAP {
+					match send_rx.try_recv() {
+						Ok(data) => queue.push_back(data),
+						Err(_) => break,
+					}
+				}
+
+				if let Some(data) = queue.pop_front() {
+					let written =
+						try_break!(write_message(&mut writer, &data, writer_tracker.clone()));
+					if written.is_none() {
+						queue.push_front(data);
 					}
-					Err(RecvTimeoutError::Timeout) => {}
 				}
 
 				// check the close channel
@@ -348,14 +408,47 @@ where
 				}
 			}
 
-			debug!(
-				"Shutting down writer connection with {}",
-				writer
-					.peer_addr()
-					.map(|a| a.to_string())
-					.unwrap_or_else(|_| "?".to_owned())
-			);
-			let _ = writer.shutdown(Shutdown::Both);
+			debug!("Shutting down writer connection with {}", peer_addr);
+			let _ = writer.get_ref().shutdown(Shutdown::Both);
 		})?;
 	Ok((reader_thread, writer_thread))
 }
+
+/// Messages waiting to be sent, bulk data (blocks, segments and txhashset
+/// archives) going out after anything else.
+#[derive(Default)]
+struct SendQueue {
+	urgent: VecDeque<Msg>,
+	bulk: VecDeque<Msg>,
+}
+
+impl SendQueue {
+	fn is_empty(&self) -> bool {
+		self.urgent.is_empty() && self.bulk.is_empty()
+	}
+
+	fn len(&self) -> usize {
+		self.urgent.len() + self.bulk.len()
+	}
+
+	fn push_back(&mut self, msg: Msg) {
+		if msg.is_bulk() {
+			self.bulk.push_back(msg)
+		} else {
+			self.urgent.push_back(msg)
+		}
+	}
+
+	/// Puts back a message we failed to send, to be retried first.
+	fn push_front(&mut self, msg: Msg) {
+		if msg.is_bulk() {
+			self.bulk.push_front(msg)
+		} else {
+			self.urgent.push_front(msg)
+		}
+	}
+
+	fn pop_front(&mut self) -> Option<Msg> {
+		self.urgent.pop_front().or_else(|| self.bulk.pop_front())
+	}
+}
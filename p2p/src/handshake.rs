@@ -12,11 +12,15 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::bandwidth::Bandwidth;
 use crate::conn::Tracker;
 use crate::core::core::hash::Hash;
 use crate::core::pow::Difficulty;
 use crate::core::ser::ProtocolVersion;
-use crate::msg::{read_message, write_message, Hand, Msg, Shake, Type, USER_AGENT};
+use crate::msg::{
+	read_message, read_message_with_body, write_message, Hand, Msg, Shake, Type, USER_AGENT,
+};
+use crate::noise::{handshake_transcript, EphemeralKey, KeyExchange, Session, Transcript};
 use crate::peer::Peer;
 use crate::types::{Capabilities, Direction, Error, P2PConfig, PeerAddr, PeerInfo, PeerLiveInfo};
 use crate::util::RwLock;
@@ -65,6 +69,8 @@ pub struct Handshake {
 	config: P2PConfig,
 	protocol_version: ProtocolVersion,
 	tracker: Arc<Tracker>,
+	/// Bandwidth limits of the connections we establish.
+	pub bandwidth: Bandwidth,
 }
 
 impl Handshake {
@@ -74,6 +80,7 @@ impl Handshake {
 			nonces: Arc::new(RwLock::new(VecDeque::with_capacity(NONCES_CAP))),
 			addrs: Arc::new(RwLock::new(VecDeque::with_capacity(ADDRS_CAP))),
 			genesis,
+			bandwidth: Bandwidth::new(&config),
 			config,
 			protocol_version: ProtocolVersion::local(),
 			tracker: Arc::new(Tracker::new()),
@@ -97,8 +104,9 @@ impl Handshake {
 		capabilities: Capabilities,
 		total_difficulty: Difficulty,
 		self_addr: PeerAddr,
+		peer_addr: PeerAddr,
 		conn: &mut TcpStream,
-	) -> Result<PeerInfo, Error> {
+	) -> Result<(PeerInfo, Option<Session>), Error> {
 		// Set explicit timeouts on the tcp stream for hand/shake messages.
 		// Once the peer is up and running we will set new values for these.
 		// We initiate this connection, writing a Hand message and read a Shake reply.
@@ -107,9 +115,11 @@ impl Handshake {
 
 		// prepare the first part of the handshake
 		let nonce = self.next_nonce();
-		let peer_addr = match conn.peer_addr() {
-			Ok(pa) => PeerAddr(pa),
-			Err(e) => return Err(Error::Connection(e)),
+
+		// Older peers can't read onion addresses, the Hand only carries ip ones.
+		let receiver_addr = match peer_addr {
+			PeerAddr::Ip(_) => peer_addr,
+			PeerAddr::Onion(_) => PeerAddr::Ip(conn.peer_addr()?),
 		};
 
 		let hand = Hand {
@@ -119,7 +129,7 @@ impl Handshake {
 			genesis: self.genesis,
 			total_difficulty,
 			sender_addr: self_addr,
-			receiver_addr: peer_addr,
+			receiver_addr,
 			user_agent: USER_AGENT.to_string(),
 		};
 
@@ -127,7 +137,9 @@ impl Handshake {
 		let msg = Msg::new(Type::Hand, hand, self.protocol_version)?;
 		write_message(conn, &msg, self.tracker.clone())?;
 
-		let shake: Shake = read_message(conn, self.protocol_version, Type::Shake)?;
+		let (shake, shake_body): (Shake, _) =
+			read_message_with_body(conn, self.protocol_version, Type::Shake)?;
+		let transcript = handshake_transcript(msg.body(), &shake_body);
 		if shake.genesis != self.genesis {
 			return Err(Error::GenesisMismatch {
 				us: self.genesis,
@@ -152,16 +164,26 @@ impl Handshake {
 			return Err(Error::ConnectionClose);
 		}
 
+		let session = self.key_exchange(
+			capabilities,
+			peer_info.capabilities,
+			negotiated_version,
+			true,
+			&transcript,
+			conn,
+		)?;
+
 		debug!(
-			"Connected! Cumulative {} offered from {:?}, {:?}, {:?}, {:?}",
+			"Connected! Cumulative {} offered from {:?}, {:?}, {:?}, {:?}, encrypted: {}",
 			shake.total_difficulty.to_num(),
 			peer_info.addr,
 			peer_info.version,
 			peer_info.user_agent,
 			peer_info.capabilities,
+			session.is_some(),
 		);
 		// when more than one protocol version is supported, choosing should go here
-		Ok(peer_info)
+		Ok((peer_info, session))
 	}
 
 	pub fn accept(
@@ -169,14 +191,15 @@ impl Handshake {
 		capab: Capabilities,
 		total_difficulty: Difficulty,
 		conn: &mut TcpStream,
-	) -> Result<PeerInfo, Error> {
+	) -> Result<(PeerInfo, Option<Session>), Error> {
 		// Set explicit timeouts on the tcp stream for hand/shake messages.
 		// Once the peer is up and running we will set new values for these.
 		// We accept an inbound connection, reading a Hand then writing a Shake reply.
 		let _ = conn.set_read_timeout(Some(HAND_READ_TIMEOUT));
 		let _ = conn.set_write_timeout(Some(SHAKE_WRITE_TIMEOUT));
 
-		let hand: Hand = read_message(conn, self.protocol_version, Type::Hand)?;
+		let (hand, hand_body): (Hand, _) =
+			read_message_with_body(conn, self.protocol_version, Type::Hand)?;
 
 		// all the reasons we could refuse this connection for
 		if hand.genesis != self.genesis {
@@ -187,7 +210,7 @@ impl Handshake {
 		} else {
 			// check the nonce to see if we are trying to connect to ourselves
 			let nonces = self.nonces.read();
-			let addr = resolve_peer_addr(hand.sender_addr, &conn);
+			let addr = resolve_peer_addr(hand.sender_addr, conn)?;
 			if nonces.contains(&hand.nonce) {
 				// save ip addresses of ourselves
 				let mut addrs = self.addrs.write();
@@ -205,7 +228,7 @@ impl Handshake {
 		let peer_info = PeerInfo {
 			capabilities: hand.capabilities,
 			user_agent: hand.user_agent,
-			addr: resolve_peer_addr(hand.sender_addr, &conn),
+			addr: resolve_peer_addr(hand.sender_addr, conn)?,
 			version: negotiated_version,
 			live_info: Arc::new(RwLock::new(PeerLiveInfo::new(hand.total_difficulty))),
 			direction: Direction::Inbound,
@@ -230,10 +253,46 @@ impl Handshake {
 
 		let msg = Msg::new(Type::Shake, shake, negotiated_version)?;
 		write_message(conn, &msg, self.tracker.clone())?;
+		let transcript = handshake_transcript(&hand_body, msg.body());
 
-		trace!("Success handshake with {}.", peer_info.addr);
+		let session = self.key_exchange(
+			capab,
+			peer_info.capabilities,
+			negotiated_version,
+			false,
+			&transcript,
+			conn,
+		)?;
+
+		trace!(
+			"Success handshake with {}, encrypted: {}.",
+			peer_info.addr,
+			session.is_some()
+		);
 
-		Ok(peer_info)
+		Ok((peer_info, session))
+	}
+
+	/// Exchange ephemeral keys to encrypt the rest of the connection, if both
+	/// ourselves and the peer support it. Otherwise we stay in plaintext.
+	/// The keys are bound to the transcript of the Hand/Shake exchange.
+	fn key_exchange(
+		&self,
+		ours: Capabilities,
+		theirs: Capabilities,
+		version: ProtocolVersion,
+		initiator: bool,
+		transcript: &Transcript,
+		conn: &mut TcpStream,
+	) -> Result<Option<Session>, Error> {
+		if !ours.contains(Capabilities::ENCRYPTED) || !theirs.contains(Capabilities::ENCRYPTED) {
+			return Ok(None);
+		}
+		let key = EphemeralKey::generate()?;
+		let msg = Msg::new(Type::KeyExchange, key.key_exchange(), version)?;
+		write_message(conn, &msg, self.tracker.clone())?;
+		let peer_key: KeyExchange = read_message(conn, version, Type::KeyExchange)?;
+		Ok(Some(key.agree(&peer_key, initiator, transcript)?))
 	}
 
 	/// Generate a new random nonce and store it in our ring buffer
@@ -250,11 +309,15 @@ impl Handshake {
 }
 
 /// Resolve the correct peer_addr based on the connection and the advertised port.
-fn resolve_peer_addr(advertised: PeerAddr, conn: &TcpStream) -> PeerAddr {
-	let port = advertised.0.port();
-	if let Ok(addr) = conn.peer_addr() {
-		PeerAddr(SocketAddr::new(addr.ip(), port))
-	} else {
-		advertised
+/// A claimed onion address can't be verified, the peer is known by the address
+/// of the connection instead.
+fn resolve_peer_addr(advertised: PeerAddr, conn: &TcpStream) -> Result<PeerAddr, Error> {
+	match (advertised, conn.peer_addr()) {
+		(PeerAddr::Ip(advertised), Ok(addr)) => {
+			Ok(PeerAddr::Ip(SocketAddr::new(addr.ip(), advertised.port())))
+		}
+		(PeerAddr::Onion(_), Ok(addr)) => Ok(PeerAddr::Ip(addr)),
+		(PeerAddr::Ip(_), Err(_)) => Ok(advertised),
+		(PeerAddr::Onion(_), Err(e)) => Err(Error::Connection(e)),
 	}
 }
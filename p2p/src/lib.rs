@@ -36,14 +36,17 @@ extern crate serde_derive;
 #[macro_use]
 extern crate log;
 
+pub mod bandwidth;
 mod codec;
 mod conn;
 pub mod handshake;
 pub mod msg;
+pub mod noise;
 mod peer;
 mod peers;
 mod protocol;
 mod serv;
+pub mod socks;
 mod store;
 pub mod types;
 
@@ -53,6 +56,7 @@ pub use crate::peers::Peers;
 pub use crate::serv::{DummyAdapter, Server};
 pub use crate::store::{PeerData, State};
 pub use crate::types::{
-	Capabilities, ChainAdapter, Direction, Error, P2PConfig, PeerAddr, PeerInfo, ReasonForBan,
-	Seeding, TxHashSetRead, MAX_BLOCK_HEADERS, MAX_LOCATORS, MAX_PEER_ADDRS,
+	Capabilities, ChainAdapter, Direction, Error, OnionAddr, P2PConfig, PeerAddr, PeerInfo,
+	ReasonForBan, ScoreEvent, Seeding, TxHashSetRead, BAN_SCORE, MAX_BLOCK_HEADERS, MAX_LOCATORS,
+	MAX_PEER_ADDRS, MAX_SCORE, MAX_TX_KERNEL_HASHES, REWARD_INTERVAL,
 };
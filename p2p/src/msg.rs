@@ -15,12 +15,13 @@
 //! Message types that transit over the network and related serialization code.
 
 use crate::chain::txhashset::BitmapSegment;
+use crate::chain::{BlockOutputsProof, MAX_OUTPUTS_PROOF_HASHES};
 use crate::conn::Tracker;
 use crate::core::core::hash::Hash;
 use crate::core::core::transaction::{OutputIdentifier, TxKernel};
 use crate::core::core::{
-	BlockHeader, Segment, SegmentIdentifier, Transaction, UntrustedBlock, UntrustedBlockHeader,
-	UntrustedCompactBlock,
+	Block, BlockHeader, Segment, SegmentIdentifier, ShortId, Transaction, UntrustedBlock,
+	UntrustedBlockHeader, UntrustedCompactBlock,
 };
 use crate::core::pow::Difficulty;
 use crate::core::ser::{
@@ -29,7 +30,7 @@ use crate::core::ser::{
 use crate::core::{consensus, global};
 use crate::types::{
 	AttachmentMeta, AttachmentUpdate, Capabilities, Error, PeerAddr, ReasonForBan,
-	MAX_BLOCK_HEADERS, MAX_LOCATORS, MAX_PEER_ADDRS,
+	MAX_BLOCK_HEADERS, MAX_LOCATORS, MAX_PEER_ADDRS, MAX_TX_KERNEL_HASHES, ONION_HOST_LEN,
 };
 use crate::util::secp::pedersen::RangeProof;
 use bytes::Bytes;
@@ -82,6 +83,14 @@ enum_from_primitive! {
 		RangeProofSegment = 26,
 		GetKernelSegment = 27,
 		KernelSegment = 28,
+		TransactionKernels = 29,
+		GetTransactions = 30,
+		Transactions = 31,
+		KeyExchange = 32,
+		GetBlockTransactions = 33,
+		BlockTransactions = 34,
+		GetHistoricalBlock = 35,
+		HistoricalBlock = 36,
 	}
 }
 
@@ -104,7 +113,7 @@ fn max_msg_size(msg_type: Type) -> u64 {
 		Type::Ping => 16,
 		Type::Pong => 16,
 		Type::GetPeerAddrs => 4,
-		Type::PeerAddrs => 4 + (1 + 16 + 2) * MAX_PEER_ADDRS as u64,
+		Type::PeerAddrs => 4 + (1 + ONION_HOST_LEN as u64 + 2) * MAX_PEER_ADDRS as u64,
 		Type::GetHeaders => 1 + 32 * MAX_LOCATORS as u64,
 		Type::Header => 365,
 		Type::Headers => 2 + 365 * MAX_BLOCK_HEADERS as u64,
@@ -127,6 +136,14 @@ fn max_msg_size(msg_type: Type) -> u64 {
 		Type::RangeProofSegment => 2 * max_block_size(),
 		Type::GetKernelSegment => 41,
 		Type::KernelSegment => 2 * max_block_size(),
+		Type::TransactionKernels => 2 + 32 * MAX_TX_KERNEL_HASHES as u64,
+		Type::GetTransactions => 2 + 32 * MAX_TX_KERNEL_HASHES as u64,
+		Type::Transactions => 2 * max_block_size(),
+		Type::KeyExchange => 32,
+		Type::GetBlockTransactions => 42 + 6 * MAX_TX_KERNEL_HASHES as u64,
+		Type::BlockTransactions => 32 + 2 * max_block_size(),
+		Type::GetHistoricalBlock => 32,
+		Type::HistoricalBlock => max_block_size() + 4 + 64 * MAX_OUTPUTS_PROOF_HASHES as u64,
 	}
 }
 
@@ -163,6 +180,25 @@ impl Msg {
 	pub fn add_attachment(&mut self, attachment: File) {
 		self.attachment = Some(attachment)
 	}
+
+	/// The serialized message body, as sent on the wire.
+	pub fn body(&self) -> &[u8] {
+		&self.body
+	}
+
+	/// Whether this message carries bulk data, sent after anything else.
+	pub fn is_bulk(&self) -> bool {
+		matches!(
+			self.header.msg_type,
+			Type::Block
+				| Type::HistoricalBlock
+				| Type::TxHashSetArchive
+				| Type::OutputBitmapSegment
+				| Type::OutputSegment
+				| Type::RangeProofSegment
+				| Type::KernelSegment
+		)
+	}
 }
 
 /// Read a header from the provided stream without blocking if the
@@ -218,10 +254,23 @@ pub fn read_message<T: Readable, R: Read>(
 	version: ProtocolVersion,
 	msg_type: Type,
 ) -> Result<T, Error> {
+	read_message_with_body(stream, version, msg_type).map(|(msg, _)| msg)
+}
+
+/// Reads a full message from the underlying stream, along with its body as
+/// received on the wire.
+pub fn read_message_with_body<T: Readable, R: Read>(
+	stream: &mut R,
+	version: ProtocolVersion,
+	msg_type: Type,
+) -> Result<(T, Vec<u8>), Error> {
 	match read_header(stream, version)? {
 		MsgHeaderWrapper::Known(header) => {
 			if header.msg_type == msg_type {
-				read_body(&header, stream, version)
+				let mut body = vec![0u8; header.msg_len as usize];
+				stream.read_exact(&mut body)?;
+				let msg = ser::deserialize(&mut &body[..], version)?;
+				Ok((msg, body))
 			} else {
 				Err(Error::BadMessage)
 			}
@@ -251,6 +300,7 @@ pub fn write_message<W: Write>(
 
 	let mut buf = ser::ser_vec(&msg.header, msg.version)?;
 	buf.extend(&msg.body[..]);
+	tracker.throttle_sent(buf.len() as u64);
 	stream.write_all(&buf[..])?;
 	tracker.inc_sent(buf.len() as u64);
 	if let Some(file) = &msg.attachment {
@@ -260,6 +310,7 @@ pub fn write_message<W: Write>(
 			match file.read(&mut buf[..]) {
 				Ok(0) => break,
 				Ok(n) => {
+					tracker.throttle_sent(n as u64);
 					stream.write_all(&buf[..n])?;
 					// Increase sent bytes "quietly" without incrementing the counter.
 					// (In a loop here for the single attachment).
@@ -621,6 +672,157 @@ impl Readable for Locator {
 	}
 }
 
+/// Serializable wrapper for a batch of tx kernel hashes, announced or
+/// requested.
+pub struct TxKernelHashes {
+	pub hashes: Vec<Hash>,
+}
+
+impl Writeable for TxKernelHashes {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u16(self.hashes.len() as u16)?;
+		for h in &self.hashes {
+			h.write(writer)?
+		}
+		Ok(())
+	}
+}
+
+impl Readable for TxKernelHashes {
+	fn read<R: Reader>(reader: &mut R) -> Result<TxKernelHashes, ser::Error> {
+		let len = reader.read_u16()?;
+		if (len as u32) > MAX_TX_KERNEL_HASHES {
+			return Err(ser::Error::TooLargeReadErr);
+		}
+		let mut hashes = Vec::with_capacity(len as usize);
+		for _ in 0..len {
+			hashes.push(Hash::read(reader)?);
+		}
+		Ok(TxKernelHashes { hashes })
+	}
+}
+
+/// Serializable wrapper for a batch of txs, sent in response to a request by
+/// kernel hashes.
+pub struct Transactions {
+	pub txs: Vec<Transaction>,
+}
+
+impl Writeable for Transactions {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u16(self.txs.len() as u16)?;
+		for tx in &self.txs {
+			tx.write(writer)?
+		}
+		Ok(())
+	}
+}
+
+impl Readable for Transactions {
+	fn read<R: Reader>(reader: &mut R) -> Result<Transactions, ser::Error> {
+		let len = reader.read_u16()?;
+		if (len as u32) > MAX_TX_KERNEL_HASHES {
+			return Err(ser::Error::TooLargeReadErr);
+		}
+		let mut txs = Vec::with_capacity(len as usize);
+		for _ in 0..len {
+			txs.push(Transaction::read(reader)?);
+		}
+		Ok(Transactions { txs })
+	}
+}
+
+/// Request for the txs of a compact block missing from our pool, by the short
+/// ids of their kernels.
+pub struct BlockTransactionsRequest {
+	/// Hash of the compact block
+	pub hash: Hash,
+	/// Nonce the kernel short ids were computed with
+	pub nonce: u64,
+	/// Short ids of the missing kernels
+	pub kern_ids: Vec<ShortId>,
+}
+
+impl Writeable for BlockTransactionsRequest {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.hash.write(writer)?;
+		writer.write_u64(self.nonce)?;
+		writer.write_u16(self.kern_ids.len() as u16)?;
+		for id in &self.kern_ids {
+			id.write(writer)?
+		}
+		Ok(())
+	}
+}
+
+impl Readable for BlockTransactionsRequest {
+	fn read<R: Reader>(reader: &mut R) -> Result<BlockTransactionsRequest, ser::Error> {
+		let hash = Hash::read(reader)?;
+		let nonce = reader.read_u64()?;
+		let len = reader.read_u16()?;
+		if (len as u32) > MAX_TX_KERNEL_HASHES {
+			return Err(ser::Error::TooLargeReadErr);
+		}
+		let mut kern_ids = Vec::with_capacity(len as usize);
+		for _ in 0..len {
+			kern_ids.push(ShortId::read(reader)?);
+		}
+		Ok(BlockTransactionsRequest {
+			hash,
+			nonce,
+			kern_ids,
+		})
+	}
+}
+
+/// Serializable wrapper for the txs of a compact block, sent in response to a
+/// request by kernel short ids.
+pub struct BlockTransactions {
+	/// Hash of the compact block
+	pub hash: Hash,
+	/// The requested txs we know of
+	pub txs: Transactions,
+}
+
+impl Writeable for BlockTransactions {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.hash.write(writer)?;
+		self.txs.write(writer)
+	}
+}
+
+impl Readable for BlockTransactions {
+	fn read<R: Reader>(reader: &mut R) -> Result<BlockTransactions, ser::Error> {
+		let hash = Hash::read(reader)?;
+		let txs = Transactions::read(reader)?;
+		Ok(BlockTransactions { hash, txs })
+	}
+}
+
+/// A historical block along the hashes needed to check its outputs against its
+/// header, sent by archive nodes.
+pub struct HistoricalBlock {
+	/// The full block
+	pub block: Block,
+	/// Proof of the block outputs against the output and rangeproof roots
+	pub proof: BlockOutputsProof,
+}
+
+impl Writeable for HistoricalBlock {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.block.write(writer)?;
+		self.proof.write(writer)
+	}
+}
+
+impl Readable for HistoricalBlock {
+	fn read<R: Reader>(reader: &mut R) -> Result<HistoricalBlock, ser::Error> {
+		let block = UntrustedBlock::read(reader)?.into();
+		let proof = BlockOutputsProof::read(reader)?;
+		Ok(HistoricalBlock { block, proof })
+	}
+}
+
 /// Serializable wrapper for a list of block headers.
 pub struct Headers {
 	pub headers: Vec<BlockHeader>,
@@ -892,9 +1094,16 @@ pub enum Message {
 	TransactionKernel(Hash),
 	GetTransaction(Hash),
 	Transaction(Transaction),
+	TransactionKernels(TxKernelHashes),
+	GetTransactions(TxKernelHashes),
+	Transactions(Transactions),
+	GetBlockTransactions(BlockTransactionsRequest),
+	BlockTransactions(BlockTransactions),
 	StemTransaction(Transaction),
 	GetBlock(Hash),
 	Block(UntrustedBlock),
+	GetHistoricalBlock(Hash),
+	HistoricalBlock(HistoricalBlock),
 	GetCompactBlock(Hash),
 	CompactBlock(UntrustedCompactBlock),
 	GetHeaders(Locator),
@@ -936,9 +1145,16 @@ impl fmt::Display for Message {
 			Message::TransactionKernel(_) => write!(f, "tx kernel"),
 			Message::GetTransaction(_) => write!(f, "get tx"),
 			Message::Transaction(_) => write!(f, "tx"),
+			Message::TransactionKernels(_) => write!(f, "tx kernels"),
+			Message::GetTransactions(_) => write!(f, "get txs"),
+			Message::Transactions(_) => write!(f, "txs"),
+			Message::GetBlockTransactions(_) => write!(f, "get block txs"),
+			Message::BlockTransactions(_) => write!(f, "block txs"),
 			Message::StemTransaction(_) => write!(f, "stem tx"),
 			Message::GetBlock(_) => write!(f, "get block"),
 			Message::Block(_) => write!(f, "block"),
+			Message::GetHistoricalBlock(_) => write!(f, "get historical block"),
+			Message::HistoricalBlock(_) => write!(f, "historical block"),
 			Message::GetCompactBlock(_) => write!(f, "get compact block"),
 			Message::CompactBlock(_) => write!(f, "compact block"),
 			Message::GetHeaders(_) => write!(f, "get headers"),
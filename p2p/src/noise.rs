@@ -0,0 +1,376 @@
+// Copyright 2021 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Opt-in, opportunistic and unauthenticated encryption of the peer
+//! transport.
+//!
+//! Once the Hand/Shake exchange is done and both peers advertise the
+//! `ENCRYPTED` capability, each side sends an ephemeral X25519 public key.
+//! The shared secret is expanded (HKDF-SHA256) into one ChaCha20-Poly1305 key
+//! per direction and all subsequent traffic is sent as length prefixed,
+//! authenticated frames. Keys are never reused across connections.
+//!
+//! This only protects against passive observers. Peers have no static key or
+//! identity to check the ephemeral keys against, so an active attacker
+//! sitting between two peers can run a key exchange with each of them and
+//! read or alter everything. Such an attacker can as well clear the
+//! `ENCRYPTED` capability of both Hand and Shake, both peers then silently
+//! keep talking in plaintext.
+//!
+//! Like a Noise prologue, a hash of the Hand and Shake messages as sent on the
+//! wire is mixed into the key derivation. Peers that both go on encrypting
+//! only end up with the same keys if they saw the same handshake, so a
+//! tampered handshake with an unchanged key exchange fails the very first
+//! frame.
+
+use crate::core::ser::{self, Readable, Reader, Writeable, Writer};
+use crate::types::Error;
+use ring::aead::{self, Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305};
+use ring::agreement::{self, EphemeralPrivateKey, UnparsedPublicKey, X25519};
+use ring::digest::{self, SHA256_OUTPUT_LEN};
+use ring::hkdf::{Salt, HKDF_SHA256};
+use ring::rand::SystemRandom;
+use std::cmp::min;
+use std::io::{self, Read, Write};
+
+/// Size of an X25519 public key.
+pub const PUBLIC_KEY_LEN: usize = 32;
+
+/// Max size of the plaintext carried by a single frame.
+pub const MAX_FRAME_LEN: usize = 16 * 1024;
+
+/// Size of the big endian length prefix of each frame.
+const FRAME_HEADER_LEN: usize = 4;
+
+const INITIATOR_INFO: &[u8] = b"grin p2p initiator";
+const RESPONDER_INFO: &[u8] = b"grin p2p responder";
+
+/// Hash of the handshake messages, bound to the session keys.
+pub type Transcript = [u8; SHA256_OUTPUT_LEN];
+
+/// Hashes the bodies of the Hand and Shake messages, as sent on the wire.
+pub fn handshake_transcript(hand: &[u8], shake: &[u8]) -> Transcript {
+	let mut ctx = digest::Context::new(&digest::SHA256);
+	for msg in &[hand, shake] {
+		ctx.update(&(msg.len() as u64).to_be_bytes());
+		ctx.update(msg);
+	}
+	let mut transcript = [0; SHA256_OUTPUT_LEN];
+	transcript.copy_from_slice(ctx.finish().as_ref());
+	transcript
+}
+
+/// Our ephemeral public key, sent to the peer right after the handshake.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyExchange {
+	pub public_key: [u8; PUBLIC_KEY_LEN],
+}
+
+impl Writeable for KeyExchange {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_fixed_bytes(self.public_key)
+	}
+}
+
+impl Readable for KeyExchange {
+	fn read<R: Reader>(reader: &mut R) -> Result<KeyExchange, ser::Error> {
+		let bytes = reader.read_fixed_bytes(PUBLIC_KEY_LEN)?;
+		let mut public_key = [0; PUBLIC_KEY_LEN];
+		public_key.copy_from_slice(&bytes);
+		Ok(KeyExchange { public_key })
+	}
+}
+
+/// Ephemeral key pair generated for a single connection.
+pub struct EphemeralKey {
+	private_key: EphemeralPrivateKey,
+	public_key: [u8; PUBLIC_KEY_LEN],
+}
+
+impl EphemeralKey {
+	/// Generates a new random key pair.
+	pub fn generate() -> Result<EphemeralKey, Error> {
+		let rng = SystemRandom::new();
+		let private_key =
+			EphemeralPrivateKey::generate(&X25519, &rng).map_err(|_| Error::Encryption)?;
+		let mut public_key = [0; PUBLIC_KEY_LEN];
+		public_key.copy_from_slice(
+			private_key
+				.compute_public_key()
+				.map_err(|_| Error::Encryption)?
+				.as_ref(),
+		);
+		Ok(EphemeralKey {
+			private_key,
+			public_key,
+		})
+	}
+
+	/// The key exchange message carrying our public key.
+	pub fn key_exchange(&self) -> KeyExchange {
+		KeyExchange {
+			public_key: self.public_key,
+		}
+	}
+
+	/// Derives the session ciphers from the peer public key. Both public keys
+	/// (initiator first) and the handshake transcript salt the derivation so
+	/// each side ends up with the same pair of keys, swapped.
+	pub fn agree(
+		self,
+		peer: &KeyExchange,
+		initiator: bool,
+		transcript: &Transcript,
+	) -> Result<Session, Error> {
+		let mut salt = Vec::with_capacity(2 * PUBLIC_KEY_LEN + transcript.len());
+		if initiator {
+			salt.extend_from_slice(&self.public_key);
+			salt.extend_from_slice(&peer.public_key);
+		} else {
+			salt.extend_from_slice(&peer.public_key);
+			salt.extend_from_slice(&self.public_key);
+		}
+		salt.extend_from_slice(transcript);
+		let peer_key = UnparsedPublicKey::new(&X25519, peer.public_key);
+		let (initiator_key, responder_key) =
+			agreement::agree_ephemeral(self.private_key, &peer_key, Error::Encryption, |secret| {
+				let prk = Salt::new(HKDF_SHA256, &salt).extract(secret);
+				let expand = |info| -> Result<UnboundKey, Error> {
+					let info = [info];
+					let okm = prk
+						.expand(&info, &CHACHA20_POLY1305)
+						.map_err(|_| Error::Encryption)?;
+					Ok(UnboundKey::from(okm))
+				};
+				Ok((expand(INITIATOR_INFO)?, expand(RESPONDER_INFO)?))
+			})?;
+		let (sender, receiver) = if initiator {
+			(initiator_key, responder_key)
+		} else {
+			(responder_key, initiator_key)
+		};
+		Ok(Session {
+			sender: Cipher::new(sender),
+			receiver: Cipher::new(receiver),
+		})
+	}
+}
+
+/// Pair of ciphers, one for each direction of a connection.
+pub struct Session {
+	pub sender: Cipher,
+	pub receiver: Cipher,
+}
+
+/// Authenticated cipher for one direction of a connection. Nonces are a
+/// counter of the frames sent so far, which the peer tracks as well.
+pub struct Cipher {
+	key: LessSafeKey,
+	counter: u64,
+}
+
+impl Cipher {
+	fn new(key: UnboundKey) -> Cipher {
+		Cipher {
+			key: LessSafeKey::new(key),
+			counter: 0,
+		}
+	}
+
+	fn next_nonce(&mut self) -> Result<Nonce, Error> {
+		let mut nonce = [0; aead::NONCE_LEN];
+		nonce[4..].copy_from_slice(&self.counter.to_le_bytes());
+		self.counter = self.counter.checked_add(1).ok_or(Error::Encryption)?;
+		Ok(Nonce::assume_unique_for_key(nonce))
+	}
+
+	/// Encrypts the provided plaintext into a full frame, length prefix
+	/// included.
+	pub fn seal(&mut self, plaintext: &[u8]) -> Result<Vec<u8>, Error> {
+		if plaintext.len() > MAX_FRAME_LEN {
+			return Err(Error::Encryption);
+		}
+		let nonce = self.next_nonce()?;
+		let len = plaintext.len() + CHACHA20_POLY1305.tag_len();
+		let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + len);
+		frame.extend_from_slice(&(len as u32).to_be_bytes());
+		frame.extend_from_slice(plaintext);
+		let tag = self
+			.key
+			.seal_in_place_separate_tag(nonce, Aad::empty(), &mut frame[FRAME_HEADER_LEN..])
+			.map_err(|_| Error::Encryption)?;
+		frame.extend_from_slice(tag.as_ref());
+		Ok(frame)
+	}
+
+	/// Decrypts and authenticates the body of a frame (without its length
+	/// prefix), returning the plaintext.
+	pub fn open<'a>(&mut self, body: &'a mut [u8]) -> Result<&'a [u8], Error> {
+		let nonce = self.next_nonce()?;
+		let plaintext = self
+			.key
+			.open_in_place(nonce, Aad::empty(), body)
+			.map_err(|_| Error::Encryption)?;
+		Ok(plaintext)
+	}
+}
+
+/// Read half of a peer connection. Passes bytes through until encryption is
+/// negotiated, then decrypts incoming frames. A partially received frame is
+/// kept across read timeouts so reading can be resumed.
+pub struct FrameReader<R> {
+	inner: R,
+	cipher: Option<Cipher>,
+	frame: Vec<u8>,
+	filled: usize,
+	plaintext: Vec<u8>,
+	pos: usize,
+}
+
+impl<R> FrameReader<R> {
+	pub fn new(inner: R, cipher: Option<Cipher>) -> FrameReader<R> {
+		FrameReader {
+			inner,
+			cipher,
+			frame: vec![0; FRAME_HEADER_LEN],
+			filled: 0,
+			plaintext: vec![],
+			pos: 0,
+		}
+	}
+
+	/// Whether incoming frames are decrypted.
+	pub fn is_encrypted(&self) -> bool {
+		self.cipher.is_some()
+	}
+
+	pub fn get_ref(&self) -> &R {
+		&self.inner
+	}
+
+	pub fn into_inner(self) -> R {
+		self.inner
+	}
+}
+
+impl<R: Read> FrameReader<R> {
+	/// Reads until a full frame is received and decrypts it.
+	fn read_frame(&mut self, cipher: &mut Cipher) -> io::Result<bool> {
+		loop {
+			if self.filled == self.frame.len() {
+				if self.frame.len() == FRAME_HEADER_LEN {
+					let mut len = [0; FRAME_HEADER_LEN];
+					len.copy_from_slice(&self.frame);
+					let len = u32::from_be_bytes(len) as usize;
+					let tag_len = CHACHA20_POLY1305.tag_len();
+					if len < tag_len || len > MAX_FRAME_LEN + tag_len {
+						return Err(io::Error::new(
+							io::ErrorKind::InvalidData,
+							"invalid frame length",
+						));
+					}
+					self.frame.resize(FRAME_HEADER_LEN + len, 0);
+				} else {
+					let plaintext =
+						cipher
+							.open(&mut self.frame[FRAME_HEADER_LEN..])
+							.map_err(|_| {
+								io::Error::new(io::ErrorKind::InvalidData, "frame authentication")
+							})?;
+					self.plaintext.clear();
+					self.plaintext.extend_from_slice(plaintext);
+					self.pos = 0;
+					self.frame.truncate(FRAME_HEADER_LEN);
+					self.filled = 0;
+					if !self.plaintext.is_empty() {
+						return Ok(true);
+					}
+				}
+			}
+			let n = self.inner.read(&mut self.frame[self.filled..])?;
+			if n == 0 {
+				if self.filled == 0 {
+					return Ok(false);
+				}
+				return Err(io::ErrorKind::UnexpectedEof.into());
+			}
+			self.filled += n;
+		}
+	}
+}
+
+impl<R: Read> Read for FrameReader<R> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let mut cipher = match self.cipher.take() {
+			Some(cipher) => cipher,
+			None => return self.inner.read(buf),
+		};
+		let res = if self.pos < self.plaintext.len() {
+			Ok(true)
+		} else {
+			self.read_frame(&mut cipher)
+		};
+		self.cipher = Some(cipher);
+		if !res? {
+			return Ok(0);
+		}
+		let n = min(buf.len(), self.plaintext.len() - self.pos);
+		buf[..n].copy_from_slice(&self.plaintext[self.pos..self.pos + n]);
+		self.pos += n;
+		Ok(n)
+	}
+}
+
+/// Write half of a peer connection. Passes bytes through until encryption is
+/// negotiated, then sends them as encrypted frames.
+pub struct FrameWriter<W> {
+	inner: W,
+	cipher: Option<Cipher>,
+}
+
+impl<W> FrameWriter<W> {
+	pub fn new(inner: W, cipher: Option<Cipher>) -> FrameWriter<W> {
+		FrameWriter { inner, cipher }
+	}
+
+	/// Whether outgoing bytes are encrypted.
+	pub fn is_encrypted(&self) -> bool {
+		self.cipher.is_some()
+	}
+
+	pub fn get_ref(&self) -> &W {
+		&self.inner
+	}
+}
+
+impl<W: Write> Write for FrameWriter<W> {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		match &mut self.cipher {
+			Some(_) if buf.is_empty() => Ok(0),
+			Some(cipher) => {
+				let n = min(buf.len(), MAX_FRAME_LEN);
+				let frame = cipher
+					.seal(&buf[..n])
+					.map_err(|_| io::Error::other("frame encryption"))?;
+				self.inner.write_all(&frame)?;
+				Ok(n)
+			}
+			None => self.inner.write(buf),
+		}
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.inner.flush()
+	}
+}
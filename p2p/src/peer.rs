@@ -15,6 +15,7 @@
 use crate::util::{Mutex, RwLock};
 use std::fmt;
 use std::fs::File;
+use std::mem;
 use std::net::{Shutdown, TcpStream};
 use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, Ordering};
@@ -22,26 +23,33 @@ use std::sync::Arc;
 
 use lru_cache::LruCache;
 
+use crate::bandwidth::Bandwidth;
 use crate::chain;
 use crate::chain::txhashset::BitmapChunk;
 use crate::conn;
 use crate::core::core::hash::{Hash, Hashed};
-use crate::core::core::{OutputIdentifier, Segment, SegmentIdentifier, TxKernel};
+use crate::core::core::{OutputIdentifier, Segment, SegmentIdentifier, ShortId, TxKernel};
 use crate::core::pow::Difficulty;
 use crate::core::ser::Writeable;
 use crate::core::{core, global};
 use crate::handshake::Handshake;
-use crate::msg::{self, BanReason, GetPeerAddrs, Locator, Msg, Ping, TxHashSetRequest, Type};
+use crate::msg::{
+	self, BanReason, BlockTransactionsRequest, GetPeerAddrs, Locator, Msg, PeerAddrs, Ping,
+	SegmentRequest, TxHashSetRequest, TxKernelHashes, Type,
+};
+use crate::noise::Session;
 use crate::protocol::Protocol;
 use crate::types::{
 	Capabilities, ChainAdapter, Error, NetAdapter, P2PConfig, PeerAddr, PeerInfo, ReasonForBan,
-	TxHashSetRead,
+	ScoreEvent, TxHashSetRead, MAX_TX_KERNEL_HASHES,
 };
 use crate::util::secp::pedersen::RangeProof;
 use chrono::prelude::{DateTime, Utc};
 
 const MAX_TRACK_SIZE: usize = 30;
 const MAX_PEER_MSG_PER_MIN: u64 = 500;
+// Number of queued tx kernel hashes sent without waiting for the next relay
+const TX_RELAY_BATCH_SIZE: usize = 64;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 /// Remind: don't mix up this 'State' with that 'State' in p2p/src/store.rs,
@@ -65,6 +73,8 @@ pub struct Peer {
 	stop_handle: Mutex<conn::StopHandle>,
 	// Whether or not we requested a txhashset from this peer
 	state_sync_requested: Arc<AtomicBool>,
+	// kernel hashes of the txs waiting to be announced to this peer
+	tx_relay_queue: Mutex<Vec<Hash>>,
 }
 
 impl fmt::Debug for Peer {
@@ -75,7 +85,13 @@ impl fmt::Debug for Peer {
 
 impl Peer {
 	// Only accept and connect can be externally used to build a peer
-	fn new(info: PeerInfo, conn: TcpStream, adapter: Arc<dyn NetAdapter>) -> std::io::Result<Peer> {
+	fn new(
+		info: PeerInfo,
+		conn: TcpStream,
+		session: Option<Session>,
+		bandwidth: &Bandwidth,
+		adapter: Arc<dyn NetAdapter>,
+	) -> std::io::Result<Peer> {
 		let state = Arc::new(RwLock::new(State::Connected));
 		let state_sync_requested = Arc::new(AtomicBool::new(false));
 		let tracking_adapter = TrackingAdapter::new(adapter);
@@ -84,8 +100,9 @@ impl Peer {
 			info.clone(),
 			state_sync_requested.clone(),
 		);
-		let tracker = Arc::new(conn::Tracker::new());
-		let (sendh, stoph) = conn::listen(conn, info.version, tracker.clone(), handler)?;
+		let (upload, download) = bandwidth.limits();
+		let tracker = Arc::new(conn::Tracker::with_limits(upload, download));
+		let (sendh, stoph) = conn::listen(conn, session, info.version, tracker.clone(), handler)?;
 		let send_handle = Mutex::new(sendh);
 		let stop_handle = Mutex::new(stoph);
 		Ok(Peer {
@@ -96,6 +113,7 @@ impl Peer {
 			send_handle,
 			stop_handle,
 			state_sync_requested,
+			tx_relay_queue: Mutex::new(vec![]),
 		})
 	}
 
@@ -109,7 +127,7 @@ impl Peer {
 		debug!("accept: handshaking from {:?}", conn.peer_addr());
 		let info = hs.accept(capab, total_difficulty, &mut conn);
 		match info {
-			Ok(info) => Ok(Peer::new(info, conn, adapter)?),
+			Ok((info, session)) => Ok(Peer::new(info, conn, session, &hs.bandwidth, adapter)?),
 			Err(e) => {
 				debug!(
 					"accept: handshaking from {:?} failed with error: {:?}",
@@ -126,21 +144,21 @@ impl Peer {
 
 	pub fn connect(
 		mut conn: TcpStream,
+		peer_addr: PeerAddr,
 		capab: Capabilities,
 		total_difficulty: Difficulty,
 		self_addr: PeerAddr,
 		hs: &Handshake,
 		adapter: Arc<dyn NetAdapter>,
 	) -> Result<Peer, Error> {
-		debug!("connect: handshaking with {:?}", conn.peer_addr());
-		let info = hs.initiate(capab, total_difficulty, self_addr, &mut conn);
+		debug!("connect: handshaking with {}", peer_addr);
+		let info = hs.initiate(capab, total_difficulty, self_addr, peer_addr, &mut conn);
 		match info {
-			Ok(info) => Ok(Peer::new(info, conn, adapter)?),
+			Ok((info, session)) => Ok(Peer::new(info, conn, session, &hs.bandwidth, adapter)?),
 			Err(e) => {
 				debug!(
-					"connect: handshaking with {:?} failed with error: {:?}",
-					conn.peer_addr(),
-					e
+					"connect: handshaking with {} failed with error: {:?}",
+					peer_addr, e
 				);
 				if let Err(e) = conn.shutdown(Shutdown::Both) {
 					debug!("Error shutting down conn: {:?}", e);
@@ -285,6 +303,42 @@ impl Peer {
 		}
 	}
 
+	/// Announces the provided tx kernel hashes to the remote peer, skipping the
+	/// ones it is known to already have. Peers supporting tx packages get them
+	/// in batches, others one by one.
+	pub fn send_tx_kernel_hashes(&self, hashes: &[Hash]) -> Result<bool, Error> {
+		if !self.info.capabilities.contains(Capabilities::TX_PACKAGE) {
+			let mut sent = false;
+			for h in hashes {
+				sent |= self.send_tx_kernel_hash(*h)?;
+			}
+			return Ok(sent);
+		}
+
+		let hashes: Vec<_> = hashes
+			.iter()
+			.filter(|h| !self.tracking_adapter.has_recv(**h))
+			.cloned()
+			.collect();
+		if hashes.is_empty() {
+			return Ok(false);
+		}
+		debug!(
+			"Send {} tx kernel hashes to {}",
+			hashes.len(),
+			self.info.addr
+		);
+		for chunk in hashes.chunks(MAX_TX_KERNEL_HASHES as usize) {
+			self.send(
+				&TxKernelHashes {
+					hashes: chunk.to_vec(),
+				},
+				msg::Type::TransactionKernels,
+			)?;
+		}
+		Ok(true)
+	}
+
 	/// Sends the provided transaction to the remote peer. The request may be
 	/// dropped if the remote peer is known to already have the transaction.
 	/// We support broadcast of lightweight tx kernel hash
@@ -292,6 +346,10 @@ impl Peer {
 	pub fn send_transaction(&self, tx: &core::Transaction) -> Result<bool, Error> {
 		let kernel = &tx.kernels()[0];
 
+		if self.info.capabilities.contains(Capabilities::TX_PACKAGE) {
+			return self.queue_tx_kernel_hash(kernel.hash());
+		}
+
 		if self
 			.info
 			.capabilities
@@ -314,6 +372,36 @@ impl Peer {
 		}
 	}
 
+	/// Queues the provided tx kernel hash to be announced to the remote peer
+	/// in a batch, sending the batch right away once full.
+	fn queue_tx_kernel_hash(&self, h: Hash) -> Result<bool, Error> {
+		if self.tracking_adapter.has_recv(h) {
+			debug!(
+				"Not queueing tx kernel hash {} for {} (already seen)",
+				h, self.info.addr
+			);
+			return Ok(false);
+		}
+		let batch = {
+			let mut queue = self.tx_relay_queue.lock();
+			queue.push(h);
+			if queue.len() < TX_RELAY_BATCH_SIZE {
+				return Ok(true);
+			}
+			mem::take(&mut *queue)
+		};
+		self.send_tx_kernel_hashes(&batch)
+	}
+
+	/// Announces the tx kernel hashes queued for relay to the remote peer.
+	pub fn flush_tx_relay_queue(&self) -> Result<bool, Error> {
+		let batch = mem::take(&mut *self.tx_relay_queue.lock());
+		if batch.is_empty() {
+			return Ok(false);
+		}
+		self.send_tx_kernel_hashes(&batch)
+	}
+
 	/// Sends the provided stem transaction to the remote peer.
 	/// Note: tracking adapter is ignored for stem transactions (while under
 	/// embargo).
@@ -335,6 +423,47 @@ impl Peer {
 		self.send(&h, msg::Type::GetTransaction)
 	}
 
+	/// Sends a request for a batch of txs by kernel hash.
+	pub fn send_txs_request(&self, hashes: Vec<Hash>) -> Result<(), Error> {
+		debug!(
+			"Requesting {} txs (kernel hash) from peer {}.",
+			hashes.len(),
+			self.info.addr
+		);
+		for chunk in hashes.chunks(MAX_TX_KERNEL_HASHES as usize) {
+			self.send(
+				&TxKernelHashes {
+					hashes: chunk.to_vec(),
+				},
+				msg::Type::GetTransactions,
+			)?;
+		}
+		Ok(())
+	}
+
+	/// Sends a request for the txs of a compact block by kernel short ids.
+	pub fn send_block_txs_request(
+		&self,
+		hash: Hash,
+		nonce: u64,
+		kern_ids: Vec<ShortId>,
+	) -> Result<(), Error> {
+		debug!(
+			"Requesting {} txs of block {} from peer {}.",
+			kern_ids.len(),
+			hash,
+			self.info.addr
+		);
+		self.send(
+			&BlockTransactionsRequest {
+				hash,
+				nonce,
+				kern_ids,
+			},
+			msg::Type::GetBlockTransactions,
+		)
+	}
+
 	/// Sends a request for a specific block by hash.
 	/// Takes opts so we can track if this request was due to our node syncing or otherwise.
 	pub fn send_block_request(&self, h: Hash, opts: chain::Options) -> Result<(), Error> {
@@ -343,6 +472,12 @@ impl Peer {
 		self.send(&h, msg::Type::GetBlock)
 	}
 
+	/// Sends a request for a historical block by hash, to archive peers.
+	pub fn send_historical_block_request(&self, h: Hash) -> Result<(), Error> {
+		debug!("Requesting historical block {} from {}", h, self.info.addr);
+		self.send(h, msg::Type::GetHistoricalBlock)
+	}
+
 	/// Sends a request for a specific compact block by hash
 	pub fn send_compact_block_request(&self, h: Hash) -> Result<(), Error> {
 		debug!("Requesting compact block {} from {}", h, self.info.addr);
@@ -359,6 +494,12 @@ impl Peer {
 		)
 	}
 
+	/// Sends the provided peer addresses, unrequested.
+	pub fn send_peer_addrs(&self, peers: Vec<PeerAddr>) -> Result<(), Error> {
+		trace!("Sending {} peer addrs to {}", peers.len(), self.info.addr);
+		self.send(&PeerAddrs { peers }, msg::Type::PeerAddrs)
+	}
+
 	pub fn send_txhashset_request(&self, height: u64, hash: Hash) -> Result<(), Error> {
 		debug!(
 			"Asking {} for txhashset archive at {} {}.",
@@ -371,6 +512,60 @@ impl Peer {
 		)
 	}
 
+	pub fn send_bitmap_segment_request(
+		&self,
+		h: Hash,
+		identifier: SegmentIdentifier,
+	) -> Result<(), Error> {
+		self.send_segment_request(h, identifier, msg::Type::GetOutputBitmapSegment)
+	}
+
+	pub fn send_output_segment_request(
+		&self,
+		h: Hash,
+		identifier: SegmentIdentifier,
+	) -> Result<(), Error> {
+		self.send_segment_request(h, identifier, msg::Type::GetOutputSegment)
+	}
+
+	pub fn send_rangeproof_segment_request(
+		&self,
+		h: Hash,
+		identifier: SegmentIdentifier,
+	) -> Result<(), Error> {
+		self.send_segment_request(h, identifier, msg::Type::GetRangeProofSegment)
+	}
+
+	pub fn send_kernel_segment_request(
+		&self,
+		h: Hash,
+		identifier: SegmentIdentifier,
+	) -> Result<(), Error> {
+		self.send_segment_request(h, identifier, msg::Type::GetKernelSegment)
+	}
+
+	fn send_segment_request(
+		&self,
+		h: Hash,
+		identifier: SegmentIdentifier,
+		msg_type: msg::Type,
+	) -> Result<(), Error> {
+		trace!(
+			"Asking {} for {:?} segment {:?} at {}.",
+			self.info.addr,
+			msg_type,
+			identifier,
+			h
+		);
+		self.send(
+			&SegmentRequest {
+				block_hash: h,
+				identifier,
+			},
+			msg_type,
+		)
+	}
+
 	/// Stops the peer
 	pub fn stop(&self) {
 		debug!("Stopping peer {:?}", self.info.addr);
@@ -450,6 +645,47 @@ impl ChainAdapter for TrackingAdapter {
 		self.adapter.tx_kernel_received(kernel_hash, peer_info)
 	}
 
+	fn get_transactions(&self, kernel_hashes: &[Hash]) -> Vec<core::Transaction> {
+		self.adapter.get_transactions(kernel_hashes)
+	}
+
+	fn tx_kernels_received(
+		&self,
+		kernel_hashes: Vec<Hash>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		for h in &kernel_hashes {
+			self.push_recv(*h);
+		}
+		self.adapter.tx_kernels_received(kernel_hashes, peer_info)
+	}
+
+	fn tx_kernel_hashes(&self) -> Vec<Hash> {
+		self.adapter.tx_kernel_hashes()
+	}
+
+	fn get_block_transactions(
+		&self,
+		hash: Hash,
+		nonce: u64,
+		kern_ids: &[ShortId],
+	) -> Vec<core::Transaction> {
+		self.adapter.get_block_transactions(hash, nonce, kern_ids)
+	}
+
+	fn block_transactions_received(
+		&self,
+		hash: Hash,
+		txs: Vec<core::Transaction>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		for tx in &txs {
+			self.push_recv(tx.kernels()[0].hash());
+		}
+		self.adapter
+			.block_transactions_received(hash, txs, peer_info)
+	}
+
 	fn transaction_received(
 		&self,
 		tx: core::Transaction,
@@ -482,6 +718,16 @@ impl ChainAdapter for TrackingAdapter {
 		self.adapter.block_received(b, peer_info, req_opts)
 	}
 
+	fn historical_block_received(
+		&self,
+		b: core::Block,
+		proof: chain::BlockOutputsProof,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		self.push_recv(b.hash());
+		self.adapter.historical_block_received(b, proof, peer_info)
+	}
+
 	fn compact_block_received(
 		&self,
 		cb: core::CompactBlock,
@@ -516,6 +762,18 @@ impl ChainAdapter for TrackingAdapter {
 		self.adapter.get_block(h, peer_info)
 	}
 
+	fn get_historical_block(&self, h: Hash) -> Option<(core::Block, chain::BlockOutputsProof)> {
+		self.adapter.get_historical_block(h)
+	}
+
+	fn block_exists(&self, h: Hash) -> bool {
+		self.adapter.block_exists(h)
+	}
+
+	fn header_exists(&self, h: Hash) -> bool {
+		self.adapter.header_exists(h)
+	}
+
 	fn txhashset_read(&self, h: Hash) -> Option<TxHashSetRead> {
 		self.adapter.txhashset_read(h)
 	}
@@ -586,6 +844,48 @@ impl ChainAdapter for TrackingAdapter {
 	) -> Result<Segment<RangeProof>, chain::Error> {
 		self.adapter.get_rangeproof_segment(hash, id)
 	}
+
+	fn receive_bitmap_segment(
+		&self,
+		block_hash: Hash,
+		output_root: Hash,
+		segment: Segment<BitmapChunk>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		self.adapter
+			.receive_bitmap_segment(block_hash, output_root, segment, peer_info)
+	}
+
+	fn receive_output_segment(
+		&self,
+		block_hash: Hash,
+		bitmap_root: Hash,
+		segment: Segment<OutputIdentifier>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		self.adapter
+			.receive_output_segment(block_hash, bitmap_root, segment, peer_info)
+	}
+
+	fn receive_rangeproof_segment(
+		&self,
+		block_hash: Hash,
+		segment: Segment<RangeProof>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		self.adapter
+			.receive_rangeproof_segment(block_hash, segment, peer_info)
+	}
+
+	fn receive_kernel_segment(
+		&self,
+		block_hash: Hash,
+		segment: Segment<TxKernel>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		self.adapter
+			.receive_kernel_segment(block_hash, segment, peer_info)
+	}
 }
 
 impl NetAdapter for TrackingAdapter {
@@ -604,4 +904,8 @@ impl NetAdapter for TrackingAdapter {
 	fn is_banned(&self, addr: PeerAddr) -> bool {
 		self.adapter.is_banned(addr)
 	}
+
+	fn peer_behaviour(&self, addr: PeerAddr, event: ScoreEvent) {
+		self.adapter.peer_behaviour(addr, event)
+	}
 }
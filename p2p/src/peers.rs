@@ -24,7 +24,7 @@ use crate::chain;
 use crate::chain::txhashset::BitmapChunk;
 use crate::core::core;
 use crate::core::core::hash::{Hash, Hashed};
-use crate::core::core::{OutputIdentifier, Segment, SegmentIdentifier, TxKernel};
+use crate::core::core::{OutputIdentifier, Segment, SegmentIdentifier, ShortId, TxKernel};
 use crate::core::global;
 use crate::core::pow::Difficulty;
 use crate::msg::PeerAddrs;
@@ -32,7 +32,7 @@ use crate::peer::Peer;
 use crate::store::{PeerData, PeerStore, State};
 use crate::types::{
 	Capabilities, ChainAdapter, Error, NetAdapter, P2PConfig, PeerAddr, PeerInfo, ReasonForBan,
-	TxHashSetRead, MAX_PEER_ADDRS,
+	ScoreEvent, TxHashSetRead, BAN_SCORE, MAX_PEER_ADDRS,
 };
 use crate::util::secp::pedersen::RangeProof;
 use chrono::prelude::*;
@@ -60,6 +60,14 @@ impl Peers {
 	/// Adds the peer to our internal peer mapping. Note that the peer is still
 	/// returned so the server can run it.
 	pub fn add_connected(&self, peer: Arc<Peer>) -> Result<(), Error> {
+		// Pick up where we left off with this peer score.
+		let score = self
+			.store
+			.get_peer(peer.info.addr)
+			.map(|p| p.score)
+			.unwrap_or(0);
+		peer.info.live_info.write().score = score;
+
 		let peer_data: PeerData;
 		{
 			// Scope for peers vector lock - dont hold the peers lock while adding to lmdb
@@ -75,14 +83,36 @@ impl Peers {
 				last_banned: 0,
 				ban_reason: ReasonForBan::None,
 				last_connected: Utc::now().timestamp(),
+				score,
 			};
 			debug!("Adding newly connected peer {}.", peer_data.addr);
-			peers.insert(peer_data.addr, peer);
+			peers.insert(peer_data.addr, peer.clone());
 		}
 		debug!("Saving newly connected peer {}.", peer_data.addr);
 		if let Err(e) = self.save_peer(&peer_data) {
 			error!("Could not save connected peer address: {:?}", e);
 		}
+
+		// Announce our txs to peers able to fetch them in batches.
+		if peer.info.capabilities.contains(Capabilities::TX_PACKAGE) {
+			let hashes = self.adapter.tx_kernel_hashes();
+			if let Err(e) = peer.send_tx_kernel_hashes(&hashes) {
+				debug!("Error announcing txs to peer {}: {:?}", peer_data.addr, e);
+			}
+		}
+
+		// Advertise our onion address to peers able to read it, it is never
+		// part of the handshake.
+		if let Some(onion) = self.config.onion_address {
+			if peer.info.capabilities.contains(Capabilities::TOR_ADDRESS) {
+				if let Err(e) = peer.send_peer_addrs(vec![PeerAddr::Onion(onion)]) {
+					debug!(
+						"Error advertising onion to peer {}: {:?}",
+						peer_data.addr, e
+					);
+				}
+			}
+		}
 		Ok(())
 	}
 
@@ -97,6 +127,7 @@ impl Peers {
 			last_banned: Utc::now().timestamp(),
 			ban_reason,
 			last_connected: Utc::now().timestamp(),
+			score: BAN_SCORE,
 		};
 		debug!("Banning peer {}.", addr);
 		self.save_peer(&peer_data)
@@ -165,6 +196,61 @@ impl Peers {
 		}
 	}
 
+	/// Adjusts the score of a connected peer following some good or bad
+	/// behaviour, banning it once its score drops to BAN_SCORE. Penalties are
+	/// saved right away, rewards only when the peer goes away.
+	pub fn score_peer(&self, peer_addr: PeerAddr, event: ScoreEvent) -> Result<(), Error> {
+		let peer = self
+			.get_connected_peer(peer_addr)
+			.ok_or(Error::PeerNotFound)?;
+		let score = peer.info.update_score(event.points());
+		if event.points() < 0 {
+			debug!(
+				"Peer {} penalized for {:?}, score now {}",
+				peer_addr, event, score
+			);
+			self.store.update_score(peer_addr, score)?;
+		}
+		if score <= BAN_SCORE {
+			return self.ban_peer(peer_addr, event.ban_reason());
+		}
+		Ok(())
+	}
+
+	/// Rewards the peer for new data deemed valid by the chain, penalizes it
+	/// for invalid data with the provided reason. Valid data we already had
+	/// leaves the score untouched.
+	fn check_received(
+		&self,
+		valid: bool,
+		new: bool,
+		peer_info: &PeerInfo,
+		reason: ReasonForBan,
+	) -> Result<bool, chain::Error> {
+		let event = match (valid, new) {
+			(false, _) => ScoreEvent::Misbehaviour(reason),
+			(true, true) => ScoreEvent::UsefulData,
+			(true, false) => return Ok(true),
+		};
+		match self.score_peer(peer_info.addr, event) {
+			Err(e) if !valid => {
+				let err: chain::Error =
+					chain::ErrorKind::Other(format!("score peer error :{:?}", e)).into();
+				Err(err)
+			}
+			_ => Ok(valid),
+		}
+	}
+
+	/// Score the peer depending on whether the PIBD segment it sent us was
+	/// deemed valid by the chain.
+	fn check_segment(&self, valid: bool, peer_info: &PeerInfo) -> Result<bool, chain::Error> {
+		if !valid {
+			debug!("Received bad segment from peer {}", peer_info.addr);
+		}
+		self.check_received(valid, true, peer_info, ReasonForBan::BadSegment)
+	}
+
 	/// Unban a peer, checks if it exists and banned then unban
 	pub fn unban_peer(&self, peer_addr: PeerAddr) -> Result<(), Error> {
 		debug!("unban_peer: peer {}", peer_addr);
@@ -238,6 +324,8 @@ impl Peers {
 	/// Broadcasts the provided transaction to all our connected peers.
 	/// A peer implementation may drop the broadcast request
 	/// if it knows the remote peer already has the transaction.
+	/// Peers supporting tx packages get it announced later along with the
+	/// other txs relayed in the meantime, see `flush_tx_relay`.
 	pub fn broadcast_transaction(&self, tx: &core::Transaction) {
 		let count = self.broadcast("transaction", |p| p.send_transaction(tx));
 		debug!(
@@ -247,6 +335,15 @@ impl Peers {
 		);
 	}
 
+	/// Announces the txs queued for relay to all our connected peers.
+	pub fn flush_tx_relay(&self) {
+		for p in self.iter().connected() {
+			if let Err(e) = p.flush_tx_relay_queue() {
+				debug!("Error relaying txs to peer {:?}: {:?}", &p.info.addr, e);
+			}
+		}
+	}
+
 	/// Ping all our connected peers. Always automatically expects a pong back
 	/// or disconnects. This acts as a liveness test.
 	pub fn check_all(&self, total_difficulty: Difficulty, height: u64) {
@@ -400,6 +497,7 @@ impl Peers {
 		}
 
 		// now clean up peer map based on the list to remove
+		let mut scores = vec![];
 		{
 			let mut peers = match self.peers.try_write_for(LOCK_TIMEOUT) {
 				Some(peers) => peers,
@@ -409,10 +507,17 @@ impl Peers {
 				}
 			};
 			for addr in rm {
-				let _ = peers.get(&addr).map(|peer| peer.stop());
-				peers.remove(&addr);
+				if let Some(peer) = peers.remove(&addr) {
+					peer.stop();
+					scores.push((addr, peer.info.score()));
+				}
 			}
 		}
+
+		// keep the score the removed peers built up for their next connection
+		for (addr, score) in scores {
+			let _ = self.store.update_score(addr, score);
+		}
 	}
 
 	pub fn stop(&self) {
@@ -478,6 +583,41 @@ impl ChainAdapter for Peers {
 		self.adapter.tx_kernel_received(kernel_hash, peer_info)
 	}
 
+	fn get_transactions(&self, kernel_hashes: &[Hash]) -> Vec<core::Transaction> {
+		self.adapter.get_transactions(kernel_hashes)
+	}
+
+	fn tx_kernels_received(
+		&self,
+		kernel_hashes: Vec<Hash>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		self.adapter.tx_kernels_received(kernel_hashes, peer_info)
+	}
+
+	fn tx_kernel_hashes(&self) -> Vec<Hash> {
+		self.adapter.tx_kernel_hashes()
+	}
+
+	fn get_block_transactions(
+		&self,
+		hash: Hash,
+		nonce: u64,
+		kern_ids: &[ShortId],
+	) -> Vec<core::Transaction> {
+		self.adapter.get_block_transactions(hash, nonce, kern_ids)
+	}
+
+	fn block_transactions_received(
+		&self,
+		hash: Hash,
+		txs: Vec<core::Transaction>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		self.adapter
+			.block_transactions_received(hash, txs, peer_info)
+	}
+
 	fn transaction_received(
 		&self,
 		tx: core::Transaction,
@@ -493,23 +633,37 @@ impl ChainAdapter for Peers {
 		opts: chain::Options,
 	) -> Result<bool, chain::Error> {
 		let hash = b.hash();
-		if !self.adapter.block_received(b, peer_info, opts)? {
+		let new = !self.adapter.block_exists(hash);
+		let valid = self.adapter.block_received(b, peer_info, opts)?;
+		if !valid {
 			// if the peer sent us a block that's intrinsically bad
 			// they are either mistaken or malevolent, both of which require a ban
 			debug!(
 				"Received a bad block {} from  {}, the peer will be banned",
 				hash, peer_info.addr,
 			);
-			self.ban_peer(peer_info.addr, ReasonForBan::BadBlock)
-				.map_err(|e| {
-					let err: chain::Error =
-						chain::ErrorKind::Other(format!("ban peer error :{:?}", e)).into();
-					err
-				})?;
-			Ok(false)
-		} else {
-			Ok(true)
 		}
+		self.check_received(valid, new, peer_info, ReasonForBan::BadBlock)
+	}
+
+	fn historical_block_received(
+		&self,
+		b: core::Block,
+		proof: chain::BlockOutputsProof,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		let hash = b.hash();
+		let new = !self.adapter.block_exists(hash);
+		let valid = self
+			.adapter
+			.historical_block_received(b, proof, peer_info)?;
+		if !valid {
+			debug!(
+				"Received a bad historical block {} from {}, the peer will be banned",
+				hash, peer_info.addr,
+			);
+		}
+		self.check_received(valid, new, peer_info, ReasonForBan::BadBlock)
 	}
 
 	fn compact_block_received(
@@ -518,23 +672,17 @@ impl ChainAdapter for Peers {
 		peer_info: &PeerInfo,
 	) -> Result<bool, chain::Error> {
 		let hash = cb.hash();
-		if !self.adapter.compact_block_received(cb, peer_info)? {
+		let new = !self.adapter.block_exists(hash);
+		let valid = self.adapter.compact_block_received(cb, peer_info)?;
+		if !valid {
 			// if the peer sent us a block that's intrinsically bad
-			// they are either mistaken or malevolent, both of which require a ban
+			// they are either mistaken or malevolent, penalize them
 			debug!(
-				"Received a bad compact block {} from  {}, the peer will be banned",
+				"Received a bad compact block {} from  {}, the peer will be penalized",
 				hash, peer_info.addr
 			);
-			self.ban_peer(peer_info.addr, ReasonForBan::BadCompactBlock)
-				.map_err(|e| {
-					let err: chain::Error =
-						chain::ErrorKind::Other(format!("ban peer error :{:?}", e)).into();
-					err
-				})?;
-			Ok(false)
-		} else {
-			Ok(true)
 		}
+		self.check_received(valid, new, peer_info, ReasonForBan::BadCompactBlock)
 	}
 
 	fn header_received(
@@ -542,19 +690,11 @@ impl ChainAdapter for Peers {
 		bh: core::BlockHeader,
 		peer_info: &PeerInfo,
 	) -> Result<bool, chain::Error> {
-		if !self.adapter.header_received(bh, peer_info)? {
-			// if the peer sent us a block header that's intrinsically bad
-			// they are either mistaken or malevolent, both of which require a ban
-			self.ban_peer(peer_info.addr, ReasonForBan::BadBlockHeader)
-				.map_err(|e| {
-					let err: chain::Error =
-						chain::ErrorKind::Other(format!("ban peer error :{:?}", e)).into();
-					err
-				})?;
-			Ok(false)
-		} else {
-			Ok(true)
-		}
+		// if the peer sent us a block header that's intrinsically bad
+		// they are either mistaken or malevolent, penalize them
+		let new = !self.adapter.header_exists(bh.hash());
+		let valid = self.adapter.header_received(bh, peer_info)?;
+		self.check_received(valid, new, peer_info, ReasonForBan::BadBlockHeader)
 	}
 
 	fn headers_received(
@@ -562,19 +702,13 @@ impl ChainAdapter for Peers {
 		headers: &[core::BlockHeader],
 		peer_info: &PeerInfo,
 	) -> Result<bool, chain::Error> {
-		if !self.adapter.headers_received(headers, peer_info)? {
-			// if the peer sent us a block header that's intrinsically bad
-			// they are either mistaken or malevolent, both of which require a ban
-			self.ban_peer(peer_info.addr, ReasonForBan::BadBlockHeader)
-				.map_err(|e| {
-					let err: chain::Error =
-						chain::ErrorKind::Other(format!("ban peer error :{:?}", e)).into();
-					err
-				})?;
-			Ok(false)
-		} else {
-			Ok(true)
-		}
+		// if the peer sent us a block header that's intrinsically bad
+		// they are either mistaken or malevolent, penalize them
+		let new = headers
+			.last()
+			.is_some_and(|h| !self.adapter.header_exists(h.hash()));
+		let valid = self.adapter.headers_received(headers, peer_info)?;
+		self.check_received(valid, new, peer_info, ReasonForBan::BadBlockHeader)
 	}
 
 	fn locate_headers(&self, hs: &[Hash]) -> Result<Vec<core::BlockHeader>, chain::Error> {
@@ -585,6 +719,18 @@ impl ChainAdapter for Peers {
 		self.adapter.get_block(h, peer_info)
 	}
 
+	fn get_historical_block(&self, h: Hash) -> Option<(core::Block, chain::BlockOutputsProof)> {
+		self.adapter.get_historical_block(h)
+	}
+
+	fn block_exists(&self, h: Hash) -> bool {
+		self.adapter.block_exists(h)
+	}
+
+	fn header_exists(&self, h: Hash) -> bool {
+		self.adapter.header_exists(h)
+	}
+
 	fn txhashset_read(&self, h: Hash) -> Option<TxHashSetRead> {
 		self.adapter.txhashset_read(h)
 	}
@@ -603,21 +749,15 @@ impl ChainAdapter for Peers {
 		txhashset_data: File,
 		peer_info: &PeerInfo,
 	) -> Result<bool, chain::Error> {
-		if self.adapter.txhashset_write(h, txhashset_data, peer_info)? {
+		let bad = self.adapter.txhashset_write(h, txhashset_data, peer_info)?;
+		if bad {
 			debug!(
 				"Received a bad txhashset data from {}, the peer will be banned",
 				peer_info.addr
 			);
-			self.ban_peer(peer_info.addr, ReasonForBan::BadTxHashSet)
-				.map_err(|e| {
-					let err: chain::Error =
-						chain::ErrorKind::Other(format!("ban peer error :{:?}", e)).into();
-					err
-				})?;
-			Ok(true)
-		} else {
-			Ok(false)
 		}
+		self.check_received(!bad, true, peer_info, ReasonForBan::BadTxHashSet)
+			.map(|valid| !valid)
 	}
 
 	fn txhashset_download_update(
@@ -669,6 +809,56 @@ impl ChainAdapter for Peers {
 	) -> Result<Segment<RangeProof>, chain::Error> {
 		self.adapter.get_rangeproof_segment(hash, id)
 	}
+
+	fn receive_bitmap_segment(
+		&self,
+		block_hash: Hash,
+		output_root: Hash,
+		segment: Segment<BitmapChunk>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		let res =
+			self.adapter
+				.receive_bitmap_segment(block_hash, output_root, segment, peer_info)?;
+		self.check_segment(res, peer_info)
+	}
+
+	fn receive_output_segment(
+		&self,
+		block_hash: Hash,
+		bitmap_root: Hash,
+		segment: Segment<OutputIdentifier>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		let res =
+			self.adapter
+				.receive_output_segment(block_hash, bitmap_root, segment, peer_info)?;
+		self.check_segment(res, peer_info)
+	}
+
+	fn receive_rangeproof_segment(
+		&self,
+		block_hash: Hash,
+		segment: Segment<RangeProof>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		let res = self
+			.adapter
+			.receive_rangeproof_segment(block_hash, segment, peer_info)?;
+		self.check_segment(res, peer_info)
+	}
+
+	fn receive_kernel_segment(
+		&self,
+		block_hash: Hash,
+		segment: Segment<TxKernel>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		let res = self
+			.adapter
+			.receive_kernel_segment(block_hash, segment, peer_info)?;
+		self.check_segment(res, peer_info)
+	}
 }
 
 impl NetAdapter for Peers {
@@ -698,6 +888,7 @@ impl NetAdapter for Peers {
 				last_banned: 0,
 				ban_reason: ReasonForBan::None,
 				last_connected: Utc::now().timestamp(),
+				score: 0,
 			};
 			to_save.push(peer);
 		}
@@ -719,6 +910,12 @@ impl NetAdapter for Peers {
 			false
 		}
 	}
+
+	fn peer_behaviour(&self, addr: PeerAddr, event: ScoreEvent) {
+		if let Err(e) = self.score_peer(addr, event) {
+			debug!("Could not score peer {}: {:?}", addr, e);
+		}
+	}
 }
 
 pub struct PeersIter<I> {
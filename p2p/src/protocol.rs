@@ -17,10 +17,11 @@ use crate::conn::MessageHandler;
 use crate::core::core::{hash::Hashed, CompactBlock};
 
 use crate::msg::{
-	Consumed, Headers, Message, Msg, OutputBitmapSegmentResponse, OutputSegmentResponse, PeerAddrs,
-	Pong, SegmentRequest, SegmentResponse, TxHashSetArchive, Type,
+	BlockTransactions, Consumed, Headers, HistoricalBlock, Message, Msg,
+	OutputBitmapSegmentResponse, OutputSegmentResponse, PeerAddrs, Pong, SegmentRequest,
+	SegmentResponse, Transactions, TxHashSetArchive, Type,
 };
-use crate::types::{AttachmentMeta, Error, NetAdapter, PeerInfo};
+use crate::types::{AttachmentMeta, Capabilities, Error, NetAdapter, PeerInfo, ScoreEvent};
 use chrono::prelude::Utc;
 use rand::{thread_rng, Rng};
 use std::fs::{self, File};
@@ -139,6 +140,68 @@ impl MessageHandler for Protocol {
 				Consumed::None
 			}
 
+			Message::TransactionKernels(kernels) => {
+				debug!(
+					"handle_payload: received {} tx kernels",
+					kernels.hashes.len()
+				);
+				adapter.tx_kernels_received(kernels.hashes, &self.peer_info)?;
+				Consumed::None
+			}
+
+			Message::GetTransactions(kernels) => {
+				debug!(
+					"handle_payload: GetTransactions: {} kernels",
+					kernels.hashes.len()
+				);
+				let txs = adapter.get_transactions(&kernels.hashes);
+				if txs.is_empty() {
+					Consumed::None
+				} else {
+					Consumed::Response(Msg::new(
+						Type::Transactions,
+						Transactions { txs },
+						self.peer_info.version,
+					)?)
+				}
+			}
+
+			Message::Transactions(txs) => {
+				debug!("handle_payload: received {} txs", txs.txs.len());
+				for tx in txs.txs {
+					adapter.transaction_received(tx, false)?;
+				}
+				Consumed::None
+			}
+
+			Message::GetBlockTransactions(req) => {
+				debug!(
+					"handle_payload: GetBlockTransactions: {} kernels of block {}",
+					req.kern_ids.len(),
+					req.hash
+				);
+				// Always respond so the requester can fall back to the full block.
+				let txs = adapter.get_block_transactions(req.hash, req.nonce, &req.kern_ids);
+				Consumed::Response(Msg::new(
+					Type::BlockTransactions,
+					BlockTransactions {
+						hash: req.hash,
+						txs: Transactions { txs },
+					},
+					self.peer_info.version,
+				)?)
+			}
+
+			Message::BlockTransactions(resp) => {
+				debug!(
+					"handle_payload: received {} txs of block {}",
+					resp.txs.txs.len(),
+					resp.hash
+				);
+				adapter.block_transactions_received(resp.hash, resp.txs.txs, &self.peer_info)?;
+				Consumed::None
+			}
+
 			Message::StemTransaction(tx) => {
 				debug!("handle_payload: received stem tx");
 				adapter.transaction_received(tx, true)?;
@@ -165,6 +228,25 @@ impl MessageHandler for Protocol {
 				Consumed::None
 			}
 
+			Message::GetHistoricalBlock(h) => {
+				trace!("handle_payload: GetHistoricalBlock: {}", h);
+				if let Some((block, proof)) = adapter.get_historical_block(h) {
+					Consumed::Response(Msg::new(
+						Type::HistoricalBlock,
+						HistoricalBlock { block, proof },
+						self.peer_info.version,
+					)?)
+				} else {
+					Consumed::None
+				}
+			}
+
+			Message::HistoricalBlock(hb) => {
+				debug!("handle_payload: received historical block");
+				adapter.historical_block_received(hb.block, hb.proof, &self.peer_info)?;
+				Consumed::None
+			}
+
 			Message::GetCompactBlock(h) => {
 				if let Some(b) = adapter.get_block(h, &self.peer_info) {
 					let cb: CompactBlock = b.into();
@@ -205,7 +287,15 @@ impl MessageHandler for Protocol {
 			}
 
 			Message::GetPeerAddrs(get_peers) => {
-				let peers = adapter.find_peer_addrs(get_peers.capabilities);
+				let mut peers = adapter.find_peer_addrs(get_peers.capabilities);
+				// Older peers can't read onion addresses.
+				if !self
+					.peer_info
+					.capabilities
+					.contains(Capabilities::TOR_ADDRESS)
+				{
+					peers.retain(|addr| !addr.is_onion());
+				}
 				Consumed::Response(Msg::new(
 					Type::PeerAddrs,
 					PeerAddrs { peers },
@@ -371,13 +461,61 @@ impl MessageHandler for Protocol {
 					Consumed::None
 				}
 			}
-			Message::OutputBitmapSegment(_)
-			| Message::OutputSegment(_)
-			| Message::RangeProofSegment(_)
-			| Message::KernelSegment(_) => Consumed::None,
+			Message::OutputBitmapSegment(req) => {
+				let OutputBitmapSegmentResponse {
+					block_hash,
+					segment,
+					output_root,
+				} = req;
+				adapter.receive_bitmap_segment(
+					block_hash,
+					output_root,
+					segment.into(),
+					&self.peer_info,
+				)?;
+				Consumed::None
+			}
+			Message::OutputSegment(req) => {
+				let OutputSegmentResponse {
+					response,
+					output_bitmap_root,
+				} = req;
+				adapter.receive_output_segment(
+					response.block_hash,
+					output_bitmap_root,
+					response.segment,
+					&self.peer_info,
+				)?;
+				Consumed::None
+			}
+			Message::RangeProofSegment(req) => {
+				let SegmentResponse {
+					block_hash,
+					segment,
+				} = req;
+				adapter.receive_rangeproof_segment(block_hash, segment, &self.peer_info)?;
+				Consumed::None
+			}
+			Message::KernelSegment(req) => {
+				let SegmentResponse {
+					block_hash,
+					segment,
+				} = req;
+				adapter.receive_kernel_segment(block_hash, segment, &self.peer_info)?;
+				Consumed::None
+			}
 
 			Message::Unknown(_) => Consumed::None,
 		};
 		Ok(consumed)
 	}
+
+	fn protocol_violation(&self, e: &Error) {
+		debug!(
+			"handler: protocol violation from peer {:?}: {:?}",
+			self.peer_info.addr, e
+		);
+		self.adapter
+			.peer_behaviour(self.peer_info.addr, ScoreEvent::ProtocolViolation);
+	}
 }
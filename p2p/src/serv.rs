@@ -14,31 +14,38 @@
 
 use std::fs::File;
 use std::io;
-use std::net::{IpAddr, Shutdown, SocketAddr, SocketAddrV4, TcpListener, TcpStream};
+use std::net::{Shutdown, SocketAddr, SocketAddrV4, TcpListener, TcpStream};
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use crate::chain;
 use crate::chain::txhashset::BitmapChunk;
 use crate::core::core;
 use crate::core::core::hash::Hash;
-use crate::core::core::{OutputIdentifier, Segment, SegmentIdentifier, TxKernel};
+use crate::core::core::{OutputIdentifier, Segment, SegmentIdentifier, ShortId, TxKernel};
 use crate::core::global;
 use crate::core::pow::Difficulty;
 use crate::handshake::Handshake;
 use crate::peer::Peer;
 use crate::peers::Peers;
+use crate::socks;
 use crate::store::PeerStore;
 use crate::types::{
 	Capabilities, ChainAdapter, Error, NetAdapter, P2PConfig, PeerAddr, PeerInfo, ReasonForBan,
-	TxHashSetRead,
+	ScoreEvent, TxHashSetRead,
 };
 use crate::util::secp::pedersen::RangeProof;
 use crate::util::StopState;
 use chrono::prelude::{DateTime, Utc};
 
+/// How long to wait for an outbound connection, proxied or not.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often the txs queued for relay are announced to our peers.
+const TX_RELAY_INTERVAL: Duration = Duration::from_millis(500);
+
 /// P2P server implementation, handling bootstrapping to find and connect to
 /// peers, receiving connections from other peers and keep track of all of them.
 pub struct Server {
@@ -78,6 +85,7 @@ impl Server {
 		listener.set_nonblocking(true)?;
 
 		let sleep_time = Duration::from_millis(5);
+		let mut last_tx_relay = Instant::now();
 		loop {
 			// Pause peer ingress connection request. Only for tests.
 			if self.stop_state.is_paused() {
@@ -95,18 +103,14 @@ impl Server {
 					// we do not want.
 					stream.set_nonblocking(false)?;
 
-					let mut peer_addr = PeerAddr(peer_addr);
+					let mut peer_addr = PeerAddr::Ip(peer_addr);
 
 					// attempt to see if it an ipv4-mapped ipv6
 					// if yes convert to ipv4
-					if peer_addr.0.is_ipv6() {
-						if let IpAddr::V6(ipv6) = peer_addr.0.ip() {
-							if let Some(ipv4) = ipv6.to_ipv4() {
-								peer_addr = PeerAddr(SocketAddr::V4(SocketAddrV4::new(
-									ipv4,
-									peer_addr.0.port(),
-								)))
-							}
+					if let PeerAddr::Ip(SocketAddr::V6(addr)) = peer_addr {
+						if let Some(ipv4) = addr.ip().to_ipv4() {
+							peer_addr =
+								PeerAddr::Ip(SocketAddr::V4(SocketAddrV4::new(ipv4, addr.port())))
 						}
 					}
 
@@ -136,6 +140,10 @@ impl Server {
 			if self.stop_state.is_stopped() {
 				break;
 			}
+			if last_tx_relay.elapsed() >= TX_RELAY_INTERVAL {
+				self.peers.flush_tx_relay();
+				last_tx_relay = Instant::now();
+			}
 			thread::sleep(sleep_time);
 		}
 		Ok(())
@@ -174,16 +182,28 @@ impl Server {
 			self.config.port,
 			addr
 		);
-		match TcpStream::connect_timeout(&addr.0, Duration::from_secs(10)) {
+		let stream = match (self.config.socks_proxy, addr) {
+			(Some(proxy), _) => socks::connect(proxy, addr, CONNECT_TIMEOUT),
+			(None, PeerAddr::Ip(socket_addr)) => {
+				TcpStream::connect_timeout(&socket_addr, CONNECT_TIMEOUT)
+			}
+			(None, PeerAddr::Onion(_)) => Err(io::Error::new(
+				io::ErrorKind::Unsupported,
+				"onion address without a socks proxy",
+			)),
+		};
+		match stream {
 			Ok(stream) => {
-				let addr = SocketAddr::new(self.config.host, self.config.port);
+				// Our onion address, if any, is advertised once connected.
+				let self_addr = PeerAddr::Ip(SocketAddr::new(self.config.host, self.config.port));
 				let total_diff = self.peers.total_difficulty()?;
 
 				let peer = Peer::connect(
 					stream,
+					addr,
 					self.capabilities,
 					total_diff,
-					PeerAddr(addr),
+					self_addr,
 					&self.handshake,
 					self.peers.clone(),
 				)?;
@@ -243,7 +263,7 @@ impl Server {
 			return true;
 		}
 		if let Ok(peer_addr) = stream.peer_addr() {
-			let peer_addr = PeerAddr(peer_addr);
+			let peer_addr = PeerAddr::Ip(peer_addr);
 			if self.peers.is_banned(peer_addr) {
 				debug!("Peer {} banned, refusing connection.", peer_addr);
 				return true;
@@ -299,6 +319,35 @@ impl ChainAdapter for DummyAdapter {
 	fn tx_kernel_received(&self, _h: Hash, _peer_info: &PeerInfo) -> Result<bool, chain::Error> {
 		Ok(true)
 	}
+	fn get_transactions(&self, _hashes: &[Hash]) -> Vec<core::Transaction> {
+		vec![]
+	}
+	fn tx_kernels_received(
+		&self,
+		_hashes: Vec<Hash>,
+		_peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		Ok(true)
+	}
+	fn tx_kernel_hashes(&self) -> Vec<Hash> {
+		vec![]
+	}
+	fn get_block_transactions(
+		&self,
+		_hash: Hash,
+		_nonce: u64,
+		_kern_ids: &[ShortId],
+	) -> Vec<core::Transaction> {
+		vec![]
+	}
+	fn block_transactions_received(
+		&self,
+		_hash: Hash,
+		_txs: Vec<core::Transaction>,
+		_peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		Ok(true)
+	}
 	fn transaction_received(
 		&self,
 		_: core::Transaction,
@@ -328,6 +377,14 @@ impl ChainAdapter for DummyAdapter {
 	) -> Result<bool, chain::Error> {
 		Ok(true)
 	}
+	fn historical_block_received(
+		&self,
+		_: core::Block,
+		_: chain::BlockOutputsProof,
+		_: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		Ok(true)
+	}
 	fn headers_received(
 		&self,
 		_: &[core::BlockHeader],
@@ -341,6 +398,15 @@ impl ChainAdapter for DummyAdapter {
 	fn get_block(&self, _: Hash, _: &PeerInfo) -> Option<core::Block> {
 		None
 	}
+	fn get_historical_block(&self, _: Hash) -> Option<(core::Block, chain::BlockOutputsProof)> {
+		None
+	}
+	fn block_exists(&self, _: Hash) -> bool {
+		false
+	}
+	fn header_exists(&self, _: Hash) -> bool {
+		false
+	}
 	fn txhashset_read(&self, _h: Hash) -> Option<TxHashSetRead> {
 		unimplemented!()
 	}
@@ -410,6 +476,44 @@ impl ChainAdapter for DummyAdapter {
 	) -> Result<Segment<RangeProof>, chain::Error> {
 		unimplemented!()
 	}
+
+	fn receive_bitmap_segment(
+		&self,
+		_block_hash: Hash,
+		_output_root: Hash,
+		_segment: Segment<BitmapChunk>,
+		_peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		unimplemented!()
+	}
+
+	fn receive_output_segment(
+		&self,
+		_block_hash: Hash,
+		_bitmap_root: Hash,
+		_segment: Segment<OutputIdentifier>,
+		_peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		unimplemented!()
+	}
+
+	fn receive_rangeproof_segment(
+		&self,
+		_block_hash: Hash,
+		_segment: Segment<RangeProof>,
+		_peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		unimplemented!()
+	}
+
+	fn receive_kernel_segment(
+		&self,
+		_block_hash: Hash,
+		_segment: Segment<TxKernel>,
+		_peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		unimplemented!()
+	}
 }
 
 impl NetAdapter for DummyAdapter {
@@ -421,4 +525,5 @@ impl NetAdapter for DummyAdapter {
 	fn is_banned(&self, _: PeerAddr) -> bool {
 		false
 	}
+	fn peer_behaviour(&self, _: PeerAddr, _: ScoreEvent) {}
 }
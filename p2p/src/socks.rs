@@ -0,0 +1,145 @@
+// Copyright 2021 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Minimal SOCKS5 client (RFC 1928), no authentication, CONNECT and Tor's
+//! RESOLVE extension only. Used to reach peers, onion services included,
+//! through a Tor proxy without leaking any DNS request outside of it.
+
+use crate::types::PeerAddr;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::time::Duration;
+
+const SOCKS_VERSION: u8 = 5;
+const NO_AUTH: u8 = 0;
+const CMD_CONNECT: u8 = 1;
+const CMD_RESOLVE: u8 = 0xF0;
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+const REPLY_SUCCEEDED: u8 = 0;
+
+fn socks_error(msg: String) -> io::Error {
+	io::Error::new(io::ErrorKind::ConnectionRefused, msg)
+}
+
+/// Opens a connection to the provided peer through the SOCKS5 proxy. The
+/// returned stream is ready to use, the same way as a direct connection.
+pub fn connect(proxy: SocketAddr, addr: PeerAddr, timeout: Duration) -> io::Result<TcpStream> {
+	let mut stream = open(proxy, timeout)?;
+
+	let mut request = vec![SOCKS_VERSION, CMD_CONNECT, 0];
+	match addr {
+		PeerAddr::Ip(SocketAddr::V4(addr)) => {
+			request.push(ATYP_IPV4);
+			request.extend_from_slice(&addr.ip().octets());
+		}
+		PeerAddr::Ip(SocketAddr::V6(addr)) => {
+			request.push(ATYP_IPV6);
+			request.extend_from_slice(&addr.ip().octets());
+		}
+		PeerAddr::Onion(onion) => {
+			let host = onion.host();
+			request.push(ATYP_DOMAIN);
+			request.push(host.len() as u8);
+			request.extend_from_slice(host.as_bytes());
+		}
+	}
+	request.extend_from_slice(&addr.port().to_be_bytes());
+	stream.write_all(&request)?;
+
+	// Skip the address the proxy bound to, we have no use for it.
+	read_reply(&mut stream, proxy, &format!("connect to {}", addr))?;
+	Ok(stream)
+}
+
+/// Resolves the provided host name through the SOCKS5 proxy, using the
+/// RESOLVE extension of Tor.
+pub fn resolve(proxy: SocketAddr, host: &str, timeout: Duration) -> io::Result<IpAddr> {
+	if host.is_empty() || host.len() > u8::MAX as usize {
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidInput,
+			format!("invalid host name to resolve: {}", host),
+		));
+	}
+	let mut stream = open(proxy, timeout)?;
+
+	let mut request = vec![SOCKS_VERSION, CMD_RESOLVE, 0, ATYP_DOMAIN, host.len() as u8];
+	request.extend_from_slice(host.as_bytes());
+	request.extend_from_slice(&[0, 0]);
+	stream.write_all(&request)?;
+
+	let what = format!("resolve {}", host);
+	read_reply(&mut stream, proxy, &what)?
+		.ok_or_else(|| socks_error(format!("socks5 proxy {} failed to {}", proxy, what)))
+}
+
+// Connects to the proxy and negotiates the (lack of) authentication.
+fn open(proxy: SocketAddr, timeout: Duration) -> io::Result<TcpStream> {
+	let mut stream = TcpStream::connect_timeout(&proxy, timeout)?;
+	stream.set_read_timeout(Some(timeout))?;
+	stream.set_write_timeout(Some(timeout))?;
+
+	stream.write_all(&[SOCKS_VERSION, 1, NO_AUTH])?;
+	let mut reply = [0; 2];
+	stream.read_exact(&mut reply)?;
+	if reply != [SOCKS_VERSION, NO_AUTH] {
+		return Err(socks_error(format!(
+			"socks5 proxy {} refused authentication method: {:?}",
+			proxy, reply
+		)));
+	}
+	Ok(stream)
+}
+
+// Reads the proxy reply to our request, returning the address it carries
+// unless a domain name.
+fn read_reply(stream: &mut TcpStream, proxy: SocketAddr, what: &str) -> io::Result<Option<IpAddr>> {
+	let mut reply = [0; 4];
+	stream.read_exact(&mut reply)?;
+	if reply[0] != SOCKS_VERSION || reply[1] != REPLY_SUCCEEDED {
+		return Err(socks_error(format!(
+			"socks5 proxy {} failed to {}, reply {}",
+			proxy, what, reply[1]
+		)));
+	}
+	let ip = match reply[3] {
+		ATYP_IPV4 => {
+			let mut octets = [0; 4];
+			stream.read_exact(&mut octets)?;
+			Some(IpAddr::from(octets))
+		}
+		ATYP_IPV6 => {
+			let mut octets = [0; 16];
+			stream.read_exact(&mut octets)?;
+			Some(IpAddr::from(octets))
+		}
+		ATYP_DOMAIN => {
+			let mut len = [0; 1];
+			stream.read_exact(&mut len)?;
+			let mut domain = vec![0; len[0] as usize];
+			stream.read_exact(&mut domain)?;
+			None
+		}
+		atyp => {
+			return Err(socks_error(format!(
+				"socks5 proxy {} replied with unknown address type {}",
+				proxy, atyp
+			)))
+		}
+	};
+	let mut port = [0; 2];
+	stream.read_exact(&mut port)?;
+	Ok(ip)
+}
@@ -55,6 +55,8 @@ pub struct PeerData {
 	pub ban_reason: ReasonForBan,
 	/// Time when we last connected to this peer.
 	pub last_connected: i64,
+	/// Score of the peer when we last saw it, see `ScoreEvent`.
+	pub score: i32,
 }
 
 impl Writeable for PeerData {
@@ -67,7 +69,8 @@ impl Writeable for PeerData {
 			[write_u8, self.flags as u8],
 			[write_i64, self.last_banned],
 			[write_i32, self.ban_reason as i32],
-			[write_i64, self.last_connected]
+			[write_i64, self.last_connected],
+			[write_i32, self.score]
 		);
 		Ok(())
 	}
@@ -80,13 +83,14 @@ impl Readable for PeerData {
 		let ua = reader.read_bytes_len_prefix()?;
 		let (fl, lb, br) = ser_multiread!(reader, read_u8, read_i64, read_i32);
 
+		// the last elements were added later on and may be missing, this only
+		// works because each PeerData is read in its own vector
 		let lc = reader.read_i64();
-		// this only works because each PeerData is read in its own vector and this
-		// is the last data element
 		let last_connected = match lc {
 			Err(_) => Utc::now().timestamp(),
 			Ok(lc) => lc,
 		};
+		let score = reader.read_i32().unwrap_or(0);
 
 		let user_agent = String::from_utf8(ua).map_err(|_| ser::Error::CorruptedData)?;
 		let capabilities = Capabilities::from_bits_truncate(capab);
@@ -101,6 +105,7 @@ impl Readable for PeerData {
 				last_banned: lb,
 				ban_reason,
 				last_connected,
+				score,
 			}),
 			None => Err(ser::Error::CorruptedData),
 		}
@@ -185,7 +190,8 @@ impl PeerStore {
 	}
 
 	/// Convenience method to load a peer data, update its status and save it
-	/// back. If new state is Banned its last banned time will be updated too.
+	/// back. If new state is Banned its last banned time will be updated too,
+	/// an unbanned peer gets a fresh score.
 	pub fn update_state(&self, peer_addr: PeerAddr, new_state: State) -> Result<(), Error> {
 		let batch = self.db.batch()?;
 
@@ -193,6 +199,9 @@ impl PeerStore {
 			option_to_not_found(batch.get_ser::<PeerData>(&peer_key(peer_addr)[..]), || {
 				format!("Peer at address: {}", peer_addr)
 			})?;
+		if peer.flags == State::Banned && new_state != State::Banned {
+			peer.score = 0;
+		}
 		peer.flags = new_state;
 		if new_state == State::Banned {
 			peer.last_banned = Utc::now().timestamp();
@@ -202,6 +211,21 @@ impl PeerStore {
 		batch.commit()
 	}
 
+	/// Convenience method to load a peer data, update its score and save it
+	/// back.
+	pub fn update_score(&self, peer_addr: PeerAddr, score: i32) -> Result<(), Error> {
+		let batch = self.db.batch()?;
+
+		let mut peer =
+			option_to_not_found(batch.get_ser::<PeerData>(&peer_key(peer_addr)[..]), || {
+				format!("Peer at address: {}", peer_addr)
+			})?;
+		peer.score = score;
+
+		batch.put_ser(&peer_key(peer_addr)[..], &peer)?;
+		batch.commit()
+	}
+
 	/// Deletes peers from the storage that satisfy some condition `predicate`
 	pub fn delete_peers<F>(&self, predicate: F) -> Result<(), Error>
 	where
@@ -23,13 +23,13 @@ use std::sync::Arc;
 
 use chrono::prelude::*;
 use serde::de::{SeqAccess, Visitor};
-use serde::{Deserialize, Deserializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use crate::chain;
 use crate::chain::txhashset::BitmapChunk;
 use crate::core::core;
 use crate::core::core::hash::Hash;
-use crate::core::core::{OutputIdentifier, Segment, SegmentIdentifier, TxKernel};
+use crate::core::core::{OutputIdentifier, Segment, SegmentIdentifier, ShortId, TxKernel};
 use crate::core::global;
 use crate::core::pow::Difficulty;
 use crate::core::ser::{self, ProtocolVersion, Readable, Reader, Writeable, Writer};
@@ -50,6 +50,20 @@ pub const MAX_PEER_ADDRS: u32 = 256;
 /// Maximum number of block header hashes to send as part of a locator
 pub const MAX_LOCATORS: u32 = 20;
 
+/// Maximum number of tx kernel hashes to announce or request in a single
+/// message
+pub const MAX_TX_KERNEL_HASHES: u32 = 512;
+
+/// Score at or below which a peer gets banned
+pub const BAN_SCORE: i32 = -100;
+
+/// Max score a peer can build up through good behaviour
+pub const MAX_SCORE: i32 = 100;
+
+/// Min interval in seconds between two rewards of a peer, so its score only
+/// builds up slowly with useful data
+pub const REWARD_INTERVAL: i64 = 60;
+
 /// How long a banned peer should be banned for
 const BAN_WINDOW: i64 = 10800;
 
@@ -90,6 +104,8 @@ pub enum Error {
 	PeerNotBanned,
 	PeerException,
 	Internal,
+	/// Failed to negotiate or use the encrypted transport
+	Encryption,
 }
 
 impl From<ser::Error> for Error {
@@ -113,13 +129,105 @@ impl From<io::Error> for Error {
 	}
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
-pub struct PeerAddr(pub SocketAddr);
+/// Length of the host part of a Tor v3 onion address, without the ".onion"
+/// suffix (base32 encoding of the service public key, checksum and version).
+pub const ONION_HOST_LEN: usize = 56;
+
+const ONION_SUFFIX: &str = ".onion";
+
+/// Tor v3 onion service address.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct OnionAddr {
+	host: [u8; ONION_HOST_LEN],
+	port: u16,
+}
+
+impl OnionAddr {
+	/// Builds an onion address from its host (with or without the ".onion"
+	/// suffix) and port.
+	pub fn new(host: &str, port: u16) -> Option<OnionAddr> {
+		let host = host.strip_suffix(ONION_SUFFIX).unwrap_or(host);
+		let host = OnionAddr::parse_host(host.as_bytes())?;
+		Some(OnionAddr { host, port })
+	}
+
+	fn parse_host(bytes: &[u8]) -> Option<[u8; ONION_HOST_LEN]> {
+		if bytes.len() != ONION_HOST_LEN
+			|| !bytes
+				.iter()
+				.all(|b| b.is_ascii_lowercase() || (b'2'..=b'7').contains(b))
+		{
+			return None;
+		}
+		let mut host = [0; ONION_HOST_LEN];
+		host.copy_from_slice(bytes);
+		Some(host)
+	}
+
+	/// Full host name, ".onion" suffix included.
+	pub fn host(&self) -> String {
+		format!("{}{}", String::from_utf8_lossy(&self.host), ONION_SUFFIX)
+	}
+
+	pub fn port(&self) -> u16 {
+		self.port
+	}
+}
+
+impl FromStr for OnionAddr {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<OnionAddr, String> {
+		let (host, port) = s
+			.rsplit_once(':')
+			.ok_or_else(|| format!("missing port in onion address {}", s))?;
+		if !host.ends_with(ONION_SUFFIX) {
+			return Err(format!("not an onion address: {}", s));
+		}
+		let port = port
+			.parse()
+			.map_err(|_| format!("invalid port in onion address {}", s))?;
+		OnionAddr::new(host, port).ok_or_else(|| format!("invalid onion address: {}", s))
+	}
+}
+
+impl fmt::Display for OnionAddr {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}:{}", self.host(), self.port)
+	}
+}
+
+impl fmt::Debug for OnionAddr {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self)
+	}
+}
+
+impl Serialize for OnionAddr {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.collect_str(self)
+	}
+}
+
+impl<'de> Deserialize<'de> for OnionAddr {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		let s = String::deserialize(deserializer)?;
+		OnionAddr::from_str(&s).map_err(serde::de::Error::custom)
+	}
+}
+
+/// Address of a peer, either a regular ip address or a Tor onion service
+/// (only reachable through a SOCKS5 proxy).
+#[derive(Debug, Clone, Copy)]
+pub enum PeerAddr {
+	Ip(SocketAddr),
+	Onion(OnionAddr),
+}
 
 impl Writeable for PeerAddr {
 	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
-		match self.0 {
-			SocketAddr::V4(sav4) => {
+		match self {
+			PeerAddr::Ip(SocketAddr::V4(sav4)) => {
 				ser_multiwrite!(
 					writer,
 					[write_u8, 0],
@@ -127,13 +235,18 @@ impl Writeable for PeerAddr {
 					[write_u16, sav4.port()]
 				);
 			}
-			SocketAddr::V6(sav6) => {
+			PeerAddr::Ip(SocketAddr::V6(sav6)) => {
 				writer.write_u8(1)?;
 				for seg in &sav6.ip().segments() {
 					writer.write_u16(*seg)?;
 				}
 				writer.write_u16(sav6.port())?;
 			}
+			PeerAddr::Onion(onion) => {
+				writer.write_u8(2)?;
+				writer.write_fixed_bytes(onion.host)?;
+				writer.write_u16(onion.port)?;
+			}
 		}
 		Ok(())
 	}
@@ -141,22 +254,27 @@ impl Writeable for PeerAddr {
 
 impl Readable for PeerAddr {
 	fn read<R: Reader>(reader: &mut R) -> Result<PeerAddr, ser::Error> {
-		let v4_or_v6 = reader.read_u8()?;
-		if v4_or_v6 == 0 {
+		let addr_type = reader.read_u8()?;
+		if addr_type == 0 {
 			let ip = reader.read_fixed_bytes(4)?;
 			let port = reader.read_u16()?;
-			Ok(PeerAddr(SocketAddr::V4(SocketAddrV4::new(
+			Ok(PeerAddr::Ip(SocketAddr::V4(SocketAddrV4::new(
 				Ipv4Addr::new(ip[0], ip[1], ip[2], ip[3]),
 				port,
 			))))
+		} else if addr_type == 2 {
+			let bytes = reader.read_fixed_bytes(ONION_HOST_LEN)?;
+			let host = OnionAddr::parse_host(&bytes).ok_or(ser::Error::CorruptedData)?;
+			let port = reader.read_u16()?;
+			Ok(PeerAddr::Onion(OnionAddr { host, port }))
 		} else {
 			let ip = try_iter_map_vec!(0..8, |_| reader.read_u16());
 			let ipv6 = Ipv6Addr::new(ip[0], ip[1], ip[2], ip[3], ip[4], ip[5], ip[6], ip[7]);
 			let port = reader.read_u16()?;
 			if let Some(ipv4) = ipv6.to_ipv4() {
-				Ok(PeerAddr(SocketAddr::V4(SocketAddrV4::new(ipv4, port))))
+				Ok(PeerAddr::Ip(SocketAddr::V4(SocketAddrV4::new(ipv4, port))))
 			} else {
-				Ok(PeerAddr(SocketAddr::V6(SocketAddrV6::new(
+				Ok(PeerAddr::Ip(SocketAddr::V6(SocketAddrV6::new(
 					ipv6, port, 0, 0,
 				))))
 			}
@@ -164,11 +282,35 @@ impl Readable for PeerAddr {
 	}
 }
 
+impl Serialize for PeerAddr {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.collect_str(self)
+	}
+}
+
+impl<'de> Deserialize<'de> for PeerAddr {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		let s = String::deserialize(deserializer)?;
+		PeerAddr::from_str(&s).map_err(serde::de::Error::custom)
+	}
+}
+
+impl FromStr for PeerAddr {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<PeerAddr, String> {
+		match SocketAddr::from_str(s) {
+			Ok(addr) => Ok(PeerAddr::Ip(addr)),
+			Err(_) => OnionAddr::from_str(s).map(PeerAddr::Onion),
+		}
+	}
+}
+
 impl<'de> Visitor<'de> for PeerAddrs {
 	type Value = PeerAddrs;
 
 	fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-		formatter.write_str("an array of dns names or IP addresses")
+		formatter.write_str("an array of dns names, IP or onion addresses")
 	}
 
 	fn visit_seq<M>(self, mut access: M) -> Result<Self::Value, M::Error>
@@ -178,15 +320,15 @@ impl<'de> Visitor<'de> for PeerAddrs {
 		let mut peers = Vec::with_capacity(access.size_hint().unwrap_or(0));
 
 		while let Some(entry) = access.next_element::<&str>()? {
-			match SocketAddr::from_str(entry) {
-				// Try to parse IP address first
-				Ok(ip) => peers.push(PeerAddr(ip)),
+			match PeerAddr::from_str(entry) {
+				// Try to parse IP (or onion) address first
+				Ok(addr) => peers.push(addr),
 				// If that fails it's probably a DNS record
 				Err(_) => {
 					let socket_addrs = entry.to_socket_addrs().map_err(|_| {
 						serde::de::Error::custom(format!("Unable to resolve DNS: {}", entry))
 					})?;
-					peers.append(&mut socket_addrs.map(PeerAddr).collect());
+					peers.append(&mut socket_addrs.map(PeerAddr::Ip).collect());
 				}
 			}
 		}
@@ -205,24 +347,27 @@ impl<'de> Deserialize<'de> for PeerAddrs {
 
 impl std::hash::Hash for PeerAddr {
 	/// If loopback address then we care about ip and port.
-	/// If regular address then we only care about the ip and ignore the port.
+	/// If regular (or onion) address then we only care about the ip (or host)
+	/// and ignore the port.
 	fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-		if self.0.ip().is_loopback() {
-			self.0.hash(state);
-		} else {
-			self.0.ip().hash(state);
+		match self {
+			PeerAddr::Ip(addr) if addr.ip().is_loopback() => addr.hash(state),
+			PeerAddr::Ip(addr) => addr.ip().hash(state),
+			PeerAddr::Onion(onion) => onion.host.hash(state),
 		}
 	}
 }
 
 impl PartialEq for PeerAddr {
 	/// If loopback address then we care about ip and port.
-	/// If regular address then we only care about the ip and ignore the port.
+	/// If regular (or onion) address then we only care about the ip (or host)
+	/// and ignore the port.
 	fn eq(&self, other: &PeerAddr) -> bool {
-		if self.0.ip().is_loopback() {
-			self.0 == other.0
-		} else {
-			self.0.ip() == other.0.ip()
+		match (self, other) {
+			(PeerAddr::Ip(a), PeerAddr::Ip(b)) if a.ip().is_loopback() => a == b,
+			(PeerAddr::Ip(a), PeerAddr::Ip(b)) => a.ip() == b.ip(),
+			(PeerAddr::Onion(a), PeerAddr::Onion(b)) => a.host == b.host,
+			_ => false,
 		}
 	}
 }
@@ -231,7 +376,10 @@ impl Eq for PeerAddr {}
 
 impl std::fmt::Display for PeerAddr {
 	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-		write!(f, "{}", self.0)
+		match self {
+			PeerAddr::Ip(addr) => write!(f, "{}", addr),
+			PeerAddr::Onion(onion) => write!(f, "{}", onion),
+		}
 	}
 }
 
@@ -240,16 +388,41 @@ impl PeerAddr {
 	/// defaults to port 3414 on mainnet and 13414 on testnet.
 	pub fn from_ip(addr: IpAddr) -> PeerAddr {
 		let port = if global::is_testnet() { 13414 } else { 3414 };
-		PeerAddr(SocketAddr::new(addr, port))
+		PeerAddr::Ip(SocketAddr::new(addr, port))
+	}
+
+	/// The socket address, none for onion addresses.
+	pub fn socket_addr(&self) -> Option<SocketAddr> {
+		match self {
+			PeerAddr::Ip(addr) => Some(*addr),
+			PeerAddr::Onion(_) => None,
+		}
+	}
+
+	pub fn port(&self) -> u16 {
+		match self {
+			PeerAddr::Ip(addr) => addr.port(),
+			PeerAddr::Onion(onion) => onion.port,
+		}
+	}
+
+	pub fn is_onion(&self) -> bool {
+		match self {
+			PeerAddr::Ip(_) => false,
+			PeerAddr::Onion(_) => true,
+		}
 	}
 
 	/// If the ip is loopback then our key is "ip:port" (mainly for local usernet testing).
 	/// Otherwise we only care about the ip (we disallow multiple peers on the same ip address).
+	/// Onion addresses are keyed by host.
 	pub fn as_key(&self) -> String {
-		if self.0.ip().is_loopback() {
-			format!("{}:{}", self.0.ip(), self.0.port())
-		} else {
-			format!("{}", self.0.ip())
+		match self {
+			PeerAddr::Ip(addr) if addr.ip().is_loopback() => {
+				format!("{}:{}", addr.ip(), addr.port())
+			}
+			PeerAddr::Ip(addr) => format!("{}", addr.ip()),
+			PeerAddr::Onion(onion) => onion.host(),
 		}
 	}
 }
@@ -285,6 +458,32 @@ pub struct P2PConfig {
 	pub peer_listener_buffer_count: Option<u32>,
 
 	pub dandelion_peer: Option<PeerAddr>,
+
+	/// Whether to encrypt the transport with peers supporting it. The
+	/// encryption is opportunistic and unauthenticated: it keeps passive
+	/// observers out but not an attacker in the middle of the connection,
+	/// who can also downgrade it to plaintext.
+	pub encrypted_transport: Option<bool>,
+
+	/// SOCKS5 proxy (Tor) used for all outbound peer connections and dns seed
+	/// lookups
+	pub socks_proxy: Option<SocketAddr>,
+
+	/// Our onion service address, advertised to peers supporting it once
+	/// connected
+	pub onion_address: Option<OnionAddr>,
+
+	/// Max upload rate across all peers, in bytes per second
+	pub upload_limit: Option<u64>,
+
+	/// Max download rate across all peers, in bytes per second
+	pub download_limit: Option<u64>,
+
+	/// Max upload rate to a single peer, in bytes per second
+	pub peer_upload_limit: Option<u64>,
+
+	/// Max download rate from a single peer, in bytes per second
+	pub peer_download_limit: Option<u64>,
 }
 
 /// Default address for peer-to-peer connections.
@@ -305,6 +504,13 @@ impl Default for P2PConfig {
 			peer_min_preferred_outbound_count: None,
 			peer_listener_buffer_count: None,
 			dandelion_peer: None,
+			encrypted_transport: None,
+			socks_proxy: None,
+			onion_address: None,
+			upload_limit: None,
+			download_limit: None,
+			peer_upload_limit: None,
+			peer_download_limit: None,
 		}
 	}
 }
@@ -351,6 +557,12 @@ impl P2PConfig {
 			None => PEER_LISTENER_BUFFER_COUNT,
 		}
 	}
+
+	/// return whether the (opportunistic, unauthenticated) encrypted transport
+	/// is enabled
+	pub fn encrypted_transport(&self) -> bool {
+		self.encrypted_transport.unwrap_or(false)
+	}
 }
 
 /// Type of seeding the server will use to find other peers on the network.
@@ -391,6 +603,15 @@ bitflags! {
 		const PIBD_HIST = 0b0001_0000;
 		/// Can provide historical blocks for archival sync.
 		const BLOCK_HIST = 0b0010_0000;
+		/// Can announce and request txs by kernel hash in batches.
+		const TX_PACKAGE = 0b0100_0000;
+		/// Can encrypt the transport once the handshake is done, opportunistic
+		/// and unauthenticated encryption.
+		const ENCRYPTED = 0b1000_0000;
+		/// Can read and relay onion peer addresses.
+		const TOR_ADDRESS = 0b1_0000_0000;
+		/// Can provide historical blocks along the proof of their outputs.
+		const BLOCK_HIST_PROOF = 0b10_0000_0000;
 	}
 }
 
@@ -402,6 +623,8 @@ impl Default for Capabilities {
 			| Capabilities::PEER_LIST
 			| Capabilities::TX_KERNEL_HASH
 			| Capabilities::PIBD_HIST
+			| Capabilities::TX_PACKAGE
+			| Capabilities::TOR_ADDRESS
 	}
 }
 
@@ -426,6 +649,47 @@ enum_from_primitive! {
 		ManualBan = 5,
 		FraudHeight = 6,
 		BadHandshake = 7,
+		BadSegment = 8,
+		LowScore = 9,
+	}
+}
+
+/// Good or bad behaviour of a peer, adjusting its score. Minor misbehaviour
+/// only costs a few points so honest but flaky peers are not banned right
+/// away, while repeated misbehaviour eventually gets a peer banned.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScoreEvent {
+	/// Sent us a valid block, header or segment we did not have yet.
+	UsefulData,
+	/// Did not answer one of our requests in time.
+	SlowResponse,
+	/// Sent us a message we could not make sense of.
+	ProtocolViolation,
+	/// Sent us invalid data, for the provided reason.
+	Misbehaviour(ReasonForBan),
+}
+
+impl ScoreEvent {
+	/// Points added to (or removed from) the peer score.
+	pub fn points(&self) -> i32 {
+		match self {
+			ScoreEvent::UsefulData => 1,
+			ScoreEvent::SlowResponse => -5,
+			ScoreEvent::ProtocolViolation => -20,
+			ScoreEvent::Misbehaviour(ReasonForBan::BadSegment) => -25,
+			ScoreEvent::Misbehaviour(ReasonForBan::BadBlockHeader)
+			| ScoreEvent::Misbehaviour(ReasonForBan::BadCompactBlock) => -50,
+			// anything else (bad full block or txhashset) is a ban, whatever the score
+			ScoreEvent::Misbehaviour(_) => BAN_SCORE - MAX_SCORE,
+		}
+	}
+
+	/// Reason recorded if this event gets the peer banned.
+	pub fn ban_reason(&self) -> ReasonForBan {
+		match self {
+			ScoreEvent::Misbehaviour(reason) => *reason,
+			_ => ReasonForBan::LowScore,
+		}
 	}
 }
 
@@ -436,6 +700,8 @@ pub struct PeerLiveInfo {
 	pub last_seen: DateTime<Utc>,
 	pub stuck_detector: DateTime<Utc>,
 	pub first_seen: DateTime<Utc>,
+	pub score: i32,
+	pub last_rewarded: Option<DateTime<Utc>>,
 }
 
 /// General information about a connected peer that's useful to other modules.
@@ -457,6 +723,8 @@ impl PeerLiveInfo {
 			first_seen: Utc::now(),
 			last_seen: Utc::now(),
 			stuck_detector: Utc::now(),
+			score: 0,
+			last_rewarded: None,
 		}
 	}
 }
@@ -490,6 +758,29 @@ impl PeerInfo {
 		self.live_info.read().first_seen
 	}
 
+	/// The current score of the peer, see `ScoreEvent`.
+	pub fn score(&self) -> i32 {
+		self.live_info.read().score
+	}
+
+	/// Adds the provided points to the peer score (capped at MAX_SCORE) and
+	/// returns the new score. Rewards within REWARD_INTERVAL of the previous
+	/// one are ignored. Takes a write lock on the live_info.
+	pub fn update_score(&self, points: i32) -> i32 {
+		let mut live_info = self.live_info.write();
+		if points > 0 {
+			let now = Utc::now();
+			if let Some(last) = live_info.last_rewarded {
+				if now - last < chrono::Duration::seconds(REWARD_INTERVAL) {
+					return live_info.score;
+				}
+			}
+			live_info.last_rewarded = Some(now);
+		}
+		live_info.score = live_info.score.saturating_add(points).min(MAX_SCORE);
+		live_info.score
+	}
+
 	/// Update the total_difficulty, height and last_seen of the peer.
 	/// Takes a write lock on the live_info.
 	pub fn update(&self, height: u64, total_difficulty: Difficulty) {
@@ -563,6 +854,38 @@ pub trait ChainAdapter: Sync + Send {
 		peer_info: &PeerInfo,
 	) -> Result<bool, chain::Error>;
 
+	/// The txs we know of with any of the provided kernel hashes.
+	fn get_transactions(&self, kernel_hashes: &[Hash]) -> Vec<core::Transaction>;
+
+	/// A batch of tx kernel hashes has been announced by one of our peers.
+	fn tx_kernels_received(
+		&self,
+		kernel_hashes: Vec<Hash>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error>;
+
+	/// Kernel hashes of the txs we can relay, announced to newly connected
+	/// peers.
+	fn tx_kernel_hashes(&self) -> Vec<Hash>;
+
+	/// The recent txs we know of with kernels matching any of the provided
+	/// short ids of a compact block.
+	fn get_block_transactions(
+		&self,
+		hash: Hash,
+		nonce: u64,
+		kern_ids: &[ShortId],
+	) -> Vec<core::Transaction>;
+
+	/// The txs of a compact block we asked one of our peers for have been
+	/// received.
+	fn block_transactions_received(
+		&self,
+		hash: Hash,
+		txs: Vec<core::Transaction>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error>;
+
 	/// A block has been received from one of our peers. Returns true if the
 	/// block could be handled properly and is not deemed defective by the
 	/// chain. Returning false means the block will never be valid and
@@ -580,6 +903,16 @@ pub trait ChainAdapter: Sync + Send {
 		peer_info: &PeerInfo,
 	) -> Result<bool, chain::Error>;
 
+	/// A historical block we asked an archive peer for has been received.
+	/// Returning false means the block or its proof is defective and may
+	/// result in the peer being banned.
+	fn historical_block_received(
+		&self,
+		b: core::Block,
+		proof: chain::BlockOutputsProof,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error>;
+
 	fn header_received(
 		&self,
 		bh: core::BlockHeader,
@@ -604,6 +937,16 @@ pub trait ChainAdapter: Sync + Send {
 	/// Converts block to v2 compatibility if necessary (based on peer protocol version).
 	fn get_block(&self, h: Hash, peer_info: &PeerInfo) -> Option<core::Block>;
 
+	/// Gets a historical full block by its hash, along the proof of its outputs.
+	/// Only served by archive nodes.
+	fn get_historical_block(&self, h: Hash) -> Option<(core::Block, chain::BlockOutputsProof)>;
+
+	/// Whether we already have the full block with the provided hash.
+	fn block_exists(&self, h: Hash) -> bool;
+
+	/// Whether we already have the block header with the provided hash.
+	fn header_exists(&self, h: Hash) -> bool;
+
 	/// Provides a reading view into the current txhashset state as well as
 	/// the required indexes for a consumer to rewind to a consistant state
 	/// at the provided block hash.
@@ -667,6 +1010,42 @@ pub trait ChainAdapter: Sync + Send {
 		hash: Hash,
 		id: SegmentIdentifier,
 	) -> Result<Segment<RangeProof>, chain::Error>;
+
+	/// A bitmap segment has been received from one of our peers, in response to
+	/// one of our PIBD requests. Returning false means the segment is invalid and
+	/// may result in the peer being banned.
+	fn receive_bitmap_segment(
+		&self,
+		block_hash: Hash,
+		output_root: Hash,
+		segment: Segment<BitmapChunk>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error>;
+
+	/// An output segment has been received from one of our peers.
+	fn receive_output_segment(
+		&self,
+		block_hash: Hash,
+		bitmap_root: Hash,
+		segment: Segment<OutputIdentifier>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error>;
+
+	/// A rangeproof segment has been received from one of our peers.
+	fn receive_rangeproof_segment(
+		&self,
+		block_hash: Hash,
+		segment: Segment<RangeProof>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error>;
+
+	/// A kernel segment has been received from one of our peers.
+	fn receive_kernel_segment(
+		&self,
+		block_hash: Hash,
+		segment: Segment<TxKernel>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error>;
 }
 
 /// Additional methods required by the protocol that don't need to be
@@ -684,6 +1063,9 @@ pub trait NetAdapter: ChainAdapter {
 
 	/// Is this peer currently banned?
 	fn is_banned(&self, addr: PeerAddr) -> bool;
+
+	/// Observed some good or bad behaviour from a connected peer.
+	fn peer_behaviour(&self, _: PeerAddr, _: ScoreEvent);
 }
 
 #[derive(Clone, Debug)]
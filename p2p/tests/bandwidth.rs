@@ -0,0 +1,70 @@
+// Copyright 2021 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::{Duration, Instant};
+
+use grin_p2p as p2p;
+
+use crate::p2p::bandwidth::{Bandwidth, RateLimiter};
+
+// A second worth of transfers goes through right away, anything beyond has
+// to wait for the allowance to build up again.
+#[test]
+fn test_rate_limiter() {
+	let mut limiter = RateLimiter::new(1000);
+	let now = Instant::now();
+	assert_eq!(limiter.consume_at(1000, now), Duration::from_secs(0));
+	assert_eq!(limiter.consume_at(500, now), Duration::from_millis(500));
+
+	// debt accumulates, minus the allowance built up in the meantime
+	let later = now + Duration::from_millis(250);
+	assert_eq!(limiter.consume_at(500, later), Duration::from_millis(750));
+
+	// once paid off, the allowance never exceeds a second worth of transfers
+	let much_later = later + Duration::from_secs(10);
+	assert_eq!(limiter.consume_at(1000, much_later), Duration::from_secs(0));
+	assert_eq!(
+		limiter.consume_at(100, much_later),
+		Duration::from_millis(100)
+	);
+}
+
+// The global limit is shared by all connections, the per peer ones are not.
+#[test]
+fn test_bandwidth_limits() {
+	let config = p2p::P2PConfig {
+		upload_limit: Some(100_000),
+		peer_download_limit: Some(100_000),
+		..p2p::P2PConfig::default()
+	};
+	let bandwidth = Bandwidth::new(&config);
+
+	let (upload1, download1) = bandwidth.limits();
+	let (upload2, download2) = bandwidth.limits();
+
+	// use up the global upload allowance from the first connection, the second
+	// connection has to wait
+	assert_eq!(upload1.delay(100_000), Duration::from_secs(0));
+	assert!(upload2.delay(10_000) > Duration::from_millis(90));
+
+	// download limits are per connection
+	assert_eq!(download1.delay(100_000), Duration::from_secs(0));
+	assert_eq!(download2.delay(10_000), Duration::from_secs(0));
+	assert!(download1.delay(10_000) > Duration::from_millis(90));
+
+	// no limit configured
+	let (upload, download) = Bandwidth::new(&p2p::P2PConfig::default()).limits();
+	assert_eq!(upload.delay(u64::MAX), Duration::from_secs(0));
+	assert_eq!(download.delay(u64::MAX), Duration::from_secs(0));
+}
@@ -0,0 +1,330 @@
+// Copyright 2021 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Common test functions
+
+use self::chain::txhashset::BitmapChunk;
+use self::core::core::hash::{Hash, Hashed};
+use self::core::core::{
+	OutputIdentifier, Segment, SegmentIdentifier, ShortId, Transaction, TxKernel,
+};
+use self::core::pow::Difficulty;
+use self::p2p::types::{NetAdapter, PeerAddr, PeerInfo, ScoreEvent, TxHashSetRead};
+use self::p2p::{Capabilities, ChainAdapter};
+use self::util::secp::pedersen::RangeProof;
+use self::util::{OneTime, RwLock, StopState};
+use chrono::prelude::{DateTime, Utc};
+use grin_chain as chain;
+use grin_core as core;
+use grin_p2p as p2p;
+use grin_util as util;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::sync::{Arc, Weak};
+use std::thread;
+
+pub fn open_port() -> u16 {
+	// use port 0 to allow the OS to assign an open port
+	// TcpListener's Drop impl will unbind the port as soon as
+	// listener goes out of scope
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	listener.local_addr().unwrap().port()
+}
+
+/// Starts a p2p server listening on an open local port.
+pub fn start_server(db_root: &str, adapter: Arc<dyn ChainAdapter>) -> Arc<p2p::Server> {
+	let p2p_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		peers_allow: None,
+		peers_deny: None,
+		..p2p::P2PConfig::default()
+	};
+	let server = Arc::new(
+		p2p::Server::new(
+			db_root,
+			Capabilities::default(),
+			p2p_config,
+			adapter,
+			Hash::from_vec(&[]),
+			Arc::new(StopState::new()),
+		)
+		.unwrap(),
+	);
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen());
+	server
+}
+
+/// Configurable chain adapter for tests, knowing nothing by default. Keeps
+/// the txs it receives by kernel hash, fetching the announced ones it misses
+/// from the announcing peer once given the peers.
+pub struct TestAdapter {
+	pub txs: RwLock<HashMap<Hash, Transaction>>,
+	pub peers: OneTime<Weak<p2p::Peers>>,
+	/// Headers we already have
+	pub known_headers: RwLock<HashSet<Hash>>,
+	/// Headers at this height are deemed invalid
+	pub bad_height: Option<u64>,
+}
+
+impl Default for TestAdapter {
+	fn default() -> TestAdapter {
+		TestAdapter {
+			txs: RwLock::new(HashMap::new()),
+			peers: OneTime::new(),
+			known_headers: RwLock::new(HashSet::new()),
+			bad_height: None,
+		}
+	}
+}
+
+impl ChainAdapter for TestAdapter {
+	fn total_difficulty(&self) -> Result<Difficulty, chain::Error> {
+		Ok(Difficulty::min_dma())
+	}
+	fn total_height(&self) -> Result<u64, chain::Error> {
+		Ok(0)
+	}
+	fn get_transaction(&self, _h: Hash) -> Option<core::core::Transaction> {
+		None
+	}
+
+	fn tx_kernel_received(&self, _h: Hash, _peer_info: &PeerInfo) -> Result<bool, chain::Error> {
+		Ok(true)
+	}
+	fn get_transactions(&self, hashes: &[Hash]) -> Vec<core::core::Transaction> {
+		let txs = self.txs.read();
+		hashes.iter().filter_map(|h| txs.get(h).cloned()).collect()
+	}
+	fn tx_kernels_received(
+		&self,
+		hashes: Vec<Hash>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		let missing: Vec<_> = hashes
+			.into_iter()
+			.filter(|h| !self.txs.read().contains_key(h))
+			.collect();
+		if !missing.is_empty() && self.peers.is_init() {
+			let peers = self.peers.borrow().upgrade().unwrap();
+			let peer = peers.get_connected_peer(peer_info.addr).unwrap();
+			peer.send_txs_request(missing).unwrap();
+		}
+		Ok(true)
+	}
+	fn tx_kernel_hashes(&self) -> Vec<Hash> {
+		vec![]
+	}
+	fn get_block_transactions(
+		&self,
+		_hash: Hash,
+		_nonce: u64,
+		_kern_ids: &[ShortId],
+	) -> Vec<core::core::Transaction> {
+		vec![]
+	}
+	fn block_transactions_received(
+		&self,
+		_hash: Hash,
+		_txs: Vec<core::core::Transaction>,
+		_peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		Ok(true)
+	}
+	fn transaction_received(
+		&self,
+		tx: core::core::Transaction,
+		_stem: bool,
+	) -> Result<bool, chain::Error> {
+		self.txs.write().insert(tx.kernels()[0].hash(), tx);
+		Ok(true)
+	}
+	fn compact_block_received(
+		&self,
+		_cb: core::core::CompactBlock,
+		_peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		Ok(true)
+	}
+	fn header_received(
+		&self,
+		bh: core::core::BlockHeader,
+		_peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		Ok(Some(bh.height) != self.bad_height)
+	}
+	fn block_received(
+		&self,
+		_: core::core::Block,
+		_: &PeerInfo,
+		_: chain::Options,
+	) -> Result<bool, chain::Error> {
+		Ok(true)
+	}
+	fn historical_block_received(
+		&self,
+		_: core::core::Block,
+		_: chain::BlockOutputsProof,
+		_: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		Ok(true)
+	}
+	fn headers_received(
+		&self,
+		_: &[core::core::BlockHeader],
+		_: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		Ok(true)
+	}
+	fn locate_headers(&self, _: &[Hash]) -> Result<Vec<core::core::BlockHeader>, chain::Error> {
+		Ok(vec![])
+	}
+	fn get_block(&self, _: Hash, _: &PeerInfo) -> Option<core::core::Block> {
+		None
+	}
+	fn get_historical_block(
+		&self,
+		_: Hash,
+	) -> Option<(core::core::Block, chain::BlockOutputsProof)> {
+		None
+	}
+	fn block_exists(&self, _: Hash) -> bool {
+		false
+	}
+	fn header_exists(&self, h: Hash) -> bool {
+		self.known_headers.read().contains(&h)
+	}
+	fn txhashset_read(&self, _h: Hash) -> Option<TxHashSetRead> {
+		unimplemented!()
+	}
+
+	fn txhashset_archive_header(&self) -> Result<core::core::BlockHeader, chain::Error> {
+		unimplemented!()
+	}
+
+	fn txhashset_receive_ready(&self) -> bool {
+		false
+	}
+
+	fn txhashset_write(
+		&self,
+		_h: Hash,
+		_txhashset_data: File,
+		_peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		Ok(false)
+	}
+
+	fn txhashset_download_update(
+		&self,
+		_start_time: DateTime<Utc>,
+		_downloaded_size: u64,
+		_total_size: u64,
+	) -> bool {
+		false
+	}
+
+	fn get_tmp_dir(&self) -> PathBuf {
+		unimplemented!()
+	}
+
+	fn get_tmpfile_pathname(&self, _tmpfile_name: String) -> PathBuf {
+		unimplemented!()
+	}
+
+	fn get_kernel_segment(
+		&self,
+		_hash: Hash,
+		_id: SegmentIdentifier,
+	) -> Result<Segment<TxKernel>, chain::Error> {
+		unimplemented!()
+	}
+
+	fn get_bitmap_segment(
+		&self,
+		_hash: Hash,
+		_id: SegmentIdentifier,
+	) -> Result<(Segment<BitmapChunk>, Hash), chain::Error> {
+		unimplemented!()
+	}
+
+	fn get_output_segment(
+		&self,
+		_hash: Hash,
+		_id: SegmentIdentifier,
+	) -> Result<(Segment<OutputIdentifier>, Hash), chain::Error> {
+		unimplemented!()
+	}
+
+	fn get_rangeproof_segment(
+		&self,
+		_hash: Hash,
+		_id: SegmentIdentifier,
+	) -> Result<Segment<RangeProof>, chain::Error> {
+		unimplemented!()
+	}
+
+	fn receive_bitmap_segment(
+		&self,
+		_block_hash: Hash,
+		_output_root: Hash,
+		_segment: Segment<BitmapChunk>,
+		_peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		unimplemented!()
+	}
+
+	fn receive_output_segment(
+		&self,
+		_block_hash: Hash,
+		_bitmap_root: Hash,
+		_segment: Segment<OutputIdentifier>,
+		_peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		unimplemented!()
+	}
+
+	fn receive_rangeproof_segment(
+		&self,
+		_block_hash: Hash,
+		_segment: Segment<RangeProof>,
+		_peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		unimplemented!()
+	}
+
+	fn receive_kernel_segment(
+		&self,
+		_block_hash: Hash,
+		_segment: Segment<TxKernel>,
+		_peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		unimplemented!()
+	}
+}
+
+impl NetAdapter for TestAdapter {
+	fn find_peer_addrs(&self, _: Capabilities) -> Vec<PeerAddr> {
+		vec![]
+	}
+	fn peer_addrs_received(&self, _: Vec<PeerAddr>) {}
+	fn peer_difficulty(&self, _: PeerAddr, _: Difficulty, _: u64) {}
+	fn is_banned(&self, _: PeerAddr) -> bool {
+		false
+	}
+	fn peer_behaviour(&self, _: PeerAddr, _: ScoreEvent) {}
+}
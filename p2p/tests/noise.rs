@@ -0,0 +1,155 @@
+// Copyright 2021 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_p2p as p2p;
+
+use p2p::noise::{
+	handshake_transcript, EphemeralKey, FrameReader, FrameWriter, Session, Transcript,
+	MAX_FRAME_LEN,
+};
+use std::io::{self, Read, Write};
+
+fn sessions() -> (Session, Session) {
+	let transcript = handshake_transcript(b"hand", b"shake");
+	sessions_with(&transcript, &transcript)
+}
+
+fn sessions_with(initiator_view: &Transcript, responder_view: &Transcript) -> (Session, Session) {
+	let initiator = EphemeralKey::generate().unwrap();
+	let responder = EphemeralKey::generate().unwrap();
+	let initiator_exchange = initiator.key_exchange();
+	let responder_exchange = responder.key_exchange();
+	(
+		initiator
+			.agree(&responder_exchange, true, initiator_view)
+			.unwrap(),
+		responder
+			.agree(&initiator_exchange, false, responder_view)
+			.unwrap(),
+	)
+}
+
+// Reader returning the data a few bytes at a time, timing out every other call.
+struct Flaky<'a> {
+	data: &'a [u8],
+	timeout: bool,
+}
+
+impl<'a> Read for Flaky<'a> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		self.timeout = !self.timeout;
+		if self.timeout {
+			return Err(io::ErrorKind::TimedOut.into());
+		}
+		let n = buf.len().min(self.data.len()).min(7);
+		buf[..n].copy_from_slice(&self.data[..n]);
+		self.data = &self.data[n..];
+		Ok(n)
+	}
+}
+
+#[test]
+fn encrypted_roundtrip() {
+	let (initiator, responder) = sessions();
+	let msg: Vec<u8> = (0..3 * MAX_FRAME_LEN + 100).map(|i| i as u8).collect();
+
+	let mut writer = FrameWriter::new(vec![], Some(initiator.sender));
+	writer.write_all(&msg).unwrap();
+	writer.write_all(b"ping").unwrap();
+	let sent = writer.get_ref().clone();
+	assert_eq!(sent.len(), msg.len() + 4 + 5 * (4 + 16));
+	assert!(!sent.windows(4).any(|w| w == b"ping"));
+
+	// Reading resumes across timeouts, even in the middle of a frame.
+	let mut reader = FrameReader::new(
+		Flaky {
+			data: &sent,
+			timeout: false,
+		},
+		Some(responder.receiver),
+	);
+	let mut received = vec![];
+	let mut buf = [0; 1000];
+	loop {
+		match reader.read(&mut buf) {
+			Ok(0) => break,
+			Ok(n) => received.extend_from_slice(&buf[..n]),
+			Err(e) => assert_eq!(e.kind(), io::ErrorKind::TimedOut),
+		}
+	}
+	assert_eq!(&received[..msg.len()], &msg[..]);
+	assert_eq!(&received[msg.len()..], b"ping");
+
+	// The other direction uses its own key.
+	let mut writer = FrameWriter::new(vec![], Some(responder.sender));
+	writer.write_all(b"pong").unwrap();
+	let mut reader = FrameReader::new(&writer.get_ref()[..], Some(initiator.receiver));
+	let mut buf = [0; 4];
+	reader.read_exact(&mut buf).unwrap();
+	assert_eq!(&buf, b"pong");
+}
+
+#[test]
+fn encrypted_tampering() {
+	let (initiator, responder) = sessions();
+	let mut writer = FrameWriter::new(vec![], Some(initiator.sender));
+	writer.write_all(b"hello").unwrap();
+	let mut sent = writer.get_ref().clone();
+	sent[6] ^= 1;
+
+	let mut reader = FrameReader::new(&sent[..], Some(responder.receiver));
+	let mut buf = [0; 5];
+	assert_eq!(
+		reader.read_exact(&mut buf).unwrap_err().kind(),
+		io::ErrorKind::InvalidData
+	);
+}
+
+// Peers that saw a different handshake, tampered with in transit, don't
+// agree on the session keys.
+#[test]
+fn encrypted_transcript_mismatch() {
+	let (initiator, responder) = sessions_with(
+		&handshake_transcript(b"hand", b"shake"),
+		&handshake_transcript(b"hanD", b"shake"),
+	);
+	let mut writer = FrameWriter::new(vec![], Some(initiator.sender));
+	writer.write_all(b"hello").unwrap();
+
+	let mut reader = FrameReader::new(&writer.get_ref()[..], Some(responder.receiver));
+	let mut buf = [0; 5];
+	assert_eq!(
+		reader.read_exact(&mut buf).unwrap_err().kind(),
+		io::ErrorKind::InvalidData
+	);
+
+	// Message boundaries are part of the transcript.
+	assert_ne!(
+		handshake_transcript(b"hand", b"shake"),
+		handshake_transcript(b"hands", b"hake")
+	);
+}
+
+#[test]
+fn plaintext_passthrough() {
+	let mut writer = FrameWriter::new(vec![], None);
+	writer.write_all(b"hello").unwrap();
+	assert!(!writer.is_encrypted());
+	assert_eq!(writer.get_ref(), b"hello");
+
+	let mut reader = FrameReader::new(&b"hello"[..], None);
+	let mut buf = vec![];
+	reader.read_to_end(&mut buf).unwrap();
+	assert_eq!(buf, b"hello");
+}
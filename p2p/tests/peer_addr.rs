@@ -17,7 +17,8 @@ use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 
 use grin_p2p as p2p;
 
-use crate::p2p::types::PeerAddr;
+use crate::p2p::types::{OnionAddr, PeerAddr};
+use grin_core::ser;
 
 // Test the behavior of a hashmap of peers keyed by peer_addr.
 #[test]
@@ -25,14 +26,14 @@ fn test_peer_addr_hashing() {
 	let mut peers: HashMap<PeerAddr, String> = HashMap::new();
 
 	let socket_addr1 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 0, 1)), 8080);
-	let peer_addr1 = PeerAddr(socket_addr1);
+	let peer_addr1 = PeerAddr::Ip(socket_addr1);
 	peers.insert(peer_addr1, "peer1".into());
 
 	assert!(peers.contains_key(&peer_addr1));
 	assert_eq!(peers.len(), 1);
 
 	let socket_addr2 = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 0, 1)), 8081);
-	let peer_addr2 = PeerAddr(socket_addr2);
+	let peer_addr2 = PeerAddr::Ip(socket_addr2);
 
 	// Expected behavior here is to ignore the port when hashing peer_addr.
 	// This means the two peer_addr instances above are seen as the same addr.
@@ -48,8 +49,52 @@ fn test_peer_addr_hashing() {
 
 	// Check they are treated as the same even though their underlying ports are different.
 	assert_eq!(peer_addr1, peer_addr2);
-	assert_eq!(peer_addr1.0, socket_addr1);
-	assert_eq!(peer_addr2.0, socket_addr2);
-	assert_eq!(peer_addr1.0.port(), 8080);
-	assert_eq!(peer_addr2.0.port(), 8081);
+	assert_eq!(peer_addr1.socket_addr(), Some(socket_addr1));
+	assert_eq!(peer_addr2.socket_addr(), Some(socket_addr2));
+	assert_eq!(peer_addr1.port(), 8080);
+	assert_eq!(peer_addr2.port(), 8081);
+}
+
+const ONION_HOST: &str = "vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd";
+
+// Onion addresses are keyed by host, like regular addresses by ip.
+#[test]
+fn test_onion_peer_addr() {
+	let addr: PeerAddr = format!("{}.onion:3414", ONION_HOST).parse().unwrap();
+	assert!(addr.is_onion());
+	assert_eq!(addr.socket_addr(), None);
+	assert_eq!(addr.port(), 3414);
+	assert_eq!(addr.to_string(), format!("{}.onion:3414", ONION_HOST));
+	assert_eq!(addr.as_key(), format!("{}.onion", ONION_HOST));
+
+	let other_port = PeerAddr::Onion(OnionAddr::new(ONION_HOST, 13414).unwrap());
+	assert_eq!(addr, other_port);
+	let mut peers: HashMap<PeerAddr, String> = HashMap::new();
+	peers.insert(addr, "peer1".into());
+	assert!(peers.contains_key(&other_port));
+
+	// Serialized as tag, host and port.
+	let version = ser::ProtocolVersion::local();
+	let vec = ser::ser_vec(&addr, version).unwrap();
+	assert_eq!(vec.len(), 1 + 56 + 2);
+	let read: PeerAddr = ser::deserialize(&mut &vec[..], version).unwrap();
+	assert_eq!(read.to_string(), addr.to_string());
+
+	let ip: PeerAddr = "10.0.0.1:3414".parse().unwrap();
+	assert_ne!(addr, ip);
+	assert_eq!(
+		serde_json::to_string(&ip).unwrap(),
+		"\"10.0.0.1:3414\"".to_string()
+	);
+	let json = serde_json::to_string(&addr).unwrap();
+	let from_json: PeerAddr = serde_json::from_str(&json).unwrap();
+	assert_eq!(from_json.to_string(), addr.to_string());
+
+	// Wrong length, uppercase, missing port or suffix.
+	assert!("abc.onion:3414".parse::<PeerAddr>().is_err());
+	assert!(format!("{}.onion:3414", ONION_HOST.to_uppercase())
+		.parse::<PeerAddr>()
+		.is_err());
+	assert!(format!("{}.onion", ONION_HOST).parse::<PeerAddr>().is_err());
+	assert!(format!("{}:3414", ONION_HOST).parse::<PeerAddr>().is_err());
 }
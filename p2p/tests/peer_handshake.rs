@@ -45,10 +45,18 @@ fn test_setup() {
 
 // Starts a server and connects a client peer to it to check handshake,
 // followed by a ping/pong exchange to make sure the connection is live.
+// Same over the encrypted transport, and falling back to plaintext when only
+// one side supports it.
 #[test]
 fn peer_handshake() {
 	test_setup();
+	handshake(p2p::Capabilities::UNKNOWN, p2p::Capabilities::UNKNOWN);
+	handshake(p2p::Capabilities::ENCRYPTED, p2p::Capabilities::ENCRYPTED);
+	handshake(p2p::Capabilities::ENCRYPTED, p2p::Capabilities::UNKNOWN);
+	handshake(p2p::Capabilities::UNKNOWN, p2p::Capabilities::ENCRYPTED);
+}
 
+fn handshake(server_capab: p2p::Capabilities, client_capab: p2p::Capabilities) {
 	let p2p_config = p2p::P2PConfig {
 		host: "127.0.0.1".parse().unwrap(),
 		port: open_port(),
@@ -60,7 +68,7 @@ fn peer_handshake() {
 	let server = Arc::new(
 		p2p::Server::new(
 			".grin",
-			p2p::Capabilities::UNKNOWN,
+			server_capab,
 			p2p_config.clone(),
 			net_adapter.clone(),
 			Hash::from_vec(&vec![]),
@@ -77,10 +85,11 @@ fn peer_handshake() {
 	let addr = SocketAddr::new(p2p_config.host, p2p_config.port);
 	let socket = TcpStream::connect_timeout(&addr, time::Duration::from_secs(10)).unwrap();
 
-	let my_addr = PeerAddr("127.0.0.1:5000".parse().unwrap());
+	let my_addr = PeerAddr::Ip("127.0.0.1:5000".parse().unwrap());
 	let peer = Peer::connect(
 		socket,
-		p2p::Capabilities::UNKNOWN,
+		PeerAddr::Ip(addr),
+		client_capab,
 		Difficulty::min_dma(),
 		my_addr,
 		&p2p::handshake::Handshake::new(Hash::from_vec(&vec![]), p2p_config.clone()),
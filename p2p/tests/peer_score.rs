@@ -0,0 +1,201 @@
+// Copyright 2021 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+pub mod common;
+
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::{thread, time};
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use crate::common::{start_server, TestAdapter};
+use crate::core::core::hash::Hashed;
+use crate::core::global;
+use crate::core::pow::{Difficulty, Proof};
+use crate::core::ser::{self, ProtocolVersion};
+use crate::p2p::types::PeerLiveInfo;
+use crate::p2p::{
+	Capabilities, ChainAdapter, Direction, PeerAddr, PeerData, PeerInfo, ReasonForBan, ScoreEvent,
+	State, BAN_SCORE, MAX_SCORE, REWARD_INTERVAL,
+};
+use chrono::Duration;
+use grin_util as util;
+use grin_util::RwLock;
+
+// Height of the headers our test chain deems invalid.
+const BAD_HEIGHT: u64 = 13;
+
+// Allows the next reward of the peer without waiting for REWARD_INTERVAL.
+fn rewind_last_reward(info: &PeerInfo) {
+	let mut live_info = info.live_info.write();
+	live_info.last_rewarded = live_info
+		.last_rewarded
+		.map(|t| t - Duration::seconds(REWARD_INTERVAL));
+}
+
+fn peer_addr() -> PeerAddr {
+	PeerAddr::Ip(SocketAddr::new(
+		IpAddr::V4(Ipv4Addr::new(192, 168, 0, 1)),
+		3414,
+	))
+}
+
+fn peer_info() -> PeerInfo {
+	PeerInfo {
+		capabilities: Capabilities::default(),
+		user_agent: "test".to_string(),
+		version: ProtocolVersion::local(),
+		addr: peer_addr(),
+		direction: Direction::Outbound,
+		live_info: Arc::new(RwLock::new(PeerLiveInfo::new(Difficulty::zero()))),
+	}
+}
+
+// Minor misbehaviour only gets a peer banned once repeated, while invalid
+// full blocks get it banned whatever its score.
+#[test]
+fn test_score_events() {
+	let info = peer_info();
+	assert_eq!(info.score(), 0);
+
+	let bad_segment = ScoreEvent::Misbehaviour(ReasonForBan::BadSegment);
+	for _ in 0..3 {
+		assert!(info.update_score(bad_segment.points()) > BAN_SCORE);
+	}
+	assert!(info.update_score(bad_segment.points()) <= BAN_SCORE);
+	assert_eq!(bad_segment.ban_reason(), ReasonForBan::BadSegment);
+
+	let info = peer_info();
+	assert!(info.update_score(ScoreEvent::SlowResponse.points()) > BAN_SCORE);
+	assert!(info.update_score(ScoreEvent::ProtocolViolation.points()) > BAN_SCORE);
+	assert_eq!(
+		ScoreEvent::SlowResponse.ban_reason(),
+		ReasonForBan::LowScore
+	);
+
+	// rewards are rate limited
+	let info = peer_info();
+	assert_eq!(info.update_score(ScoreEvent::UsefulData.points()), 1);
+	assert_eq!(info.update_score(ScoreEvent::UsefulData.points()), 1);
+
+	// good behaviour builds up to MAX_SCORE, which only lets a peer get away
+	// with some minor misbehaviour
+	for _ in 0..2 * MAX_SCORE {
+		rewind_last_reward(&info);
+		info.update_score(ScoreEvent::UsefulData.points());
+	}
+	assert_eq!(info.score(), MAX_SCORE);
+	let bad_header = ScoreEvent::Misbehaviour(ReasonForBan::BadBlockHeader);
+	assert!(info.update_score(bad_header.points()) > BAN_SCORE);
+	let bad_block = ScoreEvent::Misbehaviour(ReasonForBan::BadBlock);
+	assert!(info.update_score(bad_block.points()) <= BAN_SCORE);
+}
+
+// The score is stored after the other fields of the peer data and defaults
+// to 0 for data saved before it was introduced.
+#[test]
+fn test_peer_data_score() {
+	let version = ProtocolVersion::local();
+	let data = PeerData {
+		addr: peer_addr(),
+		capabilities: Capabilities::default(),
+		user_agent: "test".to_string(),
+		flags: State::Healthy,
+		last_banned: 0,
+		ban_reason: ReasonForBan::None,
+		last_connected: 1_600_000_000,
+		score: -42,
+	};
+
+	let vec = ser::ser_vec(&data, version).unwrap();
+	let read: PeerData = ser::deserialize(&mut &vec[..], version).unwrap();
+	assert_eq!(read.score, -42);
+	assert_eq!(read.last_connected, 1_600_000_000);
+
+	let old = &vec[..vec.len() - 4];
+	let read: PeerData = ser::deserialize(&mut &old[..], version).unwrap();
+	assert_eq!(read.score, 0);
+	assert_eq!(read.last_connected, 1_600_000_000);
+}
+
+// Headers received from a connected peer only reward it when new to us, and
+// get it banned once invalid often enough.
+#[test]
+fn test_score_received_headers() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+	let db_root = "target/tmp/.peer_score";
+	let _ = fs::remove_dir_all(db_root);
+
+	let header = |height| {
+		let mut header = core::core::BlockHeader {
+			height,
+			..Default::default()
+		};
+		header.pow.proof = Proof::random(global::proofsize());
+		header
+	};
+	let known = header(1);
+	let adapter = TestAdapter {
+		bad_height: Some(BAD_HEIGHT),
+		..TestAdapter::default()
+	};
+	adapter.known_headers.write().insert(known.hash());
+	let server = start_server(&format!("{}/server", db_root), Arc::new(adapter));
+	let client = start_server(
+		&format!("{}/client", db_root),
+		Arc::new(p2p::DummyAdapter {}),
+	);
+	thread::sleep(time::Duration::from_secs(1));
+
+	let addr = SocketAddr::new(server.config.host, server.config.port);
+	client.connect(PeerAddr::Ip(addr)).unwrap();
+	thread::sleep(time::Duration::from_secs(1));
+	let peer = server.peers.iter().connected().into_iter().next().unwrap();
+	let peers = &server.peers;
+
+	// a header we already have is not rewarded
+	assert!(peers.header_received(known, &peer.info).unwrap());
+	assert_eq!(peer.info.score(), 0);
+
+	// a new one is, but only once per REWARD_INTERVAL
+	assert!(peers.header_received(header(2), &peer.info).unwrap());
+	assert_eq!(peer.info.score(), 1);
+	assert!(peers.header_received(header(3), &peer.info).unwrap());
+	assert_eq!(peer.info.score(), 1);
+	rewind_last_reward(&peer.info);
+	assert!(peers.header_received(header(4), &peer.info).unwrap());
+	assert_eq!(peer.info.score(), 2);
+
+	// invalid headers cost the peer its connection once repeated
+	assert!(!peers
+		.header_received(header(BAD_HEIGHT), &peer.info)
+		.unwrap());
+	assert!(!peers
+		.header_received(header(BAD_HEIGHT), &peer.info)
+		.unwrap());
+	assert!(!peers.is_banned(peer.info.addr));
+	assert!(!peers
+		.header_received(header(BAD_HEIGHT), &peer.info)
+		.unwrap());
+	assert!(peers.is_banned(peer.info.addr));
+	assert!(peers.get_connected_peer(peer.info.addr).is_none());
+
+	server.stop();
+	client.stop();
+	let _ = fs::remove_dir_all(db_root);
+}
@@ -12,6 +12,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use grin_chain::BlockOutputsProof;
+use grin_core::core::hash::Hash;
+use grin_core::core::ShortId;
+use grin_core::ser;
 use grin_p2p as p2p;
 
 use num::FromPrimitive;
@@ -49,28 +53,104 @@ fn test_capabilities() {
 		p2p::types::Capabilities::UNKNOWN
 	);
 	assert_eq!(
-		p2p::types::Capabilities::from_bits_truncate(0b10000000 as u32),
+		p2p::types::Capabilities::from_bits_truncate(0b10000000000 as u32),
 		p2p::types::Capabilities::UNKNOWN
 	);
 
 	assert_eq!(
 		expected,
-		p2p::types::Capabilities::from_bits_truncate(0b11111 as u32),
+		p2p::types::Capabilities::from_bits_truncate(0b101011111 as u32),
 	);
 	assert_eq!(
 		expected,
-		p2p::types::Capabilities::from_bits_truncate(0b00011111 as u32),
+		p2p::types::Capabilities::from_bits_truncate(0b0101011111 as u32),
 	);
 
 	assert_eq!(
 		expected,
-		p2p::types::Capabilities::from_bits_truncate(0b01011111 as u32),
+		p2p::types::Capabilities::from_bits_truncate(0b10101011111 as u32),
 	);
 
-	assert!(p2p::types::Capabilities::from_bits_truncate(0b01011111 as u32).contains(expected));
+	assert!(p2p::types::Capabilities::from_bits_truncate(0b111111111 as u32).contains(expected));
 
 	assert!(
 		p2p::types::Capabilities::from_bits_truncate(0b00101111 as u32)
 			.contains(p2p::types::Capabilities::TX_KERNEL_HASH)
 	);
+
+	assert_eq!(
+		p2p::types::Capabilities::from_bits_truncate(0b10000000 as u32),
+		p2p::types::Capabilities::ENCRYPTED
+	);
+	assert!(!expected.contains(p2p::types::Capabilities::ENCRYPTED));
+
+	assert_eq!(
+		p2p::types::Capabilities::from_bits_truncate(0b1000000000 as u32),
+		p2p::types::Capabilities::BLOCK_HIST_PROOF
+	);
+	assert!(!expected.contains(p2p::types::Capabilities::BLOCK_HIST_PROOF));
+}
+
+#[test]
+fn test_tx_kernel_hashes() {
+	let version = ser::ProtocolVersion::local();
+	let kernels = p2p::msg::TxKernelHashes {
+		hashes: vec![Hash::default(); 3],
+	};
+	let vec = ser::ser_vec(&kernels, version).unwrap();
+	assert_eq!(vec.len(), 2 + 3 * 32);
+	let kernels: p2p::msg::TxKernelHashes = ser::deserialize(&mut &vec[..], version).unwrap();
+	assert_eq!(kernels.hashes, vec![Hash::default(); 3]);
+
+	let too_many = p2p::msg::TxKernelHashes {
+		hashes: vec![Hash::default(); p2p::types::MAX_TX_KERNEL_HASHES as usize + 1],
+	};
+	let vec = ser::ser_vec(&too_many, version).unwrap();
+	let res: Result<p2p::msg::TxKernelHashes, _> = ser::deserialize(&mut &vec[..], version);
+	assert_eq!(res.err(), Some(ser::Error::TooLargeReadErr));
+}
+
+#[test]
+fn test_block_transactions_request() {
+	let version = ser::ProtocolVersion::local();
+	let req = p2p::msg::BlockTransactionsRequest {
+		hash: Hash::default(),
+		nonce: 42,
+		kern_ids: vec![ShortId::zero(); 3],
+	};
+	let vec = ser::ser_vec(&req, version).unwrap();
+	assert_eq!(vec.len(), 32 + 8 + 2 + 3 * 6);
+	let req: p2p::msg::BlockTransactionsRequest = ser::deserialize(&mut &vec[..], version).unwrap();
+	assert_eq!(req.nonce, 42);
+	assert_eq!(req.kern_ids, vec![ShortId::zero(); 3]);
+
+	let too_many = p2p::msg::BlockTransactionsRequest {
+		kern_ids: vec![ShortId::zero(); p2p::types::MAX_TX_KERNEL_HASHES as usize + 1],
+		..req
+	};
+	let vec = ser::ser_vec(&too_many, version).unwrap();
+	let res: Result<p2p::msg::BlockTransactionsRequest, _> =
+		ser::deserialize(&mut &vec[..], version);
+	assert_eq!(res.err(), Some(ser::Error::TooLargeReadErr));
+}
+
+#[test]
+fn test_block_outputs_proof() {
+	let version = ser::ProtocolVersion::local();
+	let proof = BlockOutputsProof {
+		output_hashes: vec![Hash::default(); 3],
+		rangeproof_hashes: vec![Hash::default(); 2],
+	};
+	let vec = ser::ser_vec(&proof, version).unwrap();
+	assert_eq!(vec.len(), 2 + 3 * 32 + 2 + 2 * 32);
+	let res: BlockOutputsProof = ser::deserialize(&mut &vec[..], version).unwrap();
+	assert_eq!(res, proof);
+
+	let too_many = BlockOutputsProof {
+		output_hashes: vec![Hash::default(); grin_chain::MAX_OUTPUTS_PROOF_HASHES as usize + 1],
+		..proof
+	};
+	let vec = ser::ser_vec(&too_many, version).unwrap();
+	let res: Result<BlockOutputsProof, _> = ser::deserialize(&mut &vec[..], version);
+	assert_eq!(res.err(), Some(ser::Error::TooLargeReadErr));
 }
@@ -0,0 +1,184 @@
+// Copyright 2021 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+use grin_util::StopState;
+
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::{thread, time};
+
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::core::pow::Difficulty;
+use crate::p2p::types::{OnionAddr, PeerAddr};
+
+const SERVER_ONION: &str = "vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd";
+const CLIENT_ONION: &str = "2gzyxa5ihm7nsggfxnu52rck2vv4rvmdlkiu3zzui5du4xyclen53wid";
+
+// Stub SOCKS5 proxy, accepting a single CONNECT to the onion host of the
+// server and relaying it to the server actual address.
+fn socks_stub(target: SocketAddr) -> SocketAddr {
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let addr = listener.local_addr().unwrap();
+	thread::spawn(move || {
+		let (mut client, _) = listener.accept().unwrap();
+		let mut greeting = [0; 3];
+		client.read_exact(&mut greeting).unwrap();
+		assert_eq!(greeting, [5, 1, 0]);
+		client.write_all(&[5, 0]).unwrap();
+
+		let mut request = [0; 5];
+		client.read_exact(&mut request).unwrap();
+		assert_eq!(request[..4], [5, 1, 0, 3]);
+		let mut host = vec![0; request[4] as usize];
+		client.read_exact(&mut host).unwrap();
+		assert_eq!(host, format!("{}.onion", SERVER_ONION).into_bytes());
+		let mut port = [0; 2];
+		client.read_exact(&mut port).unwrap();
+		assert_eq!(u16::from_be_bytes(port), target.port());
+
+		let server = TcpStream::connect(target).unwrap();
+		client.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0]).unwrap();
+
+		let mut client_r = client.try_clone().unwrap();
+		let mut server_w = server.try_clone().unwrap();
+		thread::spawn(move || io::copy(&mut client_r, &mut server_w));
+		let (mut server_r, mut client_w) = (server, client);
+		let _ = io::copy(&mut server_r, &mut client_w);
+	});
+	addr
+}
+
+// Stub SOCKS5 proxy, answering a single Tor RESOLVE of the given host.
+fn resolve_stub(host: &'static str, ip: Ipv4Addr) -> SocketAddr {
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let addr = listener.local_addr().unwrap();
+	thread::spawn(move || {
+		let (mut client, _) = listener.accept().unwrap();
+		let mut greeting = [0; 3];
+		client.read_exact(&mut greeting).unwrap();
+		assert_eq!(greeting, [5, 1, 0]);
+		client.write_all(&[5, 0]).unwrap();
+
+		let mut request = [0; 5];
+		client.read_exact(&mut request).unwrap();
+		assert_eq!(request[..4], [5, 0xF0, 0, 3]);
+		let mut name = vec![0; request[4] as usize + 2];
+		client.read_exact(&mut name).unwrap();
+		assert_eq!(&name[..host.len()], host.as_bytes());
+
+		let mut reply = vec![5, 0, 0, 1];
+		reply.extend_from_slice(&ip.octets());
+		reply.extend_from_slice(&[0, 0]);
+		client.write_all(&reply).unwrap();
+	});
+	addr
+}
+
+fn open_port() -> u16 {
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	listener.local_addr().unwrap().port()
+}
+
+fn server(db_root: &str, p2p_config: p2p::P2PConfig) -> Arc<p2p::Server> {
+	Arc::new(
+		p2p::Server::new(
+			db_root,
+			p2p::Capabilities::default(),
+			p2p_config,
+			Arc::new(p2p::DummyAdapter {}),
+			Hash::from_vec(&[]),
+			Arc::new(StopState::new()),
+		)
+		.unwrap(),
+	)
+}
+
+// Connects to an onion peer through a SOCKS5 proxy, advertising our own
+// onion address.
+#[test]
+fn connect_through_socks_proxy() {
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+	let _ = std::fs::remove_dir_all("target/.socks");
+
+	let server_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		..p2p::P2PConfig::default()
+	};
+	let listener = server("target/.socks/server", server_config.clone());
+	let listener_c = listener.clone();
+	let _ = thread::spawn(move || listener_c.listen());
+	thread::sleep(time::Duration::from_secs(1));
+
+	let server_addr = SocketAddr::new(server_config.host, server_config.port);
+	let onion = PeerAddr::Onion(OnionAddr::new(SERVER_ONION, server_config.port).unwrap());
+	let client_onion = OnionAddr::new(CLIENT_ONION, 3414).unwrap();
+
+	// Onion peers can't be reached without a proxy.
+	let direct = server("target/.socks/direct", p2p::P2PConfig::default());
+	assert!(direct.connect(onion).is_err());
+
+	let client = server(
+		"target/.socks/client",
+		p2p::P2PConfig {
+			socks_proxy: Some(socks_stub(server_addr)),
+			onion_address: Some(client_onion),
+			..p2p::P2PConfig::default()
+		},
+	);
+	let peer = client.connect(onion).unwrap();
+	assert_eq!(peer.info.addr.to_string(), onion.to_string());
+
+	peer.send_ping(Difficulty::min_dma(), 0).unwrap();
+	thread::sleep(time::Duration::from_secs(1));
+
+	// The server knows us by the proxy connection, our onion address is only
+	// learnt once connected and never trusted as the peer identity.
+	assert!(listener
+		.peers
+		.get_connected_peer(PeerAddr::Onion(client_onion))
+		.is_none());
+	let server_peer = listener
+		.peers
+		.iter()
+		.connected()
+		.into_iter()
+		.next()
+		.unwrap();
+	assert!(!server_peer.info.addr.is_onion());
+	assert_eq!(server_peer.info.total_difficulty(), Difficulty::min_dma());
+	assert!(listener
+		.peers
+		.exists_peer(PeerAddr::Onion(client_onion))
+		.unwrap());
+
+	let _ = std::fs::remove_dir_all("target/.socks");
+}
+
+// Resolves a DNS name through the proxy instead of the local resolver.
+#[test]
+fn resolve_through_socks_proxy() {
+	let ip = Ipv4Addr::new(10, 1, 2, 3);
+	let proxy = resolve_stub("seed.example.com", ip);
+	let resolved =
+		p2p::socks::resolve(proxy, "seed.example.com", time::Duration::from_secs(5)).unwrap();
+	assert_eq!(resolved, IpAddr::V4(ip));
+}
@@ -0,0 +1,85 @@
+// Copyright 2021 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+pub mod common;
+
+use grin_core as core;
+use grin_p2p as p2p;
+
+use grin_util as util;
+
+use std::fs;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::{thread, time};
+
+use crate::common::TestAdapter;
+use crate::core::core::hash::Hashed;
+use crate::core::core::{FeeFields, KernelFeatures, Transaction, TxKernel};
+use crate::core::global;
+use crate::p2p::types::PeerAddr;
+
+// Setup test with AutomatedTesting chain_type;
+fn test_setup() {
+	// Set "global" chain type here as we spawn peer threads for read/write.
+	global::init_global_chain_type(global::ChainTypes::AutomatedTesting);
+	util::init_test_logger();
+}
+
+fn start_server(db_root: &str) -> (Arc<p2p::Server>, Arc<TestAdapter>) {
+	let adapter = Arc::new(TestAdapter::default());
+	let server = common::start_server(db_root, adapter.clone());
+	adapter.peers.init(Arc::downgrade(&server.peers));
+	(server, adapter)
+}
+
+// A tx relayed to a peer supporting tx packages is announced by kernel hash
+// in a batch, then requested and sent in full.
+#[test]
+fn tx_relay() {
+	test_setup();
+	let db_root = "target/tmp/.tx_relay";
+	let _ = fs::remove_dir_all(db_root);
+
+	let (server, adapter) = start_server(&format!("{}/server", db_root));
+	let (client, client_adapter) = start_server(&format!("{}/client", db_root));
+	thread::sleep(time::Duration::from_secs(1));
+
+	let addr = SocketAddr::new(server.config.host, server.config.port);
+	client.connect(PeerAddr::Ip(addr)).unwrap();
+	thread::sleep(time::Duration::from_secs(1));
+	assert_eq!(server.peers.iter().connected().count(), 1);
+
+	let tx = Transaction::empty().with_kernel(TxKernel::with_features(KernelFeatures::Plain {
+		fee: FeeFields::from(1),
+	}));
+	let kernel_hash = tx.kernels()[0].hash();
+	adapter.txs.write().insert(kernel_hash, tx.clone());
+	server.peers.broadcast_transaction(&tx);
+
+	let mut received = false;
+	for _ in 0..50 {
+		thread::sleep(time::Duration::from_millis(100));
+		if let Some(x) = client_adapter.txs.read().get(&kernel_hash) {
+			assert_eq!(x.hash(), tx.hash());
+			received = true;
+			break;
+		}
+	}
+	assert!(received);
+
+	server.stop();
+	client.stop();
+	let _ = fs::remove_dir_all(db_root);
+}
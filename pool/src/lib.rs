@@ -34,7 +34,7 @@ mod pool;
 pub mod transaction_pool;
 pub mod types;
 
-pub use crate::pool::Pool;
+pub use crate::pool::{Pool, MAX_REPLACED_TXS};
 pub use crate::transaction_pool::TransactionPool;
 pub use crate::types::{
 	BlockChain, DandelionConfig, PoolAdapter, PoolConfig, PoolEntry, PoolError, TxSource,
@@ -15,6 +15,7 @@
 //! Transaction pool implementation.
 //! Used for both the txpool and stempool layers in the pool.
 
+use self::core::consensus;
 use self::core::core::hash::{Hash, Hashed};
 use self::core::core::id::{ShortId, ShortIdentifiable};
 use self::core::core::transaction;
@@ -22,15 +23,23 @@ use self::core::core::verifier_cache::VerifierCache;
 use self::core::core::{
 	Block, BlockHeader, BlockSums, Committed, OutputIdentifier, Transaction, TxKernel, Weighting,
 };
+use self::util::secp::pedersen::Commitment;
 use self::util::RwLock;
 use crate::types::{BlockChain, PoolEntry, PoolError};
 use grin_core as core;
 use grin_util as util;
-use std::cmp::Reverse;
-use std::collections::{HashMap, HashSet};
+use std::cmp::{max, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::sync::Arc;
 use util::static_secp_instance;
 
+/// Weight of an output spent within the pool along with its spending input,
+/// removed by cut-through when aggregating.
+const CUT_THROUGH_WEIGHT: u64 = consensus::INPUT_WEIGHT + consensus::OUTPUT_WEIGHT;
+
+/// Maximum number of txs (kernels) a single replacement can evict from the pool.
+pub const MAX_REPLACED_TXS: usize = 100;
+
 pub struct Pool<B, V>
 where
 	B: BlockChain,
@@ -76,6 +85,17 @@ where
 		None
 	}
 
+	/// Query the tx pool for the txs with any of the provided kernel hashes.
+	/// Each tx is returned once, in pool order.
+	pub fn retrieve_txs_by_kernel_hashes(&self, hashes: &[Hash]) -> Vec<Transaction> {
+		let hashes: HashSet<_> = hashes.iter().collect();
+		self.entries
+			.iter()
+			.filter(|x| x.tx.kernels().iter().any(|k| hashes.contains(&k.hash())))
+			.map(|x| x.tx.clone())
+			.collect()
+	}
+
 	/// Query the tx pool for all known txs based on kernel short_ids
 	/// from the provided compact_block.
 	/// Note: does not validate that we return the full set of required txs.
@@ -115,21 +135,22 @@ where
 	}
 
 	/// Take pool transactions, filtering and ordering them in a way that's
-	/// appropriate to put in a mined block. Aggregates chains of dependent
-	/// transactions, orders by fee over weight and ensures the total weight
-	/// does not exceed the provided max_weight (miner defined block weight).
+	/// appropriate to put in a mined block. Orders transactions by the fee
+	/// over weight of their package (along with their unconfirmed ancestors)
+	/// and ensures the total weight does not exceed the provided max_weight
+	/// (miner defined block weight).
 	pub fn prepare_mineable_transactions(
 		&self,
 		max_weight: u64,
 	) -> Result<Vec<Transaction>, PoolError> {
 		let weighting = Weighting::AsLimitedTransaction(max_weight);
 
-		// Sort the txs in the pool via the "bucket" logic to -
+		// Sort the txs in the pool via the "package" logic to -
 		//   * maintain dependency ordering
-		//   * maximize cut-through
+		//   * let children pay for their parents
 		//   * maximize overall fees
 		let header = self.blockchain.chain_head()?;
-		let txs = self.bucket_transactions(weighting);
+		let txs = self.package_transactions(weighting);
 
 		// Iteratively apply the txs to the current chain state,
 		// rejecting any that do not result in a valid state.
@@ -343,123 +364,162 @@ where
 		Ok(())
 	}
 
-	// Use our bucket logic to identify the best transaction for eviction and evict it.
-	// We want to avoid evicting a transaction where another transaction depends on it.
-	// We want to evict a transaction with low fee_rate.
+	/// Entries conflicting with the provided tx, spending some of the same
+	/// inputs, along with the entries depending on them or on the provided
+	/// entries (replaced from another pool). Entries the tx is made of (their
+	/// kernels are part of it) are not conflicts.
+	pub fn conflicting_entries(&self, tx: &Transaction, replaced: &[PoolEntry]) -> Vec<PoolEntry> {
+		let tx_inputs: Vec<_> = tx.inputs().into();
+		let mut spent: HashSet<Commitment> = tx_inputs.iter().map(|x| x.commitment()).collect();
+		spent.extend(
+			replaced
+				.iter()
+				.flat_map(|x| x.tx.outputs().iter().map(|x| x.commitment())),
+		);
+		let kernels = tx.kernels().iter().collect::<HashSet<_>>();
+		let mut conflicts = vec![];
+		// Entries are in insertion order so parents come before their children.
+		for entry in &self.entries {
+			if entry.tx.kernels().iter().all(|k| kernels.contains(k)) {
+				continue;
+			}
+			let inputs: Vec<_> = entry.tx.inputs().into();
+			if inputs.iter().any(|x| spent.contains(&x.commitment())) {
+				spent.extend(entry.tx.outputs().iter().map(|x| x.commitment()));
+				conflicts.push(entry.clone());
+			}
+		}
+		conflicts
+	}
+
+	/// Remove the provided entries from the pool.
+	pub fn remove_entries(&mut self, entries: &[PoolEntry]) {
+		self.entries
+			.retain(|x| !entries.iter().any(|y| y.tx.kernels() == x.tx.kernels()));
+	}
+
+	// Use our package logic to identify the best transaction for eviction and evict it.
+	// We want to avoid evicting a transaction where another transaction depends on it,
+	// the last tx has none as descendants always come after their ancestors.
+	// We want to evict a transaction with low package fee_rate.
 	pub fn evict_transaction(&mut self) {
-		if let Some(evictable_transaction) = self.bucket_transactions(Weighting::NoLimit).last() {
+		if let Some(evictable_transaction) = self.package_transactions(Weighting::NoLimit).last() {
 			self.entries.retain(|x| x.tx != *evictable_transaction);
 		};
 	}
 
-	/// Buckets consist of a vec of txs and track the aggregate fee_rate.
-	/// We aggregate (cut-through) dependent transactions within a bucket *unless* adding a tx
-	/// would reduce the aggregate fee_rate, in which case we start a new bucket.
-	/// Note this new bucket will by definition have a lower fee_rate than the bucket
-	/// containing the tx it depends on.
-	/// Sorting the buckets by fee_rate will therefore preserve dependency ordering,
-	/// maximizing both cut-through and overall fees.
-	fn bucket_transactions(&self, weighting: Weighting) -> Vec<Transaction> {
-		let mut tx_buckets: Vec<Bucket> = Vec::new();
-		let mut output_commits = HashMap::new();
-		let mut rejected = HashSet::new();
-
-		for entry in &self.entries {
-			// check the commits index to find parents and their position
-			// if single parent then we are good, we can bucket it with its parent
-			// if multiple parents then we need to combine buckets, but for now simply reject it (rare case)
-			let mut insert_pos = None;
-			let mut is_rejected = false;
+	/// Order the pool txs by "package" fee rate, so a high fee child pays for
+	/// its low fee ancestors (CPFP). The package of a tx is the tx along with
+	/// its pool ancestors not selected yet.
+	/// The package with the highest fee rate is selected first (oldest first
+	/// on equal fee rates), ancestors before descendants, and the packages of
+	/// its descendants are updated to exclude the selected txs.
+	/// Packages exceeding the weighting max weight are skipped, in favor of
+	/// the next ones still fitting.
+	/// An output spent within the pool is cut-through along with its spending
+	/// input when aggregated, so neither counts towards the package weights.
+	fn package_transactions(&self, weighting: Weighting) -> Vec<Transaction> {
+		let height = self.blockchain.chain_head().map(|x| x.height).unwrap_or(0);
+		let count = self.entries.len();
+
+		// Index of the entry creating each output of the pool.
+		let mut output_entries = HashMap::new();
+		for (idx, entry) in self.entries.iter().enumerate() {
+			for out in entry.tx.outputs() {
+				output_entries.insert(out.commitment(), idx);
+			}
+		}
 
+		// Fee, weight and pool ancestors of each tx.
+		// Entries are in insertion order so parents come before their children.
+		let mut fees = Vec::with_capacity(count);
+		let mut weights = Vec::with_capacity(count);
+		let mut ancestors: Vec<HashSet<usize>> = Vec::with_capacity(count);
+		for (idx, entry) in self.entries.iter().enumerate() {
+			let mut weight = entry.tx.weight();
+			let mut tx_ancestors = HashSet::new();
 			let tx_inputs: Vec<_> = entry.tx.inputs().into();
 			for input in tx_inputs {
-				if rejected.contains(&input.commitment()) {
-					// Depends on a rejected tx, so reject this one.
-					is_rejected = true;
-					continue;
-				} else if let Some(pos) = output_commits.get(&input.commitment()) {
-					if insert_pos.is_some() {
-						// Multiple dependencies so reject this tx (pick it up in next block).
-						is_rejected = true;
-						continue;
-					} else {
-						// Track the pos of the bucket we fall into.
-						insert_pos = Some(*pos);
+				match output_entries.get(&input.commitment()) {
+					Some(&parent) if parent < idx => {
+						weight = weight.saturating_sub(CUT_THROUGH_WEIGHT);
+						tx_ancestors.insert(parent);
+						tx_ancestors.extend(ancestors[parent].iter().cloned());
 					}
+					_ => {}
 				}
 			}
+			fees.push(entry.tx.fee(height));
+			weights.push(weight);
+			ancestors.push(tx_ancestors);
+		}
 
-			// If this tx is rejected then store all output commitments in our rejected set.
-			if is_rejected {
-				for out in entry.tx.outputs() {
-					rejected.insert(out.commitment());
-				}
+		let mut descendants = vec![vec![]; count];
+		for (idx, tx_ancestors) in ancestors.iter().enumerate() {
+			for &ancestor in tx_ancestors {
+				descendants[ancestor].push(idx);
+			}
+		}
 
-				// Done with this entry (rejected), continue to next entry.
+		let mut package_fees = Vec::with_capacity(count);
+		let mut package_weights = Vec::with_capacity(count);
+		let mut packages = BinaryHeap::with_capacity(count);
+		for idx in 0..count {
+			let fee = ancestors[idx]
+				.iter()
+				.fold(fees[idx], |acc, &x| acc.saturating_add(fees[x]));
+			let weight = ancestors[idx]
+				.iter()
+				.fold(weights[idx], |acc, &x| acc.saturating_add(weights[x]));
+			package_fees.push(fee);
+			package_weights.push(weight);
+			packages.push((package_fee_rate(fee, weight), Reverse(idx)));
+		}
+
+		let max_weight = weighting.max_weight();
+		let mut selected = vec![false; count];
+		let mut total_weight = 0u64;
+		let mut txs = vec![];
+		while let Some((fee_rate, Reverse(idx))) = packages.pop() {
+			// Skip selected txs and packages outdated by a previous selection.
+			if selected[idx]
+				|| fee_rate != package_fee_rate(package_fees[idx], package_weights[idx])
+			{
+				continue;
+			}
+			let weight = total_weight.saturating_add(package_weights[idx]);
+			if max_weight.is_some_and(|max_weight| weight > max_weight) {
 				continue;
 			}
+			total_weight = weight;
 
-			let height = self.blockchain.chain_head().map(|x| x.height).unwrap_or(0);
-			match insert_pos {
-				None => {
-					// No parent tx, just add to the end in its own bucket.
-					// This is the common case for non 0-conf txs in the txpool.
-					// We assume the tx is valid here as we validated it on the way into the txpool.
-					insert_pos = Some(tx_buckets.len());
-					tx_buckets.push(Bucket::new(entry.tx.clone(), tx_buckets.len(), height));
-				}
-				Some(pos) => {
-					// We found a single parent tx, so aggregate in the bucket
-					// if the aggregate tx is a valid tx.
-					// Otherwise discard and let the next block pick this tx up.
-					let bucket = &tx_buckets[pos];
-
-					if let Ok(new_bucket) = bucket.aggregate_with_tx(
-						entry.tx.clone(),
-						weighting,
-						self.verifier_cache.clone(),
-						height,
-					) {
-						if new_bucket.fee_rate >= bucket.fee_rate {
-							// Only aggregate if it would not reduce the fee_rate ratio.
-							tx_buckets[pos] = new_bucket;
-						} else {
-							// Otherwise put it in its own bucket at the end.
-							// Note: This bucket will have a lower fee_rate
-							// than the bucket it depends on.
-							tx_buckets.push(Bucket::new(
-								entry.tx.clone(),
-								tx_buckets.len(),
-								height,
-							));
-						}
-					} else {
-						// Aggregation failed so discard this new tx.
-						is_rejected = true;
-					}
-				}
+			let mut package: Vec<usize> = ancestors[idx]
+				.iter()
+				.cloned()
+				.filter(|&x| !selected[x])
+				.collect();
+			package.push(idx);
+			package.sort_unstable();
+			for &x in &package {
+				selected[x] = true;
+				txs.push(self.entries[x].tx.clone());
 			}
 
-			if is_rejected {
-				for out in entry.tx.outputs() {
-					rejected.insert(out.commitment());
-				}
-			} else if let Some(insert_pos) = insert_pos {
-				// We successfully added this tx to our set of buckets.
-				// Update commits index for subsequent txs.
-				for out in entry.tx.outputs() {
-					output_commits.insert(out.commitment(), insert_pos);
+			// The selected txs are no longer part of their descendants packages.
+			for &x in &package {
+				for &descendant in &descendants[x] {
+					if !selected[descendant] {
+						package_fees[descendant] -= fees[x];
+						package_weights[descendant] -= weights[x];
+						packages.push((
+							package_fee_rate(package_fees[descendant], package_weights[descendant]),
+							Reverse(descendant),
+						));
+					}
 				}
 			}
 		}
-
-		// Sort buckets by fee_rate (descending) and age (oldest first).
-		// Txs with highest fee_rate will be prioritied.
-		// Aggregation that increases the fee_rate of a bucket will prioritize the bucket.
-		// Oldest (based on pool insertion time) will then be prioritized.
-		tx_buckets.sort_unstable_by_key(|x| (Reverse(x.fee_rate), x.age_idx));
-
-		tx_buckets.into_iter().flat_map(|x| x.raw_txs).collect()
+		txs
 	}
 
 	/// TODO - This is kernel based. How does this interact with NRD?
@@ -513,40 +573,34 @@ where
 	}
 }
 
-struct Bucket {
-	raw_txs: Vec<Transaction>,
-	fee_rate: u64,
-	age_idx: usize,
-}
-
-impl Bucket {
-	/// Construct a new bucket with the given tx.
-	/// also specifies an "age_idx" so we can sort buckets by age
-	/// as well as fee_rate. Txs are maintained in the pool in insert order
-	/// so buckets with low age_idx contain oldest txs.
-	fn new(tx: Transaction, age_idx: usize, height: u64) -> Bucket {
-		Bucket {
-			fee_rate: tx.fee_rate(height),
-			raw_txs: vec![tx],
-			age_idx,
-		}
+/// Replace-by-fee rules, a tx double spending pool txs replaces them (and the
+/// txs depending on them) if:
+/// * it replaces no more than MAX_REPLACED_TXS txs
+/// * its fee rate is strictly higher than the fee rate of every replaced tx
+/// * its fee covers the fees of the replaced txs plus its own relay fee
+pub fn verify_replacement(
+	tx: &Transaction,
+	replaced: &[PoolEntry],
+	height: u64,
+) -> Result<(), PoolError> {
+	let replaced_txs = replaced.iter().map(|x| x.tx.kernels().len()).sum();
+	if replaced_txs > MAX_REPLACED_TXS {
+		return Err(PoolError::TooManyReplacements(replaced_txs));
 	}
-
-	fn aggregate_with_tx(
-		&self,
-		new_tx: Transaction,
-		weighting: Weighting,
-		verifier_cache: Arc<RwLock<dyn VerifierCache>>,
-		height: u64,
-	) -> Result<Bucket, PoolError> {
-		let mut raw_txs = self.raw_txs.clone();
-		raw_txs.push(new_tx);
-		let agg_tx = transaction::aggregate(&raw_txs)?;
-		agg_tx.validate(weighting, verifier_cache, height)?;
-		Ok(Bucket {
-			fee_rate: agg_tx.fee_rate(height),
-			raw_txs: raw_txs,
-			age_idx: self.age_idx,
-		})
+	let fee = tx.fee(height);
+	let fee_rate = tx.fee_rate(height);
+	let replaced_fees = replaced
+		.iter()
+		.map(|x| x.tx.fee(height))
+		.fold(0u64, |acc, x| acc.saturating_add(x));
+	if replaced.iter().any(|x| x.tx.fee_rate(height) >= fee_rate)
+		|| fee < replaced_fees.saturating_add(tx.accept_fee(height))
+	{
+		return Err(PoolError::LowFeeReplacement(fee));
 	}
+	Ok(())
+}
+
+fn package_fee_rate(fee: u64, weight: u64) -> u64 {
+	fee / max(weight, 1)
 }
@@ -18,19 +18,25 @@
 //! valid chain state.
 
 use self::core::core::hash::{Hash, Hashed};
-use self::core::core::id::ShortId;
+use self::core::core::id::{ShortId, ShortIdentifiable};
 use self::core::core::verifier_cache::VerifierCache;
 use self::core::core::{
-	transaction, Block, BlockHeader, HeaderVersion, OutputIdentifier, Transaction, Weighting,
+	transaction, Block, BlockHeader, HeaderVersion, KernelFeatures, OutputIdentifier, Transaction,
+	Weighting,
 };
 use self::core::global;
+use self::core::ser::{BinReader, BinWriter, ProtocolVersion, Readable, Reader, Writeable, Writer};
 use self::util::RwLock;
-use crate::pool::Pool;
+use crate::pool::{self, Pool};
 use crate::types::{BlockChain, PoolAdapter, PoolConfig, PoolEntry, PoolError, TxSource};
 use chrono::prelude::*;
 use grin_core as core;
 use grin_util as util;
-use std::collections::VecDeque;
+use std::cmp::{max, Reverse};
+use std::collections::{HashSet, VecDeque};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
 use std::sync::Arc;
 
 /// Transaction pool implementation.
@@ -182,6 +188,59 @@ where
 		// NRD kernels only valid post HF3 and if NRD feature enabled.
 		self.verify_kernel_variants(tx, header)?;
 
+		// A tx double spending pool txs can replace them if it pays enough.
+		let replaced = self.replaced_entries(tx, header)?;
+		if replaced.is_empty() {
+			return self.add_entry(entry, stem, header);
+		}
+
+		let txpool_entries = self.txpool.entries.clone();
+		let stempool_entries = self.stempool.entries.clone();
+		self.txpool.remove_entries(&replaced);
+		self.stempool.remove_entries(&replaced);
+		let res = self.add_entry(entry, stem, header);
+		if res.is_ok() {
+			debug!("add_to_pool: replaced {} txs", replaced.len());
+		} else {
+			// The replacement turned out invalid, keep the original txs.
+			self.txpool.entries = txpool_entries;
+			self.stempool.entries = stempool_entries;
+		}
+		res
+	}
+
+	// Entries of both the txpool and stempool replaced by the tx, if it
+	// double spends any of them and satisfies the replace-by-fee rules.
+	fn replaced_entries(
+		&self,
+		tx: &Transaction,
+		header: &BlockHeader,
+	) -> Result<Vec<PoolEntry>, PoolError> {
+		// Stempool txs may depend on txpool ones, but not the other way around.
+		let mut replaced = self.txpool.conflicting_entries(tx, &[]);
+		for entry in self.stempool.conflicting_entries(tx, &replaced) {
+			if !replaced
+				.iter()
+				.any(|x| x.tx.kernels() == entry.tx.kernels())
+			{
+				replaced.push(entry);
+			}
+		}
+		if !replaced.is_empty() {
+			pool::verify_replacement(tx, &replaced, header.height)?;
+		}
+		Ok(replaced)
+	}
+
+	// Validate the entry and add it to the stempool or txpool.
+	fn add_entry(
+		&mut self,
+		entry: PoolEntry,
+		stem: bool,
+		header: &BlockHeader,
+	) -> Result<(), PoolError> {
+		let tx = &entry.tx;
+
 		// Does this transaction pay the required fees and fit within the pool capacity?
 		let acceptability = self.is_acceptable(tx, stem);
 		let mut evict = false;
@@ -306,21 +365,31 @@ where
 		debug!("truncate_reorg_cache: size: {}", cache.len());
 	}
 
-	pub fn reconcile_reorg_cache(&mut self, header: &BlockHeader) -> Result<(), PoolError> {
+	/// Re-add the recently accepted txs from the reorg_cache after a reorg.
+	/// Txs still in the pool or mined on the new chain are rejected, so the
+	/// returned txs are the ones that were rolled back and returned to the pool.
+	pub fn reconcile_reorg_cache(
+		&mut self,
+		header: &BlockHeader,
+	) -> Result<Vec<Transaction>, PoolError> {
 		let entries = self.reorg_cache.read().iter().cloned().collect::<Vec<_>>();
 		debug!(
 			"reconcile_reorg_cache: size: {}, block: {:?} ...",
 			entries.len(),
 			header.hash(),
 		);
+		let mut returned = vec![];
 		for entry in entries {
-			let _ = self.add_to_txpool(&entry, header);
+			if self.add_to_txpool(&entry, header).is_ok() {
+				returned.push(entry.tx);
+			}
 		}
 		debug!(
-			"reconcile_reorg_cache: block: {:?} ... done.",
-			header.hash()
+			"reconcile_reorg_cache: block: {:?} ... done, {} txs returned to pool.",
+			header.hash(),
+			returned.len(),
 		);
-		Ok(())
+		Ok(returned)
 	}
 
 	/// Reconcile the transaction pool (both txpool and stempool) against the
@@ -345,6 +414,12 @@ where
 		self.txpool.retrieve_tx_by_kernel_hash(hash)
 	}
 
+	/// Retrieve the txs with any of the provided kernel hashes.
+	/// Note: we only look in the txpool for this (stempool is under embargo).
+	pub fn retrieve_txs_by_kernel_hashes(&self, hashes: &[Hash]) -> Vec<Transaction> {
+		self.txpool.retrieve_txs_by_kernel_hashes(hashes)
+	}
+
 	/// Retrieve all transactions matching the provided "compact block"
 	/// based on the kernel set.
 	/// Note: we only look in the txpool for this (stempool is under embargo).
@@ -357,6 +432,30 @@ where
 		self.txpool.retrieve_transactions(hash, nonce, kern_ids)
 	}
 
+	/// Retrieve the recently accepted txs with kernels matching any of the
+	/// provided "compact block" kernel short ids, including the ones already
+	/// mined and gone from the txpool.
+	pub fn retrieve_recent_transactions(
+		&self,
+		hash: Hash,
+		nonce: u64,
+		kern_ids: &[ShortId],
+	) -> Vec<Transaction> {
+		let mut txs: Vec<Transaction> = self
+			.reorg_cache
+			.read()
+			.iter()
+			.filter(|x| {
+				x.tx.kernels()
+					.iter()
+					.any(|k| kern_ids.contains(&k.short_id(&hash, nonce)))
+			})
+			.map(|x| x.tx.clone())
+			.collect();
+		txs.dedup();
+		txs
+	}
+
 	/// Whether the transaction is acceptable to the pool, given both how
 	/// full the pool is and the transaction weight.
 	fn is_acceptable(&self, tx: &Transaction, stem: bool) -> Result<(), PoolError> {
@@ -387,10 +486,199 @@ where
 		self.txpool.size()
 	}
 
+	/// Save the txpool, stempool and reorg cache entries to the provided file.
+	/// Entries are serialized with the local db protocol version, keeping the
+	/// "features and commit" inputs, and the file starts with that version so
+	/// later versions can still read it.
+	pub fn save(&self, path: &Path) -> Result<(), PoolError> {
+		let reorg_cache: Vec<_> = self.reorg_cache.read().iter().cloned().collect();
+		let tmp_path = path.with_extension("tmp");
+		{
+			let version = ProtocolVersion::local_db();
+			let mut file = BufWriter::new(File::create(&tmp_path).map_err(persistence_err)?);
+			let mut writer = BinWriter::new(&mut file, version);
+			writer.write_u32(version.value())?;
+			for entries in &[&self.txpool.entries, &self.stempool.entries, &reorg_cache] {
+				writer.write_u64(entries.len() as u64)?;
+				for entry in entries.iter() {
+					entry.write(&mut writer)?;
+				}
+			}
+			file.flush().map_err(persistence_err)?;
+		}
+		fs::rename(&tmp_path, path).map_err(persistence_err)?;
+		debug!(
+			"save: {} txpool, {} stempool and {} reorg cache entries",
+			self.txpool.size(),
+			self.stempool.size(),
+			reorg_cache.len(),
+		);
+		Ok(())
+	}
+
+	/// Restore the entries saved to the provided file, replacing the current
+	/// ones. The txpool and stempool txs are revalidated against the current
+	/// chain head, only the ones still valid are kept.
+	/// Returns the number of txs restored to the txpool and stempool.
+	pub fn load(&mut self, path: &Path) -> Result<usize, PoolError> {
+		let mut file = BufReader::new(File::open(path).map_err(persistence_err)?);
+		let version =
+			ProtocolVersion(BinReader::new(&mut file, ProtocolVersion::local()).read_u32()?);
+		if version > ProtocolVersion::local() {
+			return Err(PoolError::Persistence(format!(
+				"unsupported version {}",
+				version
+			)));
+		}
+		let mut reader = BinReader::new(&mut file, version);
+		let mut lists = vec![];
+		for _ in 0..3 {
+			let count = reader.read_u64()?;
+			let mut entries = vec![];
+			for _ in 0..count {
+				entries.push(PoolEntry::read(&mut reader)?);
+			}
+			lists.push(entries);
+		}
+		let reorg_cache = lists.pop().unwrap_or_default();
+		let stempool = lists.pop().unwrap_or_default();
+		let txpool = lists.pop().unwrap_or_default();
+
+		let header = self.chain_head()?;
+		self.txpool.entries = self.revalidate(txpool, None, &header)?;
+		let txpool_agg = self.txpool.all_transactions_aggregate(None)?;
+		self.stempool.entries = self.revalidate(stempool, txpool_agg, &header)?;
+		*self.reorg_cache.write() = reorg_cache.into_iter().collect();
+
+		debug!(
+			"load: {} txpool, {} stempool entries still valid at {}",
+			self.txpool.size(),
+			self.stempool.size(),
+			header.hash(),
+		);
+		Ok(self.txpool.size() + self.stempool.size())
+	}
+
+	// The entries still valid against the provided header, in order.
+	fn revalidate(
+		&self,
+		entries: Vec<PoolEntry>,
+		extra_tx: Option<Transaction>,
+		header: &BlockHeader,
+	) -> Result<Vec<PoolEntry>, PoolError> {
+		let entries: Vec<_> = entries
+			.into_iter()
+			.filter(|x| self.blockchain.verify_tx_lock_height(&x.tx).is_ok())
+			.take(self.config.max_pool_size)
+			.collect();
+		let txs: Vec<_> = entries.iter().map(|x| x.tx.clone()).collect();
+		let valid: HashSet<_> = self
+			.txpool
+			.validate_raw_txs(&txs, extra_tx, header, Weighting::NoLimit)?
+			.iter()
+			.map(|x| x.hash())
+			.collect();
+		Ok(entries
+			.into_iter()
+			.filter(|x| valid.contains(&x.tx.hash()))
+			.collect())
+	}
+
 	/// Returns a vector of transactions from the txpool so we can build a
 	/// block from them.
 	pub fn prepare_mineable_transactions(&self) -> Result<Vec<Transaction>, PoolError> {
 		self.txpool
 			.prepare_mineable_transactions(self.config.mineable_max_weight)
 	}
+
+	/// Recommended fee rates (fee per weight unit) for a tx to be mined within
+	/// 1 up to `target_blocks` blocks, in that order. Combines the lowest fee
+	/// rates that made it in the provided recent full blocks (oldest first)
+	/// with the txpool txs competing for the next blocks `mineable_max_weight`
+	/// budget.
+	pub fn estimate_fee(
+		&self,
+		target_blocks: u64,
+		recent_blocks: &[Block],
+	) -> Result<Vec<u64>, PoolError> {
+		let header = self.chain_head()?;
+		let min_rate = global::get_accept_fee_base();
+		let budget = Weighting::AsLimitedTransaction(self.config.mineable_max_weight)
+			.max_weight()
+			.unwrap_or(self.config.mineable_max_weight);
+
+		// The fee rate needed to make it in each recent block, the minimum one
+		// if the block had room left for a basic tx. Otherwise the lowest fee
+		// rate of its txs, taking each tx at the block's average tx weight as
+		// aggregated txs can't be told apart.
+		let basic_weight = Transaction::weight_by_iok(1, 2, 1);
+		let block_rates: Vec<u64> = recent_blocks
+			.iter()
+			.map(|b| {
+				let fees: Vec<u64> = b
+					.kernels()
+					.iter()
+					.filter_map(|k| match k.features {
+						KernelFeatures::Coinbase => None,
+						KernelFeatures::Plain { fee } => Some(fee),
+						KernelFeatures::HeightLocked { fee, .. } => Some(fee),
+						KernelFeatures::NoRecentDuplicate { fee, .. } => Some(fee),
+					})
+					.map(|fee_fields| fee_fields.fee(b.header.height))
+					.collect();
+				let coinbase_outputs = b.outputs().iter().filter(|x| x.is_coinbase()).count();
+				let weight = Transaction::weight_by_iok(
+					b.inputs().len() as u64,
+					(b.outputs().len() - coinbase_outputs) as u64,
+					fees.len() as u64,
+				);
+				match fees.iter().min() {
+					Some(fee) if weight.saturating_add(basic_weight) > budget => max(
+						min_rate,
+						fee.saturating_mul(fees.len() as u64) / max(weight, 1),
+					),
+					_ => min_rate,
+				}
+			})
+			.collect();
+
+		// The txpool fee rates, highest first, along with the tx weights.
+		let mut pool_rates: Vec<(u64, u64)> = self
+			.txpool
+			.entries
+			.iter()
+			.map(|x| (x.tx.fee_rate(header.height), x.tx.weight()))
+			.collect();
+		pool_rates.sort_unstable_by_key(|x| Reverse(x.0));
+
+		let estimates = (1..=target_blocks)
+			.map(|target| {
+				// Outbid the first txpool tx not fitting in the next target blocks.
+				let limit = budget.saturating_mul(target);
+				let mut weight = 0u64;
+				let pool_rate = pool_rates
+					.iter()
+					.find(|(_, w)| {
+						weight = weight.saturating_add(*w);
+						weight > limit
+					})
+					.map_or(min_rate, |(rate, _)| rate.saturating_add(1));
+
+				// Mined within target blocks over any window of recent blocks.
+				let size = (target as usize).clamp(1, max(block_rates.len(), 1));
+				let block_rate = block_rates
+					.windows(size)
+					.filter_map(|w| w.iter().min().copied())
+					.max()
+					.unwrap_or(min_rate);
+
+				max(min_rate, max(pool_rate, block_rate))
+			})
+			.collect();
+		Ok(estimates)
+	}
+}
+
+fn persistence_err(e: std::io::Error) -> PoolError {
+	PoolError::Persistence(e.to_string())
 }
@@ -22,6 +22,7 @@ use self::core::core::hash::Hash;
 use self::core::core::transaction::{self, Transaction};
 use self::core::core::{BlockHeader, BlockSums, Inputs, OutputIdentifier};
 use self::core::global::DEFAULT_ACCEPT_FEE_BASE;
+use self::core::ser::{self, Readable, Reader, Writeable, Writer};
 use chrono::prelude::*;
 use failure::Fail;
 use grin_core as core;
@@ -126,6 +127,15 @@ pub struct PoolConfig {
 	/// blocks.
 	#[serde(default = "default_mineable_max_weight")]
 	pub mineable_max_weight: u64,
+
+	/// Save the pool txs on disk on shutdown, restoring them on startup.
+	#[serde(default = "default_persist_pool")]
+	pub persist_pool: bool,
+
+	/// Period (in minutes) between saves of the pool txs while running, 0 to
+	/// only save them on shutdown.
+	#[serde(default = "default_persist_period")]
+	pub persist_period: u32,
 }
 
 impl Default for PoolConfig {
@@ -136,6 +146,8 @@ impl Default for PoolConfig {
 			max_pool_size: default_max_pool_size(),
 			max_stempool_size: default_max_stempool_size(),
 			mineable_max_weight: default_mineable_max_weight(),
+			persist_pool: default_persist_pool(),
+			persist_period: default_persist_period(),
 		}
 	}
 }
@@ -156,6 +168,12 @@ fn default_max_stempool_size() -> usize {
 fn default_mineable_max_weight() -> u64 {
 	consensus::MAX_BLOCK_WEIGHT
 }
+fn default_persist_pool() -> bool {
+	true
+}
+fn default_persist_period() -> u32 {
+	5
+}
 
 /// Represents a single entry in the pool.
 /// A single (possibly aggregated) transaction.
@@ -179,6 +197,33 @@ impl PoolEntry {
 	}
 }
 
+impl Writeable for PoolEntry {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u8(self.src as u8)?;
+		writer.write_i64(self.tx_at.timestamp_millis())?;
+		self.tx.write(writer)
+	}
+}
+
+impl Readable for PoolEntry {
+	fn read<R: Reader>(reader: &mut R) -> Result<PoolEntry, ser::Error> {
+		let src = match reader.read_u8()? {
+			0 => TxSource::PushApi,
+			1 => TxSource::Broadcast,
+			2 => TxSource::Fluff,
+			3 => TxSource::EmbargoExpired,
+			4 => TxSource::Deaggregate,
+			_ => return Err(ser::Error::CorruptedData),
+		};
+		let tx_at = Utc
+			.timestamp_millis_opt(reader.read_i64()?)
+			.single()
+			.ok_or(ser::Error::CorruptedData)?;
+		let tx = Transaction::read(reader)?;
+		Ok(PoolEntry { src, tx_at, tx })
+	}
+}
+
 /// Used to make decisions based on transaction acceptance priority from
 /// various sources. For example, a node may want to bypass pool size
 /// restrictions when accepting a transaction from a local wallet.
@@ -187,11 +232,11 @@ impl PoolEntry {
 /// once we get a better sense of what transaction building might look like.
 #[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub enum TxSource {
-	PushApi,
-	Broadcast,
-	Fluff,
-	EmbargoExpired,
-	Deaggregate,
+	PushApi = 0,
+	Broadcast = 1,
+	Fluff = 2,
+	EmbargoExpired = 3,
+	Deaggregate = 4,
 }
 
 impl TxSource {
@@ -235,6 +280,12 @@ pub enum PoolError {
 	/// Transaction fee is too low given its weight
 	#[fail(display = "Low fee transaction {}", _0)]
 	LowFeeTransaction(u64),
+	/// Transaction double spends pool txs without paying enough to replace them
+	#[fail(display = "Low fee replacement {}", _0)]
+	LowFeeReplacement(u64),
+	/// Transaction double spends more pool txs than a replacement can evict
+	#[fail(display = "Too many replacements {}", _0)]
+	TooManyReplacements(usize),
 	/// Attempt to add a duplicate output to the pool.
 	#[fail(display = "Duplicate commitment")]
 	DuplicateCommitment,
@@ -250,6 +301,9 @@ pub enum PoolError {
 	/// NRD kernels are not valid if relative_height rule not met.
 	#[fail(display = "NRD kernel relative height")]
 	NRDKernelRelativeHeight,
+	/// Failure to save or restore the pool txs on disk.
+	#[fail(display = "Pool persistence error {}", _0)]
+	Persistence(String),
 	/// Other kinds of error (not yet pulled out into meaningful errors).
 	#[fail(display = "General pool error {}", _0)]
 	Other(String),
@@ -282,6 +336,12 @@ impl From<committed::Error> for PoolError {
 	}
 }
 
+impl From<ser::Error> for PoolError {
+	fn from(e: ser::Error) -> PoolError {
+		PoolError::Persistence(e.to_string())
+	}
+}
+
 /// Interface that the pool requires from a blockchain implementation.
 pub trait BlockChain: Sync + Send {
 	/// Verify any coinbase outputs being spent
@@ -0,0 +1,109 @@
+// Copyright 2021 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Test coverage for selecting low fee txs along with their high fee children.
+
+pub mod common;
+
+use self::core::core::hash::Hashed;
+use self::core::core::verifier_cache::LruVerifierCache;
+use self::core::global;
+use self::keychain::{ExtKeychain, Keychain};
+use self::util::RwLock;
+use crate::common::*;
+use grin_core as core;
+use grin_keychain as keychain;
+use grin_util as util;
+use std::sync::Arc;
+
+#[test]
+fn test_child_pays_for_parent() {
+	util::init_test_logger();
+	global::set_local_chain_type(global::ChainTypes::AutomatedTesting);
+	global::set_local_accept_fee_base(1);
+	let keychain: ExtKeychain = Keychain::from_random_seed(false).unwrap();
+
+	let db_root = "target/.child_pays_for_parent";
+	clean_output_dir(db_root.into());
+
+	let genesis = genesis_block(&keychain);
+	let chain = Arc::new(init_chain(db_root, genesis));
+	let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+
+	let mut pool = init_transaction_pool(
+		Arc::new(ChainAdapter {
+			chain: chain.clone(),
+		}),
+		verifier_cache,
+	);
+
+	add_some_blocks(&chain, 4 * 3, &keychain);
+	let header_1 = chain.get_header_by_height(1).unwrap();
+	let initial_tx = test_transaction_spending_coinbase(
+		&keychain,
+		&header_1,
+		vec![1_000_000, 2_000_000, 3_000_000, 4_000_000],
+	);
+	add_block(&chain, &[initial_tx], &keychain);
+	let header = chain.head_header().unwrap();
+
+	// Two low fee parents and a high fee child spending both.
+	let parent_1 = test_transaction(&keychain, vec![1_000_000], vec![999_000]);
+	let parent_2 = test_transaction(&keychain, vec![2_000_000], vec![1_999_000]);
+	let child = test_transaction(&keychain, vec![999_000, 1_999_000], vec![2_798_000]);
+	// Txs with a fee rate in between, not all fitting in a block.
+	let tx_1 = test_transaction(
+		&keychain,
+		vec![3_000_000],
+		vec![700_000, 710_000, 720_000, 770_000],
+	);
+	let tx_2 = test_transaction(
+		&keychain,
+		vec![4_000_000],
+		vec![780_000, 781_000, 782_000, 783_000, 774_000],
+	);
+
+	assert_eq!(
+		[&parent_1, &parent_2, &child, &tx_1, &tx_2]
+			.iter()
+			.map(|x| x.fee_rate(header.height))
+			.collect::<Vec<_>>(),
+		[40, 40, 7692, 1136, 917]
+	);
+
+	for tx in &[&parent_1, &parent_2, &child, &tx_1, &tx_2] {
+		pool.add_to_pool(test_source(), (*tx).clone(), false, &header)
+			.unwrap();
+	}
+	assert_eq!(pool.total_size(), 5);
+
+	// The child lifts both its parents ahead of the other txs, tx_2 does not
+	// fit in the remaining block weight.
+	let txs = pool.prepare_mineable_transactions().unwrap();
+	assert_eq!(
+		txs.iter().map(|x| x.kernels()).collect::<Vec<_>>(),
+		[&parent_1, &parent_2, &child, &tx_1]
+			.iter()
+			.map(|x| x.kernels())
+			.collect::<Vec<_>>()
+	);
+
+	add_block(&chain, &txs, &keychain);
+	let block = chain.get_block(&chain.head().unwrap().hash()).unwrap();
+	pool.reconcile_block(&block).unwrap();
+	assert_eq!(pool.total_size(), 1);
+	assert_eq!(pool.txpool.entries[0].tx.kernels(), tx_2.kernels());
+
+	clean_output_dir(db_root.into());
+}
@@ -172,6 +172,8 @@ where
 			max_pool_size: 50,
 			max_stempool_size: 50,
 			mineable_max_weight: 10_000,
+			persist_pool: false,
+			persist_period: 0,
 		},
 		chain.clone(),
 		verifier_cache.clone(),
@@ -0,0 +1,130 @@
+// Copyright 2021 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+pub mod common;
+
+use self::core::core::verifier_cache::LruVerifierCache;
+use self::core::core::{transaction, Block};
+use self::core::global;
+use self::keychain::{ExtKeychain, Keychain};
+use self::util::RwLock;
+use crate::common::*;
+use grin_core as core;
+use grin_keychain as keychain;
+use grin_util as util;
+use std::slice;
+use std::sync::Arc;
+
+#[test]
+fn test_fee_estimation() {
+	util::init_test_logger();
+	global::set_local_chain_type(global::ChainTypes::AutomatedTesting);
+	global::set_local_accept_fee_base(1);
+	let keychain: ExtKeychain = Keychain::from_random_seed(false).unwrap();
+
+	let db_root = "target/.fee_estimation";
+	clean_output_dir(db_root.into());
+
+	let genesis = genesis_block(&keychain);
+	let chain = Arc::new(init_chain(db_root, genesis));
+	let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+
+	let mut pool = init_transaction_pool(
+		Arc::new(ChainAdapter {
+			chain: chain.clone(),
+		}),
+		verifier_cache,
+	);
+
+	add_some_blocks(&chain, 4 * 3, &keychain);
+	let empty_block = chain
+		.get_block(&chain.head().unwrap().last_block_h)
+		.unwrap();
+
+	// Nothing to compete with, the minimum fee rate.
+	assert_eq!(pool.estimate_fee(2, &[]).unwrap(), [1, 1]);
+	assert_eq!(
+		pool.estimate_fee(1, slice::from_ref(&empty_block)).unwrap(),
+		[1]
+	);
+
+	// A full block (tx weight 193 of 226) at a fee rate of 500.
+	let header_1 = chain.get_header_by_height(1).unwrap();
+	let outputs = [
+		100_000, 110_000, 120_000, 130_000, 140_000, 150_000, 160_000,
+	];
+	let change = 60_000_000_000 - outputs.iter().sum::<u64>() - 1_000 - 193 * 500;
+	let initial_tx = test_transaction_spending_coinbase(
+		&keychain,
+		&header_1,
+		outputs.iter().cloned().chain([1_000, change]).collect(),
+	);
+	assert_eq!(initial_tx.fee_rate(header_1.height), 500);
+	add_block(&chain, &[initial_tx], &keychain);
+	let full_block = chain
+		.get_block(&chain.head().unwrap().last_block_h)
+		.unwrap();
+	let header = chain.head_header().unwrap();
+
+	// Txs of weight 46 at fee rates 100 down to 60, the last one not fitting
+	// in the next block.
+	for (i, input) in outputs.iter().take(5).enumerate() {
+		let fee = 46 * (100 - 10 * i as u64);
+		let tx = test_transaction(
+			&keychain,
+			vec![*input],
+			vec![input / 2, input - input / 2 - fee],
+		);
+		pool.add_to_pool(test_source(), tx, false, &header).unwrap();
+	}
+	assert_eq!(pool.estimate_fee(2, &[]).unwrap(), [61, 1]);
+
+	// The full block sets the rate to be mined in the next block, waiting for
+	// one more block any fee rate would have made it.
+	assert_eq!(
+		pool.estimate_fee(3, &[full_block.clone(), empty_block.clone()])
+			.unwrap(),
+		[500, 1, 1]
+	);
+	assert_eq!(
+		pool.estimate_fee(2, &[full_block.clone(), full_block, empty_block])
+			.unwrap(),
+		[500, 500]
+	);
+
+	// A full block of 4 txs of weight 46, the lowest fee rate of 100 sets
+	// the rate rather than the block average of 400.
+	let txs: Vec<_> = [500, 500, 500, 100]
+		.iter()
+		.zip(outputs.iter().skip(3))
+		.map(|(rate, input)| {
+			let fee = 46 * rate;
+			test_transaction(
+				&keychain,
+				vec![*input],
+				vec![input / 2, input - input / 2 - fee],
+			)
+		})
+		.collect();
+	let mixed_block = Block {
+		header: header.clone(),
+		body: transaction::aggregate(&txs).unwrap().body,
+	};
+	assert_eq!(
+		pool.estimate_fee(1, slice::from_ref(&mixed_block)).unwrap(),
+		[100]
+	);
+
+	clean_output_dir(db_root.into());
+}
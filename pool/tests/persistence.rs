@@ -0,0 +1,103 @@
+// Copyright 2021 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+pub mod common;
+
+use self::core::core::verifier_cache::LruVerifierCache;
+use self::core::global;
+use self::core::ser::{self, ProtocolVersion};
+use self::keychain::{ExtKeychain, Keychain};
+use self::pool::PoolEntry;
+use self::util::RwLock;
+use crate::common::*;
+use grin_core as core;
+use grin_keychain as keychain;
+use grin_pool as pool;
+use grin_util as util;
+use std::path::Path;
+use std::sync::Arc;
+
+#[test]
+fn test_pool_persistence() {
+	util::init_test_logger();
+	global::set_local_chain_type(global::ChainTypes::AutomatedTesting);
+	global::set_local_accept_fee_base(1);
+	let keychain: ExtKeychain = Keychain::from_random_seed(false).unwrap();
+
+	let db_root = "target/.pool_persistence";
+	clean_output_dir(db_root.into());
+
+	let genesis = genesis_block(&keychain);
+	let chain = Arc::new(init_chain(db_root, genesis));
+	let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+	let adapter = Arc::new(ChainAdapter {
+		chain: chain.clone(),
+	});
+
+	let mut pool = init_transaction_pool(adapter.clone(), verifier_cache.clone());
+
+	add_some_blocks(&chain, 4 * 3, &keychain);
+	let header_1 = chain.get_header_by_height(1).unwrap();
+	let initial_tx =
+		test_transaction_spending_coinbase(&keychain, &header_1, vec![1_000, 2_000, 3_000]);
+	add_block(&chain, &[initial_tx], &keychain);
+	let header = chain.head_header().unwrap();
+
+	let tx = test_transaction(&keychain, vec![1_000], vec![900]);
+	let child_tx = test_transaction(&keychain, vec![900], vec![800]);
+	let stem_tx = test_transaction(&keychain, vec![2_000], vec![1_900]);
+	let mined_tx = test_transaction(&keychain, vec![3_000], vec![2_900]);
+	for tx in &[&tx, &child_tx, &mined_tx] {
+		pool.add_to_pool(test_source(), (*tx).clone(), false, &header)
+			.unwrap();
+	}
+	pool.add_to_pool(test_source(), stem_tx.clone(), true, &header)
+		.unwrap();
+	assert_eq!(pool.txpool.size(), 3);
+	assert_eq!(pool.stempool.size(), 1);
+
+	let path = Path::new(db_root).join("txpool.bin");
+	pool.save(&path).unwrap();
+
+	// One of the txs gets mined while the node is down.
+	add_block(&chain, &[mined_tx], &keychain);
+
+	// Restored and revalidated against the new chain head.
+	let mut restored = init_transaction_pool(adapter, verifier_cache);
+	assert_eq!(restored.load(&path).unwrap(), 3);
+	assert_eq!(restored.txpool.size(), 2);
+	assert_eq!(restored.stempool.size(), 1);
+	assert_eq!(restored.reorg_cache.read().len(), 3);
+	for (entry, original) in restored.txpool.entries.iter().zip(&pool.txpool.entries) {
+		assert_eq!(entry.tx, original.tx);
+		assert_eq!(entry.src, original.src);
+		assert_eq!(
+			entry.tx_at.timestamp_millis(),
+			original.tx_at.timestamp_millis()
+		);
+	}
+	assert_eq!(restored.stempool.entries[0].tx, pool.stempool.entries[0].tx);
+
+	assert!(restored
+		.load(&Path::new(db_root).join("missing.bin"))
+		.is_err());
+
+	// An out of range timestamp is corrupted data, not a panic.
+	let mut data = vec![1u8];
+	data.extend_from_slice(&i64::MAX.to_be_bytes());
+	let entry: Result<PoolEntry, _> = ser::deserialize(&mut &data[..], ProtocolVersion::local());
+	assert!(matches!(entry, Err(ser::Error::CorruptedData)));
+
+	clean_output_dir(db_root.into());
+}
@@ -0,0 +1,116 @@
+// Copyright 2021 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+pub mod common;
+
+use self::chain::types::Options;
+use self::core::core::hash::Hashed;
+use self::core::core::verifier_cache::LruVerifierCache;
+use self::core::core::{Block, BlockHeader, Transaction};
+use self::core::global;
+use self::core::libtx::{reward, ProofBuilder};
+use self::core::pow::{self, Difficulty};
+use self::keychain::{ExtKeychain, ExtKeychainPath, Keychain};
+use self::util::RwLock;
+use crate::common::ChainAdapter;
+use crate::common::*;
+use chrono::Duration;
+use grin_chain as chain;
+use grin_core as core;
+use grin_keychain as keychain;
+use grin_util as util;
+use std::sync::Arc;
+
+// Build a fork block on top of prev, heavy enough to reorg the chain.
+fn fork_block<K>(
+	chain: &chain::Chain,
+	prev: &BlockHeader,
+	txs: &[Transaction],
+	keychain: &K,
+) -> Block
+where
+	K: Keychain,
+{
+	let height = prev.height + 1;
+	let fee = txs.iter().map(|x| x.fee(height)).sum();
+	let key_id = ExtKeychainPath::new(2, height as u32, 0, 0, 0).to_identifier();
+	let reward =
+		reward::output(keychain, &ProofBuilder::new(keychain), &key_id, fee, false).unwrap();
+	let difficulty = Difficulty::from_num(1_000);
+	let mut block = Block::new(prev, txs, difficulty, reward).unwrap();
+	block.header.timestamp = prev.timestamp + Duration::seconds(60);
+	block.header.pow.total_difficulty = prev.total_difficulty() + difficulty;
+	block.header.pow.proof = pow::Proof::random(global::proofsize());
+	chain.set_txhashset_roots(&mut block).unwrap();
+	block
+}
+
+#[test]
+fn test_reconcile_reorg_cache() {
+	util::init_test_logger();
+	global::set_local_chain_type(global::ChainTypes::AutomatedTesting);
+	global::set_local_accept_fee_base(1);
+	let keychain: ExtKeychain = Keychain::from_random_seed(false).unwrap();
+
+	let db_root = "target/.reorg_cache";
+	clean_output_dir(db_root.into());
+
+	let genesis = genesis_block(&keychain);
+	let chain = Arc::new(init_chain(db_root, genesis));
+	let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+
+	let mut pool = init_transaction_pool(
+		Arc::new(ChainAdapter {
+			chain: chain.clone(),
+		}),
+		verifier_cache,
+	);
+
+	add_some_blocks(&chain, 4 * 3, &keychain);
+	let header_1 = chain.get_header_by_height(1).unwrap();
+	let initial_tx = test_transaction_spending_coinbase(&keychain, &header_1, vec![1_000, 2_000]);
+	add_block(&chain, &[initial_tx], &keychain);
+	let fork_prev = chain.head_header().unwrap();
+
+	// Both txs go through the pool (and the reorg cache) and get mined.
+	let tx_a = test_transaction(&keychain, vec![1_000], vec![800]);
+	let tx_b = test_transaction(&keychain, vec![2_000], vec![1_500]);
+	for tx in &[tx_a.clone(), tx_b.clone()] {
+		pool.add_to_pool(test_source(), tx.clone(), false, &fork_prev)
+			.unwrap();
+	}
+	add_block(&chain, &[tx_a.clone(), tx_b.clone()], &keychain);
+	let block = chain.get_block(&chain.head().unwrap().hash()).unwrap();
+	pool.reconcile_block(&block).unwrap();
+	assert_eq!(pool.total_size(), 0);
+
+	// Reorg to a fork only containing tx_b.
+	let fork = fork_block(&chain, &fork_prev, &[tx_b], &keychain);
+	chain
+		.process_block(fork.clone(), Options::SKIP_POW)
+		.unwrap();
+	assert_eq!(chain.head().unwrap().hash(), fork.hash());
+	pool.reconcile_block(&fork).unwrap();
+
+	// Only tx_a was rolled back and returned to the pool.
+	let returned = pool.reconcile_reorg_cache(&fork.header).unwrap();
+	assert_eq!(returned.len(), 1);
+	assert_eq!(returned[0].kernels(), tx_a.kernels());
+	assert_eq!(pool.total_size(), 1);
+
+	// Nothing left to return on a second pass.
+	assert!(pool.reconcile_reorg_cache(&fork.header).unwrap().is_empty());
+
+	clean_output_dir(db_root.into());
+}
@@ -0,0 +1,179 @@
+// Copyright 2021 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+pub mod common;
+
+use self::core::core::verifier_cache::LruVerifierCache;
+use self::core::global;
+use self::keychain::{ExtKeychain, Keychain};
+use self::pool::{PoolError, MAX_REPLACED_TXS};
+use self::util::RwLock;
+use crate::common::*;
+use grin_core as core;
+use grin_keychain as keychain;
+use grin_pool as pool;
+use grin_util as util;
+use std::sync::Arc;
+
+#[test]
+fn test_replace_by_fee() {
+	util::init_test_logger();
+	global::set_local_chain_type(global::ChainTypes::AutomatedTesting);
+	global::set_local_accept_fee_base(1);
+	let keychain: ExtKeychain = Keychain::from_random_seed(false).unwrap();
+
+	let db_root = "target/.replace_by_fee";
+	clean_output_dir(db_root.into());
+
+	let genesis = genesis_block(&keychain);
+	let chain = Arc::new(init_chain(db_root, genesis));
+	let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+
+	let mut pool = init_transaction_pool(
+		Arc::new(ChainAdapter {
+			chain: chain.clone(),
+		}),
+		verifier_cache,
+	);
+
+	add_some_blocks(&chain, 4 * 3, &keychain);
+	let header_1 = chain.get_header_by_height(1).unwrap();
+	let initial_tx =
+		test_transaction_spending_coinbase(&keychain, &header_1, vec![1_000, 2_000, 3_000]);
+	add_block(&chain, &[initial_tx], &keychain);
+	let header = chain.head_header().unwrap();
+
+	// A tx (fee 100, weight 25) and its child (fee 50) in the txpool.
+	let tx = test_transaction(&keychain, vec![1_000], vec![900]);
+	let child_tx = test_transaction(&keychain, vec![900], vec![850]);
+	pool.add_to_pool(test_source(), tx.clone(), false, &header)
+		.unwrap();
+	pool.add_to_pool(test_source(), child_tx.clone(), false, &header)
+		.unwrap();
+	assert_eq!(pool.txpool.size(), 2);
+
+	// Same fee rate, not strictly higher.
+	let same_rate_tx = test_transaction(&keychain, vec![1_000], vec![880]);
+	assert_eq!(
+		pool.add_to_pool(test_source(), same_rate_tx, false, &header),
+		Err(PoolError::LowFeeReplacement(120))
+	);
+
+	// Higher fee rate but not paying for the replaced txs (150) and its own
+	// relay (25).
+	let low_fee_tx = test_transaction(&keychain, vec![1_000], vec![840]);
+	assert_eq!(
+		pool.add_to_pool(test_source(), low_fee_tx, false, &header),
+		Err(PoolError::LowFeeReplacement(160))
+	);
+	assert_eq!(pool.txpool.size(), 2);
+
+	// Replaces both the tx and its child.
+	let replacement_tx = test_transaction(&keychain, vec![1_000], vec![820]);
+	pool.add_to_pool(test_source(), replacement_tx.clone(), false, &header)
+		.unwrap();
+	assert_eq!(pool.txpool.size(), 1);
+	assert_eq!(
+		pool.txpool.entries[0].tx.kernels(),
+		replacement_tx.kernels()
+	);
+
+	// A fluffed tx replaces a stem tx.
+	let stem_tx = test_transaction(&keychain, vec![2_000], vec![1_900]);
+	pool.add_to_pool(test_source(), stem_tx, true, &header)
+		.unwrap();
+	assert_eq!(pool.stempool.size(), 1);
+	let fluff_tx = test_transaction(&keychain, vec![2_000], vec![1_800]);
+	pool.add_to_pool(test_source(), fluff_tx, false, &header)
+		.unwrap();
+	assert!(pool.stempool.is_empty());
+	assert_eq!(pool.txpool.size(), 2);
+
+	// Stem txs depending on a replaced txpool tx are replaced too.
+	let stem_child_tx = test_transaction(&keychain, vec![1_800], vec![1_750]);
+	pool.add_to_pool(test_source(), stem_child_tx, true, &header)
+		.unwrap();
+	assert_eq!(pool.stempool.size(), 1);
+	let low_fee_tx = test_transaction(&keychain, vec![2_000], vec![1_730]);
+	assert_eq!(
+		pool.add_to_pool(test_source(), low_fee_tx, false, &header),
+		Err(PoolError::LowFeeReplacement(270))
+	);
+	let fluff_tx = test_transaction(&keychain, vec![2_000], vec![1_700]);
+	pool.add_to_pool(test_source(), fluff_tx, false, &header)
+		.unwrap();
+	assert!(pool.stempool.is_empty());
+	assert_eq!(pool.txpool.size(), 2);
+
+	// An invalid replacement leaves the pool untouched.
+	let tx = test_transaction(&keychain, vec![3_000], vec![2_900]);
+	pool.add_to_pool(test_source(), tx.clone(), false, &header)
+		.unwrap();
+	let invalid_tx = test_transaction(&keychain, vec![3_000, 10_000], vec![12_000]);
+	assert!(pool
+		.add_to_pool(test_source(), invalid_tx, false, &header)
+		.is_err());
+	assert_eq!(pool.txpool.size(), 3);
+	assert!(pool.txpool.contains_tx(&tx));
+
+	clean_output_dir(db_root.into());
+}
+
+// A replacement cannot evict more than MAX_REPLACED_TXS txs.
+#[test]
+fn test_replace_by_fee_limit() {
+	util::init_test_logger();
+	global::set_local_chain_type(global::ChainTypes::AutomatedTesting);
+	global::set_local_accept_fee_base(1);
+	let keychain: ExtKeychain = Keychain::from_random_seed(false).unwrap();
+
+	let db_root = "target/.replace_by_fee_limit";
+	clean_output_dir(db_root.into());
+
+	let genesis = genesis_block(&keychain);
+	let chain = Arc::new(init_chain(db_root, genesis));
+	let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+
+	let mut pool = init_transaction_pool(
+		Arc::new(ChainAdapter {
+			chain: chain.clone(),
+		}),
+		verifier_cache,
+	);
+	pool.config.max_pool_size = 2 * MAX_REPLACED_TXS;
+
+	add_some_blocks(&chain, 4 * 3, &keychain);
+	let header_1 = chain.get_header_by_height(1).unwrap();
+	let initial_tx = test_transaction_spending_coinbase(&keychain, &header_1, vec![100_000]);
+	add_block(&chain, &[initial_tx], &keychain);
+	let header = chain.head_header().unwrap();
+
+	// A chain of MAX_REPLACED_TXS + 1 txs, each spending the previous one.
+	let mut value = 100_000;
+	for _ in 0..=MAX_REPLACED_TXS {
+		let tx = test_transaction(&keychain, vec![value], vec![value - 30]);
+		pool.add_to_pool(test_source(), tx, false, &header).unwrap();
+		value -= 30;
+	}
+	assert_eq!(pool.txpool.size(), MAX_REPLACED_TXS + 1);
+
+	let replacement_tx = test_transaction(&keychain, vec![100_000], vec![90_000]);
+	assert_eq!(
+		pool.add_to_pool(test_source(), replacement_tx, false, &header),
+		Err(PoolError::TooManyReplacements(MAX_REPLACED_TXS + 1))
+	);
+	assert_eq!(pool.txpool.size(), MAX_REPLACED_TXS + 1);
+
+	clean_output_dir(db_root.into());
+}
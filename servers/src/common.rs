@@ -18,3 +18,4 @@ pub mod adapters;
 pub mod hooks;
 pub mod stats;
 pub mod types;
+pub mod webhooks;
@@ -16,30 +16,32 @@
 //! events to consumers of those events.
 
 use crate::util::RwLock;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::path::PathBuf;
 use std::sync::{Arc, Weak};
 use std::thread;
 use std::time::Instant;
 
-use crate::chain::txhashset::BitmapChunk;
+use crate::chain::txhashset::{BitmapChunk, SegmentType, SegmentTypeIdentifier};
 use crate::chain::{
 	self, BlockStatus, ChainAdapter, Options, SyncState, SyncStatus, TxHashsetDownloadStats,
 };
 use crate::common::hooks::{ChainEvents, NetEvents};
 use crate::common::types::{ChainValidationMode, DandelionEpoch, ServerConfig};
 use crate::core::core::hash::{Hash, Hashed};
+use crate::core::core::id::ShortIdentifiable;
 use crate::core::core::transaction::Transaction;
 use crate::core::core::verifier_cache::VerifierCache;
 use crate::core::core::{
 	BlockHeader, BlockSums, CompactBlock, Inputs, OutputIdentifier, Segment, SegmentIdentifier,
-	TxKernel,
+	ShortId, TxKernel,
 };
 use crate::core::pow::Difficulty;
 use crate::core::ser::ProtocolVersion;
 use crate::core::{core, global};
 use crate::p2p;
-use crate::p2p::types::PeerInfo;
+use crate::p2p::types::{PeerAddr, PeerInfo};
 use crate::pool::{self, BlockChain, PoolAdapter};
 use crate::util::secp::pedersen::RangeProof;
 use crate::util::OneTime;
@@ -69,6 +71,8 @@ where
 	peers: OneTime<Weak<p2p::Peers>>,
 	config: ServerConfig,
 	hooks: Vec<Box<dyn NetEvents + Send + Sync>>,
+	// compact blocks waiting for the missing txs requested from a peer
+	pending_compact_blocks: RwLock<HashMap<Hash, (CompactBlock, PeerAddr)>>,
 }
 
 impl<B, P, V> p2p::ChainAdapter for NetToChainAdapter<B, P, V>
@@ -107,6 +111,136 @@ where
 		Ok(true)
 	}
 
+	fn get_transactions(&self, kernel_hashes: &[Hash]) -> Vec<core::Transaction> {
+		// Keep the response within the weight of a block.
+		let max_weight = global::max_block_weight();
+		let mut weight = 0;
+		self.tx_pool
+			.read()
+			.retrieve_txs_by_kernel_hashes(kernel_hashes)
+			.into_iter()
+			.take_while(|tx| {
+				weight += tx.weight();
+				weight <= max_weight
+			})
+			.collect()
+	}
+
+	fn tx_kernels_received(
+		&self,
+		kernel_hashes: Vec<Hash>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		// nothing much we can do with new transactions while syncing
+		if self.sync_state.is_syncing() {
+			return Ok(true);
+		}
+
+		let known: HashSet<_> = self
+			.tx_pool
+			.read()
+			.retrieve_txs_by_kernel_hashes(&kernel_hashes)
+			.iter()
+			.flat_map(|tx| tx.kernels().iter().map(|k| k.hash()))
+			.collect();
+		let missing: Vec<_> = kernel_hashes
+			.into_iter()
+			.filter(|h| !known.contains(h))
+			.collect();
+
+		if !missing.is_empty() {
+			self.request_transactions(missing, peer_info);
+		}
+		Ok(true)
+	}
+
+	fn tx_kernel_hashes(&self) -> Vec<Hash> {
+		self.tx_pool
+			.read()
+			.txpool
+			.entries
+			.iter()
+			.filter_map(|x| x.tx.kernels().first().map(|k| k.hash()))
+			.collect()
+	}
+
+	fn get_block_transactions(
+		&self,
+		hash: Hash,
+		nonce: u64,
+		kern_ids: &[ShortId],
+	) -> Vec<core::Transaction> {
+		// Keep the response within the weight of a block.
+		let max_weight = global::max_block_weight();
+		let mut weight = 0;
+		self.tx_pool
+			.read()
+			.retrieve_recent_transactions(hash, nonce, kern_ids)
+			.into_iter()
+			.take_while(|tx| {
+				weight += tx.weight();
+				weight <= max_weight
+			})
+			.collect()
+	}
+
+	fn block_transactions_received(
+		&self,
+		hash: Hash,
+		txs: Vec<core::Transaction>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		// Only the peer we asked gets to complete the compact block.
+		let cb = {
+			let mut pending = self.pending_compact_blocks.write();
+			match pending.get(&hash) {
+				Some((_, addr)) if *addr == peer_info.addr => pending.remove(&hash).map(|x| x.0),
+				_ => None,
+			}
+		};
+		let cb = match cb {
+			Some(cb) => cb,
+			None => {
+				debug!("Unrequested txs of block {} from {}", hash, peer_info.addr);
+				return Ok(true);
+			}
+		};
+		if self.chain().block_exists(hash)? {
+			return Ok(true);
+		}
+
+		let (mut block_txs, mut missing_short_ids) = {
+			self.tx_pool
+				.read()
+				.retrieve_transactions(hash, cb.nonce, cb.kern_ids())
+		};
+		let pool_txs = block_txs.len();
+		for tx in txs {
+			let ids: Vec<_> = tx
+				.kernels()
+				.iter()
+				.map(|k| k.short_id(&hash, cb.nonce))
+				.collect();
+			let missing = missing_short_ids.len();
+			missing_short_ids.retain(|id| !ids.contains(id));
+			if missing_short_ids.len() < missing {
+				block_txs.push(tx);
+			}
+		}
+		debug!(
+			"block_transactions_received: txs from tx pool - {}, from peer - {}, (unknown kern_ids: {})",
+			pool_txs,
+			block_txs.len() - pool_txs,
+			missing_short_ids.len(),
+		);
+
+		if !missing_short_ids.is_empty() {
+			self.request_block(&cb.header, peer_info, chain::Options::NONE);
+			return Ok(true);
+		}
+		self.hydrate_compact_block(cb, &block_txs, peer_info)
+	}
+
 	fn transaction_received(
 		&self,
 		tx: core::Transaction,
@@ -158,6 +292,24 @@ where
 		self.process_block(b, peer_info, opts)
 	}
 
+	fn historical_block_received(
+		&self,
+		b: core::Block,
+		proof: chain::BlockOutputsProof,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		if self.chain().block_exists(b.hash())? {
+			return Ok(true);
+		}
+		debug!(
+			"Received historical block {} at {} from {} going to process.",
+			b.hash(),
+			b.header.height,
+			peer_info.addr,
+		);
+		self.process_historical_block(b, proof, peer_info)
+	}
+
 	fn compact_block_received(
 		&self,
 		cb: core::CompactBlock,
@@ -223,51 +375,16 @@ where
 				missing_short_ids.len(),
 			);
 
-			// If we have missing kernels then we know we cannot hydrate this compact block.
+			// If we have missing kernels then we know we cannot hydrate this compact block
+			// before fetching them, or the full block.
 			if !missing_short_ids.is_empty() {
-				self.request_block(&cb.header, peer_info, chain::Options::NONE);
-				return Ok(true);
-			}
-
-			let block = match core::Block::hydrate_from(cb.clone(), &txs) {
-				Ok(block) => {
-					if !self.sync_state.is_syncing() {
-						for hook in &self.hooks {
-							hook.on_block_received(&block, &peer_info.addr);
-						}
-					}
-					block
-				}
-				Err(e) => {
-					debug!("Invalid hydrated block {}: {:?}", cb.hash(), e);
-					return Ok(false);
-				}
-			};
-
-			if let Ok(prev) = self.chain().get_previous_header(&cb.header) {
-				if block
-					.validate(&prev.total_kernel_offset, self.verifier_cache.clone())
-					.is_ok()
-				{
-					debug!(
-						"successfully hydrated block: {} at {} ({})",
-						block.header.hash(),
-						block.header.height,
-						block.inputs().version_str(),
-					);
-					self.process_block(block, peer_info, chain::Options::NONE)
-				} else if self.sync_state.status() == SyncStatus::NoSync {
-					debug!("adapter: block invalid after hydration, requesting full block");
+				if !self.request_block_transactions(cb.clone(), missing_short_ids, peer_info) {
 					self.request_block(&cb.header, peer_info, chain::Options::NONE);
-					Ok(true)
-				} else {
-					debug!("block invalid after hydration, ignoring it, cause still syncing");
-					Ok(true)
 				}
-			} else {
-				debug!("failed to retrieve previous block header (still syncing?)");
-				Ok(true)
+				return Ok(true);
 			}
+
+			self.hydrate_compact_block(cb, &txs, peer_info)
 		}
 	}
 
@@ -391,6 +508,29 @@ where
 			.unwrap_or(None)
 	}
 
+	fn get_historical_block(&self, h: Hash) -> Option<(core::Block, chain::BlockOutputsProof)> {
+		// Only archive nodes have all the hashes needed to prove historical blocks.
+		if !self.chain().archive_mode() {
+			return None;
+		}
+		let b = self.chain().get_block(&h).ok()?;
+		match self.chain().get_block_outputs_proof(&b) {
+			Ok(proof) => Some((b, proof)),
+			Err(e) => {
+				debug!("get_historical_block: no proof for block {}: {}", h, e);
+				None
+			}
+		}
+	}
+
+	fn block_exists(&self, h: Hash) -> bool {
+		self.chain().block_exists(h).unwrap_or(false)
+	}
+
+	fn header_exists(&self, h: Hash) -> bool {
+		self.chain().get_block_header(&h).is_ok()
+	}
+
 	/// Provides a reading view into the current txhashset state as well as
 	/// the required indexes for a consumer to rewind to a consistent state
 	/// at the provided block hash.
@@ -550,6 +690,80 @@ where
 		}
 		segmenter.rangeproof_segment(id)
 	}
+
+	fn receive_bitmap_segment(
+		&self,
+		block_hash: Hash,
+		output_root: Hash,
+		segment: Segment<BitmapChunk>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		debug!(
+			"Received bitmap segment {} for block_hash: {} from {}",
+			segment.identifier().idx,
+			block_hash,
+			peer_info.addr
+		);
+		let id = SegmentTypeIdentifier::new(SegmentType::Bitmap, segment.identifier());
+		self.process_segment(block_hash, id, peer_info, |desegmenter| {
+			desegmenter.add_bitmap_segment(segment, output_root)
+		})
+	}
+
+	fn receive_output_segment(
+		&self,
+		block_hash: Hash,
+		_bitmap_root: Hash,
+		segment: Segment<OutputIdentifier>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		debug!(
+			"Received output segment {} for block_hash: {} from {}",
+			segment.identifier().idx,
+			block_hash,
+			peer_info.addr
+		);
+		let id = SegmentTypeIdentifier::new(SegmentType::Output, segment.identifier());
+		self.process_segment(block_hash, id, peer_info, |desegmenter| {
+			desegmenter.add_output_segment(segment)
+		})
+	}
+
+	fn receive_rangeproof_segment(
+		&self,
+		block_hash: Hash,
+		segment: Segment<RangeProof>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		debug!(
+			"Received rangeproof segment {} for block_hash: {} from {}",
+			segment.identifier().idx,
+			block_hash,
+			peer_info.addr
+		);
+		let id = SegmentTypeIdentifier::new(SegmentType::RangeProof, segment.identifier());
+		self.process_segment(block_hash, id, peer_info, |desegmenter| {
+			desegmenter.add_rangeproof_segment(segment)
+		})
+	}
+
+	fn receive_kernel_segment(
+		&self,
+		block_hash: Hash,
+		segment: Segment<TxKernel>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		debug!(
+			"Received kernel segment {} for block_hash: {} from {}",
+			segment.identifier().idx,
+			block_hash,
+			peer_info.addr
+		);
+		let id = SegmentTypeIdentifier::new(SegmentType::Kernel, segment.identifier());
+		self.process_segment(block_hash, id, peer_info, |desegmenter| {
+			desegmenter.add_kernel_segment(segment)
+		})
+	}
 }
 
 impl<B, P, V> NetToChainAdapter<B, P, V>
@@ -575,6 +789,7 @@ where
 			peers: OneTime::new(),
 			config,
 			hooks,
+			pending_compact_blocks: RwLock::new(HashMap::new()),
 		}
 	}
 
@@ -597,6 +812,55 @@ where
 			.expect("Failed to upgrade weak ref to our chain.")
 	}
 
+	// Hand a received PIBD segment over to our desegmenter (if we are currently
+	// rebuilding the txhashset at the given block).
+	// Returns false if the segment is invalid, which will get the peer banned.
+	// Accepted segments are recorded along with their sender, for our PIBD stats.
+	fn process_segment<F>(
+		&self,
+		block_hash: Hash,
+		id: SegmentTypeIdentifier,
+		peer_info: &PeerInfo,
+		add_segment: F,
+	) -> Result<bool, chain::Error>
+	where
+		F: FnOnce(&mut chain::txhashset::Desegmenter) -> Result<(), chain::Error>,
+	{
+		let desegmenter = self.chain().desegmenter();
+		let mut desegmenter = desegmenter.write();
+		let desegmenter = match desegmenter.as_mut() {
+			Some(d) if d.header().hash() == block_hash => d,
+			_ => {
+				debug!(
+					"Segment for block_hash {} received but not expected, ignoring.",
+					block_hash
+				);
+				return Ok(true);
+			}
+		};
+		match add_segment(desegmenter) {
+			Ok(()) => {
+				self.sync_state
+					.pibd_segment_received(id, peer_info.addr.to_string());
+				Ok(true)
+			}
+			Err(e) => match e.kind() {
+				chain::ErrorKind::SegmentError(_) | chain::ErrorKind::InvalidSegmentHeight => {
+					debug!("Invalid segment for block_hash {}: {:?}", block_hash, e);
+					Ok(false)
+				}
+				_ => {
+					error!(
+						"Failed to apply segment for block_hash {}: {}",
+						block_hash, e
+					);
+					self.sync_state.set_sync_error(e);
+					Ok(true)
+				}
+			},
+		}
+	}
+
 	// Find the first locator hash that refers to a known header on our main chain.
 	fn find_common_header(&self, locator: &[Hash]) -> Option<BlockHeader> {
 		let header_pmmr = self.chain().header_pmmr();
@@ -675,6 +939,27 @@ where
 		}
 	}
 
+	fn process_historical_block(
+		&self,
+		b: core::Block,
+		proof: chain::BlockOutputsProof,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		match self.chain().process_historical_block(&b, &proof) {
+			Ok(()) => Ok(true),
+			Err(e) => {
+				debug!(
+					"process_historical_block: block {} at {} from {} refused by chain: {}",
+					b.hash(),
+					b.header.height,
+					peer_info.addr,
+					e.kind()
+				);
+				Ok(!e.is_bad_data())
+			}
+		}
+	}
+
 	fn validate_chain(&self, bhash: Hash) {
 		// If we are running in "validate the full chain every block" then
 		// panic here if validation fails for any reason.
@@ -723,6 +1008,108 @@ where
 		self.send_tx_request_to_peer(h, peer_info, |peer, h| peer.send_tx_request(h))
 	}
 
+	// Fetch the txs announced by a peer we are missing in a single request.
+	fn request_transactions(&self, hashes: Vec<Hash>, peer_info: &PeerInfo) {
+		match self.peers().get_connected_peer(peer_info.addr) {
+			None => debug!(
+				"request_transactions: can't send request to peer {:?}, not connected",
+				peer_info.addr
+			),
+			Some(peer) => {
+				if let Err(e) = peer.send_txs_request(hashes) {
+					error!("request_transactions: failed: {:?}", e)
+				}
+			}
+		}
+	}
+
+	// Fetch the txs of a compact block missing from our pool in a single
+	// request, keeping the compact block around until they are received.
+	// Returns false if the peer can't provide them.
+	fn request_block_transactions(
+		&self,
+		cb: CompactBlock,
+		kern_ids: Vec<ShortId>,
+		peer_info: &PeerInfo,
+	) -> bool {
+		if !peer_info
+			.capabilities
+			.contains(p2p::Capabilities::TX_PACKAGE)
+			|| kern_ids.len() > p2p::MAX_TX_KERNEL_HASHES as usize
+		{
+			return false;
+		}
+		let peer = match self.peers().get_connected_peer(peer_info.addr) {
+			Some(peer) => peer,
+			None => return false,
+		};
+
+		let hash = cb.hash();
+		let nonce = cb.nonce;
+		{
+			// Forget about the older compact blocks, their txs are not coming.
+			let height = cb.header.height;
+			let mut pending = self.pending_compact_blocks.write();
+			pending.retain(|_, (x, _)| x.header.height + 1 >= height);
+			pending.insert(hash, (cb, peer_info.addr));
+		}
+		if let Err(e) = peer.send_block_txs_request(hash, nonce, kern_ids) {
+			error!("request_block_transactions: failed: {:?}", e);
+			self.pending_compact_blocks.write().remove(&hash);
+			return false;
+		}
+		true
+	}
+
+	// Hydrate a compact block with the provided txs and push it through the
+	// chain pipeline, requesting the full block if it turns out invalid.
+	fn hydrate_compact_block(
+		&self,
+		cb: CompactBlock,
+		txs: &[Transaction],
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		let block = match core::Block::hydrate_from(cb.clone(), txs) {
+			Ok(block) => {
+				if !self.sync_state.is_syncing() {
+					for hook in &self.hooks {
+						hook.on_block_received(&block, &peer_info.addr);
+					}
+				}
+				block
+			}
+			Err(e) => {
+				debug!("Invalid hydrated block {}: {:?}", cb.hash(), e);
+				return Ok(false);
+			}
+		};
+
+		if let Ok(prev) = self.chain().get_previous_header(&cb.header) {
+			if block
+				.validate(&prev.total_kernel_offset, self.verifier_cache.clone())
+				.is_ok()
+			{
+				debug!(
+					"successfully hydrated block: {} at {} ({})",
+					block.header.hash(),
+					block.header.height,
+					block.inputs().version_str(),
+				);
+				self.process_block(block, peer_info, chain::Options::NONE)
+			} else if self.sync_state.status() == SyncStatus::NoSync {
+				debug!("adapter: block invalid after hydration, requesting full block");
+				self.request_block(&cb.header, peer_info, chain::Options::NONE);
+				Ok(true)
+			} else {
+				debug!("block invalid after hydration, ignoring it, cause still syncing");
+				Ok(true)
+			}
+		} else {
+			debug!("failed to retrieve previous block header (still syncing?)");
+			Ok(true)
+		}
+	}
+
 	// After receiving a compact block if we cannot successfully hydrate
 	// it into a full block then fallback to requesting the full block
 	// from the same peer that gave us the compact block
@@ -840,7 +1227,26 @@ where
 		}
 
 		if status.is_reorg() {
-			let _ = self.tx_pool.write().reconcile_reorg_cache(&b.header);
+			let returned = self
+				.tx_pool
+				.write()
+				.reconcile_reorg_cache(&b.header)
+				.unwrap_or_default();
+			let kernels: Vec<_> = returned
+				.iter()
+				.flat_map(|tx| tx.kernels().iter().cloned())
+				.collect();
+			for hook in &self.hooks {
+				hook.on_reorg_completed(b, status, &kernels);
+			}
+		}
+	}
+
+	fn blocks_disconnected(&self, headers: &[core::BlockHeader], _opts: Options) {
+		for header in headers {
+			for hook in &self.hooks {
+				hook.on_block_disconnected(header);
+			}
 		}
 	}
 }
@@ -1018,9 +1424,11 @@ impl pool::BlockChain for PoolToChainAdapter {
 	}
 
 	fn validate_tx(&self, tx: &Transaction) -> Result<(), pool::PoolError> {
-		self.chain()
-			.validate_tx(tx)
-			.map_err(|_| pool::PoolError::Other("failed to validate tx".to_string()))
+		self.chain().validate_tx(tx).map_err(|e| match e.kind() {
+			chain::ErrorKind::DuplicateCommitment(_) => pool::PoolError::DuplicateCommitment,
+			chain::ErrorKind::NRDRelativeHeight => pool::PoolError::NRDKernelRelativeHeight,
+			_ => pool::PoolError::Other("failed to validate tx".to_string()),
+		})
 	}
 
 	fn validate_inputs(&self, inputs: &Inputs) -> Result<Vec<OutputIdentifier>, pool::PoolError> {
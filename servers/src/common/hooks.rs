@@ -15,46 +15,57 @@
 //! This module allows to register callbacks on certain events. To add a custom
 //! callback simply implement the coresponding trait and add it to the init function
 
-extern crate hyper;
-extern crate hyper_rustls;
-extern crate tokio;
-
+use crate::api::EventPublisher;
 use crate::chain::BlockStatus;
 use crate::common::types::{ServerConfig, WebHooksConfig};
+use crate::common::webhooks::WebHookQueue;
 use crate::core::core;
 use crate::core::core::hash::Hashed;
 use crate::p2p::types::PeerAddr;
-use futures::TryFutureExt;
 use grin_util::ToHex;
-use hyper::client::HttpConnector;
-use hyper::header::HeaderValue;
-use hyper::Client;
-use hyper::{Body, Method, Request};
-use hyper_rustls::HttpsConnector;
 use serde::Serialize;
 use serde_json::{json, to_string};
-use std::time::Duration;
-use tokio::runtime::{Builder, Runtime};
+use std::sync::Arc;
 
 /// Returns the list of event hooks that will be initialized for network events
-pub fn init_net_hooks(config: &ServerConfig) -> Vec<Box<dyn NetEvents + Send + Sync>> {
+pub fn init_net_hooks(
+	config: &ServerConfig,
+	publisher: Arc<EventPublisher>,
+	webhooks: Option<Arc<WebHookQueue>>,
+) -> Vec<Box<dyn NetEvents + Send + Sync>> {
 	let mut list: Vec<Box<dyn NetEvents + Send + Sync>> = Vec::new();
 	list.push(Box::new(EventLogger));
-	if config.webhook_config.block_received_url.is_some()
-		|| config.webhook_config.tx_received_url.is_some()
-		|| config.webhook_config.header_received_url.is_some()
-	{
-		list.push(Box::new(WebHook::from_config(&config.webhook_config)));
+	list.push(Box::new(EventStream { publisher }));
+	if let Some(queue) = webhooks {
+		if config.webhook_config.block_received_url.is_some()
+			|| config.webhook_config.tx_received_url.is_some()
+			|| config.webhook_config.header_received_url.is_some()
+		{
+			list.push(Box::new(WebHook::from_config(
+				&config.webhook_config,
+				queue,
+			)));
+		}
 	}
 	list
 }
 
 /// Returns the list of event hooks that will be initialized for chain events
-pub fn init_chain_hooks(config: &ServerConfig) -> Vec<Box<dyn ChainEvents + Send + Sync>> {
+pub fn init_chain_hooks(
+	config: &ServerConfig,
+	publisher: Arc<EventPublisher>,
+	webhooks: Option<Arc<WebHookQueue>>,
+) -> Vec<Box<dyn ChainEvents + Send + Sync>> {
 	let mut list: Vec<Box<dyn ChainEvents + Send + Sync>> = Vec::new();
 	list.push(Box::new(EventLogger));
-	if config.webhook_config.block_accepted_url.is_some() {
-		list.push(Box::new(WebHook::from_config(&config.webhook_config)));
+	list.push(Box::new(EventStream { publisher }));
+	if let Some(queue) = webhooks {
+		if config.webhook_config.block_accepted_url.is_some() {
+			list.push(Box::new(WebHook::from_config(
+				&config.webhook_config,
+				queue,
+			)));
+		}
 	}
 	list
 }
@@ -77,6 +88,20 @@ pub trait NetEvents {
 pub trait ChainEvents {
 	/// Triggers when a new block is accepted by the chain (might be a Reorg or a Fork)
 	fn on_block_accepted(&self, block: &core::Block, status: BlockStatus) {}
+
+	/// Triggers for each block rewound by a reorg, most recent first, before
+	/// the block causing the reorg is accepted
+	fn on_block_disconnected(&self, header: &core::BlockHeader) {}
+
+	/// Triggers once a reorg is fully processed, with the kernels of the
+	/// rolled back transactions that were returned to the pool
+	fn on_reorg_completed(
+		&self,
+		block: &core::Block,
+		status: BlockStatus,
+		kernels: &[core::TxKernel],
+	) {
+	}
 }
 
 /// Basic Logger
@@ -165,6 +190,62 @@ impl ChainEvents for EventLogger {
 			}
 		}
 	}
+
+	fn on_block_disconnected(&self, header: &core::BlockHeader) {
+		info!("block_disconnected: {} at {}", header.hash(), header.height);
+	}
+
+	fn on_reorg_completed(
+		&self,
+		block: &core::Block,
+		_status: BlockStatus,
+		kernels: &[core::TxKernel],
+	) {
+		info!(
+			"reorg_completed: {} at {}, {} kernels returned to pool",
+			block.hash(),
+			block.header.height,
+			kernels.len(),
+		);
+	}
+}
+
+/// Forwards events to the clients of the node API events WebSocket
+struct EventStream {
+	publisher: Arc<EventPublisher>,
+}
+
+impl NetEvents for EventStream {
+	fn on_transaction_received(&self, tx: &core::Transaction) {
+		self.publisher.tx_received(tx);
+	}
+
+	fn on_block_received(&self, block: &core::Block, addr: &PeerAddr) {
+		self.publisher.block_received(block, addr);
+	}
+
+	fn on_header_received(&self, header: &core::BlockHeader, addr: &PeerAddr) {
+		self.publisher.header_received(header, addr);
+	}
+}
+
+impl ChainEvents for EventStream {
+	fn on_block_accepted(&self, block: &core::Block, status: BlockStatus) {
+		self.publisher.block_accepted(block, &status);
+	}
+
+	fn on_block_disconnected(&self, header: &core::BlockHeader) {
+		self.publisher.block_disconnected(header);
+	}
+
+	fn on_reorg_completed(
+		&self,
+		block: &core::Block,
+		status: BlockStatus,
+		kernels: &[core::TxKernel],
+	) {
+		self.publisher.reorg_completed(block, &status, kernels);
+	}
 }
 
 fn parse_url(value: &Option<String>) -> Option<hyper::Uri> {
@@ -187,7 +268,7 @@ fn parse_url(value: &Option<String>) -> Option<hyper::Uri> {
 	}
 }
 
-/// A struct that holds the hyper/tokio runtime.
+/// Queues notifications for the configured urls, see `webhooks` for delivery.
 struct WebHook {
 	/// url to POST transaction data when a new transaction arrives from a peer
 	tx_received_url: Option<hyper::Uri>,
@@ -197,77 +278,28 @@ struct WebHook {
 	block_received_url: Option<hyper::Uri>,
 	/// url to POST block data when a new block is accepted by our node (might be a reorg or a fork)
 	block_accepted_url: Option<hyper::Uri>,
-	/// The hyper client to be used for all requests
-	client: Client<HttpsConnector<HttpConnector>>,
-	/// The tokio event loop
-	runtime: Runtime,
+	/// The on-disk queue notifications are delivered from
+	queue: Arc<WebHookQueue>,
 }
 
 impl WebHook {
-	/// Instantiates a Webhook struct
-	fn new(
-		tx_received_url: Option<hyper::Uri>,
-		header_received_url: Option<hyper::Uri>,
-		block_received_url: Option<hyper::Uri>,
-		block_accepted_url: Option<hyper::Uri>,
-		nthreads: u16,
-		timeout: u16,
-	) -> WebHook {
-		let keep_alive = Duration::from_secs(timeout as u64);
-
-		info!(
-			"Spawning {} threads for webhooks (timeout set to {} secs)",
-			nthreads, timeout
-		);
-
-		let https = HttpsConnector::new();
-		let client = Client::builder()
-			.pool_idle_timeout(keep_alive)
-			.build::<_, hyper::Body>(https);
-
+	/// Instantiates a Webhook struct from a configuration file
+	fn from_config(config: &WebHooksConfig, queue: Arc<WebHookQueue>) -> WebHook {
 		WebHook {
-			tx_received_url,
-			block_received_url,
-			header_received_url,
-			block_accepted_url,
-			client,
-			runtime: Builder::new()
-				.threaded_scheduler()
-				.enable_all()
-				.core_threads(nthreads as usize)
-				.build()
-				.unwrap(),
+			tx_received_url: parse_url(&config.tx_received_url),
+			header_received_url: parse_url(&config.header_received_url),
+			block_received_url: parse_url(&config.block_received_url),
+			block_accepted_url: parse_url(&config.block_accepted_url),
+			queue,
 		}
 	}
 
-	/// Instantiates a Webhook struct from a configuration file
-	fn from_config(config: &WebHooksConfig) -> WebHook {
-		WebHook::new(
-			parse_url(&config.tx_received_url),
-			parse_url(&config.header_received_url),
-			parse_url(&config.block_received_url),
-			parse_url(&config.block_accepted_url),
-			config.nthreads,
-			config.timeout,
-		)
-	}
-
-	fn post(&self, url: hyper::Uri, data: String) {
-		let mut req = Request::new(Body::from(data));
-		*req.method_mut() = Method::POST;
-		*req.uri_mut() = url.clone();
-		req.headers_mut().insert(
-			hyper::header::CONTENT_TYPE,
-			HeaderValue::from_static("application/json"),
-		);
-
-		let future = self.client.request(req).map_err(move |_res| {
-			warn!("Error sending POST request to {}", url);
-		});
-
-		self.runtime.spawn(future);
-	}
-	fn make_request<T: Serialize>(&self, payload: &T, uri: &Option<hyper::Uri>) -> bool {
+	fn make_request<T: Serialize>(
+		&self,
+		payload: &T,
+		uri: &Option<hyper::Uri>,
+		event: &str,
+	) -> bool {
 		if let Some(url) = uri {
 			let payload = match to_string(payload) {
 				Ok(serialized) => serialized,
@@ -275,7 +307,9 @@ impl WebHook {
 					return false; // print error message
 				}
 			};
-			self.post(url.clone(), payload);
+			if let Err(e) = self.queue.enqueue(&url.to_string(), event, &payload) {
+				error!("Failed to queue {} webhook to {}: {}", event, url, e);
+			}
 		}
 		true
 	}
@@ -311,7 +345,7 @@ impl ChainEvents for WebHook {
 			})
 		};
 
-		if !self.make_request(&payload, &self.block_accepted_url) {
+		if !self.make_request(&payload, &self.block_accepted_url, "block_accepted") {
 			error!(
 				"Failed to serialize block {} at height {}",
 				block.hash(),
@@ -328,7 +362,7 @@ impl NetEvents for WebHook {
 			"hash": tx.hash().to_hex(),
 			"data": tx
 		});
-		if !self.make_request(&payload, &self.tx_received_url) {
+		if !self.make_request(&payload, &self.tx_received_url, "tx_received") {
 			error!("Failed to serialize transaction {}", tx.hash());
 		}
 	}
@@ -340,7 +374,7 @@ impl NetEvents for WebHook {
 			"peer": addr,
 			"data": block
 		});
-		if !self.make_request(&payload, &self.block_received_url) {
+		if !self.make_request(&payload, &self.block_received_url, "block_received") {
 			error!(
 				"Failed to serialize block {} at height {}",
 				block.hash().to_hex(),
@@ -356,7 +390,7 @@ impl NetEvents for WebHook {
 			"peer": addr,
 			"data": header
 		});
-		if !self.make_request(&payload, &self.header_received_url) {
+		if !self.make_request(&payload, &self.header_received_url, "header_received") {
 			error!(
 				"Failed to serialize header {} at height {}",
 				header.hash(),
@@ -105,6 +105,13 @@ pub struct WorkerStats {
 	pub initial_block_height: u64,
 	/// pow difficulty this worker is using
 	pub pow_difficulty: u64,
+	/// pow difficulty this worker was using before the last adjustment, still
+	/// accepted until the next job
+	pub previous_pow_difficulty: u64,
+	/// number of valid shares submitted since the last difficulty adjustment
+	pub shares_since_retarget: u64,
+	/// Timestamp of the last difficulty adjustment
+	pub last_retarget: SystemTime,
 	/// number of valid shares submitted
 	pub num_accepted: u64,
 	/// number of invalid shares submitted
@@ -259,6 +266,9 @@ impl Default for WorkerStats {
 			last_seen: SystemTime::now(),
 			initial_block_height: 0,
 			pow_difficulty: 0,
+			previous_pow_difficulty: 0,
+			shares_since_retarget: 0,
+			last_retarget: SystemTime::now(),
 			num_accepted: 0,
 			num_rejected: 0,
 			num_stale: 0,
@@ -171,6 +171,14 @@ pub struct ServerConfig {
 	/// Whether this node is a full archival node or a fast-sync, pruned node
 	pub archive_mode: Option<bool>,
 
+	/// Whether to maintain an index of the blocks creating and spending
+	/// every output commitment (for block explorers)
+	pub output_history_index: Option<bool>,
+
+	/// Whether to maintain an index of kernel excess commitments to speed up
+	/// kernel lookups without height hints
+	pub kernel_excess_index: Option<bool>,
+
 	/// Whether to skip the sync timeout on startup
 	/// (To assist testing on solo chains)
 	pub skip_sync_wait: Option<bool>,
@@ -224,6 +232,8 @@ impl Default for ServerConfig {
 			chain_type: ChainTypes::default(),
 			future_time_limit: default_future_time_limit(),
 			archive_mode: Some(false),
+			output_history_index: Some(false),
+			kernel_excess_index: Some(false),
 			chain_validation_mode: ChainValidationMode::default(),
 			pool_config: pool::PoolConfig::default(),
 			skip_sync_wait: Some(false),
@@ -235,6 +245,13 @@ impl Default for ServerConfig {
 	}
 }
 
+impl ServerConfig {
+	/// TLS configuration of the API server, if any
+	pub fn tls_config(&self) -> Result<Option<api::TLSConfig>, Error> {
+		tls_config(&self.tls_certificate_file, &self.tls_certificate_key)
+	}
+}
+
 /// Stratum (Mining server) configuration
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct StratumServerConfig {
@@ -252,9 +269,30 @@ pub struct StratumServerConfig {
 	/// Minimum difficulty for worker shares
 	pub minimum_share_difficulty: u64,
 
+	/// Number of shares per minute each worker's share difficulty is adjusted
+	/// to produce (vardiff), all workers stay at the minimum if not set
+	#[serde(default)]
+	pub vardiff_shares_per_minute: Option<u32>,
+
+	/// How often, in seconds, the share difficulty of each worker is adjusted
+	#[serde(default = "default_vardiff_retarget_time")]
+	pub vardiff_retarget_time: u64,
+
 	/// Base address to the HTTP wallet receiver
 	pub wallet_listener_url: String,
 
+	/// TLS certificate file, workers connect over TLS if set
+	#[serde(default)]
+	pub tls_certificate_file: Option<String>,
+	/// TLS certificate private key file
+	#[serde(default)]
+	pub tls_certificate_key: Option<String>,
+
+	/// File with the logins allowed to mine, with their password and
+	/// optionally their own wallet receiver, anyone can mine if not set
+	#[serde(default)]
+	pub credentials_file: Option<String>,
+
 	/// Attributes the reward to a random private key instead of contacting the
 	/// wallet receiver. Mostly used for tests.
 	pub burn_reward: bool,
@@ -264,15 +302,44 @@ impl Default for StratumServerConfig {
 	fn default() -> StratumServerConfig {
 		StratumServerConfig {
 			wallet_listener_url: "http://127.0.0.1:3415".to_string(),
+			credentials_file: None,
+			tls_certificate_file: None,
+			tls_certificate_key: None,
 			burn_reward: false,
 			attempt_time_per_block: 15,
 			minimum_share_difficulty: 1,
+			vardiff_shares_per_minute: None,
+			vardiff_retarget_time: default_vardiff_retarget_time(),
 			enable_stratum_server: Some(false),
 			stratum_server_addr: Some("127.0.0.1:3416".to_string()),
 		}
 	}
 }
 
+impl StratumServerConfig {
+	/// TLS configuration of the stratum server, if any
+	pub fn tls_config(&self) -> Result<Option<api::TLSConfig>, Error> {
+		tls_config(&self.tls_certificate_file, &self.tls_certificate_key)
+	}
+}
+
+fn tls_config(
+	certificate_file: &Option<String>,
+	certificate_key: &Option<String>,
+) -> Result<Option<api::TLSConfig>, Error> {
+	match (certificate_file, certificate_key) {
+		(None, _) => Ok(None),
+		(Some(file), Some(key)) => Ok(Some(api::TLSConfig::new(file.clone(), key.clone()))),
+		(Some(_), None) => Err(Error::ArgumentError(
+			"Private key for certificate is not set".to_string(),
+		)),
+	}
+}
+
+fn default_vardiff_retarget_time() -> u64 {
+	60
+}
+
 /// Web hooks configuration
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct WebHooksConfig {
@@ -290,6 +357,25 @@ pub struct WebHooksConfig {
 	/// timeout in seconds for the http request
 	#[serde(default = "default_timeout")]
 	pub timeout: u16,
+	/// number of delivery attempts before a notification is dead-lettered
+	#[serde(default = "default_max_attempts")]
+	pub max_attempts: u32,
+	/// delay in seconds before the first retry, doubled on each further retry
+	#[serde(default = "default_retry_base_delay")]
+	pub retry_base_delay: u64,
+	/// maximum delay in seconds between two retries
+	#[serde(default = "default_retry_max_delay")]
+	pub retry_max_delay: u64,
+}
+
+impl WebHooksConfig {
+	/// Whether any webhook url is configured
+	pub fn is_enabled(&self) -> bool {
+		self.tx_received_url.is_some()
+			|| self.header_received_url.is_some()
+			|| self.block_received_url.is_some()
+			|| self.block_accepted_url.is_some()
+	}
 }
 
 fn default_timeout() -> u16 {
@@ -300,6 +386,18 @@ fn default_nthreads() -> u16 {
 	4
 }
 
+fn default_max_attempts() -> u32 {
+	15
+}
+
+fn default_retry_base_delay() -> u64 {
+	2
+}
+
+fn default_retry_max_delay() -> u64 {
+	3600
+}
+
 impl Default for WebHooksConfig {
 	fn default() -> WebHooksConfig {
 		WebHooksConfig {
@@ -309,6 +407,9 @@ impl Default for WebHooksConfig {
 			block_accepted_url: None,
 			nthreads: default_nthreads(),
 			timeout: default_timeout(),
+			max_attempts: default_max_attempts(),
+			retry_base_delay: default_retry_base_delay(),
+			retry_max_delay: default_retry_max_delay(),
 		}
 	}
 }
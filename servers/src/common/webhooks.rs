@@ -0,0 +1,572 @@
+// Copyright 2021 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Durable webhook delivery. Notifications are queued in their own db,
+//! delivered in order for each url with an exponential backoff between
+//! retries, and moved to a dead letter list once their attempts are exhausted.
+
+use crate::api::{self, WebHookDeadLetter, WebHookDeadLetters};
+use crate::common::types::WebHooksConfig;
+use crate::core::ser::{self, Readable, Reader, Writeable, Writer};
+use crate::store::{self, to_key, u64_to_key, Store};
+use crate::util::{Mutex, StopState};
+use chrono::Utc;
+use futures::future::join_all;
+use hyper::client::HttpConnector;
+use hyper::header::HeaderValue;
+use hyper::{Body, Client, Method, Request};
+use hyper_rustls::HttpsConnector;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tokio::runtime::Builder;
+
+const DB_NAME: &str = "webhook";
+const STORE_SUBPATH: &str = "webhooks";
+
+const QUEUED_PREFIX: u8 = b'Q';
+const DEAD_LETTER_PREFIX: u8 = b'D';
+const PAYLOAD_PREFIX: u8 = b'P';
+
+/// Errors are kept for inspection, no need to store a whole html page.
+const MAX_ERROR_LEN: usize = 512;
+
+/// The http client used to deliver notifications.
+pub type WebHookClient = Client<HttpsConnector<HttpConnector>>;
+
+/// A webhook notification, queued or dead-lettered.
+/// The (potentially large) JSON payload is stored separately.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebHookEntry {
+	/// Id of the notification, increasing in queuing order
+	pub id: u64,
+	/// Url to POST the payload to
+	pub url: String,
+	/// Event that triggered the notification
+	pub event: String,
+	/// Time the notification was queued
+	pub created_at: i64,
+	/// Number of failed delivery attempts
+	pub attempts: u32,
+	/// Time of the next delivery attempt
+	pub next_attempt_at: i64,
+	/// Error of the last failed delivery attempt
+	pub last_error: String,
+}
+
+impl Writeable for WebHookEntry {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u64(self.id)?;
+		writer.write_bytes(&self.url)?;
+		writer.write_bytes(&self.event)?;
+		writer.write_i64(self.created_at)?;
+		writer.write_u32(self.attempts)?;
+		writer.write_i64(self.next_attempt_at)?;
+		writer.write_bytes(&self.last_error)
+	}
+}
+
+impl Readable for WebHookEntry {
+	fn read<R: Reader>(reader: &mut R) -> Result<WebHookEntry, ser::Error> {
+		let id = reader.read_u64()?;
+		let url = read_string(reader)?;
+		let event = read_string(reader)?;
+		let created_at = reader.read_i64()?;
+		let attempts = reader.read_u32()?;
+		let next_attempt_at = reader.read_i64()?;
+		let last_error = read_string(reader)?;
+		Ok(WebHookEntry {
+			id,
+			url,
+			event,
+			created_at,
+			attempts,
+			next_attempt_at,
+			last_error,
+		})
+	}
+}
+
+fn read_string<R: Reader>(reader: &mut R) -> Result<String, ser::Error> {
+	String::from_utf8(reader.read_bytes_len_prefix()?).map_err(|_| ser::Error::CorruptedData)
+}
+
+/// Delay in seconds before the next attempt after the provided number of
+/// failed attempts.
+fn retry_delay(attempts: u32, config: &WebHooksConfig) -> u64 {
+	let factor = 1u64
+		.checked_shl(attempts.saturating_sub(1))
+		.unwrap_or(u64::MAX);
+	config
+		.retry_base_delay
+		.saturating_mul(factor)
+		.min(config.retry_max_delay)
+}
+
+/// Notifications queued for a given url, in queuing order.
+#[derive(Default)]
+struct UrlQueue {
+	ids: VecDeque<u64>,
+	/// Time before which the first notification is not due.
+	next_attempt_at: i64,
+}
+
+/// On-disk queue of webhook notifications.
+pub struct WebHookQueue {
+	db: Store,
+	next_id: AtomicU64,
+	/// Ids of the queued notifications by url, so finding the due ones does
+	/// not require reading the whole queue.
+	pending: Mutex<HashMap<String, UrlQueue>>,
+}
+
+impl WebHookQueue {
+	/// Opens (or creates) the webhook queue under the provided root path.
+	pub fn new(db_root: &str) -> Result<WebHookQueue, store::Error> {
+		let db = Store::new(db_root, Some(DB_NAME), Some(STORE_SUBPATH), None)?;
+		let queue = WebHookQueue {
+			db,
+			next_id: AtomicU64::new(0),
+			pending: Mutex::new(HashMap::new()),
+		};
+		queue.load_pending()?;
+		let last_id = queue
+			.queued()?
+			.iter()
+			.chain(queue.dead_letter_entries()?.iter())
+			.map(|e| e.id)
+			.max();
+		if let Some(last_id) = last_id {
+			queue.next_id.store(last_id + 1, Ordering::SeqCst);
+		}
+		Ok(queue)
+	}
+
+	/// Queues a notification for delivery.
+	pub fn enqueue(&self, url: &str, event: &str, payload: &str) -> Result<u64, store::Error> {
+		let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+		let now = Utc::now().timestamp();
+		let entry = WebHookEntry {
+			id,
+			url: url.to_string(),
+			event: event.to_string(),
+			created_at: now,
+			attempts: 0,
+			next_attempt_at: now,
+			last_error: String::new(),
+		};
+		let batch = self.db.batch()?;
+		batch.put(&u64_to_key(PAYLOAD_PREFIX, id), payload.as_bytes())?;
+		batch.put_ser(&u64_to_key(QUEUED_PREFIX, id), &entry)?;
+		batch.commit()?;
+		self.pending
+			.lock()
+			.entry(entry.url)
+			.or_default()
+			.ids
+			.push_back(id);
+		Ok(id)
+	}
+
+	/// Rebuilds the queued ids by url from the db.
+	fn load_pending(&self) -> Result<(), store::Error> {
+		let mut pending: HashMap<String, UrlQueue> = HashMap::new();
+		for entry in self.queued()? {
+			let next_attempt_at = entry.next_attempt_at;
+			let queue = pending.entry(entry.url).or_insert_with(|| UrlQueue {
+				ids: VecDeque::new(),
+				next_attempt_at,
+			});
+			queue.ids.push_back(entry.id);
+		}
+		*self.pending.lock() = pending;
+		Ok(())
+	}
+
+	/// Id of the first notification for the url, if due.
+	fn due_head(&self, url: &str, now: i64) -> Option<u64> {
+		let pending = self.pending.lock();
+		let queue = pending.get(url)?;
+		if queue.next_attempt_at > now {
+			return None;
+		}
+		queue.ids.front().cloned()
+	}
+
+	/// Removes the first notification for the url, delivered or dead-lettered.
+	fn pop_head(&self, url: &str, id: u64) {
+		let mut pending = self.pending.lock();
+		if let Some(queue) = pending.get_mut(url) {
+			if queue.ids.front() == Some(&id) {
+				queue.ids.pop_front();
+			}
+			if queue.ids.is_empty() {
+				pending.remove(url);
+			}
+		}
+	}
+
+	/// Delays the next attempt for the url.
+	fn delay_head(&self, url: &str, next_attempt_at: i64) {
+		if let Some(queue) = self.pending.lock().get_mut(url) {
+			queue.next_attempt_at = next_attempt_at;
+		}
+	}
+
+	/// Notifications waiting for delivery, in queuing order.
+	pub fn queued(&self) -> Result<Vec<WebHookEntry>, store::Error> {
+		self.entries(QUEUED_PREFIX)
+	}
+
+	/// Notifications that exhausted their delivery attempts, in queuing order.
+	pub fn dead_letter_entries(&self) -> Result<Vec<WebHookEntry>, store::Error> {
+		self.entries(DEAD_LETTER_PREFIX)
+	}
+
+	fn entries(&self, prefix: u8) -> Result<Vec<WebHookEntry>, store::Error> {
+		let protocol_version = self.db.protocol_version();
+		let iter = self.db.iter(&to_key(prefix, ""), move |_, mut v| {
+			ser::deserialize(&mut v, protocol_version).map_err(From::from)
+		})?;
+		Ok(iter.collect())
+	}
+
+	/// The JSON payload of a notification.
+	pub fn payload(&self, id: u64) -> Result<String, store::Error> {
+		let payload = self.db.read_with(&u64_to_key(PAYLOAD_PREFIX, id), |_, v| {
+			Ok(String::from_utf8_lossy(v).into_owned())
+		})?;
+		store::option_to_not_found(Ok(payload), || format!("webhook payload {}", id))
+	}
+
+	fn delivered(&self, entry: &WebHookEntry) -> Result<(), store::Error> {
+		let batch = self.db.batch()?;
+		batch.delete(&u64_to_key(QUEUED_PREFIX, entry.id))?;
+		batch.delete(&u64_to_key(PAYLOAD_PREFIX, entry.id))?;
+		batch.commit()
+	}
+
+	/// Records a failed attempt, scheduling a retry or moving the notification
+	/// to the dead letters. Returns true if it was dead-lettered.
+	fn failed(
+		&self,
+		entry: &mut WebHookEntry,
+		error: &str,
+		config: &WebHooksConfig,
+	) -> Result<bool, store::Error> {
+		entry.attempts += 1;
+		entry.last_error = error.chars().take(MAX_ERROR_LEN).collect();
+		let dead = entry.attempts >= config.max_attempts;
+		let batch = self.db.batch()?;
+		if dead {
+			warn!(
+				"webhook: giving up on {} notification {} to {} after {} attempts: {}",
+				entry.event, entry.id, entry.url, entry.attempts, entry.last_error
+			);
+			batch.delete(&u64_to_key(QUEUED_PREFIX, entry.id))?;
+			batch.put_ser(&u64_to_key(DEAD_LETTER_PREFIX, entry.id), entry)?;
+		} else {
+			let delay = retry_delay(entry.attempts, config);
+			debug!(
+				"webhook: failed to deliver {} notification {} to {}, retrying in {}s: {}",
+				entry.event, entry.id, entry.url, delay, entry.last_error
+			);
+			entry.next_attempt_at = Utc::now().timestamp().saturating_add(delay as i64);
+			batch.put_ser(&u64_to_key(QUEUED_PREFIX, entry.id), entry)?;
+		}
+		batch.commit()?;
+		Ok(dead)
+	}
+
+	/// Moves dead letters (all of them if no ids are provided) back to the
+	/// queue, where they take their original place in the per url ordering.
+	pub fn replay(&self, ids: Option<&[u64]>) -> Result<usize, store::Error> {
+		let now = Utc::now().timestamp();
+		let batch = self.db.batch()?;
+		let mut count = 0;
+		for mut entry in self.dead_letter_entries()? {
+			if let Some(ids) = ids {
+				if !ids.contains(&entry.id) {
+					continue;
+				}
+			}
+			entry.attempts = 0;
+			entry.next_attempt_at = now;
+			batch.delete(&u64_to_key(DEAD_LETTER_PREFIX, entry.id))?;
+			batch.put_ser(&u64_to_key(QUEUED_PREFIX, entry.id), &entry)?;
+			count += 1;
+		}
+		batch.commit()?;
+		self.load_pending()?;
+		Ok(count)
+	}
+
+	/// Attempts delivery of the due notifications, concurrently across urls
+	/// but in order for any given url: a url's notifications wait until the
+	/// first one is delivered (or dead-lettered).
+	pub async fn deliver_due(
+		&self,
+		client: &WebHookClient,
+		config: &WebHooksConfig,
+	) -> Result<(), store::Error> {
+		let now = Utc::now().timestamp();
+		let due: Vec<String> = self
+			.pending
+			.lock()
+			.iter()
+			.filter(|(_, queue)| queue.next_attempt_at <= now)
+			.map(|(url, _)| url.clone())
+			.collect();
+		let timeout = Duration::from_secs(config.timeout as u64);
+		let results = join_all(
+			due.iter()
+				.map(|url| self.deliver_in_order(url, client, config, timeout)),
+		)
+		.await;
+		results.into_iter().collect()
+	}
+
+	async fn deliver_in_order(
+		&self,
+		url: &str,
+		client: &WebHookClient,
+		config: &WebHooksConfig,
+		timeout: Duration,
+	) -> Result<(), store::Error> {
+		let now = Utc::now().timestamp();
+		while let Some(id) = self.due_head(url, now) {
+			let mut entry: WebHookEntry = match self.db.get_ser(&u64_to_key(QUEUED_PREFIX, id))? {
+				Some(entry) => entry,
+				None => {
+					self.pop_head(url, id);
+					continue;
+				}
+			};
+			if entry.next_attempt_at > now {
+				self.delay_head(url, entry.next_attempt_at);
+				break;
+			}
+			let payload = self.payload(entry.id)?;
+			match post(client, &entry.url, payload, timeout).await {
+				Ok(()) => {
+					self.delivered(&entry)?;
+					self.pop_head(url, id);
+				}
+				Err(e) => {
+					if self.failed(&mut entry, &e, config)? {
+						self.pop_head(url, id);
+					} else {
+						self.delay_head(url, entry.next_attempt_at);
+						break;
+					}
+				}
+			}
+		}
+		Ok(())
+	}
+}
+
+async fn post(
+	client: &WebHookClient,
+	url: &str,
+	payload: String,
+	timeout: Duration,
+) -> Result<(), String> {
+	let uri: hyper::Uri = url.parse().map_err(|e| format!("invalid url: {}", e))?;
+	let mut req = Request::new(Body::from(payload));
+	*req.method_mut() = Method::POST;
+	*req.uri_mut() = uri;
+	req.headers_mut().insert(
+		hyper::header::CONTENT_TYPE,
+		HeaderValue::from_static("application/json"),
+	);
+	match tokio::time::timeout(timeout, client.request(req)).await {
+		Err(_) => Err("request timed out".to_string()),
+		Ok(Err(e)) => Err(e.to_string()),
+		Ok(Ok(resp)) if resp.status().is_success() => Ok(()),
+		Ok(Ok(resp)) => Err(format!("unexpected http status {}", resp.status())),
+	}
+}
+
+impl WebHookDeadLetters for WebHookQueue {
+	fn dead_letters(&self, include_payload: bool) -> Result<Vec<WebHookDeadLetter>, api::Error> {
+		let to_api_err = |e: store::Error| api::ErrorKind::Internal(e.to_string());
+		let mut res = vec![];
+		for entry in self.dead_letter_entries().map_err(to_api_err)? {
+			let payload = if include_payload {
+				let payload = self.payload(entry.id).map_err(to_api_err)?;
+				Some(serde_json::from_str(&payload).unwrap_or(serde_json::Value::String(payload)))
+			} else {
+				None
+			};
+			res.push(WebHookDeadLetter {
+				id: entry.id,
+				url: entry.url,
+				event: entry.event,
+				created_at: entry.created_at,
+				attempts: entry.attempts,
+				last_error: entry.last_error,
+				payload,
+			});
+		}
+		Ok(res)
+	}
+
+	fn replay_dead_letters(&self, ids: Option<Vec<u64>>) -> Result<usize, api::Error> {
+		self.replay(ids.as_deref())
+			.map_err(|e| api::ErrorKind::Internal(e.to_string()).into())
+	}
+}
+
+/// Starts the thread delivering the queued webhook notifications.
+pub fn start_delivery(
+	queue: Arc<WebHookQueue>,
+	config: WebHooksConfig,
+	stop_state: Arc<StopState>,
+) -> std::io::Result<thread::JoinHandle<()>> {
+	info!(
+		"Spawning {} threads for webhooks (timeout set to {} secs)",
+		config.nthreads, config.timeout
+	);
+	thread::Builder::new()
+		.name("webhooks".to_string())
+		.spawn(move || {
+			let mut runtime = Builder::new()
+				.threaded_scheduler()
+				.enable_all()
+				.core_threads(config.nthreads as usize)
+				.build()
+				.unwrap();
+			let client = Client::builder()
+				.pool_idle_timeout(Duration::from_secs(config.timeout as u64))
+				.build::<_, Body>(HttpsConnector::new());
+			while !stop_state.is_stopped() {
+				if let Err(e) = runtime.block_on(queue.deliver_due(&client, &config)) {
+					error!("webhook: failed to deliver notifications: {}", e);
+				}
+				thread::sleep(Duration::from_millis(500));
+			}
+		})
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::core::global;
+	use std::fs;
+
+	fn test_config() -> WebHooksConfig {
+		WebHooksConfig {
+			max_attempts: 2,
+			retry_base_delay: 0,
+			..WebHooksConfig::default()
+		}
+	}
+
+	#[test]
+	fn test_retry_delay() {
+		let config = WebHooksConfig {
+			retry_base_delay: 2,
+			retry_max_delay: 60,
+			..WebHooksConfig::default()
+		};
+		assert_eq!(retry_delay(1, &config), 2);
+		assert_eq!(retry_delay(2, &config), 4);
+		assert_eq!(retry_delay(5, &config), 32);
+		assert_eq!(retry_delay(6, &config), 60);
+		assert_eq!(retry_delay(100, &config), 60);
+	}
+
+	#[test]
+	fn test_queue_retries_and_dead_letters() {
+		global::set_local_chain_type(global::ChainTypes::AutomatedTesting);
+		let db_root = "target/.webhook_queue";
+		let _ = fs::remove_dir_all(db_root);
+		// Nothing listens on port 1 so all deliveries fail.
+		let url = "http://127.0.0.1:1/accepted";
+		let other_url = "http://127.0.0.1:1/tx";
+		let config = test_config();
+		let client = Client::builder().build::<_, Body>(HttpsConnector::new());
+		let mut runtime = Builder::new()
+			.basic_scheduler()
+			.enable_all()
+			.build()
+			.unwrap();
+
+		{
+			let queue = WebHookQueue::new(db_root).unwrap();
+			assert_eq!(
+				queue.enqueue(url, "block_accepted", "{\"a\":1}").unwrap(),
+				0
+			);
+			assert_eq!(
+				queue.enqueue(url, "block_accepted", "{\"a\":2}").unwrap(),
+				1
+			);
+			assert_eq!(queue.enqueue(other_url, "tx_received", "{}").unwrap(), 2);
+
+			// Only the first notification of each url is attempted.
+			runtime
+				.block_on(queue.deliver_due(&client, &config))
+				.unwrap();
+			let attempts: Vec<_> = queue.queued().unwrap().iter().map(|e| e.attempts).collect();
+			assert_eq!(attempts, vec![1, 0, 1]);
+			assert!(!queue.queued().unwrap()[0].last_error.is_empty());
+
+			// The second attempt dead-letters the heads, the next one in line gets
+			// its first attempt.
+			runtime
+				.block_on(queue.deliver_due(&client, &config))
+				.unwrap();
+			let queued = queue.queued().unwrap();
+			assert_eq!(queued.len(), 1);
+			assert_eq!((queued[0].id, queued[0].attempts), (1, 1));
+
+			let dead = queue.dead_letters(false).unwrap();
+			assert_eq!(dead.iter().map(|d| d.id).collect::<Vec<_>>(), vec![0, 2]);
+			assert_eq!(dead[0].event, "block_accepted");
+			assert_eq!(dead[0].attempts, 2);
+			assert!(dead[0].payload.is_none());
+			let dead = queue.dead_letters(true).unwrap();
+			assert_eq!(dead[0].payload, Some(serde_json::json!({"a": 1})));
+
+			// Replaying puts the notification back in front of its url's queue.
+			assert_eq!(queue.replay_dead_letters(Some(vec![0])).unwrap(), 1);
+			let queued = queue.queued().unwrap();
+			assert_eq!(queued.iter().map(|e| e.id).collect::<Vec<_>>(), vec![0, 1]);
+			assert_eq!(queued[0].attempts, 0);
+
+			// and is attempted again on the next delivery.
+			runtime
+				.block_on(queue.deliver_due(&client, &config))
+				.unwrap();
+			assert_eq!(queue.queued().unwrap()[0].attempts, 1);
+		}
+
+		// Everything survives a restart and ids keep increasing.
+		{
+			let queue = WebHookQueue::new(db_root).unwrap();
+			assert_eq!(queue.queued().unwrap().len(), 2);
+			assert_eq!(queue.dead_letter_entries().unwrap().len(), 1);
+			assert_eq!(queue.payload(1).unwrap(), "{\"a\":2}");
+			assert_eq!(queue.enqueue(url, "block_accepted", "{}").unwrap(), 3);
+			assert_eq!(queue.replay_dead_letters(None).unwrap(), 1);
+			assert!(queue.dead_letter_entries().unwrap().is_empty());
+		}
+
+		let _ = fs::remove_dir_all(db_root);
+	}
+}
@@ -22,7 +22,7 @@ use chrono::{Duration, MIN_DATE};
 use p2p::{msg::PeerAddrs, P2PConfig};
 use rand::prelude::*;
 use std::collections::HashMap;
-use std::net::ToSocketAddrs;
+use std::net::{SocketAddr, ToSocketAddrs};
 use std::sync::{mpsc, Arc};
 use std::{cmp, str, thread, time};
 
@@ -49,6 +49,9 @@ const TESTNET_DNS_SEEDS: &[&str] = &[
 	"floonet.seed.grin.prokapi.com",   // hendi@prokapi.com
 ];
 
+// How long to wait for a dns seed to be resolved through the socks proxy.
+const DNS_RESOLVE_TIMEOUT: time::Duration = time::Duration::from_secs(10);
+
 pub fn connect_and_monitor(
 	p2p_server: Arc<p2p::Server>,
 	seed_list: Box<dyn Fn() -> Vec<PeerAddr> + Send>,
@@ -227,11 +230,13 @@ fn monitor_peers(peers: Arc<p2p::Peers>, config: p2p::P2PConfig, tx: mpsc::Sende
 	// intentionally make too many attempts (2x) as some (most?) will fail
 	// as many nodes in our db are not publicly accessible
 	let max_peer_attempts = 128;
-	let new_peers = peers.find_peers(
+	let mut new_peers = peers.find_peers(
 		p2p::State::Healthy,
 		p2p::Capabilities::UNKNOWN,
 		max_peer_attempts as usize,
 	);
+	// try the peers that behaved best last time we were connected first
+	new_peers.sort_by_key(|p| cmp::Reverse(p.score));
 
 	// Only queue up connection attempts for candidate peers where we
 	// are confident we do not yet know about this peer.
@@ -318,6 +323,11 @@ fn listen_for_addrs(
 	let connect_min_interval = 30;
 	let max_outbound_attempts = 128;
 	for addr in addrs.into_iter().take(max_outbound_attempts) {
+		// onion peers are only reachable through a socks proxy
+		if addr.is_onion() && p2p.config.socks_proxy.is_none() {
+			continue;
+		}
+
 		// ignore the duplicate connecting to same peer within 30 seconds
 		let now = Utc::now();
 		if let Some(last_connect_time) = connecting_history.get(&addr) {
@@ -370,37 +380,38 @@ fn listen_for_addrs(
 	}
 }
 
-pub fn default_dns_seeds() -> Box<dyn Fn() -> Vec<PeerAddr> + Send> {
-	Box::new(|| {
-		let net_seeds = if global::is_testnet() {
-			TESTNET_DNS_SEEDS
+pub fn default_dns_seeds(socks_proxy: Option<SocketAddr>) -> Box<dyn Fn() -> Vec<PeerAddr> + Send> {
+	Box::new(move || {
+		let (net_seeds, port) = if global::is_testnet() {
+			(TESTNET_DNS_SEEDS, 13414)
 		} else {
-			MAINNET_DNS_SEEDS
+			(MAINNET_DNS_SEEDS, 3414)
 		};
-		resolve_dns_to_addrs(
-			&net_seeds
-				.iter()
-				.map(|s| {
-					s.to_string()
-						+ if global::is_testnet() {
-							":13414"
-						} else {
-							":3414"
-						}
-				})
-				.collect(),
-		)
+		resolve_dns_to_addrs(net_seeds, port, socks_proxy)
 	})
 }
 
-fn resolve_dns_to_addrs(dns_records: &Vec<String>) -> Vec<PeerAddr> {
+// Resolve the dns seeds through the socks proxy when we have one, so no dns
+// request leaks outside of it. The proxy only gives us a single address per
+// seed.
+fn resolve_dns_to_addrs(
+	seeds: &[&str],
+	port: u16,
+	socks_proxy: Option<SocketAddr>,
+) -> Vec<PeerAddr> {
 	let mut addresses: Vec<PeerAddr> = vec![];
-	for dns in dns_records {
+	for dns in seeds {
 		debug!("Retrieving addresses from dns {}", dns);
-		match dns.to_socket_addrs() {
+		let resolved = match socks_proxy {
+			Some(proxy) => p2p::socks::resolve(proxy, dns, DNS_RESOLVE_TIMEOUT)
+				.map(|ip| vec![SocketAddr::new(ip, port)]),
+			None => (*dns, port).to_socket_addrs().map(|addrs| addrs.collect()),
+		};
+		match resolved {
 			Ok(addrs) => addresses.append(
 				&mut addrs
-					.map(PeerAddr)
+					.into_iter()
+					.map(PeerAddr::Ip)
 					.filter(|addr| !addresses.contains(addr))
 					.collect(),
 			),
@@ -18,19 +18,18 @@
 
 use std::fs::File;
 use std::io::prelude::*;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::{mpsc, Arc};
 use std::{convert::TryInto, fs};
 use std::{
 	thread::{self, JoinHandle},
-	time::{self, Duration},
+	time::{self, Duration, Instant},
 };
 
 use fs2::FileExt;
 use walkdir::WalkDir;
 
 use crate::api;
-use crate::api::TLSConfig;
 use crate::chain::{self, SyncState, SyncStatus};
 use crate::common::adapters::{
 	ChainToPoolAndNetAdapter, NetToChainAdapter, PoolToChainAdapter, PoolToNetAdapter,
@@ -40,11 +39,14 @@ use crate::common::stats::{
 	ChainStats, DiffBlock, DiffStats, PeerStats, ServerStateInfo, ServerStats, TxStats,
 };
 use crate::common::types::{Error, ServerConfig, StratumServerConfig};
+use crate::common::webhooks::{self, WebHookQueue};
 use crate::core::core::hash::Hashed;
 use crate::core::core::verifier_cache::LruVerifierCache;
+use crate::core::core::Block;
 use crate::core::ser::ProtocolVersion;
 use crate::core::{consensus, genesis, global, pow};
 use crate::grin::{dandelion_monitor, seed, sync};
+use crate::mining::share_store::ShareStore;
 use crate::mining::stratumserver;
 use crate::mining::test_miner::Miner;
 use crate::p2p;
@@ -60,6 +62,9 @@ pub type ServerTxPool =
 /// Arcified thread-safe LruVerifierCache
 pub type ServerVerifierCache = Arc<RwLock<LruVerifierCache>>;
 
+/// File the pool txs are saved to, under the chain data directory.
+const POOL_FILE: &str = "txpool.bin";
+
 /// Grin server holding internal structures.
 pub struct Server {
 	/// server config
@@ -84,6 +89,10 @@ pub struct Server {
 	connect_thread: Option<JoinHandle<()>>,
 	sync_thread: JoinHandle<()>,
 	dandelion_thread: JoinHandle<()>,
+	webhook_thread: Option<JoinHandle<()>>,
+	pool_persist_thread: Option<JoinHandle<()>>,
+	/// Shares and blocks of the stratum server, if enabled
+	stratum_shares: Option<Arc<ShareStore>>,
 }
 
 impl Server {
@@ -151,6 +160,69 @@ impl Server {
 		Ok(Arc::new(lock_file))
 	}
 
+	// Save the pool txs every period (in minutes, if not 0) and a last time
+	// when stopping.
+	fn persist_pool(
+		tx_pool: ServerTxPool,
+		path: PathBuf,
+		period: u32,
+		stop_state: Arc<StopState>,
+	) -> std::io::Result<JoinHandle<()>> {
+		thread::Builder::new()
+			.name("pool_persist".to_string())
+			.spawn(move || {
+				let period = Duration::from_secs(period as u64 * 60);
+				let mut last_save = Instant::now();
+				loop {
+					let stopped = stop_state.is_stopped();
+					if stopped || (!period.is_zero() && last_save.elapsed() >= period) {
+						if let Err(e) = tx_pool.read().save(&path) {
+							error!("Failed to save the pool txs: {}", e);
+						}
+						last_save = Instant::now();
+					}
+					if stopped {
+						break;
+					}
+					thread::sleep(Duration::from_secs(1));
+				}
+			})
+	}
+
+	fn genesis(config: &ServerConfig) -> Block {
+		match config.chain_type {
+			global::ChainTypes::AutomatedTesting => pow::mine_genesis_block().unwrap(),
+			global::ChainTypes::UserTesting => pow::mine_genesis_block().unwrap(),
+			global::ChainTypes::Testnet => genesis::genesis_test(),
+			global::ChainTypes::Mainnet => genesis::genesis_main(),
+		}
+	}
+
+	/// Rebuilds the chain state in our db_root by replaying the full blocks we
+	/// already have locally, without starting the p2p or api servers.
+	/// The progress callback receives the height of each replayed block and the
+	/// height we are replaying up to.
+	pub fn reindex<F>(config: ServerConfig, progress: F) -> Result<chain::Tip, Error>
+	where
+		F: Fn(u64, u64),
+	{
+		// Make sure no running server is using this db while we rebuild it.
+		let _lock_file = Server::one_grin_at_a_time(&config)?;
+
+		let genesis = Server::genesis(&config);
+		info!("Reindexing chain, genesis block: {}", genesis.hash());
+
+		let chain = chain::Chain::reindex(
+			config.db_root.clone(),
+			genesis,
+			pow::verify_size,
+			Arc::new(RwLock::new(LruVerifierCache::new())),
+			config.archive_mode.unwrap_or(false),
+			progress,
+		)?;
+		Ok(chain.head()?)
+	}
+
 	/// Instantiates a new server associated with the provided future reactor.
 	pub fn new(config: ServerConfig) -> Result<Server, Error> {
 		// Obtain our lock_file or fail immediately with an error.
@@ -180,47 +252,99 @@ impl Server {
 
 		let sync_state = Arc::new(SyncState::new());
 
+		// Shared by the chain and net hooks and the node API events WebSocket.
+		let event_publisher = Arc::new(api::EventPublisher::new());
+
+		// Webhook notifications go through an on-disk queue, delivered (and
+		// retried) by their own thread.
+		let (webhooks, webhook_thread) = if config.webhook_config.is_enabled() {
+			let queue = Arc::new(WebHookQueue::new(&config.db_root)?);
+			let thread = webhooks::start_delivery(
+				queue.clone(),
+				config.webhook_config.clone(),
+				stop_state.clone(),
+			)?;
+			(Some(queue), Some(thread))
+		} else {
+			(None, None)
+		};
+
+		// Stratum shares are recorded next to the chain data, the store is
+		// opened here for the owner API to give access to them.
+		let stratum_enabled = config
+			.stratum_mining_config
+			.as_ref()
+			.and_then(|c| c.enable_stratum_server)
+			.unwrap_or(false);
+		let stratum_shares = if stratum_enabled {
+			Some(Arc::new(ShareStore::new(&config.db_root)?))
+		} else {
+			None
+		};
+
 		let chain_adapter = Arc::new(ChainToPoolAndNetAdapter::new(
 			tx_pool.clone(),
-			init_chain_hooks(&config),
+			init_chain_hooks(&config, event_publisher.clone(), webhooks.clone()),
 		));
 
-		let genesis = match config.chain_type {
-			global::ChainTypes::AutomatedTesting => pow::mine_genesis_block().unwrap(),
-			global::ChainTypes::UserTesting => pow::mine_genesis_block().unwrap(),
-			global::ChainTypes::Testnet => genesis::genesis_test(),
-			global::ChainTypes::Mainnet => genesis::genesis_main(),
-		};
+		let genesis = Server::genesis(&config);
 
 		info!("Starting server, genesis block: {}", genesis.hash());
 
-		let shared_chain = Arc::new(chain::Chain::init(
+		let mut chain = chain::Chain::init(
 			config.db_root.clone(),
 			chain_adapter.clone(),
 			genesis.clone(),
 			pow::verify_size,
 			verifier_cache.clone(),
 			archive_mode,
-		)?);
+		)?;
+		chain.init_output_history(config.output_history_index.unwrap_or(false))?;
+		chain.init_kernel_pos_index(config.kernel_excess_index.unwrap_or(false))?;
+		let shared_chain = Arc::new(chain);
 
 		pool_adapter.set_chain(shared_chain.clone());
 
+		// Restore the pool txs saved on the last shutdown, once we have a chain
+		// to validate them against.
+		let pool_path = Path::new(&config.db_root).join(POOL_FILE);
+		let pool_persist_thread = if config.pool_config.persist_pool {
+			if pool_path.exists() {
+				match tx_pool.write().load(&pool_path) {
+					Ok(count) => info!("Restored {} txs to the pool", count),
+					Err(e) => warn!("Failed to restore the pool txs: {}", e),
+				}
+			}
+			Some(Server::persist_pool(
+				tx_pool.clone(),
+				pool_path,
+				config.pool_config.persist_period,
+				stop_state.clone(),
+			)?)
+		} else {
+			None
+		};
+
 		let net_adapter = Arc::new(NetToChainAdapter::new(
 			sync_state.clone(),
 			shared_chain.clone(),
 			tx_pool.clone(),
 			verifier_cache.clone(),
 			config.clone(),
-			init_net_hooks(&config),
+			init_net_hooks(&config, event_publisher.clone(), webhooks.clone()),
 		));
 
 		// Initialize our capabilities.
-		// Currently either "default" or with optional "archive_mode" (block history) support enabled.
-		let capabilities = if let Some(true) = config.archive_mode {
-			Capabilities::default() | Capabilities::BLOCK_HIST
+		// Currently either "default" or with optional "archive_mode" (block history) support enabled,
+		// plus the encrypted transport if enabled in the p2p config.
+		let mut capabilities = if let Some(true) = config.archive_mode {
+			Capabilities::default() | Capabilities::BLOCK_HIST | Capabilities::BLOCK_HIST_PROOF
 		} else {
 			Capabilities::default()
 		};
+		if config.p2p_config.encrypted_transport() {
+			capabilities |= Capabilities::ENCRYPTED;
+		}
 		debug!("Capabilities: {:?}", capabilities);
 
 		let p2p_server = Arc::new(p2p::Server::new(
@@ -253,7 +377,7 @@ impl Server {
 						));
 					}
 				},
-				p2p::Seeding::DNSSeed => seed::default_dns_seeds(),
+				p2p::Seeding::DNSSeed => seed::default_dns_seeds(config.p2p_config.socks_proxy),
 				_ => unreachable!(),
 			};
 
@@ -289,19 +413,7 @@ impl Server {
 		info!("Starting rest apis at: {}", &config.api_http_addr);
 		let api_secret = get_first_line(config.api_secret_path.clone());
 		let foreign_api_secret = get_first_line(config.foreign_api_secret_path.clone());
-		let tls_conf = match config.tls_certificate_file.clone() {
-			None => None,
-			Some(file) => {
-				let key = match config.tls_certificate_key.clone() {
-					Some(k) => k,
-					None => {
-						let msg = "Private key for certificate is not set".to_string();
-						return Err(Error::ArgumentError(msg));
-					}
-				};
-				Some(TLSConfig::new(file, key))
-			}
-		};
+		let tls_conf = config.tls_config()?;
 
 		// TODO fix API shutdown and join this thread
 		api::node_apis(
@@ -310,6 +422,11 @@ impl Server {
 			tx_pool.clone(),
 			p2p_server.peers.clone(),
 			sync_state.clone(),
+			event_publisher,
+			webhooks.map(|q| q as Arc<dyn api::WebHookDeadLetters>),
+			stratum_shares
+				.clone()
+				.map(|s| s as Arc<dyn api::StratumShares>),
 			api_secret,
 			foreign_api_secret,
 			tls_conf,
@@ -340,6 +457,9 @@ impl Server {
 			connect_thread,
 			sync_thread,
 			dandelion_thread,
+			webhook_thread,
+			pool_persist_thread,
+			stratum_shares,
 		})
 	}
 
@@ -379,6 +499,7 @@ impl Server {
 			self.tx_pool.clone(),
 			self.verifier_cache.clone(),
 			self.state_info.stratum_stats.clone(),
+			self.stratum_shares.clone(),
 		);
 		let _ = thread::Builder::new()
 			.name("stratum_server".to_string())
@@ -408,7 +529,12 @@ impl Server {
 			enable_stratum_server: None,
 			stratum_server_addr: None,
 			wallet_listener_url: config_wallet_url,
+			credentials_file: None,
+			tls_certificate_file: None,
+			tls_certificate_key: None,
 			minimum_share_difficulty: 1,
+			vardiff_shares_per_minute: None,
+			vardiff_retarget_time: 60,
 		};
 
 		let mut miner = Miner::new(
@@ -577,6 +703,20 @@ impl Server {
 				Err(e) => error!("failed to join to dandelion_monitor thread: {:?}", e),
 				Ok(_) => info!("dandelion_monitor thread stopped"),
 			}
+
+			if let Some(webhook_thread) = self.webhook_thread {
+				match webhook_thread.join() {
+					Err(e) => error!("failed to join to webhooks thread: {:?}", e),
+					Ok(_) => info!("webhooks thread stopped"),
+				}
+			}
+
+			if let Some(pool_persist_thread) = self.pool_persist_thread {
+				match pool_persist_thread.join() {
+					Err(e) => error!("failed to join to pool_persist thread: {:?}", e),
+					Ok(_) => info!("pool_persist thread stopped"),
+				}
+			}
 		}
 		// this call is blocking and makes sure all peers stop, however
 		// we can't be sure that we stopped a listener blocked on accept, so we don't join the p2p thread
@@ -24,6 +24,9 @@ use crate::core::core::hash::{Hash, Hashed};
 use crate::core::core::BlockHeader;
 use crate::p2p;
 
+/// Number of historical blocks we request at a time during archive sync.
+const ARCHIVE_SYNC_BLOCK_COUNT: usize = 100;
+
 pub struct BodySync {
 	chain: Arc<chain::Chain>,
 	peers: Arc<p2p::Peers>,
@@ -33,6 +36,10 @@ pub struct BodySync {
 
 	receive_timeout: DateTime<Utc>,
 	prev_blocks_received: u64,
+
+	/// Historical blocks requested during archive sync, and when to ask again.
+	archive_requested: Vec<Hash>,
+	archive_timeout: DateTime<Utc>,
 }
 
 impl BodySync {
@@ -48,6 +55,8 @@ impl BodySync {
 			blocks_requested: 0,
 			receive_timeout: Utc::now(),
 			prev_blocks_received: 0,
+			archive_requested: vec![],
+			archive_timeout: Utc::now(),
 		}
 	}
 
@@ -72,6 +81,73 @@ impl BodySync {
 		Ok(false)
 	}
 
+	/// Archive sync, once we are otherwise synced: request the full blocks missing
+	/// below our body tail from archive (BLOCK_HIST_PROOF) peers. This lets a pruned node
+	/// restarted with archive_mode enabled recover the full block history in place.
+	/// Return true as long as historical blocks are missing.
+	pub fn check_run_archive(&mut self) -> Result<bool, chain::Error> {
+		let hashes = self
+			.chain
+			.historical_blocks_to_sync(ARCHIVE_SYNC_BLOCK_COUNT)?;
+		if hashes.is_empty() {
+			self.archive_requested.clear();
+			return Ok(false);
+		}
+		if !self.archive_sync_due()? {
+			return Ok(true);
+		}
+
+		let tail = self.chain.tail()?;
+		let peers: Vec<_> = self
+			.peers
+			.iter()
+			.with_capabilities(Capabilities::BLOCK_HIST_PROOF)
+			.with_difficulty(|x| x >= tail.total_difficulty)
+			.connected()
+			.into_iter()
+			.collect();
+		if peers.is_empty() {
+			debug!("archive_sync: no archive peers to request historical blocks from");
+			return Ok(true);
+		}
+
+		debug!(
+			"archive_sync: body tail at {}, requesting {} historical blocks from {} peers",
+			tail.height,
+			hashes.len(),
+			peers.len(),
+		);
+
+		self.archive_requested.clear();
+		self.archive_timeout = Utc::now() + Duration::seconds(10);
+		let mut rng = rand::thread_rng();
+		for hash in hashes {
+			if let Some(peer) = peers.choose(&mut rng) {
+				if let Err(e) = peer.send_historical_block_request(hash) {
+					debug!("Skipped request to {}: {:?}", peer.info.addr, e);
+					peer.stop();
+				} else {
+					self.archive_requested.push(hash);
+				}
+			}
+		}
+		Ok(true)
+	}
+
+	// Should we request more historical blocks? Either all the ones we asked for
+	// were received or we waited long enough and ask again.
+	fn archive_sync_due(&self) -> Result<bool, chain::Error> {
+		if Utc::now() > self.archive_timeout {
+			return Ok(true);
+		}
+		for hash in &self.archive_requested {
+			if !self.chain.block_exists(*hash)? {
+				return Ok(false);
+			}
+		}
+		Ok(true)
+	}
+
 	/// Is our local node running in archive_mode?
 	fn archive_mode(&self) -> bool {
 		self.chain.archive_mode()
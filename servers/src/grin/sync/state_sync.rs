@@ -14,13 +14,35 @@
 
 use chrono::prelude::{DateTime, Utc};
 use chrono::Duration;
+use rand::seq::SliceRandom;
+use rand::thread_rng;
+use std::collections::HashMap;
 use std::sync::Arc;
 
-use crate::chain::{self, SyncState, SyncStatus};
-use crate::core::core::hash::Hashed;
+use crate::chain::txhashset::{SegmentType, SegmentTypeIdentifier};
+use crate::chain::{
+	self, PibdPeerStats, SegmentDownloadStats, SyncState, SyncStatus, TxHashsetPibdStats,
+};
+use crate::core::core::hash::{Hash, Hashed};
+use crate::core::core::{BlockHeader, HeaderVersion};
 use crate::core::global;
 use crate::core::pow::Difficulty;
-use crate::p2p::{self, Capabilities, Peer};
+use crate::p2p::{self, Capabilities, Peer, PeerAddr, ScoreEvent};
+
+/// Maximum number of PIBD segment requests we keep in flight across all peers.
+const PIBD_MAX_IN_FLIGHT: usize = 32;
+
+/// Seconds after which an unanswered PIBD segment request is asked again
+/// (possibly from another peer).
+const PIBD_REQUEST_TIMEOUT_SECS: i64 = 30;
+
+/// Minutes without any PIBD progress before we give up on PIBD and fall back
+/// to requesting a full txhashset archive.
+const PIBD_STALL_TIMEOUT_MINS: i64 = 5;
+
+/// Transient PIBD failures (stalls, IO errors) we resume from our saved progress
+/// before falling back to the txhashset archive.
+const PIBD_MAX_RETRIES: u32 = 3;
 
 /// Fast sync has 3 "states":
 /// * syncing headers
@@ -28,6 +50,8 @@ use crate::p2p::{self, Capabilities, Peer};
 /// * once we have the state, get blocks after that
 ///
 /// The StateSync struct implements and monitors the middle step.
+/// The txhashset state is preferably rebuilt from segments requested from
+/// several PIBD capable peers, with the txhashset zip archive as a fallback.
 pub struct StateSync {
 	sync_state: Arc<SyncState>,
 	peers: Arc<p2p::Peers>,
@@ -35,6 +59,20 @@ pub struct StateSync {
 
 	prev_state_sync: Option<DateTime<Utc>>,
 	state_sync_peer: Option<Arc<Peer>>,
+
+	/// Whether we are currently syncing state via PIBD (as opposed to a zip archive).
+	pibd_active: bool,
+	/// Set once PIBD failed, we then fall back to the txhashset archive.
+	pibd_failed: bool,
+	/// Transient PIBD failures so far, see PIBD_MAX_RETRIES.
+	pibd_retries: u32,
+	/// PIBD segment requests currently in flight, with the peer asked and when.
+	pibd_requests: HashMap<SegmentTypeIdentifier, (PeerAddr, DateTime<Utc>)>,
+	/// Total number of segments applied so far, to detect a stalled PIBD sync.
+	pibd_progress: u64,
+	pibd_last_progress: DateTime<Utc>,
+	/// Per peer PIBD stats, reported as part of our sync status.
+	pibd_peer_stats: HashMap<PeerAddr, PibdPeerStats>,
 }
 
 impl StateSync {
@@ -49,6 +87,13 @@ impl StateSync {
 			chain,
 			prev_state_sync: None,
 			state_sync_peer: None,
+			pibd_active: false,
+			pibd_failed: false,
+			pibd_retries: 0,
+			pibd_requests: HashMap::new(),
+			pibd_progress: 0,
+			pibd_last_progress: Utc::now(),
+			pibd_peer_stats: HashMap::new(),
 		}
 	}
 
@@ -67,14 +112,32 @@ impl StateSync {
 		);
 
 		let mut sync_need_restart = false;
+		let mut pibd_invalid = false;
 
 		// check sync error
 		if let Some(sync_error) = self.sync_state.sync_error() {
 			error!("state_sync: error = {}. restart fast sync", sync_error);
 			sync_need_restart = true;
+			if self.pibd_active {
+				pibd_invalid = self
+					.sync_state
+					.sync_error_kind()
+					.is_some_and(|kind| is_pibd_invalid(&kind));
+				self.pibd_retries += 1;
+				if pibd_invalid {
+					warn!("state_sync: PIBD txhashset invalid, falling back to txhashset archive");
+					self.pibd_failed = true;
+				} else if self.pibd_retries >= PIBD_MAX_RETRIES {
+					warn!("state_sync: PIBD keeps failing, falling back to txhashset archive");
+					self.pibd_failed = true;
+				} else {
+					warn!("state_sync: PIBD interrupted, resuming from saved progress");
+				}
+			}
 		}
 
 		// check peer connection status of this sync
+		// (PIBD spreads requests over several peers and handles lost peers itself)
 		if let Some(ref peer) = self.state_sync_peer {
 			if let SyncStatus::TxHashsetDownload { .. } = self.sync_state.status() {
 				if !peer.is_connected() {
@@ -101,11 +164,14 @@ impl StateSync {
 		);
 
 		if sync_need_restart || done {
-			self.state_sync_reset();
+			// only forget about our PIBD progress once done or if it turned out invalid
+			self.state_sync_reset(done || pibd_invalid);
 			self.sync_state.clear_sync_error();
 		}
 
 		if done {
+			self.pibd_failed = false;
+			self.pibd_retries = 0;
 			return false;
 		}
 
@@ -114,7 +180,7 @@ impl StateSync {
 			let (go, download_timeout) = self.state_sync_due();
 
 			if let SyncStatus::TxHashsetDownload { .. } = self.sync_state.status() {
-				if download_timeout {
+				if download_timeout && !self.pibd_active {
 					error!("state_sync: TxHashsetDownload status timeout in 10 minutes!");
 					self.sync_state.set_sync_error(
 						chain::ErrorKind::SyncError(format!("{:?}", p2p::Error::Timeout)).into(),
@@ -124,10 +190,8 @@ impl StateSync {
 
 			if go {
 				self.state_sync_peer = None;
-				match self.request_state(&header_head) {
-					Ok(peer) => {
-						self.state_sync_peer = Some(peer);
-					}
+				match self.start_state_sync(header_head) {
+					Ok(()) => {}
 					Err(e) => self
 						.sync_state
 						.set_sync_error(chain::ErrorKind::SyncError(format!("{:?}", e)).into()),
@@ -146,19 +210,286 @@ impl StateSync {
 					},
 				);
 
-				self.sync_state
-					.update(SyncStatus::TxHashsetDownload(Default::default()));
+				if self.pibd_active {
+					self.sync_state
+						.update(SyncStatus::TxHashsetPibdDownload(Default::default()));
+				} else {
+					self.sync_state
+						.update(SyncStatus::TxHashsetDownload(Default::default()));
+				}
+			}
+		}
+
+		if self.pibd_active {
+			if let SyncStatus::TxHashsetPibdDownload { .. } = self.sync_state.status() {
+				self.continue_pibd();
 			}
 		}
 		true
 	}
 
-	fn request_state(&self, header_head: &chain::Tip) -> Result<Arc<Peer>, p2p::Error> {
+	/// Start syncing the txhashset state at the archive header, via PIBD if we can,
+	/// otherwise by requesting the txhashset archive from a single peer.
+	fn start_state_sync(&mut self, header_head: &chain::Tip) -> Result<(), p2p::Error> {
+		let archive_header = self.archive_header(header_head)?;
+
+		if self.pibd_due(&archive_header) {
+			debug!(
+				"state_sync: starting PIBD at txhashset_head: {} / {}",
+				archive_header.height,
+				archive_header.hash()
+			);
+			self.chain.init_desegmenter(&archive_header).map_err(|e| {
+				error!("state_sync: failed to init desegmenter: {:?}", e);
+				p2p::Error::Internal
+			})?;
+			self.pibd_active = true;
+			self.pibd_progress = 0;
+			self.pibd_last_progress = Utc::now();
+			self.pibd_peer_stats.clear();
+			return Ok(());
+		}
+
+		let peer = self.request_state(header_head, &archive_header)?;
+		self.state_sync_peer = Some(peer);
+		Ok(())
+	}
+
+	/// PIBD requires (at least one) PIBD capable peer with the most work and an archive
+	/// header committing to the merged output root (header version 3 and later).
+	fn pibd_due(&self, archive_header: &BlockHeader) -> bool {
+		!self.pibd_failed
+			&& archive_header.version >= HeaderVersion(3)
+			&& !self.pibd_peers().is_empty()
+	}
+
+	/// Connected PIBD capable peers with the most work.
+	fn pibd_peers(&self) -> Vec<Arc<Peer>> {
+		let peers_iter = || {
+			self.peers
+				.iter()
+				.with_capabilities(Capabilities::PIBD_HIST)
+				.connected()
+		};
+		let max_diff = peers_iter().max_difficulty().unwrap_or(Difficulty::zero());
+		peers_iter()
+			.with_difficulty(|x| x >= max_diff)
+			.into_iter()
+			.collect()
+	}
+
+	/// Check on PIBD progress: finalize the txhashset once all segments are in,
+	/// otherwise (re)request the segments we need next from our PIBD peers.
+	fn continue_pibd(&mut self) {
+		let desegmenter = self.chain.desegmenter();
+		let (archive_hash, complete, stats, desired) = {
+			let desegmenter = desegmenter.read();
+			let desegmenter = match desegmenter.as_ref() {
+				Some(d) => d,
+				None => return,
+			};
+			let mmr_stats = |t| SegmentDownloadStats {
+				segments_done: desegmenter.applied_count(t),
+				segments_total: desegmenter.segment_count(t),
+			};
+			let stats = TxHashsetPibdStats {
+				bitmap: mmr_stats(SegmentType::Bitmap),
+				output: mmr_stats(SegmentType::Output),
+				rangeproof: mmr_stats(SegmentType::RangeProof),
+				kernel: mmr_stats(SegmentType::Kernel),
+				..Default::default()
+			};
+			(
+				desegmenter.header().hash(),
+				desegmenter.is_complete(),
+				stats,
+				desegmenter.next_desired_segments(),
+			)
+		};
+		let progress = stats.segments_done();
+
+		if complete {
+			info!("state_sync: all PIBD segments received, validating txhashset");
+			self.pibd_requests.clear();
+			if let Err(e) = self.chain.finalize_desegmenter(self.sync_state.as_ref()) {
+				error!("state_sync: failed to finalize PIBD txhashset: {:?}", e);
+				self.sync_state.set_sync_error(e);
+			}
+			return;
+		}
+
+		let now = Utc::now();
+		if progress != self.pibd_progress {
+			self.pibd_progress = progress;
+			self.pibd_last_progress = now;
+		} else if now - self.pibd_last_progress > Duration::minutes(PIBD_STALL_TIMEOUT_MINS) {
+			error!(
+				"state_sync: no PIBD progress in {} minutes!",
+				PIBD_STALL_TIMEOUT_MINS
+			);
+			self.sync_state.set_sync_error(
+				chain::ErrorKind::SyncError(format!("{:?}", p2p::Error::Timeout)).into(),
+			);
+			return;
+		}
+
+		// Credit received segments to the peer that sent them, as long as that is
+		// the peer we asked (unsolicited segments say nothing about its latency).
+		for (id, sender) in self.sync_state.take_pibd_received() {
+			let (addr, requested_at) = match self.pibd_requests.get(&id) {
+				Some((addr, requested_at)) if addr.to_string() == sender => (*addr, *requested_at),
+				_ => continue,
+			};
+			self.pibd_requests.remove(&id);
+			let stats = self.pibd_peer_stats.entry(addr).or_default();
+			let latency = (now - requested_at).num_milliseconds().max(0) as u64;
+			stats.segments_received += 1;
+			stats.avg_latency_ms = (stats.avg_latency_ms * (stats.segments_received - 1) + latency)
+				/ stats.segments_received;
+		}
+
+		// Forget about requests that were answered (by another peer), timed out or whose
+		// peer went away, so the corresponding segments get requested again (from any peer).
+		let peers = &self.peers;
+		let peer_stats = &mut self.pibd_peer_stats;
+		self.pibd_requests.retain(|id, (addr, requested_at)| {
+			if !desired.contains(id) {
+				return false;
+			}
+			let stats = peer_stats.entry(*addr).or_default();
+			if now - *requested_at >= Duration::seconds(PIBD_REQUEST_TIMEOUT_SECS) {
+				stats.segments_timed_out += 1;
+				let _ = peers.score_peer(*addr, ScoreEvent::SlowResponse);
+				return false;
+			}
+			peers
+				.get_connected_peer(*addr)
+				.map(|p| p.is_connected())
+				.unwrap_or(false)
+		});
+
+		let peers = self.pibd_peers();
+		if !peers.is_empty() {
+			self.request_segments(archive_hash, desired, &peers, now);
+		}
+		self.update_pibd_status(stats);
+	}
+
+	/// Request the desired segments we do not have in flight yet, from random PIBD peers.
+	fn request_segments(
+		&mut self,
+		archive_hash: Hash,
+		desired: Vec<SegmentTypeIdentifier>,
+		peers: &[Arc<Peer>],
+		now: DateTime<Utc>,
+	) {
+		let mut rng = thread_rng();
+		for id in desired {
+			if self.pibd_requests.len() >= PIBD_MAX_IN_FLIGHT {
+				break;
+			}
+			if self.pibd_requests.contains_key(&id) {
+				continue;
+			}
+			let peer = match peers.choose(&mut rng) {
+				Some(peer) => peer,
+				None => break,
+			};
+			let res = match id.segment_type {
+				SegmentType::Bitmap => {
+					peer.send_bitmap_segment_request(archive_hash, id.identifier)
+				}
+				SegmentType::Output => {
+					peer.send_output_segment_request(archive_hash, id.identifier)
+				}
+				SegmentType::RangeProof => {
+					peer.send_rangeproof_segment_request(archive_hash, id.identifier)
+				}
+				SegmentType::Kernel => {
+					peer.send_kernel_segment_request(archive_hash, id.identifier)
+				}
+			};
+			match res {
+				Ok(()) => {
+					self.pibd_requests.insert(id, (peer.info.addr, now));
+					self.pibd_peer_stats
+						.entry(peer.info.addr)
+						.or_default()
+						.segments_requested += 1;
+				}
+				Err(e) => {
+					debug!(
+						"state_sync: failed to request segment {:?} from {}: {:?}",
+						id, peer.info.addr, e
+					);
+				}
+			}
+		}
+	}
+
+	/// Report PIBD progress, per MMR and per peer, in our sync status.
+	fn update_pibd_status(&self, mut stats: TxHashsetPibdStats) {
+		let prev = match self.sync_state.status() {
+			SyncStatus::TxHashsetPibdDownload(prev) => prev,
+			_ => return,
+		};
+		stats.start_time = prev.start_time;
+		stats.peers = self
+			.pibd_peer_stats
+			.iter()
+			.map(|(addr, peer_stats)| PibdPeerStats {
+				addr: addr.to_string(),
+				in_flight: self
+					.pibd_requests
+					.values()
+					.filter(|(a, _)| a == addr)
+					.count() as u64,
+				..peer_stats.clone()
+			})
+			.collect();
+		stats.peers.sort_by(|a, b| a.addr.cmp(&b.addr));
+		self.sync_state.update_txhashset_pibd_download(stats);
+	}
+
+	/// The header of the txhashset state we want, at the most recent archive height
+	/// below the state sync threshold.
+	fn archive_header(&self, header_head: &chain::Tip) -> Result<BlockHeader, p2p::Error> {
 		let threshold = global::state_sync_threshold() as u64;
 		let archive_interval = global::txhashset_archive_interval();
 		let mut txhashset_height = header_head.height.saturating_sub(threshold);
 		txhashset_height = txhashset_height.saturating_sub(txhashset_height % archive_interval);
 
+		let mut txhashset_head = self
+			.chain
+			.get_block_header(&header_head.prev_block_h)
+			.map_err(|e| {
+				error!(
+					"chain error during getting a block header {}: {:?}",
+					&header_head.prev_block_h, e
+				);
+				p2p::Error::Internal
+			})?;
+		while txhashset_head.height > txhashset_height {
+			txhashset_head = self
+				.chain
+				.get_previous_header(&txhashset_head)
+				.map_err(|e| {
+					error!(
+						"chain error during getting a previous block header {}: {:?}",
+						txhashset_head.hash(),
+						e
+					);
+					p2p::Error::Internal
+				})?;
+		}
+		Ok(txhashset_head)
+	}
+
+	fn request_state(
+		&self,
+		header_head: &chain::Tip,
+		txhashset_head: &BlockHeader,
+	) -> Result<Arc<Peer>, p2p::Error> {
 		let peers_iter = || {
 			self.peers
 				.iter()
@@ -178,29 +509,6 @@ impl StateSync {
 
 		if let Some(peer) = peer {
 			// ask for txhashset at state_sync_threshold
-			let mut txhashset_head = self
-				.chain
-				.get_block_header(&header_head.prev_block_h)
-				.map_err(|e| {
-					error!(
-						"chain error during getting a block header {}: {:?}",
-						&header_head.prev_block_h, e
-					);
-					p2p::Error::Internal
-				})?;
-			while txhashset_head.height > txhashset_height {
-				txhashset_head = self
-					.chain
-					.get_previous_header(&txhashset_head)
-					.map_err(|e| {
-						error!(
-							"chain error during getting a previous block header {}: {:?}",
-							txhashset_head.hash(),
-							e
-						);
-						p2p::Error::Internal
-					})?;
-			}
 			let bhash = txhashset_head.hash();
 			debug!(
 				"state_sync: before txhashset request, header head: {} / {}, txhashset_head: {} / {}",
@@ -237,8 +545,29 @@ impl StateSync {
 		}
 	}
 
-	fn state_sync_reset(&mut self) {
+	fn state_sync_reset(&mut self, discard_pibd: bool) {
 		self.prev_state_sync = None;
 		self.state_sync_peer = None;
+		if self.pibd_active {
+			if discard_pibd {
+				self.chain.reset_desegmenter();
+			} else {
+				self.chain.release_desegmenter();
+			}
+		}
+		self.pibd_active = false;
+		self.pibd_requests.clear();
+		self.pibd_progress = 0;
+		self.pibd_peer_stats.clear();
+		self.sync_state.take_pibd_received();
+	}
+}
+
+/// Whether a PIBD sync error means the txhashset we are rebuilding is invalid (a root
+/// or segment failed validation), as opposed to a transient failure we can resume from.
+fn is_pibd_invalid(kind: &chain::ErrorKind) -> bool {
+	match kind {
+		chain::ErrorKind::SyncError(_) => false,
+		kind => chain::Error::from(kind.clone()).is_bad_data(),
 	}
 }
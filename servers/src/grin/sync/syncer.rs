@@ -177,6 +177,13 @@ impl SyncRunner {
 					unwrap_or_restart_loop!(self.chain.compact());
 				}
 
+				// In archive mode, fill in any full blocks missing below our body tail.
+				// Check back every second while doing so.
+				if unwrap_or_restart_loop!(body_sync.check_run_archive()) {
+					thread::sleep(time::Duration::from_secs(1));
+					continue;
+				}
+
 				// sleep for 10 secs but check stop signal every second
 				for _ in 1..10 {
 					thread::sleep(time::Duration::from_secs(1));
@@ -199,6 +206,7 @@ impl SyncRunner {
 			let mut check_state_sync = false;
 			match self.sync_state.status() {
 				SyncStatus::TxHashsetDownload { .. }
+				| SyncStatus::TxHashsetPibdDownload { .. }
 				| SyncStatus::TxHashsetSetup
 				| SyncStatus::TxHashsetRangeProofsValidation { .. }
 				| SyncStatus::TxHashsetKernelsValidation { .. }
@@ -15,5 +15,6 @@
 //! Mining + Mining server
 
 mod mine_block;
+pub mod share_store;
 pub mod stratumserver;
 pub mod test_miner;
@@ -74,6 +74,45 @@ pub fn get_block(
 	key_id: Option<Identifier>,
 	wallet_listener_url: Option<String>,
 ) -> (core::Block, BlockFees) {
+	get_block_with_retries(
+		chain,
+		tx_pool,
+		verifier_cache,
+		key_id,
+		wallet_listener_url,
+		None,
+	)
+	.expect("retrying until we get a block")
+}
+
+/// Same as get_block, but gives up after max_attempts failed attempts and
+/// right away if the wallet listener cannot be reached.
+pub fn try_get_block(
+	chain: &Arc<chain::Chain>,
+	tx_pool: &ServerTxPool,
+	verifier_cache: ServerVerifierCache,
+	key_id: Option<Identifier>,
+	wallet_listener_url: Option<String>,
+	max_attempts: u32,
+) -> Result<(core::Block, BlockFees), Error> {
+	get_block_with_retries(
+		chain,
+		tx_pool,
+		verifier_cache,
+		key_id,
+		wallet_listener_url,
+		Some(max_attempts),
+	)
+}
+
+fn get_block_with_retries(
+	chain: &Arc<chain::Chain>,
+	tx_pool: &ServerTxPool,
+	verifier_cache: ServerVerifierCache,
+	key_id: Option<Identifier>,
+	wallet_listener_url: Option<String>,
+	max_attempts: Option<u32>,
+) -> Result<(core::Block, BlockFees), Error> {
 	let wallet_retry_interval = 5;
 	// get the latest chain state and build a block on top of it
 	let mut result = build_block(
@@ -83,7 +122,14 @@ pub fn get_block(
 		key_id.clone(),
 		wallet_listener_url.clone(),
 	);
+	let mut attempts = 1;
 	while let Err(e) = result {
+		if max_attempts.is_some_and(|max| attempts >= max)
+			|| (max_attempts.is_some() && matches!(e, self::Error::WalletComm(_)))
+		{
+			return Err(e);
+		}
+		attempts += 1;
 		let mut new_key_id = key_id.to_owned();
 		match e {
 			self::Error::Chain(c) => match c.kind() {
@@ -124,7 +170,7 @@ pub fn get_block(
 			wallet_listener_url.clone(),
 		);
 	}
-	return result.unwrap();
+	result
 }
 
 /// Builds a new block with the chain head as previous and eligible
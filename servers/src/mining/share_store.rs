@@ -0,0 +1,440 @@
+// Copyright 2021 The Grin Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Persistent record of the shares accepted and the blocks found by the
+//! stratum server, used to split rewards between workers (PPLNS).
+
+use crate::api::{self, PplnsShare, PplnsWindow, StratumBlock, StratumBlockStatus, StratumShares};
+use crate::core::core::hash::Hash;
+use crate::core::global;
+use crate::core::ser::{self, Readable, Reader, Writeable, Writer};
+use crate::store::{self, to_key, u64_to_key, Store};
+use crate::util::{Mutex, ToHex};
+use chrono::Utc;
+use std::collections::HashMap;
+
+const DB_NAME: &str = "stratum";
+const STORE_SUBPATH: &str = "shares";
+
+const SHARE_PREFIX: u8 = b'S';
+const BLOCK_PREFIX: u8 = b'B';
+const NEXT_SHARE_ID_KEY: &[u8] = b"N";
+
+/// Default PPLNS window, in multiples of the block difficulty.
+const DEFAULT_PPLNS_WINDOW_FACTOR: u64 = 2;
+
+/// An accepted share.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Share {
+	/// Id of the share, increasing in submission order
+	pub id: u64,
+	/// Login (or id) of the worker that submitted the share
+	pub worker: String,
+	/// Difficulty the share is credited for
+	pub difficulty: u64,
+	/// Height of the block the share was mined on
+	pub height: u64,
+	/// Time the share was accepted
+	pub timestamp: i64,
+}
+
+impl Writeable for Share {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u64(self.id)?;
+		writer.write_bytes(&self.worker)?;
+		writer.write_u64(self.difficulty)?;
+		writer.write_u64(self.height)?;
+		writer.write_i64(self.timestamp)
+	}
+}
+
+impl Readable for Share {
+	fn read<R: Reader>(reader: &mut R) -> Result<Share, ser::Error> {
+		Ok(Share {
+			id: reader.read_u64()?,
+			worker: read_string(reader)?,
+			difficulty: reader.read_u64()?,
+			height: reader.read_u64()?,
+			timestamp: reader.read_i64()?,
+		})
+	}
+}
+
+/// A block found by a share.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FoundBlock {
+	/// Id of the share that found the block
+	pub share_id: u64,
+	/// Hash of the block
+	pub hash: Hash,
+	/// Height of the block
+	pub height: u64,
+	/// Login (or id) of the worker that found the block
+	pub worker: String,
+	/// Difficulty of the block
+	pub difficulty: u64,
+	/// Time the block was found
+	pub timestamp: i64,
+	/// Whether the block is (still) part of the chain
+	pub status: StratumBlockStatus,
+}
+
+impl Writeable for FoundBlock {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u64(self.share_id)?;
+		self.hash.write(writer)?;
+		writer.write_u64(self.height)?;
+		writer.write_bytes(&self.worker)?;
+		writer.write_u64(self.difficulty)?;
+		writer.write_i64(self.timestamp)?;
+		writer.write_u8(match self.status {
+			StratumBlockStatus::Unconfirmed => 0,
+			StratumBlockStatus::Confirmed => 1,
+			StratumBlockStatus::Orphaned => 2,
+		})
+	}
+}
+
+impl Readable for FoundBlock {
+	fn read<R: Reader>(reader: &mut R) -> Result<FoundBlock, ser::Error> {
+		Ok(FoundBlock {
+			share_id: reader.read_u64()?,
+			hash: Hash::read(reader)?,
+			height: reader.read_u64()?,
+			worker: read_string(reader)?,
+			difficulty: reader.read_u64()?,
+			timestamp: reader.read_i64()?,
+			status: match reader.read_u8()? {
+				0 => StratumBlockStatus::Unconfirmed,
+				1 => StratumBlockStatus::Confirmed,
+				2 => StratumBlockStatus::Orphaned,
+				_ => return Err(ser::Error::CorruptedData),
+			},
+		})
+	}
+}
+
+impl From<FoundBlock> for StratumBlock {
+	fn from(block: FoundBlock) -> StratumBlock {
+		StratumBlock {
+			height: block.height,
+			hash: block.hash.to_hex(),
+			worker: block.worker,
+			difficulty: block.difficulty,
+			share_id: block.share_id,
+			timestamp: block.timestamp,
+			status: block.status,
+		}
+	}
+}
+
+fn read_string<R: Reader>(reader: &mut R) -> Result<String, ser::Error> {
+	String::from_utf8(reader.read_bytes_len_prefix()?).map_err(|_| ser::Error::CorruptedData)
+}
+
+/// Store of the shares and blocks of the stratum server.
+pub struct ShareStore {
+	db: Store,
+	// Held while adding a share, so ids are allocated and persisted in order
+	next_id: Mutex<u64>,
+}
+
+impl ShareStore {
+	/// Opens (or creates) the share store under the provided root path.
+	pub fn new(db_root: &str) -> Result<ShareStore, store::Error> {
+		let db = Store::new(db_root, Some(DB_NAME), Some(STORE_SUBPATH), None)?;
+		let next_id = db.get_ser::<u64>(NEXT_SHARE_ID_KEY)?.unwrap_or(0);
+		Ok(ShareStore {
+			db,
+			next_id: Mutex::new(next_id),
+		})
+	}
+
+	/// Records an accepted share.
+	pub fn add_share(
+		&self,
+		worker: &str,
+		difficulty: u64,
+		height: u64,
+	) -> Result<Share, store::Error> {
+		let mut next_id = self.next_id.lock();
+		let id = *next_id;
+		let share = Share {
+			id,
+			worker: worker.to_string(),
+			difficulty,
+			height,
+			timestamp: Utc::now().timestamp(),
+		};
+		let batch = self.db.batch()?;
+		batch.put_ser(&u64_to_key(SHARE_PREFIX, id), &share)?;
+		batch.put_ser(NEXT_SHARE_ID_KEY, &(id + 1))?;
+		batch.commit()?;
+		*next_id = id + 1;
+		Ok(share)
+	}
+
+	/// Records the block found by the provided share, unconfirmed until buried
+	/// deep enough in the chain, see update_block_status.
+	pub fn add_block(
+		&self,
+		share: &Share,
+		hash: Hash,
+		difficulty: u64,
+	) -> Result<FoundBlock, store::Error> {
+		let block = FoundBlock {
+			share_id: share.id,
+			hash,
+			height: share.height,
+			worker: share.worker.clone(),
+			difficulty,
+			timestamp: share.timestamp,
+			status: StratumBlockStatus::Unconfirmed,
+		};
+		let batch = self.db.batch()?;
+		batch.put_ser(&u64_to_key(BLOCK_PREFIX, share.id), &block)?;
+		batch.commit()?;
+		Ok(block)
+	}
+
+	/// The share with the provided id.
+	pub fn get_share(&self, id: u64) -> Result<Share, store::Error> {
+		store::option_to_not_found(self.db.get_ser(&u64_to_key(SHARE_PREFIX, id)), || {
+			format!("stratum share {}", id)
+		})
+	}
+
+	/// The blocks found, oldest first.
+	pub fn blocks(&self) -> Result<Vec<FoundBlock>, store::Error> {
+		let protocol_version = self.db.protocol_version();
+		let iter = self.db.iter(&to_key(BLOCK_PREFIX, ""), move |_, mut v| {
+			ser::deserialize(&mut v, protocol_version).map_err(From::from)
+		})?;
+		Ok(iter.collect())
+	}
+
+	/// Confirms the unconfirmed blocks whose coinbase matured at the provided
+	/// chain height, or marks them orphaned if `on_chain` tells us (given the
+	/// block height and hash) they are not part of the chain anymore.
+	pub fn update_block_status<F>(&self, head_height: u64, on_chain: F) -> Result<(), store::Error>
+	where
+		F: Fn(u64, &Hash) -> bool,
+	{
+		let batch = self.db.batch()?;
+		for mut block in self.blocks()? {
+			if block.status != StratumBlockStatus::Unconfirmed
+				|| head_height < block.height + global::coinbase_maturity()
+			{
+				continue;
+			}
+			block.status = if on_chain(block.height, &block.hash) {
+				StratumBlockStatus::Confirmed
+			} else {
+				StratumBlockStatus::Orphaned
+			};
+			batch.put_ser(&u64_to_key(BLOCK_PREFIX, block.share_id), &block)?;
+		}
+		batch.commit()
+	}
+
+	/// The PPLNS window of a found block: going back from the share that found
+	/// it, as many shares as needed to cover the window difficulty.
+	pub fn pplns(&self, block: FoundBlock, window: u64) -> Result<PplnsWindow, store::Error> {
+		let mut by_worker: HashMap<String, PplnsShare> = HashMap::new();
+		let mut difficulty = 0u64;
+		let mut start = block.timestamp;
+		let mut id = Some(block.share_id);
+		while let Some(share_id) = id {
+			if difficulty >= window {
+				break;
+			}
+			let share = self.get_share(share_id)?;
+			difficulty = difficulty.saturating_add(share.difficulty);
+			start = share.timestamp;
+			let entry = by_worker
+				.entry(share.worker.clone())
+				.or_insert_with(|| PplnsShare {
+					worker: share.worker.clone(),
+					shares: 0,
+					difficulty: 0,
+					fraction: 0.0,
+				});
+			entry.shares += 1;
+			entry.difficulty = entry.difficulty.saturating_add(share.difficulty);
+			id = share_id.checked_sub(1);
+		}
+
+		let mut workers: Vec<PplnsShare> = by_worker
+			.into_values()
+			.map(|mut s| {
+				s.fraction = s.difficulty as f64 / difficulty as f64;
+				s
+			})
+			.collect();
+		workers.sort_by(|a, b| {
+			b.difficulty
+				.cmp(&a.difficulty)
+				.then_with(|| a.worker.cmp(&b.worker))
+		});
+		Ok(PplnsWindow {
+			block: block.into(),
+			window,
+			difficulty,
+			start,
+			workers,
+		})
+	}
+}
+
+impl StratumShares for ShareStore {
+	fn found_blocks(&self) -> Result<Vec<StratumBlock>, api::Error> {
+		let blocks = self
+			.blocks()
+			.map_err(|e| api::ErrorKind::Internal(e.to_string()))?;
+		Ok(blocks.into_iter().map(|b| b.into()).collect())
+	}
+
+	fn pplns_window(
+		&self,
+		height: Option<u64>,
+		window: Option<u64>,
+	) -> Result<PplnsWindow, api::Error> {
+		let blocks = self
+			.blocks()
+			.map_err(|e| api::ErrorKind::Internal(e.to_string()))?;
+		let block = blocks
+			.into_iter()
+			.rev()
+			.filter(|b| b.status != StratumBlockStatus::Orphaned)
+			.find(|b| height.is_none_or(|h| b.height == h))
+			.ok_or(api::ErrorKind::NotFound)?;
+		let window =
+			window.unwrap_or_else(|| block.difficulty.saturating_mul(DEFAULT_PPLNS_WINDOW_FACTOR));
+		self.pplns(block, window)
+			.map_err(|e| api::ErrorKind::Internal(e.to_string()).into())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use std::fs;
+
+	#[test]
+	fn test_pplns_window() {
+		global::set_local_chain_type(global::ChainTypes::AutomatedTesting);
+		let db_root = "target/.share_store";
+		let _ = fs::remove_dir_all(db_root);
+
+		{
+			let shares = ShareStore::new(db_root).unwrap();
+			for _ in 0..3 {
+				shares.add_share("alice", 10, 1).unwrap();
+			}
+			let share = shares.add_share("bob", 20, 1).unwrap();
+			shares.add_block(&share, Hash::default(), 25).unwrap();
+			for _ in 0..2 {
+				shares.add_share("bob", 20, 2).unwrap();
+			}
+		}
+
+		// Shares and blocks survive a restart, ids keep increasing.
+		let shares = ShareStore::new(db_root).unwrap();
+		let share = shares.add_share("alice", 10, 2).unwrap();
+		assert_eq!(share.id, 6);
+		shares.add_block(&share, Hash::default(), 40).unwrap();
+
+		let blocks = shares.found_blocks().unwrap();
+		assert_eq!(blocks.len(), 2);
+		assert_eq!(
+			(
+				blocks[0].height,
+				blocks[0].share_id,
+				blocks[0].worker.as_str()
+			),
+			(1, 3, "bob")
+		);
+		assert_eq!(
+			(
+				blocks[1].height,
+				blocks[1].share_id,
+				blocks[1].worker.as_str()
+			),
+			(2, 6, "alice")
+		);
+
+		// Default window is twice the block difficulty: 50 for the first
+		// block, only 50 recorded up to it.
+		let window = shares.pplns_window(Some(1), None).unwrap();
+		assert_eq!(window.window, 50);
+		assert_eq!(window.difficulty, 50);
+		assert_eq!(
+			window.workers,
+			vec![
+				PplnsShare {
+					worker: "alice".to_string(),
+					shares: 3,
+					difficulty: 30,
+					fraction: 0.6,
+				},
+				PplnsShare {
+					worker: "bob".to_string(),
+					shares: 1,
+					difficulty: 20,
+					fraction: 0.4,
+				},
+			]
+		);
+
+		// The last block by default, with a window covering the 3 last shares.
+		let window = shares.pplns_window(None, Some(45)).unwrap();
+		assert_eq!(window.block.height, 2);
+		assert_eq!(window.difficulty, 50);
+		assert_eq!(window.workers[0].worker, "bob");
+		assert_eq!(window.workers[0].shares, 2);
+		assert_eq!(window.workers[1].worker, "alice");
+		assert_eq!(window.workers[1].shares, 1);
+
+		assert!(shares.pplns_window(Some(3), None).is_err());
+
+		// Found blocks stay unconfirmed until their coinbase matured, they are
+		// then confirmed if still on the chain and orphaned otherwise.
+		let maturity = global::coinbase_maturity();
+		shares.update_block_status(maturity, |_, _| true).unwrap();
+		let status: Vec<_> = shares.blocks().unwrap().iter().map(|b| b.status).collect();
+		assert_eq!(
+			status,
+			vec![
+				StratumBlockStatus::Unconfirmed,
+				StratumBlockStatus::Unconfirmed
+			]
+		);
+		shares
+			.update_block_status(maturity + 1, |height, _| height != 1)
+			.unwrap();
+		shares
+			.update_block_status(maturity + 2, |_, _| true)
+			.unwrap();
+		let status: Vec<_> = shares.blocks().unwrap().iter().map(|b| b.status).collect();
+		assert_eq!(
+			status,
+			vec![StratumBlockStatus::Orphaned, StratumBlockStatus::Confirmed]
+		);
+		// Orphaned blocks have no PPLNS window.
+		assert!(shares.pplns_window(Some(1), None).is_err());
+		assert_eq!(shares.pplns_window(None, None).unwrap().block.height, 2);
+
+		let _ = fs::remove_dir_all(db_root);
+	}
+}
@@ -17,14 +17,18 @@
 use futures::channel::mpsc;
 use futures::pin_mut;
 use futures::{SinkExt, StreamExt, TryStreamExt};
+use ring::constant_time::verify_slices_are_equal;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpListener;
 use tokio::runtime::Runtime;
+use tokio_rustls::TlsAcceptor;
 use tokio_util::codec::{Framed, LinesCodec};
 
 use crate::util::RwLock;
 use chrono::prelude::Utc;
 use serde_json::Value;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fs;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::thread;
@@ -40,11 +44,21 @@ use crate::core::global;
 use crate::core::{pow, ser};
 use crate::keychain;
 use crate::mining::mine_block;
+use crate::mining::share_store::ShareStore;
 use crate::util::ToHex;
 use crate::{ServerTxPool, ServerVerifierCache};
 
 type Tx = mpsc::UnboundedSender<String>;
 
+/// Maximum factor by which a worker's share difficulty changes in a single
+/// adjustment.
+const VARDIFF_MAX_ADJUSTMENT: f64 = 4.0;
+/// Share rates within this ratio of the target don't trigger an adjustment.
+const VARDIFF_TOLERANCE: f64 = 0.25;
+/// Attempts at building a block for a job before skipping it until the
+/// next rebuild.
+const MAX_BLOCK_BUILD_ATTEMPTS: u32 = 3;
+
 // ----------------------------------------
 // http://www.jsonrpc.org/specification
 // RPC Methods
@@ -118,6 +132,18 @@ impl RpcError {
 			message: "Share rejected due to low difficulty".to_string(),
 		}
 	}
+	pub fn invalid_extranonce() -> Self {
+		RpcError {
+			code: -32504,
+			message: "Nonce outside of the worker extranonce".to_string(),
+		}
+	}
+	pub fn unauthorized() -> Self {
+		RpcError {
+			code: -32500,
+			message: "Unauthorized - Please login".to_string(),
+		}
+	}
 	pub fn invalid_request() -> Self {
 		RpcError {
 			code: -32600,
@@ -147,6 +173,9 @@ struct LoginParams {
 	login: String,
 	pass: String,
 	agent: String,
+	// the miner only uses nonces starting with its extranonce
+	#[serde(default)]
+	extranonce: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -164,6 +193,7 @@ pub struct JobTemplate {
 	job_id: u64,
 	difficulty: u64,
 	pre_pow: String,
+	extranonce: u32,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -176,35 +206,153 @@ pub struct WorkerStatus {
 	stale: u64,
 }
 
+/// Where the coinbase of a block goes: the wallet listener url, or None when
+/// the reward is burned.
+type Payout = Option<String>;
+
+/// The block a worker mines: the one paying to its payout, with a coinbase
+/// of its own built for its extranonce. Workers never share a coinbase, so
+/// their headers (and search spaces) are all distinct.
+type JobKey = (Payout, u32);
+
+/// Credentials of a stratum login.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StratumCredential {
+	/// Password expected at login
+	pub password: String,
+	/// Wallet listener the rewards of the blocks found by this login go to,
+	/// instead of the configured one
+	pub wallet_listener_url: Option<String>,
+}
+
+/// Reads the stratum credentials file, one login per line:
+/// `login password [wallet_listener_url]`. Empty lines and lines starting
+/// with `#` are ignored.
+pub fn read_credentials(path: &str) -> Result<HashMap<String, StratumCredential>, String> {
+	let content = fs::read_to_string(path)
+		.map_err(|e| format!("can't read stratum credentials file {}: {}", path, e))?;
+	parse_credentials(&content)
+}
+
+fn parse_credentials(content: &str) -> Result<HashMap<String, StratumCredential>, String> {
+	let mut credentials = HashMap::new();
+	for (n, line) in content.lines().enumerate() {
+		let line = line.trim();
+		if line.is_empty() || line.starts_with('#') {
+			continue;
+		}
+		let fields: Vec<&str> = line.split_whitespace().collect();
+		let credential = match fields.as_slice() {
+			[_, password] => StratumCredential {
+				password: password.to_string(),
+				wallet_listener_url: None,
+			},
+			[_, password, url] => StratumCredential {
+				password: password.to_string(),
+				wallet_listener_url: Some(url.to_string()),
+			},
+			_ => return Err(format!("invalid stratum credentials at line {}", n + 1)),
+		};
+		if credentials
+			.insert(fields[0].to_string(), credential)
+			.is_some()
+		{
+			return Err(format!("duplicate stratum login {}", fields[0]));
+		}
+	}
+	Ok(credentials)
+}
+
 struct State {
-	current_block_versions: Vec<Block>,
+	// the versions of the block being mined, one list for each job of the
+	// connected workers (as each has its own coinbase)
+	current_block_versions: HashMap<JobKey, Vec<Block>>,
+	current_height: u64,
 	// to prevent the wallet from generating a new HD key derivation for each
 	// iteration, we keep the returned derivation to provide it back when
 	// nothing has changed. We only want to create a key_id for each new block,
 	// and reuse it when we rebuild the current block to add new tx.
-	current_key_id: Option<keychain::Identifier>,
+	current_key_ids: HashMap<JobKey, keychain::Identifier>,
 	current_difficulty: u64,
 	minimum_share_difficulty: u64,
 }
 
 impl State {
 	pub fn new(minimum_share_difficulty: u64) -> Self {
-		let blocks = vec![Block::default()];
 		State {
-			current_block_versions: blocks,
-			current_key_id: None,
+			current_block_versions: HashMap::new(),
+			current_height: 0,
+			current_key_ids: HashMap::new(),
 			current_difficulty: <u64>::max_value(),
 			minimum_share_difficulty: minimum_share_difficulty,
 		}
 	}
 }
 
+/// Per worker variable share difficulty, adjusted so each worker submits
+/// shares at the configured rate.
+#[derive(Clone, Debug)]
+struct VarDiff {
+	shares_per_minute: f64,
+	retarget_time: u64,
+	minimum_difficulty: u64,
+}
+
+impl VarDiff {
+	fn from_config(config: &StratumServerConfig) -> Option<VarDiff> {
+		match config.vardiff_shares_per_minute {
+			Some(spm) if spm > 0 => Some(VarDiff {
+				shares_per_minute: spm as f64,
+				retarget_time: config.vardiff_retarget_time.max(1),
+				minimum_difficulty: config.minimum_share_difficulty.max(1),
+			}),
+			_ => None,
+		}
+	}
+
+	// Adjusts the worker's share difficulty once its retarget time has elapsed,
+	// based on the rate of shares it submitted since the last adjustment. The
+	// difficulty never goes above the block difficulty. Returns whether the
+	// difficulty changed.
+	fn retarget(&self, stats: &mut WorkerStats, max_difficulty: u64, now: SystemTime) -> bool {
+		let elapsed = match now.duration_since(stats.last_retarget) {
+			Ok(elapsed) if elapsed.as_secs() >= self.retarget_time => elapsed.as_secs_f64(),
+			_ => return false,
+		};
+		let expected = self.shares_per_minute * elapsed / 60.0;
+		let ratio = (stats.shares_since_retarget as f64 / expected)
+			.clamp(1.0 / VARDIFF_MAX_ADJUSTMENT, VARDIFF_MAX_ADJUSTMENT);
+		stats.shares_since_retarget = 0;
+		stats.last_retarget = now;
+		if (ratio - 1.0).abs() < VARDIFF_TOLERANCE {
+			return false;
+		}
+
+		let difficulty = ((stats.pow_difficulty as f64 * ratio) as u64)
+			.min(max_difficulty)
+			.max(self.minimum_difficulty);
+		if difficulty == stats.pow_difficulty {
+			return false;
+		}
+		stats.previous_pow_difficulty = stats.pow_difficulty;
+		stats.pow_difficulty = difficulty;
+		true
+	}
+}
+
 struct Handler {
 	id: String,
 	workers: Arc<WorkersList>,
 	sync_state: Arc<SyncState>,
 	chain: Arc<chain::Chain>,
 	current_state: Arc<RwLock<State>>,
+	vardiff: Option<VarDiff>,
+	// logins allowed to mine, anyone can if not set
+	credentials: Option<HashMap<String, StratumCredential>>,
+	// payout of the workers without their own wallet listener
+	default_payout: Payout,
+	// where accepted shares and found blocks are recorded
+	share_store: Option<Arc<ShareStore>>,
 }
 
 impl Handler {
@@ -212,46 +360,83 @@ impl Handler {
 		id: String,
 		stratum_stats: Arc<RwLock<StratumStats>>,
 		sync_state: Arc<SyncState>,
-		minimum_share_difficulty: u64,
+		config: &StratumServerConfig,
+		credentials: Option<HashMap<String, StratumCredential>>,
+		share_store: Option<Arc<ShareStore>>,
 		chain: Arc<chain::Chain>,
 	) -> Self {
+		let default_payout = if !config.burn_reward {
+			Some(config.wallet_listener_url.clone())
+		} else {
+			None
+		};
 		Handler {
 			id: id,
 			workers: Arc::new(WorkersList::new(stratum_stats)),
 			sync_state: sync_state,
 			chain: chain,
-			current_state: Arc::new(RwLock::new(State::new(minimum_share_difficulty))),
+			current_state: Arc::new(RwLock::new(State::new(config.minimum_share_difficulty))),
+			vardiff: VarDiff::from_config(config),
+			credentials,
+			default_payout,
+			share_store,
 		}
 	}
-	pub fn from_stratum(stratum: &StratumServer) -> Self {
+	pub fn from_stratum(
+		stratum: &StratumServer,
+		credentials: Option<HashMap<String, StratumCredential>>,
+	) -> Self {
 		Handler::new(
 			stratum.id.clone(),
 			stratum.stratum_stats.clone(),
 			stratum.sync_state.clone(),
-			stratum.config.minimum_share_difficulty,
+			&stratum.config,
+			credentials,
+			stratum.share_store.clone(),
 			stratum.chain.clone(),
 		)
 	}
+	// Where the blocks found by the worker pay to
+	fn payout(&self, worker: &Worker) -> Payout {
+		worker
+			.wallet_listener_url
+			.clone()
+			.or_else(|| self.default_payout.clone())
+	}
+	// The job the worker mines
+	fn job_key(&self, worker: &Worker) -> JobKey {
+		(self.payout(worker), worker.extranonce)
+	}
 	fn handle_rpc_requests(&self, request: RpcRequest, worker_id: usize) -> String {
 		self.workers.last_seen(worker_id);
 
 		// Call the handler function for requested method
 		let response = match request.method.as_str() {
 			"login" => self.handle_login(request.params, worker_id),
+			"keepalive" => self.handle_keepalive(),
+			_ if !self
+				.workers
+				.get_worker(worker_id)
+				.is_ok_and(|w| self.can_mine(&w)) =>
+			{
+				Err(RpcError::unauthorized())
+			}
 			"submit" => {
 				let res = self.handle_submit(request.params, worker_id);
 				// this key_id has been used now, reset
 				if let Ok((_, true)) = res {
-					self.current_state.write().current_key_id = None;
+					if let Ok(worker) = self.workers.get_worker(worker_id) {
+						let job_key = self.job_key(&worker);
+						self.current_state.write().current_key_ids.remove(&job_key);
+					}
 				}
 				res.map(|(v, _)| v)
 			}
-			"keepalive" => self.handle_keepalive(),
 			"getjobtemplate" => {
 				if self.sync_state.is_syncing() {
 					Err(RpcError::node_is_syncing())
 				} else {
-					self.handle_getjobtemplate()
+					self.handle_getjobtemplate(worker_id)
 				}
 			}
 			"status" => self.handle_status(worker_id),
@@ -280,9 +465,38 @@ impl Handler {
 		};
 		serde_json::to_string(&resp).unwrap()
 	}
+	// Whether the worker gets jobs, it needs to login first if we have
+	// credentials
+	fn can_mine(&self, worker: &Worker) -> bool {
+		self.credentials.is_none() || worker.authenticated
+	}
 	fn handle_login(&self, params: Option<Value>, worker_id: usize) -> Result<Value, RpcError> {
 		let params: LoginParams = parse_params(params)?;
-		self.workers.login(worker_id, params.login, params.agent)?;
+		let wallet_listener_url = match &self.credentials {
+			None => None,
+			Some(credentials) => match credentials.get(&params.login) {
+				Some(c)
+					if verify_slices_are_equal(c.password.as_bytes(), params.pass.as_bytes())
+						.is_ok() =>
+				{
+					c.wallet_listener_url.clone()
+				}
+				_ => {
+					warn!(
+						"(Server ID: {}) Worker {} failed to login as {}",
+						self.id, worker_id, params.login
+					);
+					return Err(RpcError::unauthorized());
+				}
+			},
+		};
+		self.workers.login(
+			worker_id,
+			params.login,
+			params.agent,
+			wallet_listener_url,
+			params.extranonce,
+		)?;
 		return Ok("ok".into());
 	}
 
@@ -296,14 +510,7 @@ impl Handler {
 		let stats = self.workers.get_stats(worker_id)?;
 		let status = WorkerStatus {
 			id: stats.id.clone(),
-			height: self
-				.current_state
-				.read()
-				.current_block_versions
-				.last()
-				.unwrap()
-				.header
-				.height,
+			height: self.current_state.read().current_height,
 			difficulty: stats.pow_difficulty,
 			accepted: stats.num_accepted,
 			rejected: stats.num_rejected,
@@ -313,9 +520,13 @@ impl Handler {
 		return Ok(response);
 	}
 	// Handle GETJOBTEMPLATE message
-	fn handle_getjobtemplate(&self) -> Result<Value, RpcError> {
+	fn handle_getjobtemplate(&self, worker_id: usize) -> Result<Value, RpcError> {
 		// Build a JobTemplate from a BlockHeader and return JSON
-		let job_template = self.build_block_template();
+		let worker = self.workers.get_worker(worker_id)?;
+		let mut job_template = self
+			.build_block_template(&worker)
+			.ok_or_else(RpcError::node_is_syncing)?;
+		job_template.difficulty = self.workers.get_stats(worker_id)?.pow_difficulty;
 		let response = serde_json::to_value(&job_template).unwrap();
 		debug!(
 			"(Server ID: {}) sending block {} with id {} to single worker",
@@ -324,16 +535,14 @@ impl Handler {
 		return Ok(response);
 	}
 
-	// Build and return a JobTemplate for mining the current block
-	fn build_block_template(&self) -> JobTemplate {
-		let bh = self
-			.current_state
-			.read()
+	// Build and return a JobTemplate for the provided worker to mine its
+	// current block, if we have one yet
+	fn build_block_template(&self, worker: &Worker) -> Option<JobTemplate> {
+		let current_state = self.current_state.read();
+		let block_versions = current_state
 			.current_block_versions
-			.last()
-			.unwrap()
-			.header
-			.clone();
+			.get(&self.job_key(worker))?;
+		let bh = block_versions.last()?.header.clone();
 		// Serialize the block header into pre and post nonce strings
 		let mut header_buf = vec![];
 		{
@@ -342,14 +551,14 @@ impl Handler {
 			bh.pow.write_pre_pow(&mut writer).unwrap();
 		}
 		let pre_pow = header_buf.to_hex();
-		let current_state = self.current_state.read();
 		let job_template = JobTemplate {
 			height: bh.height,
-			job_id: (current_state.current_block_versions.len() - 1) as u64,
+			job_id: (block_versions.len() - 1) as u64,
 			difficulty: current_state.minimum_share_difficulty,
 			pre_pow,
+			extranonce: worker.extranonce,
 		};
-		return job_template;
+		Some(job_template)
 	}
 	// Handle SUBMIT message
 	// params contains a solved block header
@@ -364,25 +573,48 @@ impl Handler {
 		// Validate parameters
 		let params: SubmitParams = parse_params(params)?;
 
-		let state = self.current_state.read();
-		// Find the correct version of the block to match this header
-		let b: Option<&Block> = state.current_block_versions.get(params.job_id as usize);
-		if params.height != state.current_block_versions.last().unwrap().header.height
-			|| b.is_none()
-		{
-			// Return error status
-			error!(
+		let worker = self.workers.get_worker(worker_id)?;
+		// Find the correct version of the block to match this header, among
+		// the ones of this worker's job. Our state lock is released before
+		// touching the worker stats, the stats lock being taken first when
+		// sending jobs.
+		let (b, current_difficulty) = {
+			let state = self.current_state.read();
+			let b: Option<Block> = state
+				.current_block_versions
+				.get(&self.job_key(&worker))
+				.and_then(|versions| versions.get(params.job_id as usize))
+				.filter(|_| params.height == state.current_height)
+				.cloned();
+			(b, state.current_difficulty)
+		};
+		let mut b: Block = match b {
+			Some(b) => b,
+			None => {
+				// Return error status
+				error!(
 					"(Server ID: {}) Share at height {}, edge_bits {}, nonce {}, job_id {} submitted too late",
 					self.id, params.height, params.edge_bits, params.nonce, params.job_id,
 				);
-			self.workers.update_stats(worker_id, |ws| ws.num_stale += 1);
-			return Err(RpcError::too_late());
+				self.workers.update_stats(worker_id, |ws| ws.num_stale += 1);
+				return Err(RpcError::too_late());
+			}
+		};
+
+		// Workers splitting the nonce space must stay in their own part of it
+		if !worker.owns_nonce(params.nonce) {
+			error!(
+					"(Server ID: {}) Share at height {}, edge_bits {}, nonce {}, job_id {} rejected, outside of extranonce {}",
+					self.id, params.height, params.edge_bits, params.nonce, params.job_id, worker.extranonce,
+				);
+			self.workers
+				.update_stats(worker_id, |worker_stats| worker_stats.num_rejected += 1);
+			return Err(RpcError::invalid_extranonce());
 		}
 
 		let share_difficulty: u64;
 		let mut share_is_block = false;
 
-		let mut b: Block = b.unwrap().clone();
 		// Reconstruct the blocks header with this nonce and pow added
 		b.header.pow.proof.edge_bits = params.edge_bits as u8;
 		b.header.pow.nonce = params.nonce;
@@ -401,12 +633,18 @@ impl Handler {
 
 		// Get share difficulty
 		share_difficulty = b.header.pow.to_difficulty(b.header.height).to_num();
+		// The worker's share difficulty, shares for its previous one are still
+		// accepted until it gets a new job
+		let worker_stats = self.workers.get_stats(worker_id)?;
+		let minimum_share_difficulty = worker_stats
+			.pow_difficulty
+			.min(worker_stats.previous_pow_difficulty);
 		// If the difficulty is too low its an error
-		if share_difficulty < state.minimum_share_difficulty {
+		if share_difficulty < minimum_share_difficulty {
 			// Return error status
 			error!(
 					"(Server ID: {}) Share at height {}, hash {}, edge_bits {}, nonce {}, job_id {} rejected due to low difficulty: {}/{}",
-					self.id, params.height, b.hash(), params.edge_bits, params.nonce, params.job_id, share_difficulty, state.minimum_share_difficulty,
+					self.id, params.height, b.hash(), params.edge_bits, params.nonce, params.job_id, share_difficulty, minimum_share_difficulty,
 				);
 			self.workers
 				.update_stats(worker_id, |worker_stats| worker_stats.num_rejected += 1);
@@ -414,7 +652,7 @@ impl Handler {
 		}
 
 		// If the difficulty is high enough, submit it (which also validates it)
-		if share_difficulty >= state.current_difficulty {
+		if share_difficulty >= current_difficulty {
 			// This is a full solution, submit it to the network
 			let res = self.chain.process_block(b.clone(), chain::Options::MINE);
 			if let Err(e) = res {
@@ -470,7 +708,6 @@ impl Handler {
 		}
 		// Log this as a valid share
 		self.workers.update_edge_bits(params.edge_bits as u16);
-		let worker = self.workers.get_worker(worker_id)?;
 		let submitted_by = match worker.login {
 			None => worker.id.to_string(),
 			Some(login) => login,
@@ -485,11 +722,31 @@ impl Handler {
 				b.header.pow.nonce,
 				params.job_id,
 				share_difficulty,
-				state.current_difficulty,
+				current_difficulty,
 				submitted_by,
 			);
-		self.workers
-			.update_stats(worker_id, |worker_stats| worker_stats.num_accepted += 1);
+		self.workers.update_stats(worker_id, |worker_stats| {
+			worker_stats.num_accepted += 1;
+			worker_stats.shares_since_retarget += 1;
+		});
+		if let Some(share_store) = &self.share_store {
+			// The share is credited for the difficulty asked of the worker
+			let credited = share_difficulty.min(worker_stats.pow_difficulty);
+			let res = share_store
+				.add_share(&submitted_by, credited, b.header.height)
+				.and_then(|share| {
+					if share_is_block {
+						share_store.add_block(&share, b.hash(), current_difficulty)?;
+					}
+					Ok(())
+				});
+			if let Err(e) = res {
+				error!(
+					"(Server ID: {}) Failed to record share at height {} submitted by {}: {}",
+					self.id, b.header.height, submitted_by, e
+				);
+			}
+		}
 		let submit_response = if share_is_block {
 			format!("blockfound - {}", b.hash().to_hex())
 		} else {
@@ -501,11 +758,9 @@ impl Handler {
 		));
 	} // handle submit a solution
 
-	fn broadcast_job(&self) {
-		debug!("broadcast job");
-		// Package new block into RpcRequest
-		let job_template = self.build_block_template();
-		let job_template_json = serde_json::to_string(&job_template).unwrap();
+	// Package a job template into a "job" RpcRequest
+	fn job_request(job_template: &JobTemplate) -> String {
+		let job_template_json = serde_json::to_string(job_template).unwrap();
 		// Issue #1159 - use a serde_json Value type to avoid extra quoting
 		let job_template_value: Value = serde_json::from_str(&job_template_json).unwrap();
 		let job_request = RpcRequest {
@@ -514,12 +769,63 @@ impl Handler {
 			method: String::from("job"),
 			params: Some(job_template_value),
 		};
-		let job_request_json = serde_json::to_string(&job_request).unwrap();
-		debug!(
-			"(Server ID: {}) sending block {} with id {} to stratum clients",
-			self.id, job_template.height, job_template.job_id,
-		);
-		self.workers.broadcast(job_request_json);
+		serde_json::to_string(&job_request).unwrap()
+	}
+
+	// Send the worker its current job at the given share difficulty, if it
+	// has one yet. Returns the height and id of the job sent.
+	fn send_job(&self, worker: &Worker, difficulty: u64) -> Option<(u64, u64)> {
+		let mut job_template = self.build_block_template(worker)?;
+		job_template.difficulty = difficulty;
+		let _ = worker
+			.tx
+			.unbounded_send(Handler::job_request(&job_template));
+		Some((job_template.height, job_template.job_id))
+	}
+
+	fn broadcast_job(&self) {
+		debug!("broadcast job");
+		// Each worker gets its own job, at its own share difficulty. The jobs
+		// are built once done with the worker stats, building one takes our
+		// state lock.
+		let workers = self.workers.update_each(|worker, worker_stats| {
+			if !self.can_mine(worker) {
+				return None;
+			}
+			worker_stats.previous_pow_difficulty = worker_stats.pow_difficulty;
+			Some(worker_stats.pow_difficulty)
+		});
+		let mut sent = None;
+		for (worker, difficulty) in workers {
+			sent = self.send_job(&worker, difficulty).or(sent);
+		}
+		if let Some((height, job_id)) = sent {
+			debug!(
+				"(Server ID: {}) sending block {} with id {} to stratum clients",
+				self.id, height, job_id,
+			);
+		}
+	}
+
+	// Adjust the share difficulty of the workers due for it, sending them the
+	// current job at their new difficulty
+	fn retarget_workers(&self, vardiff: &VarDiff) {
+		let max_difficulty = self.current_state.read().current_difficulty;
+		let now = SystemTime::now();
+		let workers = self.workers.update_each(|_, worker_stats| {
+			let previous = worker_stats.pow_difficulty;
+			if !vardiff.retarget(worker_stats, max_difficulty, now) {
+				return None;
+			}
+			debug!(
+				"(Server ID: {}) worker {} share difficulty adjusted from {} to {}",
+				self.id, worker_stats.id, previous, worker_stats.pow_difficulty,
+			);
+			Some(worker_stats.pow_difficulty)
+		});
+		for (worker, difficulty) in workers {
+			self.send_job(&worker, difficulty);
+		}
 	}
 
 	pub fn run(
@@ -530,43 +836,128 @@ impl Handler {
 	) {
 		debug!("Run main loop");
 		let mut deadline: i64 = 0;
+		let mut retarget_check: i64 = 0;
 		let mut head = self.chain.head().unwrap();
 		let mut current_hash = head.prev_block_h;
+		// Jobs we failed to build a block for, retried at the next rebuild
+		let mut failed_jobs: HashSet<JobKey> = HashSet::new();
+		let mut share_store_hash = current_hash;
 		loop {
 			// get the latest chain state
 			head = self.chain.head().unwrap();
 			let latest_hash = head.last_block_h;
 
+			// Confirm (or orphan) the blocks we found as the chain moves on
+			if let Some(share_store) = self
+				.share_store
+				.as_ref()
+				.filter(|_| share_store_hash != latest_hash)
+			{
+				let res = share_store.update_block_status(head.height, |height, hash| {
+					self.chain
+						.get_header_by_height(height)
+						.is_ok_and(|header| header.hash() == *hash)
+				});
+				if let Err(e) = res {
+					error!(
+						"(Server ID: {}) Failed to update found blocks status: {}",
+						self.id, e
+					);
+				}
+				share_store_hash = latest_hash;
+			}
+
+			// The jobs of the connected workers, each needs its own block
+			let jobs: HashSet<JobKey> = self
+				.workers
+				.workers()
+				.iter()
+				.filter(|w| self.can_mine(w))
+				.map(|w| self.job_key(w))
+				.collect();
+			let new_job = {
+				let state = self.current_state.read();
+				jobs.iter()
+					.filter(|j| !failed_jobs.contains(j))
+					.any(|j| !state.current_block_versions.contains_key(j))
+			};
+
 			// Build a new block if there is at least one worker and
 			// There is a new block on the chain or its time to rebuild
-			// the current one to include new transactions
-			if (current_hash != latest_hash || Utc::now().timestamp() >= deadline)
-				&& self.workers.count() > 0
+			// the current one to include new transactions (or a new
+			// worker showed up)
+			if (current_hash != latest_hash || Utc::now().timestamp() >= deadline || new_job)
+				&& !jobs.is_empty()
 			{
+				// Build the new blocks (versions) before taking our state lock, building
+				// a block involves the wallet listener of each payout. Each job gets
+				// its own coinbase output, hence its own header.
+				let key_ids = self.current_state.read().current_key_ids.clone();
+				failed_jobs.clear();
+				let mut new_blocks = vec![];
+				for job_key in &jobs {
+					match mine_block::try_get_block(
+						&self.chain,
+						tx_pool,
+						verifier_cache.clone(),
+						key_ids.get(job_key).cloned(),
+						job_key.0.clone(),
+						MAX_BLOCK_BUILD_ATTEMPTS,
+					) {
+						Ok((new_block, block_fees)) => {
+							new_blocks.push((job_key.clone(), new_block, block_fees))
+						}
+						Err(e) => {
+							error!(
+								"(Server ID: {}) failed to build block for payout {:?}, extranonce {}: {:?}",
+								self.id, job_key.0, job_key.1, e
+							);
+							failed_jobs.insert(job_key.clone());
+						}
+					}
+				}
+
 				{
 					debug!("resend updated block");
 					let mut state = self.current_state.write();
-					let wallet_listener_url = if !config.burn_reward {
-						Some(config.wallet_listener_url.clone())
-					} else {
-						None
-					};
 					// If this is a new block we will clear the current_block version history
 					let clear_blocks = current_hash != latest_hash;
-
-					// Build the new block (version)
-					let (new_block, block_fees) = mine_block::get_block(
-						&self.chain,
-						tx_pool,
-						verifier_cache.clone(),
-						state.current_key_id.clone(),
-						wallet_listener_url,
-					);
-
-					state.current_difficulty =
-						(new_block.header.total_difficulty() - head.total_difficulty).to_num();
-
-					state.current_key_id = block_fees.key_id();
+					if clear_blocks {
+						state.current_block_versions.clear();
+					}
+					// Forget about the jobs of the workers gone
+					state
+						.current_block_versions
+						.retain(|job_key, _| jobs.contains(job_key));
+					state
+						.current_key_ids
+						.retain(|job_key, _| jobs.contains(job_key));
+
+					for (job_key, new_block, block_fees) in new_blocks {
+						state.current_difficulty =
+							(new_block.header.total_difficulty() - head.total_difficulty).to_num();
+						state.current_height = new_block.header.height;
+
+						match block_fees.key_id() {
+							Some(key_id) => state.current_key_ids.insert(job_key.clone(), key_id),
+							None => state.current_key_ids.remove(&job_key),
+						};
+
+						// Update the mining stats
+						self.workers.update_block_height(new_block.header.height);
+						let difficulty =
+							new_block.header.total_difficulty() - head.total_difficulty;
+						self.workers.update_network_difficulty(difficulty.to_num());
+
+						// Add this new block candidate onto our list of block versions for this
+						// height and job
+						state
+							.current_block_versions
+							.entry(job_key)
+							.or_default()
+							.push(new_block);
+					}
+					self.workers.update_network_hashrate();
 
 					current_hash = latest_hash;
 					// set the minimum acceptable share difficulty for this block
@@ -574,23 +965,14 @@ impl Handler {
 
 					// set a new deadline for rebuilding with fresh transactions
 					deadline = Utc::now().timestamp() + config.attempt_time_per_block as i64;
-
-					// If this is a new block we will clear the current_block version history
-					if clear_blocks {
-						state.current_block_versions.clear();
-					}
-
-					// Update the mining stats
-					self.workers.update_block_height(new_block.header.height);
-					let difficulty = new_block.header.total_difficulty() - head.total_difficulty;
-					self.workers.update_network_difficulty(difficulty.to_num());
-					self.workers.update_network_hashrate();
-
-					// Add this new block candidate onto our list of block versions for this height
-					state.current_block_versions.push(new_block);
 				}
 				// Send this job to all connected workers
 				self.broadcast_job();
+			} else if let Some(vardiff) = &self.vardiff {
+				if Utc::now().timestamp() >= retarget_check {
+					self.retarget_workers(vardiff);
+					retarget_check = Utc::now().timestamp() + 1;
+				}
 			}
 
 			// sleep before restarting loop
@@ -601,7 +983,11 @@ impl Handler {
 
 // ----------------------------------------
 // Worker Factory Thread Function
-fn accept_connections(listen_addr: SocketAddr, handler: Arc<Handler>) {
+fn accept_connections(
+	listen_addr: SocketAddr,
+	handler: Arc<Handler>,
+	tls_acceptor: Option<TlsAcceptor>,
+) {
 	info!("Start tokio stratum server");
 	let task = async move {
 		let mut listener = TcpListener::bind(&listen_addr).await.unwrap_or_else(|_| {
@@ -612,50 +998,23 @@ fn accept_connections(listen_addr: SocketAddr, handler: Arc<Handler>) {
 			.filter_map(|s| async { s.map_err(|e| error!("accept error = {:?}", e)).ok() })
 			.for_each(move |socket| {
 				let handler = handler.clone();
+				let tls_acceptor = tls_acceptor.clone();
 				async move {
-					// Spawn a task to process the connection
-					let (tx, mut rx) = mpsc::unbounded();
-
-					let worker_id = handler.workers.add_worker(tx);
-					info!("Worker {} connected", worker_id);
-
-					let framed = Framed::new(socket, LinesCodec::new());
-					let (mut writer, mut reader) = framed.split();
-
-					let h = handler.clone();
-					let read = async move {
-						while let Some(line) = reader
-							.try_next()
-							.await
-							.map_err(|e| error!("error reading line: {}", e))?
-						{
-							let request = serde_json::from_str(&line)
-								.map_err(|e| error!("error serializing line: {}", e))?;
-							let resp = h.handle_rpc_requests(request, worker_id);
-							h.workers.send_to(worker_id, resp);
+					// Spawn a task to process the connection, the TLS handshake
+					// (if any) happens there too
+					match tls_acceptor {
+						None => {
+							tokio::spawn(handle_connection(socket, handler));
 						}
-
-						Result::<_, ()>::Ok(())
-					};
-
-					let write = async move {
-						while let Some(line) = rx.next().await {
-							writer
-								.send(line)
-								.await
-								.map_err(|e| error!("error writing line: {}", e))?;
+						Some(acceptor) => {
+							tokio::spawn(async move {
+								match acceptor.accept(socket).await {
+									Ok(stream) => handle_connection(stream, handler).await,
+									Err(e) => error!("Stratum TLS handshake failed: {}", e),
+								}
+							});
 						}
-
-						Result::<_, ()>::Ok(())
-					};
-
-					let task = async move {
-						pin_mut!(read, write);
-						futures::future::select(read, write).await;
-						handler.workers.remove_worker(worker_id);
-						info!("Worker {} disconnected", worker_id);
-					};
-					tokio::spawn(task);
+					}
 				}
 			});
 		server.await
@@ -665,6 +1024,52 @@ fn accept_connections(listen_addr: SocketAddr, handler: Arc<Handler>) {
 	rt.block_on(task);
 }
 
+// Registers a new worker and processes its requests until it disconnects
+async fn handle_connection<S>(socket: S, handler: Arc<Handler>)
+where
+	S: AsyncRead + AsyncWrite + Unpin,
+{
+	let (tx, mut rx) = mpsc::unbounded();
+
+	let worker_id = handler.workers.add_worker(tx);
+	info!("Worker {} connected", worker_id);
+
+	let framed = Framed::new(socket, LinesCodec::new());
+	let (mut writer, mut reader) = framed.split();
+
+	let h = handler.clone();
+	let read = async move {
+		while let Some(line) = reader
+			.try_next()
+			.await
+			.map_err(|e| error!("error reading line: {}", e))?
+		{
+			let request =
+				serde_json::from_str(&line).map_err(|e| error!("error serializing line: {}", e))?;
+			let resp = h.handle_rpc_requests(request, worker_id);
+			h.workers.send_to(worker_id, resp);
+		}
+
+		Result::<_, ()>::Ok(())
+	};
+
+	let write = async move {
+		while let Some(line) = rx.next().await {
+			writer
+				.send(line)
+				.await
+				.map_err(|e| error!("error writing line: {}", e))?;
+		}
+
+		Result::<_, ()>::Ok(())
+	};
+
+	pin_mut!(read, write);
+	futures::future::select(read, write).await;
+	handler.workers.remove_worker(worker_id);
+	info!("Worker {} disconnected", worker_id);
+}
+
 // ----------------------------------------
 // Worker Object - a connected stratum client - a miner, pool, proxy, etc...
 
@@ -674,6 +1079,11 @@ pub struct Worker {
 	agent: String,
 	login: Option<String>,
 	authenticated: bool,
+	wallet_listener_url: Option<String>,
+	// high bits of the nonces this worker searches, distinct per worker
+	extranonce: u32,
+	// whether the worker agreed to only use nonces with its extranonce
+	uses_extranonce: bool,
 	tx: Tx,
 }
 
@@ -685,9 +1095,19 @@ impl Worker {
 			agent: String::from(""),
 			login: None,
 			authenticated: false,
+			wallet_listener_url: None,
+			extranonce: id as u32,
+			uses_extranonce: false,
 			tx: tx,
 		}
 	}
+
+	/// Whether the nonce belongs to this worker's part of the nonce space:
+	/// its high 32 bits are the worker extranonce. Any nonce does for
+	/// workers not using their extranonce.
+	pub fn owns_nonce(&self, nonce: u64) -> bool {
+		!self.uses_extranonce || (nonce >> 32) as u32 == self.extranonce
+	}
 } // impl Worker
 
 struct WorkersList {
@@ -714,6 +1134,7 @@ impl WorkersList {
 		worker_stats.is_connected = true;
 		worker_stats.id = worker_id.to_string();
 		worker_stats.pow_difficulty = stratum_stats.minimum_share_difficulty;
+		worker_stats.previous_pow_difficulty = stratum_stats.minimum_share_difficulty;
 		stratum_stats.worker_stats.push(worker_stats);
 		stratum_stats.num_workers = workers_list.len();
 		worker_id
@@ -729,15 +1150,23 @@ impl WorkersList {
 		stratum_stats.num_workers = workers_list.len();
 	}
 
-	pub fn login(&self, worker_id: usize, login: String, agent: String) -> Result<(), RpcError> {
+	pub fn login(
+		&self,
+		worker_id: usize,
+		login: String,
+		agent: String,
+		wallet_listener_url: Option<String>,
+		uses_extranonce: bool,
+	) -> Result<(), RpcError> {
 		let mut wl = self.workers_list.write();
 		let mut worker = wl
 			.get_mut(&worker_id)
 			.ok_or_else(RpcError::internal_error)?;
 		worker.login = Some(login);
-		// XXX TODO Future - Validate password?
 		worker.agent = agent;
 		worker.authenticated = true;
+		worker.wallet_listener_url = wallet_listener_url;
+		worker.uses_extranonce = uses_extranonce;
 		Ok(())
 	}
 
@@ -781,14 +1210,26 @@ impl WorkersList {
 			.unbounded_send(msg);
 	}
 
-	pub fn broadcast(&self, msg: String) {
-		for worker in self.workers_list.read().values() {
-			let _ = worker.tx.unbounded_send(msg.clone());
-		}
+	/// Updates the stats of each worker, returning the workers for which the
+	/// update gave a result along with it. The stats lock is held during the
+	/// updates, which must not take any other lock.
+	pub fn update_each<T>(
+		&self,
+		mut f: impl FnMut(&Worker, &mut WorkerStats) -> Option<T>,
+	) -> Vec<(Worker, T)> {
+		let mut stratum_stats = self.stratum_stats.write();
+		self.workers_list
+			.read()
+			.values()
+			.filter_map(|worker| {
+				f(worker, &mut stratum_stats.worker_stats[worker.id])
+					.map(|res| (worker.clone(), res))
+			})
+			.collect()
 	}
 
-	pub fn count(&self) -> usize {
-		self.workers_list.read().len()
+	pub fn workers(&self) -> Vec<Worker> {
+		self.workers_list.read().values().cloned().collect()
 	}
 
 	pub fn update_edge_bits(&self, edge_bits: u16) {
@@ -829,6 +1270,7 @@ pub struct StratumServer {
 	verifier_cache: ServerVerifierCache,
 	sync_state: Arc<SyncState>,
 	stratum_stats: Arc<RwLock<StratumStats>>,
+	share_store: Option<Arc<ShareStore>>,
 }
 
 impl StratumServer {
@@ -839,6 +1281,7 @@ impl StratumServer {
 		tx_pool: ServerTxPool,
 		verifier_cache: ServerVerifierCache,
 		stratum_stats: Arc<RwLock<StratumStats>>,
+		share_store: Option<Arc<ShareStore>>,
 	) -> StratumServer {
 		StratumServer {
 			id: String::from("0"),
@@ -848,6 +1291,7 @@ impl StratumServer {
 			verifier_cache,
 			sync_state: Arc::new(SyncState::new()),
 			stratum_stats: stratum_stats,
+			share_store,
 		}
 	}
 
@@ -872,11 +1316,37 @@ impl StratumServer {
 			.parse()
 			.expect("Stratum: Incorrect address ");
 
-		let handler = Arc::new(Handler::from_stratum(&self));
+		let credentials = match &self.config.credentials_file {
+			None => None,
+			Some(path) => match read_credentials(path) {
+				Ok(credentials) => Some(credentials),
+				Err(e) => {
+					error!("Stratum server not started: {}", e);
+					return;
+				}
+			},
+		};
+
+		let tls_acceptor = match self.config.tls_config() {
+			Ok(None) => None,
+			Ok(Some(tls_config)) => match tls_config.build_server_config() {
+				Ok(server_config) => Some(TlsAcceptor::from(server_config)),
+				Err(e) => {
+					error!("Stratum server not started: {}", e);
+					return;
+				}
+			},
+			Err(e) => {
+				error!("Stratum server not started: {:?}", e);
+				return;
+			}
+		};
+
+		let handler = Arc::new(Handler::from_stratum(self, credentials));
 		let h = handler.clone();
 
 		let _listener_th = thread::spawn(move || {
-			accept_connections(listen_addr, h);
+			accept_connections(listen_addr, h, tls_acceptor);
 		});
 
 		// We have started
@@ -1055,4 +1525,148 @@ mod tests {
 
 		assert_eq!(expected_deserialized, actual_deserialized);
 	}
+
+	/// Tests the share difficulty adjustments of a worker.
+	#[test]
+	fn test_vardiff_retarget() {
+		let config = StratumServerConfig {
+			minimum_share_difficulty: 4,
+			vardiff_shares_per_minute: Some(6),
+			vardiff_retarget_time: 60,
+			..StratumServerConfig::default()
+		};
+		let vardiff = VarDiff::from_config(&config).unwrap();
+		let start = SystemTime::now();
+		let mut stats = WorkerStats {
+			pow_difficulty: 16,
+			previous_pow_difficulty: 16,
+			last_retarget: start,
+			..WorkerStats::default()
+		};
+
+		// Not due yet
+		stats.shares_since_retarget = 60;
+		assert!(!vardiff.retarget(&mut stats, 1_000, start + Duration::from_secs(30)));
+		assert_eq!(stats.pow_difficulty, 16);
+
+		// Twice the target rate doubles the difficulty
+		stats.shares_since_retarget = 12;
+		let now = start + Duration::from_secs(60);
+		assert!(vardiff.retarget(&mut stats, 1_000, now));
+		assert_eq!(stats.pow_difficulty, 32);
+		assert_eq!(stats.previous_pow_difficulty, 16);
+		assert_eq!(stats.shares_since_retarget, 0);
+		assert_eq!(stats.last_retarget, now);
+
+		// Close enough to the target rate, no change
+		stats.shares_since_retarget = 7;
+		let now = now + Duration::from_secs(60);
+		assert!(!vardiff.retarget(&mut stats, 1_000, now));
+		assert_eq!(stats.pow_difficulty, 32);
+
+		// Flooding is limited to a 4x adjustment and to the block difficulty
+		stats.shares_since_retarget = 600;
+		let now = now + Duration::from_secs(60);
+		assert!(vardiff.retarget(&mut stats, 100, now));
+		assert_eq!(stats.pow_difficulty, 100);
+
+		// No shares at all divides by 4, down to the minimum
+		let now = now + Duration::from_secs(60);
+		assert!(vardiff.retarget(&mut stats, 1_000, now));
+		assert_eq!(stats.pow_difficulty, 25);
+		let now = now + Duration::from_secs(60);
+		assert!(vardiff.retarget(&mut stats, 1_000, now));
+		assert_eq!(stats.pow_difficulty, 6);
+		let now = now + Duration::from_secs(60);
+		assert!(vardiff.retarget(&mut stats, 1_000, now));
+		assert_eq!(stats.pow_difficulty, 4);
+		let now = now + Duration::from_secs(60);
+		assert!(!vardiff.retarget(&mut stats, 1_000, now));
+
+		// Disabled unless a share rate is configured
+		assert!(VarDiff::from_config(&StratumServerConfig::default()).is_none());
+	}
+
+	/// Tests parsing the stratum credentials file.
+	#[test]
+	fn test_parse_credentials() {
+		let content = "
+			# solo miners
+			alice s3cret http://10.0.0.2:3415
+			bob hunter2
+		";
+		let credentials = parse_credentials(content).unwrap();
+		assert_eq!(credentials.len(), 2);
+		assert_eq!(
+			credentials["alice"],
+			StratumCredential {
+				password: "s3cret".to_string(),
+				wallet_listener_url: Some("http://10.0.0.2:3415".to_string()),
+			}
+		);
+		assert_eq!(credentials["bob"].password, "hunter2");
+		assert_eq!(credentials["bob"].wallet_listener_url, None);
+
+		assert!(parse_credentials("alice").is_err());
+		assert!(parse_credentials("alice a http://x extra").is_err());
+		assert!(parse_credentials("alice a\nalice b").is_err());
+		assert!(parse_credentials("").unwrap().is_empty());
+	}
+
+	/// Tests the stratum TLS configuration.
+	#[test]
+	fn test_tls_config() {
+		let mut config = StratumServerConfig::default();
+		assert!(config.tls_config().unwrap().is_none());
+
+		config.tls_certificate_file = Some("stratum.crt".to_string());
+		assert!(config.tls_config().is_err());
+
+		config.tls_certificate_key = Some("stratum.key".to_string());
+		let tls_config = config.tls_config().unwrap().unwrap();
+		assert_eq!(tls_config.certificate, "stratum.crt");
+		assert_eq!(tls_config.private_key, "stratum.key");
+		// Missing files are only found out when building the acceptor
+		assert!(tls_config.build_server_config().is_err());
+	}
+
+	/// Tests workers get distinct extranonces, hence jobs, the extranonce
+	/// being enforced once agreed to.
+	#[test]
+	fn test_worker_extranonce() {
+		global::set_local_chain_type(global::ChainTypes::AutomatedTesting);
+		let workers = WorkersList::new(Arc::new(RwLock::new(StratumStats::default())));
+		let alice = workers.add_worker(mpsc::unbounded().0);
+		let bob = workers.add_worker(mpsc::unbounded().0);
+		workers
+			.login(alice, "alice".to_string(), "".to_string(), None, true)
+			.unwrap();
+		workers
+			.login(bob, "bob".to_string(), "".to_string(), None, false)
+			.unwrap();
+
+		let alice = workers.get_worker(alice).unwrap();
+		let bob = workers.get_worker(bob).unwrap();
+		assert_ne!(alice.extranonce, bob.extranonce);
+
+		// Plenty more workers, all paying to the same wallet, still each get
+		// their own job
+		for _ in 0..200 {
+			workers.add_worker(mpsc::unbounded().0);
+		}
+		let job_keys: HashSet<JobKey> = workers
+			.workers()
+			.iter()
+			.map(|w| (w.wallet_listener_url.clone(), w.extranonce))
+			.collect();
+		assert_eq!(job_keys.len(), 202);
+
+		let alice_nonce = (alice.extranonce as u64) << 32 | 12345;
+		let bob_nonce = (bob.extranonce as u64) << 32 | 12345;
+		assert!(alice.owns_nonce(alice_nonce));
+		assert!(!alice.owns_nonce(bob_nonce));
+		// Not enforced for workers that did not ask for it
+		assert!(bob.owns_nonce(alice_nonce));
+		assert!(bob.owns_nonce(bob_nonce));
+	}
 }
@@ -19,7 +19,7 @@ use clap::ArgMatches;
 
 use crate::api::client;
 use crate::api::json_rpc::*;
-use crate::api::types::Status;
+use crate::api::types::{PplnsWindow, Status};
 use crate::config::GlobalConfig;
 use crate::p2p::types::PeerInfoDisplay;
 use crate::util::file::get_first_line;
@@ -139,6 +139,47 @@ impl HTTPNodeClient {
 		e.reset().unwrap();
 	}
 
+	pub fn show_pplns_window(&self, height: Option<u64>, window: Option<u64>) {
+		let mut e = term::stdout().unwrap();
+		let params = json!([height, window]);
+		match self.send_json_request::<PplnsWindow>("get_pplns_window", &params) {
+			Ok(pplns) => {
+				writeln!(
+					e,
+					"Block {} at height {}, found by {}",
+					pplns.block.hash, pplns.block.height, pplns.block.worker
+				)
+				.unwrap();
+				writeln!(
+					e,
+					"Window: {} share difficulty (requested {}) since {}",
+					pplns.difficulty, pplns.window, pplns.start
+				)
+				.unwrap();
+				println!();
+				writeln!(
+					e,
+					"{:<24} {:>10} {:>20} {:>10}",
+					"Worker", "Shares", "Difficulty", "Share"
+				)
+				.unwrap();
+				for w in pplns.workers {
+					writeln!(
+						e,
+						"{:<24} {:>10} {:>20} {:>9.4}%",
+						w.worker,
+						w.shares,
+						w.difficulty,
+						w.fraction * 100.0
+					)
+					.unwrap();
+				}
+			}
+			Err(_) => writeln!(e, "Failed to get the PPLNS window").unwrap(),
+		};
+		e.reset().unwrap();
+	}
+
 	pub fn unban_peer(&self, peer_addr: &SocketAddr) {
 		let mut e = term::stdout().unwrap();
 		let params = json!([peer_addr]);
@@ -181,6 +222,15 @@ pub fn client_command(client_args: &ArgMatches<'_>, global_config: GlobalConfig)
 				panic!("Invalid peer address format");
 			}
 		}
+		("pplns", Some(pplns_args)) => {
+			let height = pplns_args
+				.value_of("height")
+				.map(|h| h.parse().expect("Invalid block height"));
+			let window = pplns_args
+				.value_of("window")
+				.map(|w| w.parse().expect("Invalid window difficulty"));
+			node_client.show_pplns_window(height, window);
+		}
 		_ => panic!("Unknown client command, use 'grin help client' for details"),
 	}
 	0
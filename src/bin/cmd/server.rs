@@ -26,7 +26,6 @@ use crate::p2p::Seeding;
 use crate::servers;
 use crate::tui::ui;
 use grin_p2p::msg::PeerAddrs;
-use grin_p2p::PeerAddr;
 use grin_util::logger::LogEntry;
 use std::sync::mpsc;
 
@@ -78,6 +77,33 @@ fn start_server_tui(config: servers::ServerConfig, logs_rx: Option<mpsc::Receive
 	}
 }
 
+/// Rebuilds the chain state from the blocks in our local db, printing progress
+/// as we go.
+fn reindex_chain(config: servers::ServerConfig) -> i32 {
+	println!(
+		"Reindexing chain in {}, this may take a while...",
+		config.db_root
+	);
+	let res = servers::Server::reindex(config, |height, total| {
+		if height % 1000 == 0 || height == total {
+			println!("Reindexed block {} of {}", height, total);
+		}
+	});
+	match res {
+		Ok(head) => {
+			println!(
+				"Reindex complete, chain head {} at {}",
+				head.last_block_h, head.height
+			);
+			0
+		}
+		Err(e) => {
+			println!("Reindex failed: {:?}", e);
+			1
+		}
+	}
+}
+
 /// Handles the server part of the command line, mostly running, starting and
 /// stopping the Grin blockchain server. Processes all the command line
 /// arguments to build a proper configuration and runs Grin with that
@@ -109,7 +135,7 @@ pub fn server_command(
 		}
 
 		if let Some(seeds) = a.values_of("seed") {
-			let peers = seeds.filter_map(|s| s.parse().ok()).map(PeerAddr).collect();
+			let peers = seeds.filter_map(|s| s.parse().ok()).collect();
 			server_config.p2p_config.seeding_type = Seeding::List;
 			server_config.p2p_config.seeds = Some(PeerAddrs { peers });
 		}
@@ -120,6 +146,9 @@ pub fn server_command(
 			("run", _) => {
 				start_server(server_config, logs_rx);
 			}
+			("reindex", _) => {
+				return reindex_chain(server_config);
+			}
 			("", _) => {
 				println!("Subcommand required, use 'grin help server' for details");
 			}
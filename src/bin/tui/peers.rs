@@ -16,6 +16,7 @@
 
 use std::cmp::Ordering;
 
+use crate::chain::{SegmentDownloadStats, SyncStatus};
 use crate::servers::{PeerStats, ServerStats};
 
 use chrono::prelude::*;
@@ -154,6 +155,7 @@ impl TUIPeerView {
 						.child(TextView::new("Longest Chain: "))
 						.child(TextView::new("  ").with_name("longest_work_peer")),
 				)
+				.child(TextView::new("").with_name("pibd_sync_status"))
 				.child(TextView::new("   "))
 				.child(
 					Dialog::around(table_view.with_name(TABLE_PEER_STATUS).min_size((50, 20)))
@@ -207,5 +209,40 @@ impl TUIStatusListener for TUIPeerView {
 		let _ = c.call_on_name("longest_work_peer", |t: &mut TextView| {
 			t.set_content(lp_str);
 		});
+		let _ = c.call_on_name("pibd_sync_status", |t: &mut TextView| {
+			t.set_content(pibd_sync_status(&stats.sync_status));
+		});
+	}
+}
+
+/// Per MMR and per peer progress of PIBD state sync, empty if not currently
+/// syncing state via PIBD.
+fn pibd_sync_status(sync_status: &SyncStatus) -> String {
+	let stats = match sync_status {
+		SyncStatus::TxHashsetPibdDownload(stats) => stats,
+		_ => return "".to_string(),
+	};
+	let mmr = |s: &SegmentDownloadStats| format!("{}/{}", s.segments_done, s.segments_total);
+	let mut lines = vec![
+		"".to_string(),
+		format!(
+			"State Sync Segments: bitmap {}, output {}, rangeproof {}, kernel {}",
+			mmr(&stats.bitmap),
+			mmr(&stats.output),
+			mmr(&stats.rangeproof),
+			mmr(&stats.kernel),
+		),
+	];
+	for peer in &stats.peers {
+		lines.push(format!(
+			"  {}: received {}/{}, in flight {}, timed out {}, avg {}ms",
+			peer.addr,
+			peer.segments_received,
+			peer.segments_requested,
+			peer.in_flight,
+			peer.segments_timed_out,
+			peer.avg_latency_ms,
+		));
 	}
+	lines.join("\n")
 }
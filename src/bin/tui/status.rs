@@ -71,6 +71,16 @@ impl TUIStatusView {
 					))
 				}
 			}
+			SyncStatus::TxHashsetPibdDownload(stat) => {
+				let percent = (stat.segments_done() * 100)
+					.checked_div(stat.segments_total())
+					.unwrap_or(0);
+				Cow::Owned(format!(
+					"Sync step 2/7: Downloading chain state segments for state sync: {}% from {} peer(s)",
+					percent,
+					stat.peers.len(),
+				))
+			}
 			SyncStatus::TxHashsetSetup => {
 				Cow::Borrowed("Sync step 3/7: Preparing chain state for validation")
 			}
@@ -237,7 +247,7 @@ impl TUIStatusView {
 
 impl TUIStatusListener for TUIStatusView {
 	fn update(c: &mut Cursive, stats: &ServerStats) {
-		let basic_status = TUIStatusView::update_sync_status(stats.sync_status);
+		let basic_status = TUIStatusView::update_sync_status(stats.sync_status.clone());
 
 		c.call_on_name("basic_current_status", |t: &mut TextView| {
 			t.set_content(basic_status);
@@ -308,3 +318,30 @@ fn test_status_txhashset_rproofs() {
 	let basic_status = TUIStatusView::update_sync_status(status);
 	assert!(basic_status.contains("64%"), basic_status);
 }
+
+#[test]
+fn test_status_txhashset_pibd_download() {
+	use crate::chain::{SegmentDownloadStats, TxHashsetPibdStats};
+
+	let status = SyncStatus::TxHashsetPibdDownload(TxHashsetPibdStats {
+		bitmap: SegmentDownloadStats {
+			segments_done: 1,
+			segments_total: 1,
+		},
+		output: SegmentDownloadStats {
+			segments_done: 3,
+			segments_total: 8,
+		},
+		rangeproof: SegmentDownloadStats {
+			segments_done: 12,
+			segments_total: 32,
+		},
+		kernel: SegmentDownloadStats {
+			segments_done: 4,
+			segments_total: 9,
+		},
+		..Default::default()
+	});
+	let basic_status = TUIStatusView::update_sync_status(status);
+	assert!(basic_status.contains("40%"), "{}", basic_status);
+}
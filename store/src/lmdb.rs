@@ -269,9 +269,13 @@ impl Store {
 		}
 	}
 
-	/// Gets a `Readable` value from the db, provided its key.
+	/// Gets a value from the db, provided its key.
+	/// Deserializes the retrieved data using the provided function.
 	/// Note: Creates a new read transaction so will *not* see any uncommitted data.
-	pub fn get_ser<T: ser::Readable>(&self, key: &[u8]) -> Result<Option<T>, Error> {
+	pub fn read_with<F, T>(&self, key: &[u8], deserialize: F) -> Result<Option<T>, Error>
+	where
+		F: Fn(&[u8], &[u8]) -> Result<T, Error>,
+	{
 		let lock = self.db.read();
 		let db = lock
 			.as_ref()
@@ -279,7 +283,13 @@ impl Store {
 		let txn = lmdb::ReadTransaction::new(self.env.clone())?;
 		let access = txn.access();
 
-		self.get_with(key, &access, &db, |_, mut data| {
+		self.get_with(key, &access, db, deserialize)
+	}
+
+	/// Gets a `Readable` value from the db, provided its key.
+	/// Note: Creates a new read transaction so will *not* see any uncommitted data.
+	pub fn get_ser<T: ser::Readable>(&self, key: &[u8]) -> Result<Option<T>, Error> {
+		self.read_with(key, |_, mut data| {
 			ser::deserialize(&mut data, self.protocol_version()).map_err(From::from)
 		})
 	}
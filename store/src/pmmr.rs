@@ -60,6 +60,7 @@ pub struct PMMRBackend<T: PMMRable> {
 	data_file: DataFile<T::E>,
 	leaf_set: LeafSet,
 	prune_list: PruneList,
+	prune_list_dirty: bool,
 }
 
 impl<T: PMMRable> Backend<T> for PMMRBackend<T> {
@@ -86,6 +87,27 @@ impl<T: PMMRable> Backend<T> for PMMRBackend<T> {
 		Ok(())
 	}
 
+	/// Append the root (and parent) hashes of a subtree pruned elsewhere.
+	/// The subtree root is added to our prune_list so positions beneath it
+	/// are accounted for as compacted.
+	fn append_pruned_subtree(&mut self, pos: u64, hashes: &[Hash]) -> Result<(), String> {
+		if !self.prunable {
+			return Err("Not prunable, cannot append pruned subtree.".to_string());
+		}
+		if pmmr::is_leaf(pos) {
+			return Err(format!("Cannot append pruned subtree at leaf pos {}.", pos));
+		}
+
+		self.hash_file
+			.extend_from_slice(hashes)
+			.map_err(|e| format!("Failed to append subtree hash to file. {}", e))?;
+
+		self.prune_list.append(pos);
+		self.prune_list_dirty = true;
+
+		Ok(())
+	}
+
 	fn get_from_file(&self, position: u64) -> Option<Hash> {
 		if self.is_compacted(position) {
 			return None;
@@ -278,6 +300,7 @@ impl<T: PMMRable> PMMRBackend<T> {
 			data_file,
 			leaf_set,
 			prune_list,
+			prune_list_dirty: false,
 		})
 	}
 
@@ -325,6 +348,7 @@ impl<T: PMMRable> PMMRBackend<T> {
 			.and(self.hash_file.flush())
 			.and(self.data_file.flush())
 			.and(self.sync_leaf_set())
+			.and(self.sync_prune_list())
 			.map_err(|e| {
 				io::Error::new(
 					io::ErrorKind::Interrupted,
@@ -341,11 +365,29 @@ impl<T: PMMRable> PMMRBackend<T> {
 		self.leaf_set.flush()
 	}
 
+	// Sync the prune_list if we appended pruned subtrees to it.
+	// The prune_list is otherwise only updated (and saved) during compaction.
+	fn sync_prune_list(&mut self) -> io::Result<()> {
+		if !self.prune_list_dirty {
+			return Ok(());
+		}
+		self.prune_list.flush()?;
+		self.prune_list_dirty = false;
+		Ok(())
+	}
+
 	/// Discard the current, non synced state of the backend.
 	pub fn discard(&mut self) {
 		self.hash_file.discard();
 		self.data_file.discard();
 		self.leaf_set.discard();
+		if self.prune_list_dirty {
+			match PruneList::open(self.data_dir.join(PMMR_PRUN_FILE)) {
+				Ok(prune_list) => self.prune_list = prune_list,
+				Err(e) => error!("discard: failed to reload prune_list: {}", e),
+			}
+			self.prune_list_dirty = false;
+		}
 	}
 
 	/// Takes the leaf_set at a given cutoff_pos and generates an updated
@@ -406,6 +448,7 @@ impl<T: PMMRable> PMMRBackend<T> {
 				self.prune_list.add(pos.into());
 			}
 			self.prune_list.flush()?;
+			self.prune_list_dirty = false;
 		}
 
 		// Write the leaf_set to disk.
@@ -249,6 +249,32 @@ impl PruneList {
 		}
 	}
 
+	/// Append the pruned root at the provided position to the prune list.
+	/// The position must be to the right of all existing entries. Unlike `add`
+	/// this does not roll up siblings as the caller is appending a subtree that
+	/// was pruned elsewhere. The shift caches are updated in place.
+	pub fn append(&mut self, pos: u64) {
+		assert!(pos > 0, "prune list 1-indexed, 0 not valid pos");
+		if let Some(max) = self.bitmap.maximum() {
+			assert!(
+				pmmr::bintree_leftmost(pos) > max as u64,
+				"prune list append must be to the right of existing entries"
+			);
+		}
+
+		let prev_shift = self.get_total_shift();
+		let prev_leaf_shift = self.get_total_leaf_shift();
+		let height = bintree_postorder_height(pos);
+
+		self.bitmap.add(pos as u32);
+		self.shift_cache.push(prev_shift + 2 * ((1 << height) - 1));
+		self.leaf_shift_cache.push(if height == 0 {
+			prev_leaf_shift
+		} else {
+			prev_leaf_shift + (1 << height)
+		});
+	}
+
 	/// Number of entries in the prune_list.
 	pub fn len(&self) -> u64 {
 		self.bitmap.cardinality()
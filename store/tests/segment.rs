@@ -372,6 +372,86 @@ fn ser_round_trip() {
 	fs::remove_dir_all(&data_dir).unwrap();
 }
 
+#[test]
+fn push_pruned_subtrees() {
+	let t = Utc::now();
+	let data_dir = format!(
+		"./target/tmp/{}.{}-push_pruned_subtrees",
+		t.timestamp(),
+		t.timestamp_subsec_nanos()
+	);
+	let src_dir = format!("{}/src", data_dir);
+	let dst_dir = format!("{}/dst", data_dir);
+	fs::create_dir_all(&src_dir).unwrap();
+	fs::create_dir_all(&dst_dir).unwrap();
+
+	let n_leaves = 40;
+	let elem = |i: u32| TestElem([i / 7, i / 5, i / 3, i]);
+
+	let mut ba = PMMRBackend::new(&src_dir, true, ProtocolVersion(1), None).unwrap();
+	let mut mmr = PMMR::new(&mut ba);
+	for i in 0..n_leaves {
+		mmr.push(&elem(i)).unwrap();
+	}
+	let last_pos = mmr.unpruned_size();
+	let root = mmr.root().unwrap();
+
+	// Leaves 8..16 are beneath pos 30, leaves 32..36 beneath pos 70
+	assert_eq!(pmmr::bintree_leftmost(30), pmmr::insertion_to_pmmr_index(9));
+	assert_eq!(
+		pmmr::bintree_leftmost(70),
+		pmmr::insertion_to_pmmr_index(33)
+	);
+	let hash_30 = mmr.get_hash(30).unwrap();
+	let hash_70 = mmr.get_hash(70).unwrap();
+
+	// Rebuild the MMR elsewhere, as we would from segments, without ever
+	// having the data beneath the pruned subtrees.
+	let mut ba2 = PMMRBackend::new(&dst_dir, true, ProtocolVersion(1), None).unwrap();
+	{
+		let mut mmr2: PMMR<TestElem, _> = PMMR::new(&mut ba2);
+		for i in 0..8 {
+			mmr2.push(&elem(i)).unwrap();
+		}
+		assert!(mmr2.push_pruned_subtree(hash_30, 70).is_err());
+		mmr2.push_pruned_subtree(hash_30, 30).unwrap();
+		for i in 16..32 {
+			mmr2.push(&elem(i)).unwrap();
+		}
+		mmr2.push_pruned_subtree(hash_70, 70).unwrap();
+		for i in 36..n_leaves {
+			mmr2.push(&elem(i)).unwrap();
+		}
+		assert_eq!(mmr2.unpruned_size(), last_pos);
+		assert_eq!(mmr2.root().unwrap(), root);
+		assert_eq!(mmr2.get_hash(16), None);
+		assert_eq!(mmr2.get_hash(30), Some(hash_30));
+		assert_eq!(
+			mmr2.get_data(pmmr::insertion_to_pmmr_index(17)),
+			Some(elem(16))
+		);
+		assert_eq!(
+			mmr2.get_data(pmmr::insertion_to_pmmr_index(37)),
+			Some(elem(36))
+		);
+	}
+	ba2.sync().unwrap();
+	std::mem::drop(ba2);
+
+	// Pruned subtrees survive reopening the backend
+	let ba2 = PMMRBackend::<TestElem>::new(&dst_dir, true, ProtocolVersion(1), None).unwrap();
+	let mmr2 = ReadonlyPMMR::at(&ba2, last_pos);
+	assert_eq!(mmr2.root().unwrap(), root);
+	assert_eq!(
+		mmr2.get_data(pmmr::insertion_to_pmmr_index(n_leaves as u64)),
+		Some(elem(n_leaves - 1))
+	);
+
+	std::mem::drop(ba);
+	std::mem::drop(ba2);
+	fs::remove_dir_all(&data_dir).unwrap();
+}
+
 fn prune<T, B>(mmr: &mut PMMR<T, B>, bitmap: &mut Bitmap, leaf_idxs: &[u64])
 where
 	T: PMMRable,